@@ -0,0 +1,153 @@
+//! timings of construction and queries on the point sets of `kdtree::workloads`
+//!
+//! `cargo bench --features bench [-- filter]` runs the benchmarks whose name contains `filter`,
+//! each on every workload, and prints the median of several runs
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use kdtree::{workloads::Workload, DynPoint, KDTree, F64};
+
+const LEN: usize = 100_000;
+const DIMS: usize = 3;
+const QUERIES: usize = 10_000;
+const RUNS: usize = 7;
+const SEED: u64 = 42;
+
+type Tree = KDTree<DynPoint>;
+
+/// median time of `RUNS` runs of `run`, each on fresh input from `setup`
+fn time<I, R>(mut setup: impl FnMut() -> I, mut run: impl FnMut(I) -> R) -> Duration {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let input = setup();
+            let start = Instant::now();
+            black_box(run(input));
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+/// `data` sorted by the first coordinate, as `from_sorted_by_axis` wants it for axis 0
+fn sorted_by_first(data: &[DynPoint]) -> Vec<DynPoint> {
+    let mut sorted = data.to_vec();
+    sorted.sort_by(|a, b| a.coords[0].total_cmp(&b.coords[0]));
+    sorted
+}
+
+fn main() {
+    let filter = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .unwrap_or_default();
+
+    type Bench = fn(&[DynPoint], &Tree, &[DynPoint]) -> Duration;
+    let benches: [(&str, Bench); 13] = [
+        ("make", |data, _, _| time(|| data.to_vec(), Tree::make)),
+        ("make_threads_8", |data, _, _| {
+            time(|| data.to_vec(), |data| Tree::make_threads(data, 8))
+        }),
+        ("make_with_axes", |data, _, _| {
+            time(
+                || data.to_vec(),
+                |data| Tree::make_with_axes(data, vec![0, 1, 2]),
+            )
+        }),
+        ("make_with_buckets", |data, _, _| {
+            time(|| data.to_vec(), |data| Tree::make_with_buckets(data, 8))
+        }),
+        ("make_with_counts", |data, _, _| {
+            time(|| data.to_vec(), Tree::make_with_counts)
+        }),
+        // the same presorted input for both, to compare with partitioning at the root
+        ("make_presorted", |data, _, _| {
+            let sorted = sorted_by_first(data);
+            time(|| sorted.clone(), Tree::make)
+        }),
+        ("from_sorted_by_axis", |data, _, _| {
+            let sorted = sorted_by_first(data);
+            time(|| sorted.clone(), |data| Tree::from_sorted_by_axis(data, 0))
+        }),
+        ("make_sharded", |data, _, _| {
+            time(
+                || data.to_vec(),
+                |data| Tree::make_sharded(data, 8, |point| (point.coords[0] * 8.0) as usize % 8),
+            )
+        }),
+        ("insert", |data, _, _| {
+            time(
+                || (Tree::make(Vec::new()), data[..QUERIES].to_vec()),
+                |(mut tree, data)| {
+                    for point in data {
+                        tree.insert(point);
+                    }
+                    tree
+                },
+            )
+        }),
+        ("insert_rebuild_subtree", |data, _, _| {
+            time(
+                || {
+                    let tree = Tree::make(Vec::new())
+                        .with_depth_policy(kdtree::DepthPolicy::RebuildSubtree);
+                    (tree, data[..QUERIES].to_vec())
+                },
+                |(mut tree, data)| {
+                    for point in data {
+                        tree.insert(point);
+                    }
+                    tree
+                },
+            )
+        }),
+        ("find_nearest", |_, tree, queries| {
+            time(
+                || (),
+                |()| {
+                    for query in queries {
+                        black_box(tree.find_nearest(query));
+                    }
+                },
+            )
+        }),
+        ("find_k_nearest_10", |_, tree, queries| {
+            time(
+                || (),
+                |()| {
+                    for query in queries {
+                        black_box(tree.find_k_nearest(query, 10));
+                    }
+                },
+            )
+        }),
+        ("find_within_radius", |_, tree, queries| {
+            // about 30 points of a uniform cube around each query
+            let radius = F64::from(0.04 * 0.04);
+            time(
+                || (),
+                |()| {
+                    for query in queries {
+                        black_box(tree.find_within_radius(query, radius));
+                    }
+                },
+            )
+        }),
+    ];
+
+    for (name, bench) in benches {
+        if !name.contains(&filter) {
+            continue;
+        }
+        for workload in Workload::ALL {
+            let data = workload.generate(LEN, DIMS, SEED);
+            let tree = Tree::make(data.clone());
+            let queries = Workload::Uniform.generate(QUERIES, DIMS, SEED + 1);
+            let median = bench(&data, &tree, &queries);
+            println!("{name:<24} {:<10} {median:>12.2?}", workload.name());
+        }
+    }
+}
@@ -0,0 +1,4 @@
+//! the C interface of `kdtree` (see `include/kdtree.h` there) built as static and shared library,
+//! so the `kdtree` crate itself stays a plain Rust library
+
+pub use kdtree::ffi::*;
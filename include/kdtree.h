@@ -0,0 +1,101 @@
+/* Generated with cbindgen, regenerate with `cbindgen --config cbindgen.toml --output include/kdtree.h` */
+
+#ifndef KDTREE_H
+#define KDTREE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define KDTREE_OK 0
+
+#define KDTREE_NULL_POINTER 1
+
+#define KDTREE_EMPTY 2
+
+#define KDTREE_BUFFER_TOO_SMALL 3
+
+#define KDTREE_PANIC 4
+
+/**
+ * opaque handle handed out to C as `kdtree_t*`
+ */
+typedef struct kdtree_t kdtree_t;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * builds a tree from `n` row-major points of `dims` coordinates each, the rows are copied
+ *
+ * returns NULL if `coords` is NULL, `dims` is 0 or building fails
+ *
+ * # Safety
+ * `coords` must point to `n * dims` readable doubles
+ */
+kdtree_t *kdtree_build_f64(const double *coords, size_t n, size_t dims);
+
+/**
+ * writes the row index of the point nearest to `query` and its squared distance
+ *
+ * `out_distance` may be NULL, returns `KDTREE_EMPTY` if the tree holds no points
+ *
+ * # Safety
+ * `tree` must come from `kdtree_build_f64`, `query` must point to `dims` readable doubles
+ */
+int32_t kdtree_query_nearest(const kdtree_t *tree,
+                             const double *query,
+                             size_t *out_index,
+                             double *out_distance);
+
+/**
+ * writes the row indices and squared distances of the (up to) `k` points nearest to `query`,
+ * nearest first, and their number to `out_count`
+ *
+ * `out_distances` may be NULL
+ *
+ * # Safety
+ * `tree` must come from `kdtree_build_f64`, `query` must point to `dims` readable doubles,
+ * `out_indices` and `out_distances` (if not NULL) must have room for `k` elements
+ */
+int32_t kdtree_query_knn(const kdtree_t *tree,
+                         const double *query,
+                         size_t k,
+                         size_t *out_indices,
+                         double *out_distances,
+                         size_t *out_count);
+
+/**
+ * finds all points within squared distance `radius` (inclusive) of `query`
+ *
+ * always writes the number of matches to `out_count`; the row indices are written to
+ * `out_indices` only if `capacity` suffices, otherwise `KDTREE_BUFFER_TOO_SMALL` is returned.
+ * Call with `capacity` 0 to learn the required size first. The order of indices is unspecified.
+ *
+ * # Safety
+ * `tree` must come from `kdtree_build_f64`, `query` must point to `dims` readable doubles,
+ * `out_indices` must have room for `capacity` elements
+ */
+int32_t kdtree_query_radius(const kdtree_t *tree,
+                            const double *query,
+                            double radius,
+                            size_t *out_indices,
+                            size_t capacity,
+                            size_t *out_count);
+
+/**
+ * releases a tree, NULL is ignored
+ *
+ * # Safety
+ * `tree` must come from `kdtree_build_f64` and must not be used afterwards
+ */
+void kdtree_free(kdtree_t *tree);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* KDTREE_H */
@@ -0,0 +1,267 @@
+//! user data attached to the nodes of a tree, kept in a side table keyed by the nodes' positions
+
+use std::collections::HashMap;
+
+use super::{KDPoint, KDTree, Node};
+
+/// position of a node: the turns from the root to it, `true` going left
+///
+/// handles stay valid across inserts until the subtree containing the node is rebuilt
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct NodeHandle(Vec<bool>);
+
+impl NodeHandle {
+    /// the root's handle
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+
+    /// the parent's handle, `None` for the root
+    pub fn parent(&self) -> Option<Self> {
+        let (_, path) = self.0.split_last()?;
+        Some(Self(path.to_vec()))
+    }
+
+    /// whether this node lies in the subtree below `ancestor`, or is `ancestor`
+    pub fn is_below(&self, ancestor: &Self) -> bool {
+        self.0.starts_with(&ancestor.0)
+    }
+}
+
+/// computes the value of a node from the points of its subtree
+type OnRebuild<T, M> = Box<dyn FnMut(&[&T]) -> M>;
+
+/// a tree with a value of `M` for every node
+///
+/// `on_rebuild` computes the value of a node from all points of its subtree, it is called for
+/// every node when the tree is made and for every node of a subtree rebuilt by an insert (see
+/// `DepthPolicy::RebuildSubtree`) or by `rebalance`; nodes created by inserts start out with
+/// `M::default()` and the values of their ancestors are left as they are
+pub struct AnnotatedKDTree<T: KDPoint, M> {
+    tree: KDTree<T>,
+    metadata: HashMap<NodeHandle, M>,
+    on_rebuild: OnRebuild<T, M>,
+}
+
+impl<T: KDPoint, M: Default> AnnotatedKDTree<T, M> {
+    pub fn new(tree: KDTree<T>, on_rebuild: impl FnMut(&[&T]) -> M + 'static) -> Self {
+        let mut annotated = Self {
+            tree,
+            metadata: HashMap::new(),
+            on_rebuild: Box::new(on_rebuild),
+        };
+        annotated.recompute(NodeHandle::root());
+        annotated
+    }
+
+    /// the points, for queries
+    pub fn tree(&self) -> &KDTree<T> {
+        &self.tree
+    }
+
+    /// the value of the node at `handle`, `None` if there is no such node
+    pub fn metadata(&self, handle: &NodeHandle) -> Option<&M> {
+        self.metadata.get(handle)
+    }
+
+    pub fn metadata_mut(&mut self, handle: &NodeHandle) -> Option<&mut M> {
+        self.metadata.get_mut(handle)
+    }
+
+    /// every node's handle and point, in pre-order
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeHandle, &T)> {
+        let mut stack: Vec<_> = self
+            .tree
+            .root
+            .iter()
+            .map(|root| (NodeHandle::root(), root))
+            .collect();
+        std::iter::from_fn(move || {
+            let (handle, node) = stack.pop()?;
+            for (child, is_left) in [(&node.right, false), (&node.left, true)] {
+                if let Some(child) = child {
+                    let mut path = handle.0.clone();
+                    path.push(is_left);
+                    stack.push((NodeHandle(path), child));
+                }
+            }
+            Some((handle, &node.data))
+        })
+    }
+
+    /// inserts `data` and returns the handle of the node now holding it, `None` if the insert
+    /// rebuilt the subtree it went into
+    pub fn insert(&mut self, data: T) -> Option<NodeHandle> {
+        let placement = self.tree.insert_placed(data);
+        if let Some(depth) = placement.rebuilt {
+            self.recompute(NodeHandle(placement.path[..depth].to_vec()));
+            return None;
+        }
+        let handle = NodeHandle(placement.path);
+        self.metadata.entry(handle.clone()).or_default();
+        Some(handle)
+    }
+
+    /// `KDTree::rebalance`, recomputing the value of every node
+    pub fn rebalance(&mut self) {
+        self.tree.rebalance();
+        self.recompute(NodeHandle::root());
+    }
+
+    /// replaces the values of the subtree at `handle` with fresh ones from `on_rebuild`
+    fn recompute(&mut self, handle: NodeHandle) {
+        self.metadata.retain(|other, _| !other.is_below(&handle));
+
+        let mut node = self.tree.root.as_ref();
+        for &left in &handle.0 {
+            node = node.and_then(|node| if left { &node.left } else { &node.right }.as_deref());
+        }
+        let mut subtrees = Vec::new();
+        if let Some(node) = node {
+            node.subtrees(handle, &mut subtrees);
+        }
+        for (handle, points) in subtrees {
+            let value = (self.on_rebuild)(&points);
+            self.metadata.insert(handle, value);
+        }
+    }
+}
+
+impl<T> Node<T> {
+    /// appends this node's handle and all points of its subtree to `subtrees`, followed by those
+    /// of the nodes below it, returns the points
+    fn subtrees<'t>(
+        &'t self,
+        handle: NodeHandle,
+        subtrees: &mut Vec<(NodeHandle, Vec<&'t T>)>,
+    ) -> Vec<&'t T> {
+        let idx = subtrees.len();
+        subtrees.push((handle.clone(), Vec::new()));
+
+        let mut points: Vec<&T> = self.points().collect();
+        for (child, is_left) in [(&self.left, true), (&self.right, false)] {
+            if let Some(child) = child {
+                let mut path = handle.0.clone();
+                path.push(is_left);
+                points.extend(child.subtrees(NodeHandle(path), subtrees));
+            }
+        }
+        subtrees[idx].1 = points.clone();
+        points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DepthPolicy, Point2D, F64};
+    use rand::random;
+
+    fn key(p: &Point2D) -> (F64, F64) {
+        (p.x.into(), p.y.into())
+    }
+
+    /// the sorted keys of a subtree's points
+    fn keys(points: &[&Point2D]) -> Vec<(F64, F64)> {
+        let mut keys: Vec<_> = points.iter().map(|p| key(p)).collect();
+        keys.sort();
+        keys
+    }
+
+    /// checks that every node has the keys of its subtree as value
+    fn check_subtree_keys(annotated: &AnnotatedKDTree<Point2D, Vec<(F64, F64)>>) {
+        let nodes: Vec<_> = annotated.nodes().collect();
+        for (handle, _) in &nodes {
+            let below: Vec<&Point2D> = nodes
+                .iter()
+                .filter(|(other, _)| other.is_below(handle))
+                .map(|(_, point)| *point)
+                .collect();
+            assert_eq!(annotated.metadata(handle), Some(&keys(&below)));
+        }
+    }
+
+    #[test]
+    fn survives_inserts() {
+        let data: Vec<Point2D> = (0..100).map(|_| (random(), random()).into()).collect();
+        let mut annotated = AnnotatedKDTree::new(KDTree::make(data), |points| points.len());
+        assert_eq!(annotated.metadata(&NodeHandle::root()), Some(&100));
+        assert_eq!(annotated.nodes().count(), 100);
+        for (handle, _) in annotated.nodes() {
+            assert!(annotated.metadata(&handle).is_some());
+        }
+
+        let left = NodeHandle(vec![true]);
+        *annotated.metadata_mut(&left).unwrap() = 1000;
+        let before: Vec<_> = annotated
+            .nodes()
+            .map(|(handle, _)| annotated.metadata(&handle).copied())
+            .collect();
+        let inserted = annotated.insert((2.0, 2.0).into()).unwrap();
+        assert!(!inserted.is_below(&left));
+        assert_eq!(annotated.metadata(&inserted), Some(&0));
+        assert_eq!(annotated.metadata(&left), Some(&1000));
+        let after: Vec<_> = annotated
+            .nodes()
+            .filter(|(handle, _)| *handle != inserted)
+            .map(|(handle, _)| annotated.metadata(&handle).copied())
+            .collect();
+        assert_eq!(after, before);
+        assert!(annotated.metadata(&NodeHandle(vec![true; 20])).is_none());
+
+        annotated.rebalance();
+        assert_eq!(annotated.metadata(&NodeHandle::root()), Some(&101));
+        assert_eq!(
+            annotated.metadata(&left).map(|&size| size < 100),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn rebuilds_recompute_subtrees() {
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        let tree = KDTree::make(Vec::new())
+            .with_depth_policy(DepthPolicy::RebuildSubtree)
+            .with_max_depth(4);
+        let mut annotated = AnnotatedKDTree::new(tree, move |points: &[&Point2D]| {
+            counter.set(counter.get() + 1);
+            keys(points)
+        });
+        assert_eq!(calls.get(), 0);
+
+        let mut rebuilds = 0;
+        for i in 0..64 {
+            let point: Point2D = (i as f64, i as f64).into();
+            match annotated.insert(point.clone()) {
+                // keep the ancestors of new nodes up to date by hand
+                Some(inserted) => {
+                    let mut handle = Some(inserted);
+                    while let Some(current) = handle {
+                        let value = annotated.metadata_mut(&current).unwrap();
+                        value.push(key(&point));
+                        value.sort();
+                        handle = current.parent();
+                    }
+                }
+                None => rebuilds += 1,
+            }
+            check_subtree_keys(&annotated);
+        }
+        assert!(rebuilds > 0);
+        assert!(calls.get() > 0);
+        assert!(annotated.tree().depth() < 64);
+        for (handle, _) in annotated.nodes() {
+            assert!(annotated.metadata(&handle).is_some());
+        }
+
+        annotated.rebalance();
+        check_subtree_keys(&annotated);
+        let root = annotated.metadata(&NodeHandle::root()).unwrap();
+        assert_eq!(root.len(), 64);
+    }
+}
@@ -0,0 +1,125 @@
+use super::{KDPoint, KDTree};
+
+/// a tree holding at most `capacity` points, inserting into a full tree evicts the point farthest
+/// from `center`
+///
+/// the retained points are always the `capacity` ones nearest to `center` among all inserted so
+/// far (up to ties)
+#[derive(Debug)]
+pub struct BoundedKDTree<T: KDPoint> {
+    tree: KDTree<T>,
+    center: T,
+    capacity: usize,
+    len: usize,
+}
+
+impl<T: KDPoint> BoundedKDTree<T> {
+    pub fn new(capacity: usize, center: T) -> Self {
+        Self {
+            tree: KDTree::make(Vec::new()),
+            center,
+            capacity,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn center(&self) -> &T {
+        &self.center
+    }
+
+    /// the retained points, for queries
+    pub fn tree(&self) -> &KDTree<T> {
+        &self.tree
+    }
+
+    /// inserts `data` and returns the evicted point if the tree was full, which may be `data`
+    /// itself
+    ///
+    /// finding the farthest point visits all of them, and removing it rebuilds the subtree below
+    /// its node
+    pub fn insert(&mut self, data: T) -> Option<T> {
+        self.tree.insert(data);
+        if self.len < self.capacity {
+            self.len += 1;
+            return None;
+        }
+        let center = &self.center;
+        self.tree
+            .remove_max_by_key(|point| T::distance(center, point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2D, F64};
+
+    fn key(p: &Point2D) -> (F64, F64) {
+        (p.x.into(), p.y.into())
+    }
+
+    #[test]
+    fn keeps_nearest_to_center() {
+        use rand::random;
+
+        let capacity = 50;
+        let center: Point2D = (0.5, 0.5).into();
+        let data: Vec<Point2D> = (0..capacity + 5)
+            .map(|_| (random(), random()).into())
+            .collect();
+
+        let mut bounded = BoundedKDTree::new(capacity, center.clone());
+        let mut evicted = Vec::new();
+        for point in data.iter().cloned() {
+            evicted.extend(bounded.insert(point));
+        }
+        assert_eq!(bounded.len(), capacity);
+        assert_eq!(bounded.tree().iter().count(), capacity);
+        assert_eq!(evicted.len(), 5);
+        assert!(bounded.tree().validate());
+
+        let mut expected = data.clone();
+        expected.sort_by_key(|p| Point2D::distance(&center, p));
+        let mut expected: Vec<_> = expected[..capacity].iter().map(key).collect();
+        let mut retained: Vec<_> = bounded.tree().iter().map(key).collect();
+        expected.sort();
+        retained.sort();
+        assert_eq!(retained, expected);
+
+        let search: Point2D = (random(), random()).into();
+        let nearest = bounded.tree().find_nearest(&search).unwrap();
+        let brute_force = retained
+            .iter()
+            .map(|&(x, y)| Point2D::distance(&search, &(x.into(), y.into()).into()))
+            .min();
+        assert_eq!(Some(Point2D::distance(&search, nearest)), brute_force);
+    }
+
+    #[test]
+    fn evicts_the_newcomer() {
+        let mut bounded = BoundedKDTree::new(2, Point2D::from((0.0, 0.0)));
+        assert!(bounded.insert((1.0, 0.0).into()).is_none());
+        assert!(bounded.insert((0.0, 2.0).into()).is_none());
+        let evicted = bounded.insert((5.0, 5.0).into()).unwrap();
+        assert_eq!(key(&evicted), key(&(5.0, 5.0).into()));
+        let evicted = bounded.insert((0.5, 0.5).into()).unwrap();
+        assert_eq!(key(&evicted), key(&(0.0, 2.0).into()));
+        assert_eq!(bounded.len(), 2);
+
+        let mut empty = BoundedKDTree::new(0, Point2D::from((0.0, 0.0)));
+        assert!(empty.insert((1.0, 1.0).into()).is_some());
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+//! nearest neighbours of a fixed set of queries, kept up to date while the tree changes
+
+use super::{KDPoint, KDTree};
+
+/// the nearest point in a tree for each of a fixed set of queries
+///
+/// answers are copies, so the tree may change between refreshes. Every point inserted into or
+/// removed from the tree is to be passed to `invalidate`, `refresh` then searches again only for
+/// the queries whose answer that point may have changed.
+#[derive(Debug, Clone)]
+pub struct QueryCache<T: KDPoint> {
+    queries: Vec<T>,
+    /// the nearest point and its distance, `None` if the tree was empty
+    answers: Vec<Option<(T, T::Distance)>>,
+    /// queries to search for on the next refresh
+    stale: Vec<bool>,
+}
+
+impl<T: KDPoint + Clone> QueryCache<T> {
+    /// a cache without answers yet, the first refresh searches for all queries
+    pub fn new(queries: Vec<T>) -> Self {
+        Self {
+            answers: vec![None; queries.len()],
+            stale: vec![true; queries.len()],
+            queries,
+        }
+    }
+
+    pub fn queries(&self) -> &[T] {
+        &self.queries
+    }
+
+    /// the answer for the query at `index` as of the last refresh, `None` if there was none
+    pub fn answer(&self, index: usize) -> Option<(&T, &T::Distance)> {
+        self.answers[index]
+            .as_ref()
+            .map(|(point, distance)| (point, distance))
+    }
+
+    /// marks the queries whose answer a change of `point` may affect: those at least as far from
+    /// their answer as from `point`, and those without one
+    pub fn invalidate(&mut self, point: &T) {
+        for ((query, answer), stale) in self.queries.iter().zip(&self.answers).zip(&mut self.stale)
+        {
+            *stale |= answer
+                .as_ref()
+                .map(|(_, distance)| T::distance(query, point) <= *distance)
+                .unwrap_or(true);
+        }
+    }
+
+    /// marks all queries, for changes too large to report point by point
+    pub fn invalidate_all(&mut self) {
+        self.stale.fill(true);
+    }
+
+    /// searches `tree` for the marked queries, returns how many there were
+    pub fn refresh(&mut self, tree: &KDTree<T>) -> usize {
+        let mut searched = 0;
+        for ((query, answer), stale) in self
+            .queries
+            .iter()
+            .zip(&mut self.answers)
+            .zip(&mut self.stale)
+        {
+            if std::mem::take(stale) {
+                *answer = tree
+                    .find_nearest(query)
+                    .map(|nearest| (nearest.clone(), T::distance(query, nearest)));
+                searched += 1;
+            }
+        }
+        searched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point2D;
+    use rand::random;
+
+    fn coordinates(answer: Option<(&Point2D, &crate::F64)>) -> Option<(f64, f64)> {
+        answer.map(|(point, _)| (point.x, point.y))
+    }
+
+    #[test]
+    fn refreshes_affected_answers() {
+        let data: Vec<Point2D> = (0..200)
+            .map(|_| (random::<f64>() * 10.0, random::<f64>() * 10.0).into())
+            .collect();
+        let mut tree = KDTree::make(data);
+        let queries: Vec<Point2D> = [(1.0, 1.0), (1.0, 9.0), (9.0, 1.0), (9.0, 9.0), (5.0, 5.0)]
+            .into_iter()
+            .map(Point2D::from)
+            .collect();
+        let mut cache = QueryCache::new(queries.clone());
+        assert!(cache.answer(0).is_none());
+        assert_eq!(cache.refresh(&tree), 5);
+        assert_eq!(cache.refresh(&tree), 0);
+        for (index, query) in queries.iter().enumerate() {
+            let nearest = tree.find_nearest(query).unwrap();
+            assert_eq!(
+                coordinates(cache.answer(index)),
+                Some((nearest.x, nearest.y))
+            );
+        }
+
+        // right next to the query at (9, 1)
+        let before: Vec<_> = (0..5)
+            .map(|index| coordinates(cache.answer(index)))
+            .collect();
+        let inserted = Point2D::from((9.0, 1.0 + 1e-9));
+        tree.insert(inserted.clone());
+        cache.invalidate(&inserted);
+        assert_eq!(cache.refresh(&tree), 1);
+        for (index, before) in before.into_iter().enumerate() {
+            let after = coordinates(cache.answer(index));
+            if index == 2 {
+                assert_eq!(after, Some((9.0, 1.0 + 1e-9)));
+            } else {
+                assert_eq!(after, before);
+            }
+        }
+
+        cache.invalidate_all();
+        assert_eq!(cache.refresh(&tree), 5);
+    }
+
+    #[test]
+    fn empty_tree() {
+        let mut tree = KDTree::make(Vec::new());
+        let mut cache = QueryCache::new(vec![Point2D::from((0.0, 0.0))]);
+        assert_eq!(cache.refresh(&tree), 1);
+        assert!(cache.answer(0).is_none());
+
+        // queries without an answer take any point
+        let inserted = Point2D::from((100.0, 100.0));
+        tree.insert(inserted.clone());
+        cache.invalidate(&inserted);
+        assert_eq!(cache.refresh(&tree), 1);
+        assert_eq!(coordinates(cache.answer(0)), Some((100.0, 100.0)));
+    }
+}
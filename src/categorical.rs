@@ -0,0 +1,247 @@
+//! points with one categorical axis, whose distances come from a table, and numeric ones
+
+use std::{cmp::Ordering, sync::Arc};
+
+use super::{KDPoint, F64};
+
+/// distances between the categories `0..n`, from a square matrix
+///
+/// the matrix should be a metric: zero on the diagonal, symmetric, non-negative and satisfying
+/// the triangle inequality, or distances between points will not be one either. Searches are
+/// exact as long as the matrix is symmetric with a zero diagonal, which `new` checks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryTable {
+    distances: Vec<Vec<f64>>,
+    /// `bounds[q][s]`: smallest distance from `q` to any category at or beyond `s` as seen from
+    /// `q`, i.e. to those from `s` up if `q < s` and from `s` down if `q > s`
+    bounds: Vec<Vec<f64>>,
+}
+
+impl CategoryTable {
+    /// panics unless `distances` is square, symmetric, non-negative and zero on the diagonal
+    pub fn new(distances: Vec<Vec<f64>>) -> Self {
+        let n = distances.len();
+        for (i, row) in distances.iter().enumerate() {
+            assert_eq!(row.len(), n, "category distances are not a square matrix");
+            assert_eq!(row[i], 0.0, "category {i} is not at distance 0 from itself");
+            for (j, &distance) in row.iter().enumerate() {
+                assert!(
+                    distance >= 0.0 && distance == distances[j][i],
+                    "distances between categories {i} and {j} are not symmetric and non-negative"
+                );
+            }
+        }
+
+        let bounds = distances
+            .iter()
+            .enumerate()
+            .map(|(q, row)| {
+                let mut bound = vec![0.0; n];
+                let mut min = f64::INFINITY;
+                for s in (q + 1..n).rev() {
+                    min = min.min(row[s]);
+                    bound[s] = min;
+                }
+                min = f64::INFINITY;
+                for s in 0..q {
+                    min = min.min(row[s]);
+                    bound[s] = min;
+                }
+                bound
+            })
+            .collect();
+        Self { distances, bounds }
+    }
+
+    pub fn len(&self) -> usize {
+        self.distances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.distances.is_empty()
+    }
+
+    pub fn distance(&self, a: usize, b: usize) -> f64 {
+        self.distances[a][b]
+    }
+}
+
+/// key of a `CategoricalPoint`: the category in dimension 0, coordinates in the others
+///
+/// categories are ordered by number; keys of different kinds are never compared by the tree
+#[derive(Debug, Clone)]
+pub enum MixedKey {
+    Category(usize, Arc<CategoryTable>),
+    Number(F64),
+}
+
+impl MixedKey {
+    fn rank(&self) -> (usize, F64) {
+        match self {
+            Self::Category(category, _) => (*category, F64::from(f64::NEG_INFINITY)),
+            Self::Number(number) => (usize::MAX, *number),
+        }
+    }
+}
+
+impl PartialEq for MixedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.rank() == other.rank()
+    }
+}
+
+impl Eq for MixedKey {}
+
+impl PartialOrd for MixedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MixedKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+/// a category from `table` in dimension 0, followed by numeric coordinates
+///
+/// the distance is squared: the squared table distance between the categories plus the squared
+/// euclidean distance between the coordinates. The key distance in dimension 0 is the smallest
+/// table distance from the search's category to any category past the split, squared, which
+/// keeps pruning exact however the categories are numbered.
+#[derive(Debug, Clone)]
+pub struct CategoricalPoint {
+    pub category: usize,
+    pub coords: Vec<f64>,
+    pub table: Arc<CategoryTable>,
+}
+
+impl CategoricalPoint {
+    /// panics if `category` is not in `table`
+    pub fn new(category: usize, coords: Vec<f64>, table: &Arc<CategoryTable>) -> Self {
+        assert!(
+            category < table.len(),
+            "no category {category} in the table"
+        );
+        Self {
+            category,
+            coords,
+            table: Arc::clone(table),
+        }
+    }
+}
+
+impl KDPoint for CategoricalPoint {
+    type Key = MixedKey;
+    type Distance = F64;
+
+    fn dimensions(&self) -> usize {
+        1 + self.coords.len()
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        match dimension {
+            0 => MixedKey::Category(self.category, Arc::clone(&self.table)),
+            _ => MixedKey::Number(self.coords[dimension - 1].into()),
+        }
+    }
+
+    /// over the dimensions of `rhs`, a search point may have more
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        debug_assert!(lhs.coords.len() >= rhs.coords.len());
+        let category = lhs.table.distance(lhs.category, rhs.category);
+        let coords: f64 = lhs
+            .coords
+            .iter()
+            .zip(&rhs.coords)
+            .map(|(l, r)| (l - r) * (l - r))
+            .sum();
+        (category * category + coords).into()
+    }
+
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        match (lhs, rhs) {
+            (MixedKey::Category(search, table), MixedKey::Category(split, _)) => {
+                let bound = table.bounds[*search][*split];
+                (bound * bound).into()
+            }
+            (MixedKey::Number(lhs), MixedKey::Number(rhs)) => {
+                let dist = *lhs - *rhs;
+                dist * dist
+            }
+            _ => F64::from(0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KDTree;
+    use rand::{random, Rng};
+
+    /// colors far apart by number but close in the table, so that pruning by number would be
+    /// wrong
+    fn colors() -> Arc<CategoryTable> {
+        Arc::new(CategoryTable::new(vec![
+            vec![0.0, 5.0, 4.0, 3.0, 0.5],
+            vec![5.0, 0.0, 2.0, 4.0, 5.0],
+            vec![4.0, 2.0, 0.0, 1.0, 4.0],
+            vec![3.0, 4.0, 1.0, 0.0, 3.0],
+            vec![0.5, 5.0, 4.0, 3.0, 0.0],
+        ]))
+    }
+
+    fn random_point(table: &Arc<CategoryTable>) -> CategoricalPoint {
+        let category = rand::thread_rng().gen_range(0..table.len());
+        CategoricalPoint::new(category, vec![random(), 10.0 * random::<f64>()], table)
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let table = colors();
+        let data: Vec<CategoricalPoint> = (0..500).map(|_| random_point(&table)).collect();
+        let tree = KDTree::make(data.clone());
+        assert!(tree.validate());
+
+        for _ in 0..200 {
+            let search = random_point(&table);
+            let mut distances: Vec<F64> = data
+                .iter()
+                .map(|point| CategoricalPoint::distance(&search, point))
+                .collect();
+            distances.sort();
+
+            let nearest = tree.find_nearest(&search).unwrap();
+            assert_eq!(CategoricalPoint::distance(&search, nearest), distances[0]);
+            let found: Vec<F64> = tree
+                .find_k_nearest(&search, 10)
+                .into_iter()
+                .map(|point| CategoricalPoint::distance(&search, point))
+                .collect();
+            assert_eq!(found, distances[..10]);
+            let radius = F64::from(1.0);
+            assert_eq!(
+                tree.find_within_radius(&search, radius).len(),
+                distances.iter().filter(|d| **d <= radius).count()
+            );
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let table = colors();
+        // from 0, categories 2 and up are at least 0.5 away: 4 is
+        assert_eq!(table.bounds[0][2], 0.5);
+        // from 3, categories 1 and down are at least 3 away
+        assert_eq!(table.bounds[3][1], 3.0);
+        assert_eq!(table.bounds[2][2], 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "not symmetric")]
+    fn asymmetric() {
+        CategoryTable::new(vec![vec![0.0, 1.0], vec![2.0, 0.0]]);
+    }
+}
@@ -0,0 +1,240 @@
+//! labels and values of new points from those of their nearest neighbours
+
+use std::{collections::HashMap, hash::Hash};
+
+use super::{KDTree, SquaredEuclidean, F64};
+
+/// how much each of the nearest neighbours counts in `knn_classify` and `knn_regress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Weighting {
+    /// all the same
+    #[default]
+    Uniform,
+    /// by the inverse of the distance, in actual units rather than squared
+    InverseDistance,
+}
+
+impl<T: SquaredEuclidean> KDTree<T> {
+    /// the label with the greatest total weight among the `k` points nearest to `search`, `None`
+    /// if the tree is empty or `k` is 0
+    ///
+    /// points at distance 0 decide on their own, as if their weight were infinite; on ties, the
+    /// label of the nearest point among the tied ones wins
+    pub fn knn_classify<L: Eq + Hash + Clone>(
+        &self,
+        search: &T,
+        k: usize,
+        weighting: Weighting,
+        label: impl Fn(&T) -> L,
+    ) -> Option<L> {
+        // labels in the order of their nearest point, with their total weight
+        let mut votes: Vec<(L, f64)> = Vec::new();
+        let mut positions: HashMap<L, usize> = HashMap::new();
+        for (point, weight) in self.knn_weights(search, k, weighting) {
+            let label = label(point);
+            let position = *positions.entry(label.clone()).or_insert_with(|| {
+                votes.push((label, 0.0));
+                votes.len() - 1
+            });
+            votes[position].1 += weight;
+        }
+        votes
+            .into_iter()
+            .reduce(|best, vote| if vote.1 > best.1 { vote } else { best })
+            .map(|(label, _)| label)
+    }
+
+    /// the weighted mean of the values of the `k` points nearest to `search`, `None` if the tree
+    /// is empty or `k` is 0
+    ///
+    /// points at distance 0 decide on their own, as if their weight were infinite
+    pub fn knn_regress(
+        &self,
+        search: &T,
+        k: usize,
+        weighting: Weighting,
+        value: impl Fn(&T) -> f64,
+    ) -> Option<f64> {
+        let (sum, total) = self
+            .knn_weights(search, k, weighting)
+            .into_iter()
+            .fold((0.0, 0.0), |(sum, total), (point, weight)| {
+                (sum + weight * value(point), total + weight)
+            });
+        (total > 0.0).then(|| sum / total)
+    }
+
+    /// the `k` nearest points with their weights, nearest first; only those at distance 0, with
+    /// weight 1, if there are any
+    fn knn_weights(&self, search: &T, k: usize, weighting: Weighting) -> Vec<(&T, f64)> {
+        let found = self.find_k_nearest_with_distances(search, k);
+        let exact = found
+            .iter()
+            .take_while(|(_, distance)| *distance == F64::from(0.0))
+            .count();
+        if exact > 0 {
+            return found[..exact]
+                .iter()
+                .map(|(point, _)| (*point, 1.0))
+                .collect();
+        }
+        found
+            .into_iter()
+            .map(|(point, distance)| {
+                let weight = match weighting {
+                    Weighting::Uniform => 1.0,
+                    Weighting::InverseDistance => 1.0 / f64::from(distance).sqrt(),
+                };
+                (point, weight)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KDPoint, Point2D};
+
+    /// points along the x axis, labelled by whether they lie left of 0
+    fn line(xs: &[f64]) -> KDTree<Point2D> {
+        KDTree::make(xs.iter().map(|&x| (x, 0.0).into()).collect())
+    }
+
+    fn side(point: &Point2D) -> &'static str {
+        if point.x < 0.0 {
+            "left"
+        } else {
+            "right"
+        }
+    }
+
+    #[test]
+    fn classify() {
+        // two far points on the right, one close on the left
+        let tree = line(&[-1.0, 3.0, 4.0, 20.0]);
+        let search = (-0.5, 0.0).into();
+        let classify = |k, weighting| tree.knn_classify(&search, k, weighting, side);
+        assert_eq!(classify(3, Weighting::Uniform), Some("right"));
+        assert_eq!(classify(3, Weighting::InverseDistance), Some("left"));
+        assert_eq!(classify(1, Weighting::Uniform), Some("left"));
+        // more than there are
+        assert_eq!(classify(10, Weighting::Uniform), Some("right"));
+        assert_eq!(classify(0, Weighting::Uniform), None);
+        let empty = line(&[]);
+        assert_eq!(
+            empty.knn_classify(&search, 3, Weighting::Uniform, side),
+            None
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_nearest() {
+        let tree = line(&[-2.0, -3.0, 1.0, 4.0]);
+        let search = (0.0, 0.0).into();
+        // a uniform vote of 2 to 2, the nearest point is on the right
+        assert_eq!(
+            tree.knn_classify(&search, 4, Weighting::Uniform, side),
+            Some("right")
+        );
+        // equal weights, 1/2 + 1/2 against 1/1
+        let tree = line(&[-2.0, -2.0, 1.0]);
+        assert_eq!(
+            tree.knn_classify(&search, 3, Weighting::InverseDistance, side),
+            Some("right")
+        );
+        // equally near, whichever the search comes across first, the same every time
+        let tree = line(&[-1.0, 1.0]);
+        for _ in 0..5 {
+            assert_eq!(
+                tree.knn_classify(&search, 2, Weighting::Uniform, side),
+                tree.knn_classify(&search, 2, Weighting::Uniform, side)
+            );
+        }
+    }
+
+    #[test]
+    fn exact_matches_decide() {
+        let tree = line(&[-1.0, 2.0, 2.0, 3.0, 5.0]);
+        let on_point = (-1.0, 0.0).into();
+        for weighting in [Weighting::Uniform, Weighting::InverseDistance] {
+            assert_eq!(
+                tree.knn_classify(&on_point, 5, weighting, side),
+                Some("left")
+            );
+            assert_eq!(
+                tree.knn_regress(&on_point, 5, weighting, |p| p.x),
+                Some(-1.0)
+            );
+        }
+    }
+
+    /// a point carrying a value of its own, so points at the same position can differ
+    #[derive(Debug, Clone)]
+    struct Sample {
+        at: Point2D,
+        value: f64,
+    }
+
+    impl KDPoint for Sample {
+        type Key = F64;
+        type Distance = F64;
+
+        fn dimensions(&self) -> usize {
+            2
+        }
+
+        fn kdkey(&self, dimension: usize) -> F64 {
+            self.at.kdkey(dimension)
+        }
+
+        fn distance(lhs: &Self, rhs: &Self) -> F64 {
+            Point2D::distance(&lhs.at, &rhs.at)
+        }
+
+        fn key_distance(lhs: &F64, rhs: &F64) -> F64 {
+            Point2D::key_distance(lhs, rhs)
+        }
+    }
+
+    impl SquaredEuclidean for Sample {}
+
+    #[test]
+    fn exact_matches_are_averaged() {
+        let sample = |x, value| Sample {
+            at: (x, 0.0).into(),
+            value,
+        };
+        let tree = KDTree::make(vec![
+            sample(-1.0, 10.0),
+            sample(2.0, 1.0),
+            sample(2.0, 4.0),
+            sample(3.0, 20.0),
+            sample(5.0, 30.0),
+        ]);
+        let on_copies = sample(2.0, 0.0);
+        for weighting in [Weighting::Uniform, Weighting::InverseDistance] {
+            assert_eq!(
+                tree.knn_regress(&on_copies, 5, weighting, |s| s.value),
+                Some(2.5)
+            );
+        }
+    }
+
+    #[test]
+    fn regress() {
+        let tree = line(&[1.0, 2.0, 4.0]);
+        let search = (0.0, 0.0).into();
+        let regress = |k, weighting| tree.knn_regress(&search, k, weighting, |p| p.x);
+        assert_eq!(regress(3, Weighting::Uniform), Some(7.0 / 3.0));
+        // weights 1, 1/2 and 1/4
+        let expected = (1.0 + 1.0 + 1.0) / (1.0 + 0.5 + 0.25);
+        assert!((regress(3, Weighting::InverseDistance).unwrap() - expected).abs() < 1e-12);
+        assert_eq!(regress(1, Weighting::InverseDistance), Some(1.0));
+        assert_eq!(regress(0, Weighting::Uniform), None);
+        assert_eq!(
+            line(&[]).knn_regress(&search, 2, Weighting::Uniform, |p| p.x),
+            None
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! a point type counting the calls of its methods, for tests on how much work goes into building
+//! and searching
+
+use std::cell::Cell;
+
+use super::{KDPoint, Point2D, F64};
+
+thread_local! {
+    static KEY_CALLS: Cell<usize> = const { Cell::new(0) };
+    static DISTANCE_CALLS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// a `Point2D` counting its `kdkey` and `distance` calls on the current thread
+#[derive(Debug, Clone)]
+pub(crate) struct Counted(pub Point2D);
+
+impl KDPoint for Counted {
+    type Key = F64;
+    type Distance = F64;
+
+    fn dimensions(&self) -> usize {
+        2
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        KEY_CALLS.with(|calls| calls.set(calls.get() + 1));
+        self.0.kdkey(dimension)
+    }
+
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        DISTANCE_CALLS.with(|calls| calls.set(calls.get() + 1));
+        Point2D::distance(&lhs.0, &rhs.0)
+    }
+
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        Point2D::key_distance(lhs, rhs)
+    }
+}
+
+/// the result of `f` and the `Counted::kdkey` calls it made
+pub(crate) fn key_calls<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    counting(&KEY_CALLS, f)
+}
+
+/// the result of `f` and the `Counted::distance` calls it made
+pub(crate) fn distance_calls<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    counting(&DISTANCE_CALLS, f)
+}
+
+fn counting<R>(
+    calls: &'static std::thread::LocalKey<Cell<usize>>,
+    f: impl FnOnce() -> R,
+) -> (R, usize) {
+    let before = calls.with(Cell::get);
+    let result = f();
+    (result, calls.with(Cell::get) - before)
+}
@@ -0,0 +1,78 @@
+//! export of the points along with where they sit in the tree, for spreadsheets and data frames
+
+use std::fmt::Write;
+
+use super::{Coordinates, KDTree};
+
+impl<T: Coordinates> KDTree<T> {
+    /// all points as CSV, one row per point: the coordinates `x0`, `x1`, ... followed by the
+    /// `depth` of the node holding the point (the root is at 0) and its `split_dimension`
+    ///
+    /// the number of coordinate columns is that of the root's point, counted points get a row per
+    /// copy
+    pub fn to_csv(&self) -> String {
+        let dimensions = self
+            .root
+            .as_ref()
+            .map(|root| root.data.dimensions())
+            .unwrap_or(0);
+        let mut csv: String = (0..dimensions)
+            .map(|dimension| format!("x{dimension},"))
+            .collect();
+        csv.push_str("depth,split_dimension\n");
+
+        let mut stack: Vec<_> = self.root.iter().map(|root| (root, 0)).collect();
+        while let Some((node, depth)) = stack.pop() {
+            for point in node.points() {
+                for dimension in 0..dimensions {
+                    write!(csv, "{},", point.coordinate(dimension)).unwrap();
+                }
+                writeln!(csv, "{depth},{}", node.dimension()).unwrap();
+            }
+            for child in [&node.right, &node.left].into_iter().flatten() {
+                stack.push((child, depth + 1));
+            }
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2D, Point3D, F64};
+
+    #[test]
+    fn rows_and_header() {
+        let data: Vec<Point2D> = (0..20)
+            .map(|i| ((i % 4) as f64, i as f64 / 2.0).into())
+            .collect();
+        let tree = KDTree::make(data);
+        let csv = tree.to_csv();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "x0,x1,depth,split_dimension");
+        assert_eq!(lines.len(), tree.len() + 1);
+
+        // the root comes first
+        let root = tree.root.as_ref().unwrap();
+        assert_eq!(lines[1], format!("{},{},0,0", root.data.x, root.data.y));
+        for line in &lines[1..] {
+            let fields: Vec<f64> = line
+                .split(',')
+                .map(|field| field.parse().unwrap())
+                .collect();
+            assert_eq!(fields.len(), 4);
+            assert_eq!(fields[3], fields[2] % 2.0);
+        }
+
+        let counted = KDTree::make_with_counts(vec![Point3D::<F64>::from([1.0, 2.0, 3.0]); 3]);
+        assert_eq!(
+            counted.to_csv(),
+            "x0,x1,x2,depth,split_dimension\n1,2,3,0,0\n1,2,3,0,0\n1,2,3,0,0\n"
+        );
+        assert_eq!(
+            KDTree::<Point2D>::make(Vec::new()).to_csv(),
+            "depth,split_dimension\n"
+        );
+    }
+}
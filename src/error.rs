@@ -0,0 +1,36 @@
+//! errors for input that is unusable, returned by the `try_` variants of panicking functions
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// the named input must not be empty
+    EmptyInput(&'static str),
+    /// a point has `got` dimensions where `expected` were required
+    DimensionMismatch { expected: usize, got: usize },
+    /// a weight outside of `0.0..=1.0`
+    InvalidWeight(f64),
+    /// a file could not be parsed, these are wrapped in an `io::Error` of kind `InvalidData`
+    CorruptData(String),
+    /// `needed` nodes do not fit into an index addressing at most `capacity`
+    CapacityExceeded { capacity: usize, needed: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyInput(what) => write!(f, "{what} must not be empty"),
+            Self::DimensionMismatch { expected, got } => {
+                write!(f, "expected {expected} dimensions, got {got}")
+            }
+            Self::InvalidWeight(weight) => write!(f, "weight {weight} not within 0 and 1"),
+            Self::CorruptData(message) => f.write_str(message),
+            Self::CapacityExceeded { capacity, needed } => {
+                write!(f, "{needed} nodes exceed the capacity of {capacity}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,387 @@
+//! C interface, see `include/kdtree.h`; the `kdtree-c` crate in `capi/` builds it as static and
+//! shared library
+//!
+//! Trees built through this interface store `dims` dimensional `double` points and identify them
+//! by their row in the buffer they were built from. Distances are squared euclidean distances.
+//! No function lets a panic cross the language boundary, they report `KDTREE_PANIC` instead.
+
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{DynPoint, KDPoint, KDTree};
+
+pub const KDTREE_OK: i32 = 0;
+pub const KDTREE_NULL_POINTER: i32 = 1;
+pub const KDTREE_EMPTY: i32 = 2;
+pub const KDTREE_BUFFER_TOO_SMALL: i32 = 3;
+pub const KDTREE_PANIC: i32 = 4;
+
+#[derive(Debug, Clone)]
+struct Row {
+    index: usize,
+    point: DynPoint,
+}
+
+impl KDPoint for Row {
+    type Key = <DynPoint as KDPoint>::Key;
+    type Distance = <DynPoint as KDPoint>::Distance;
+
+    fn dimensions(&self) -> usize {
+        self.point.dimensions()
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        self.point.kdkey(dimension)
+    }
+
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        DynPoint::distance(&lhs.point, &rhs.point)
+    }
+
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        DynPoint::key_distance(lhs, rhs)
+    }
+}
+
+/// opaque handle handed out to C as `kdtree_t*`
+pub struct Handle {
+    dims: usize,
+    tree: KDTree<Row>,
+}
+
+impl Handle {
+    /// # Safety
+    /// `query` must point to `self.dims` readable doubles
+    unsafe fn search(&self, query: *const f64) -> Row {
+        Row {
+            index: usize::MAX,
+            point: slice::from_raw_parts(query, self.dims).into(),
+        }
+    }
+}
+
+fn guarded(f: impl FnOnce() -> i32) -> i32 {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(KDTREE_PANIC)
+}
+
+/// builds a tree from `n` row-major points of `dims` coordinates each, the rows are copied
+///
+/// returns NULL if `coords` is NULL, `dims` is 0 or building fails
+///
+/// # Safety
+/// `coords` must point to `n * dims` readable doubles
+#[no_mangle]
+pub unsafe extern "C" fn kdtree_build_f64(
+    coords: *const f64,
+    n: usize,
+    dims: usize,
+) -> *mut Handle {
+    if coords.is_null() || dims == 0 {
+        return ptr::null_mut();
+    }
+
+    catch_unwind(AssertUnwindSafe(|| {
+        let len = n.checked_mul(dims)?;
+        let coords = slice::from_raw_parts(coords, len);
+        let rows = coords
+            .chunks_exact(dims)
+            .enumerate()
+            .map(|(index, row)| Row {
+                index,
+                point: row.into(),
+            })
+            .collect();
+
+        Some(Box::into_raw(Box::new(Handle {
+            dims,
+            tree: KDTree::make(rows),
+        })))
+    }))
+    .ok()
+    .flatten()
+    .unwrap_or(ptr::null_mut())
+}
+
+/// writes the row index of the point nearest to `query` and its squared distance
+///
+/// `out_distance` may be NULL, returns `KDTREE_EMPTY` if the tree holds no points
+///
+/// # Safety
+/// `tree` must come from `kdtree_build_f64`, `query` must point to `dims` readable doubles
+#[no_mangle]
+pub unsafe extern "C" fn kdtree_query_nearest(
+    tree: *const Handle,
+    query: *const f64,
+    out_index: *mut usize,
+    out_distance: *mut f64,
+) -> i32 {
+    if tree.is_null() || query.is_null() || out_index.is_null() {
+        return KDTREE_NULL_POINTER;
+    }
+
+    guarded(|| {
+        let tree = &*tree;
+        let search = tree.search(query);
+        match tree.tree.find_nearest(&search) {
+            Some(nearest) => {
+                *out_index = nearest.index;
+                if !out_distance.is_null() {
+                    *out_distance = Row::distance(&search, nearest).into();
+                }
+                KDTREE_OK
+            }
+            None => KDTREE_EMPTY,
+        }
+    })
+}
+
+/// writes the row indices and squared distances of the (up to) `k` points nearest to `query`,
+/// nearest first, and their number to `out_count`
+///
+/// `out_distances` may be NULL
+///
+/// # Safety
+/// `tree` must come from `kdtree_build_f64`, `query` must point to `dims` readable doubles,
+/// `out_indices` and `out_distances` (if not NULL) must have room for `k` elements
+#[no_mangle]
+pub unsafe extern "C" fn kdtree_query_knn(
+    tree: *const Handle,
+    query: *const f64,
+    k: usize,
+    out_indices: *mut usize,
+    out_distances: *mut f64,
+    out_count: *mut usize,
+) -> i32 {
+    if tree.is_null() || query.is_null() || out_count.is_null() || (k > 0 && out_indices.is_null())
+    {
+        return KDTREE_NULL_POINTER;
+    }
+
+    guarded(|| {
+        let tree = &*tree;
+        let search = tree.search(query);
+        let found = tree.tree.find_k_nearest_with_distances(&search, k);
+
+        for (i, (row, distance)) in found.iter().enumerate() {
+            *out_indices.add(i) = row.index;
+            if !out_distances.is_null() {
+                *out_distances.add(i) = (*distance).into();
+            }
+        }
+        *out_count = found.len();
+        KDTREE_OK
+    })
+}
+
+/// finds all points within squared distance `radius` (inclusive) of `query`
+///
+/// always writes the number of matches to `out_count`; the row indices are written to
+/// `out_indices` only if `capacity` suffices, otherwise `KDTREE_BUFFER_TOO_SMALL` is returned.
+/// Call with `capacity` 0 to learn the required size first. The order of indices is unspecified.
+///
+/// # Safety
+/// `tree` must come from `kdtree_build_f64`, `query` must point to `dims` readable doubles,
+/// `out_indices` must have room for `capacity` elements
+#[no_mangle]
+pub unsafe extern "C" fn kdtree_query_radius(
+    tree: *const Handle,
+    query: *const f64,
+    radius: f64,
+    out_indices: *mut usize,
+    capacity: usize,
+    out_count: *mut usize,
+) -> i32 {
+    if tree.is_null() || query.is_null() || out_count.is_null() {
+        return KDTREE_NULL_POINTER;
+    }
+
+    guarded(|| {
+        let tree = &*tree;
+        let search = tree.search(query);
+        let found = tree.tree.find_within_radius(&search, radius.into());
+
+        *out_count = found.len();
+        if found.len() > capacity {
+            return KDTREE_BUFFER_TOO_SMALL;
+        }
+        if !found.is_empty() && out_indices.is_null() {
+            return KDTREE_NULL_POINTER;
+        }
+
+        for (i, row) in found.iter().enumerate() {
+            *out_indices.add(i) = row.index;
+        }
+        KDTREE_OK
+    })
+}
+
+/// releases a tree, NULL is ignored
+///
+/// # Safety
+/// `tree` must come from `kdtree_build_f64` and must not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn kdtree_free(tree: *mut Handle) {
+    if !tree.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(tree))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = include_str!("../include/kdtree.h");
+
+    // 4 x 2 grid of unit spaced points, row i is at (i % 4, i / 4)
+    fn grid() -> Vec<f64> {
+        (0..8)
+            .flat_map(|i| [(i % 4) as f64, (i / 4) as f64])
+            .collect()
+    }
+
+    #[test]
+    fn header_declares_exports() {
+        for declaration in [
+            "kdtree_t *kdtree_build_f64(const double *coords, size_t n, size_t dims);",
+            "int32_t kdtree_query_nearest(const kdtree_t *tree,",
+            "int32_t kdtree_query_knn(const kdtree_t *tree,",
+            "int32_t kdtree_query_radius(const kdtree_t *tree,",
+            "void kdtree_free(kdtree_t *tree);",
+            "#define KDTREE_OK 0",
+            "#define KDTREE_NULL_POINTER 1",
+            "#define KDTREE_EMPTY 2",
+            "#define KDTREE_BUFFER_TOO_SMALL 3",
+            "#define KDTREE_PANIC 4",
+        ] {
+            assert!(HEADER.contains(declaration), "missing `{declaration}`");
+        }
+    }
+
+    #[test]
+    fn nearest() {
+        let coords = grid();
+        unsafe {
+            let tree = kdtree_build_f64(coords.as_ptr(), 8, 2);
+            assert!(!tree.is_null());
+
+            let mut index = 0;
+            let mut distance = 0.0;
+            let query = [2.1, 0.8];
+            let status = kdtree_query_nearest(tree, query.as_ptr(), &mut index, &mut distance);
+            assert_eq!(status, KDTREE_OK);
+            assert_eq!(index, 6);
+            assert!((distance - 0.05).abs() < 1e-9);
+
+            let status = kdtree_query_nearest(tree, query.as_ptr(), &mut index, ptr::null_mut());
+            assert_eq!(status, KDTREE_OK);
+
+            kdtree_free(tree);
+        }
+    }
+
+    #[test]
+    fn knn() {
+        let coords = grid();
+        unsafe {
+            let tree = kdtree_build_f64(coords.as_ptr(), 8, 2);
+
+            let mut indices = [usize::MAX; 3];
+            let mut distances = [f64::NAN; 3];
+            let mut count = 0;
+            let query = [0.1, 0.0];
+            let status = kdtree_query_knn(
+                tree,
+                query.as_ptr(),
+                3,
+                indices.as_mut_ptr(),
+                distances.as_mut_ptr(),
+                &mut count,
+            );
+            assert_eq!(status, KDTREE_OK);
+            assert_eq!(count, 3);
+            assert_eq!(indices[..2], [0, 1]);
+            assert_eq!(indices[2], 4);
+            assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+
+            // asking for more than there is
+            let mut indices = [usize::MAX; 10];
+            let status = kdtree_query_knn(
+                tree,
+                query.as_ptr(),
+                10,
+                indices.as_mut_ptr(),
+                ptr::null_mut(),
+                &mut count,
+            );
+            assert_eq!(status, KDTREE_OK);
+            assert_eq!(count, 8);
+            let mut found = indices[..8].to_vec();
+            found.sort();
+            assert_eq!(found, (0..8).collect::<Vec<_>>());
+
+            kdtree_free(tree);
+        }
+    }
+
+    #[test]
+    fn radius_count_then_fill() {
+        let coords = grid();
+        unsafe {
+            let tree = kdtree_build_f64(coords.as_ptr(), 8, 2);
+            let query = [1.0, 0.0];
+
+            let mut count = 0;
+            let status =
+                kdtree_query_radius(tree, query.as_ptr(), 1.0, ptr::null_mut(), 0, &mut count);
+            assert_eq!(status, KDTREE_BUFFER_TOO_SMALL);
+            assert_eq!(count, 4);
+
+            let mut indices = vec![usize::MAX; count];
+            let status = kdtree_query_radius(
+                tree,
+                query.as_ptr(),
+                1.0,
+                indices.as_mut_ptr(),
+                indices.len(),
+                &mut count,
+            );
+            assert_eq!(status, KDTREE_OK);
+            indices.sort();
+            assert_eq!(indices, [0, 1, 2, 5]);
+
+            // nothing found fits into no buffer
+            let query = [0.5, 0.5];
+            let status =
+                kdtree_query_radius(tree, query.as_ptr(), 0.1, ptr::null_mut(), 0, &mut count);
+            assert_eq!(status, KDTREE_OK);
+            assert_eq!(count, 0);
+
+            kdtree_free(tree);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let coords = grid();
+        unsafe {
+            assert!(kdtree_build_f64(ptr::null(), 8, 2).is_null());
+            assert!(kdtree_build_f64(coords.as_ptr(), 8, 0).is_null());
+
+            let mut index = 0;
+            let query = [0.0, 0.0];
+            let status =
+                kdtree_query_nearest(ptr::null(), query.as_ptr(), &mut index, ptr::null_mut());
+            assert_eq!(status, KDTREE_NULL_POINTER);
+
+            let empty = kdtree_build_f64(coords.as_ptr(), 0, 2);
+            assert!(!empty.is_null());
+            let status = kdtree_query_nearest(empty, query.as_ptr(), &mut index, ptr::null_mut());
+            assert_eq!(status, KDTREE_EMPTY);
+            kdtree_free(empty);
+
+            kdtree_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! points read in place from one flat buffer of coordinates, row after row
+
+use super::{KDPoint, KDTree, SquaredEuclidean, F64};
+
+/// `data.len() / dims` points of `dims` coordinates each, stored one after the other
+#[derive(Debug, Clone, Copy)]
+pub struct FlatPoints<'a> {
+    pub data: &'a [f64],
+    pub dims: usize,
+}
+
+/// one point of a `FlatPoints`, the coordinates stay in the buffer
+///
+/// distances are squared euclidean as for `DynPoint`
+#[derive(Debug, Clone, Copy)]
+pub struct FlatRow<'a> {
+    /// position among the points, `usize::MAX` for a `query`
+    pub row: usize,
+    pub coords: &'a [f64],
+}
+
+impl<'a> FlatPoints<'a> {
+    /// panics if `dims` is 0 or does not divide the length of `data`
+    pub fn new(data: &'a [f64], dims: usize) -> Self {
+        assert!(
+            dims > 0 && data.len().is_multiple_of(dims),
+            "{} coordinates do not make points of {dims} dimensions",
+            data.len()
+        );
+        Self { data, dims }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() / self.dims
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn row(&self, row: usize) -> FlatRow<'a> {
+        FlatRow {
+            row,
+            coords: &self.data[row * self.dims..(row + 1) * self.dims],
+        }
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = FlatRow<'a>> + '_ {
+        (0..self.len()).map(|row| self.row(row))
+    }
+
+    /// a tree of all points, which only refer to the buffer
+    pub fn tree(&self) -> KDTree<FlatRow<'a>> {
+        KDTree::make(self.rows().collect())
+    }
+}
+
+impl<'a> FlatRow<'a> {
+    /// a point that is not part of the buffer, to search with
+    ///
+    /// a tree of `FlatRow<'a>` only takes queries borrowing for `'a` as well, so their coordinates
+    /// have to live as long as the buffer's
+    pub fn query(coords: &'a [f64]) -> Self {
+        Self {
+            row: usize::MAX,
+            coords,
+        }
+    }
+}
+
+impl KDPoint for FlatRow<'_> {
+    type Key = F64;
+    type Distance = F64;
+
+    fn dimensions(&self) -> usize {
+        self.coords.len()
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        self.coords[dimension].into()
+    }
+
+    /// over the dimensions of `rhs`, a search point may have more
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        debug_assert!(lhs.coords.len() >= rhs.coords.len());
+        lhs.coords
+            .iter()
+            .zip(rhs.coords)
+            .map(|(l, r)| (l - r) * (l - r))
+            .sum::<f64>()
+            .into()
+    }
+
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        let dist = *lhs - *rhs;
+        dist * dist
+    }
+}
+
+impl SquaredEuclidean for FlatRow<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point3D;
+    use rand::random;
+
+    #[test]
+    fn same_answers_as_structs() {
+        let data: Vec<f64> = (0..3000).map(|_| random()).collect();
+        let queries: Vec<f64> = (0..300).map(|_| random()).collect();
+        let flat = FlatPoints::new(&data, 3);
+        assert_eq!(flat.len(), 1000);
+        let tree = flat.tree();
+        let structs = KDTree::make(
+            data.chunks(3)
+                .map(|c| Point3D::from([c[0], c[1], c[2]]))
+                .collect(),
+        );
+
+        for search in queries.chunks(3) {
+            let query = FlatRow::query(search);
+            let found = tree.find_nearest(&query).unwrap();
+            let search = Point3D::from([search[0], search[1], search[2]]);
+            let expected = structs.find_nearest(&search).unwrap();
+            assert_eq!(found.coords, flat.row(found.row).coords);
+            assert_eq!(
+                FlatRow::distance(&query, found),
+                Point3D::distance(&search, expected)
+            );
+
+            let rows: Vec<usize> = tree
+                .find_k_nearest(&query, 5)
+                .into_iter()
+                .map(|row| row.row)
+                .collect();
+            assert_eq!(rows.len(), 5);
+            assert!(rows.iter().all(|&row| row < 1000));
+        }
+
+        let empty = FlatPoints::new(&[], 3);
+        assert!(empty.is_empty());
+        assert!(empty
+            .tree()
+            .find_nearest(&FlatRow::query(&[0.0; 3]))
+            .is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "4 coordinates do not make points of 3 dimensions")]
+    fn ragged_buffer() {
+        FlatPoints::new(&[0.0; 4], 3);
+    }
+}
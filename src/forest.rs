@@ -0,0 +1,268 @@
+//! approximate nearest neighbours in many dimensions, with several randomized trees over the same
+//! points searched together
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::KDPoint;
+
+/// node of a tree over the points of a forest, referring to its point by index
+#[derive(Debug)]
+struct IndexNode {
+    index: usize,
+    /// split dimension
+    dimension: usize,
+    left: Option<Box<Self>>,
+    right: Option<Box<Self>>,
+}
+
+impl IndexNode {
+    /// splits at the median of a random dimension, `indices` is reordered
+    fn make<T: KDPoint>(indices: &mut [usize], data: &[T], rng: &mut StdRng) -> Option<Self> {
+        if indices.is_empty() {
+            return None;
+        }
+
+        let dimension = rng.gen_range(0..data[indices[0]].dimensions());
+        let median = indices.len() / 2;
+        indices.select_nth_unstable_by(median, |&l, &r| {
+            data[l].kdkey(dimension).cmp(&data[r].kdkey(dimension))
+        });
+        let (left, rest) = indices.split_at_mut(median);
+        let (index, right) = rest.split_first_mut().unwrap();
+
+        Some(Self {
+            index: *index,
+            dimension,
+            left: Self::make(left, data, rng).map(Box::new),
+            right: Self::make(right, data, rng).map(Box::new),
+        })
+    }
+}
+
+/// subtree of any of the trees whose points are all at least `bound` away from the search, `None`
+/// standing for no bound at all
+struct Branch<'f, D> {
+    bound: Option<D>,
+    node: &'f IndexNode,
+}
+
+impl<'f, D: Ord> PartialEq for Branch<'f, D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bound == other.bound
+    }
+}
+
+impl<'f, D: Ord> Eq for Branch<'f, D> {}
+
+impl<'f, D: Ord> PartialOrd for Branch<'f, D> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'f, D: Ord> Ord for Branch<'f, D> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bound.cmp(&other.bound)
+    }
+}
+
+/// several trees over the same points, each splitting on randomly chosen dimensions
+///
+/// a single tree prunes poorly once there are more than a handful of dimensions; searching a few
+/// differently split trees together finds good candidates much sooner. The points are stored once,
+/// the trees only refer to them.
+#[derive(Debug)]
+pub struct KDForest<T> {
+    data: Vec<T>,
+    trees: Vec<IndexNode>,
+}
+
+impl<T: KDPoint> KDForest<T> {
+    /// builds `n_trees` trees, the same `seed` gives the same trees
+    pub fn make(data: Vec<T>, n_trees: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut indices: Vec<usize> = (0..data.len()).collect();
+        let trees = (0..n_trees)
+            .filter_map(|_| IndexNode::make(&mut indices, &data, &mut rng))
+            .collect();
+        Self { data, trees }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// up to `k` points near `search` with their distances, nearest first
+    ///
+    /// the subtrees of all trees are searched best first from a single queue, each point is
+    /// looked at no more than once, and the search stops after `max_candidates` points or once
+    /// nothing left can be closer than the `k` found; with `max_candidates` at least `len()` the
+    /// result is exact
+    pub fn ann_search(
+        &self,
+        search: &T,
+        k: usize,
+        max_candidates: usize,
+    ) -> Vec<(&T, T::Distance)> {
+        let mut seen = vec![false; self.data.len()];
+        let mut candidates = 0;
+        // the best `k` so far, the worst on top
+        let mut found: BinaryHeap<(T::Distance, usize)> =
+            BinaryHeap::with_capacity(k.min(self.len()));
+        let mut branches: BinaryHeap<_> = self
+            .trees
+            .iter()
+            .map(|root| {
+                Reverse(Branch {
+                    bound: None,
+                    node: root,
+                })
+            })
+            .collect();
+        if k == 0 {
+            branches.clear();
+        }
+
+        'search: while let Some(Reverse(Branch { bound, node })) = branches.pop() {
+            if found.len() == k && found.peek().map(|(worst, _)| worst) < bound.as_ref() {
+                break;
+            }
+
+            // down to a leaf, leaving the far sides for later
+            let mut node = Some(node);
+            while let Some(current) = node {
+                if candidates == max_candidates {
+                    break 'search;
+                }
+                let point = &self.data[current.index];
+                if !std::mem::replace(&mut seen[current.index], true) {
+                    candidates += 1;
+                    found.push((T::distance(search, point), current.index));
+                    if found.len() > k {
+                        found.pop();
+                    }
+                }
+
+                let dimension = current.dimension;
+                let go_left = search.kdkey(dimension) < point.kdkey(dimension);
+                let (near, far) = if go_left {
+                    (&current.left, &current.right)
+                } else {
+                    (&current.right, &current.left)
+                };
+                if let Some(far) = far {
+                    let to_split =
+                        T::key_distance(&search.kdkey(dimension), &point.kdkey(dimension));
+                    branches.push(Reverse(Branch {
+                        bound: Ord::max(bound.clone(), Some(to_split)),
+                        node: far,
+                    }));
+                }
+                node = near.as_deref();
+            }
+        }
+
+        found
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(distance, index)| (&self.data[index], distance))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DynPoint;
+    use rand::random;
+
+    fn random_points(n: usize, dimensions: usize) -> Vec<DynPoint> {
+        (0..n)
+            .map(|_| DynPoint::from((0..dimensions).map(|_| random()).collect::<Vec<f64>>()))
+            .collect()
+    }
+
+    /// distances of the `k` nearest points, by brute force
+    fn exact(data: &[DynPoint], search: &DynPoint, k: usize) -> Vec<crate::F64> {
+        let mut distances: Vec<_> = data
+            .iter()
+            .map(|point| DynPoint::distance(search, point))
+            .collect();
+        distances.sort();
+        distances.truncate(k);
+        distances
+    }
+
+    #[test]
+    fn recall_in_32_dimensions() {
+        let data = random_points(2000, 32);
+        let forest = KDForest::make(data.clone(), 8, 7);
+        let k = 10;
+        let queries = 50;
+
+        let mut hits = 0;
+        for search in random_points(queries, 32) {
+            let found = forest.ann_search(&search, k, 800);
+            assert_eq!(found.len(), k);
+            assert!(found.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+            let expected = exact(&data, &search, k);
+            hits += found
+                .iter()
+                .filter(|(_, distance)| *distance <= expected[k - 1])
+                .count();
+        }
+        let recall = hits as f64 / (k * queries) as f64;
+        assert!(recall > 0.8, "recall {recall}");
+    }
+
+    #[test]
+    fn exact_with_full_budget() {
+        let data = random_points(300, 5);
+        let forest = KDForest::make(data.clone(), 3, 1);
+        assert_eq!(forest.len(), 300);
+        for search in random_points(20, 5) {
+            let found = forest.ann_search(&search, 7, data.len());
+            let distances: Vec<_> = found.iter().map(|(_, distance)| *distance).collect();
+            assert_eq!(distances, exact(&data, &search, 7));
+
+            // every point is looked at once, no matter how many trees hold it
+            let all = forest.ann_search(&search, data.len() + 5, usize::MAX);
+            assert_eq!(all.len(), data.len());
+            let all = forest.ann_search(&search, usize::MAX, usize::MAX);
+            assert_eq!(all.len(), data.len());
+        }
+    }
+
+    #[test]
+    fn seeded_and_degenerate() {
+        let data = random_points(200, 8);
+        let search = random_points(1, 8).pop().unwrap();
+        let a = KDForest::make(data.clone(), 4, 42);
+        let b = KDForest::make(data, 4, 42);
+        let found = |forest: &KDForest<DynPoint>| -> Vec<_> {
+            forest
+                .ann_search(&search, 5, 30)
+                .into_iter()
+                .map(|(point, _)| point.coords.clone())
+                .collect()
+        };
+        assert_eq!(found(&a), found(&b));
+
+        assert!(a.ann_search(&search, 0, 100).is_empty());
+        assert!(a.ann_search(&search, 5, 0).is_empty());
+        let empty = KDForest::<DynPoint>::make(Vec::new(), 4, 0);
+        assert!(empty.is_empty());
+        assert!(empty.ann_search(&search, 5, 100).is_empty());
+        let no_trees = KDForest::make(random_points(10, 8), 0, 0);
+        assert!(no_trees.ann_search(&search, 5, 100).is_empty());
+    }
+}
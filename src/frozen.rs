@@ -0,0 +1,534 @@
+//! read-only trees laid out for queries
+
+use std::{
+    collections::BinaryHeap,
+    fmt::Debug,
+    num::{NonZeroU32, NonZeroU64},
+};
+
+use super::{compare_element, Candidate, Error, KDPoint, KDTree, Node};
+
+/// index of a node of a `FrozenKDTree`, what a tree uses limits how many nodes it may have
+///
+/// `Option<Self>` should be no larger than `Self`, as it is for `U32` and `U64`. Only frozen trees
+/// are indexed, a `KDTree` links its nodes by `Box`; `KDTree::try_freeze` picks the index.
+pub trait NodeIndex: Copy + Debug {
+    /// number of nodes it can tell apart, indices are below it
+    const CAPACITY: usize;
+
+    /// `None` if `index` is not below `CAPACITY`
+    fn from_usize(index: usize) -> Option<Self>;
+
+    fn to_usize(self) -> usize;
+}
+
+/// index of up to `u32::MAX` nodes, in 4 bytes with or without `Option`
+///
+/// stores the complement of the index, which is never 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct U32(NonZeroU32);
+
+/// index of up to `u64::MAX` nodes, in 8 bytes with or without `Option`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct U64(NonZeroU64);
+
+impl NodeIndex for U32 {
+    const CAPACITY: usize = u32::MAX as usize;
+
+    fn from_usize(index: usize) -> Option<Self> {
+        let index = u32::try_from(index).ok()?;
+        NonZeroU32::new(!index).map(Self)
+    }
+
+    fn to_usize(self) -> usize {
+        !self.0.get() as usize
+    }
+}
+
+impl NodeIndex for U64 {
+    const CAPACITY: usize = usize::MAX;
+
+    fn from_usize(index: usize) -> Option<Self> {
+        let index = u64::try_from(index).ok()?;
+        NonZeroU64::new(!index).map(Self)
+    }
+
+    fn to_usize(self) -> usize {
+        !self.0.get() as usize
+    }
+}
+
+/// a balanced tree that can no longer change, made by `KDTree::freeze`
+///
+/// nodes refer to their children by `I`, see `KDTree::try_freeze` for trees too large for `U32`
+///
+/// nodes live in a single vector in pre-order, the points of every subtree next to each other in
+/// another. Every node knows the smallest and largest key of its subtree in each dimension, so
+/// queries skip subtrees that cannot hold a result even where the split planes alone would not.
+/// Queries answer exactly as those of the tree it was made from after `rebalance`, and visit no
+/// nodes that those would not.
+#[derive(Debug)]
+pub struct FrozenKDTree<T: KDPoint, I: NodeIndex = U32> {
+    nodes: Vec<FrozenNode<I>>,
+    points: Vec<T>,
+    /// smallest and largest key of every node's subtree, `dimensions` entries per node
+    bounds: Vec<(T::Key, T::Key)>,
+    dimensions: usize,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct FrozenNode<I> {
+    dimension: usize,
+    /// copies of the node's first point, as `Node::count`
+    count: usize,
+    /// the node's own points, the first one holds the split
+    start: usize,
+    end: usize,
+    /// end of the points of the subtree, which start at `start`
+    subtree_end: usize,
+    left: Option<I>,
+    right: Option<I>,
+}
+
+impl<T: KDPoint> KDTree<T> {
+    /// rebalances the tree and turns it into a `FrozenKDTree`
+    ///
+    /// panics if the tree has `U32::CAPACITY` nodes or more, see `try_freeze`
+    pub fn freeze(self) -> FrozenKDTree<T> {
+        match self.try_freeze() {
+            Ok(frozen) => frozen,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// `freeze` with nodes indexed by `I`, failing if the tree has more nodes than `I` can
+    /// address, e.g. `try_freeze::<U64>()` for trees beyond the capacity of `U32`
+    pub fn try_freeze<I: NodeIndex>(mut self) -> Result<FrozenKDTree<T, I>, Error> {
+        self.rebalance();
+        let needed = self.root.as_ref().map(Node::nodes).unwrap_or(0);
+        if needed > I::CAPACITY {
+            return Err(Error::CapacityExceeded {
+                capacity: I::CAPACITY,
+                needed,
+            });
+        }
+        let mut frozen = FrozenKDTree {
+            nodes: Vec::new(),
+            points: Vec::with_capacity(self.len),
+            bounds: Vec::new(),
+            dimensions: self.tree_dimensions(),
+            len: self.len,
+        };
+        if let Some(root) = self.root {
+            frozen.push(root);
+        }
+        for node in &frozen.nodes {
+            let points = &frozen.points[node.start..node.subtree_end];
+            for dimension in 0..frozen.dimensions {
+                let keys = || points.iter().map(|point| point.kdkey(dimension));
+                // every subtree holds at least its node's point
+                frozen
+                    .bounds
+                    .push((keys().min().unwrap(), keys().max().unwrap()));
+            }
+        }
+        Ok(frozen)
+    }
+}
+
+impl<T> Node<T> {
+    /// number of nodes in this subtree
+    fn nodes(&self) -> usize {
+        let below: usize = [&self.left, &self.right]
+            .into_iter()
+            .flatten()
+            .map(|child| child.nodes())
+            .sum();
+        1 + below
+    }
+}
+
+impl<T: KDPoint, I: NodeIndex> FrozenKDTree<T, I> {
+    /// appends `node` and its subtree in pre-order, returns its index, which `try_freeze` made
+    /// sure `I` can hold
+    fn push(&mut self, mut node: Node<T>) -> I {
+        let index = self.nodes.len();
+        let start = self.points.len();
+        let dimension = node.dimension();
+        let (left, right) = (node.left.take(), node.right.take());
+        let (data, count, bucket) = node.into_own();
+        self.points.push(data);
+        self.points.extend(bucket);
+        self.nodes.push(FrozenNode {
+            dimension,
+            count,
+            start,
+            end: self.points.len(),
+            subtree_end: 0,
+            left: None,
+            right: None,
+        });
+        let left = left.map(|child| self.push(*child));
+        let right = right.map(|child| self.push(*child));
+        let frozen = &mut self.nodes[index];
+        frozen.left = left;
+        frozen.right = right;
+        frozen.subtree_end = self.points.len();
+        I::from_usize(index).unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// all points, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.nodes.len()).flat_map(|index| self.node_points(index))
+    }
+
+    /// as `KDTree::find_nearest`
+    pub fn find_nearest(&self, search: &T) -> Option<&T> {
+        self.nearest(search).0
+    }
+
+    /// as `KDTree::find_k_nearest`
+    pub fn find_k_nearest(&self, search: &T, k: usize) -> Vec<&T> {
+        let mut heap = BinaryHeap::with_capacity(k.min(self.len));
+        if !self.nodes.is_empty() && k > 0 {
+            self.k_nearest(0, search, k, &mut heap, &mut 0);
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|candidate| candidate.data)
+            .collect()
+    }
+
+    /// as `KDTree::find_within_radius`
+    pub fn find_within_radius(&self, search: &T, radius: T::Distance) -> Vec<&T> {
+        let mut found = Vec::new();
+        if !self.nodes.is_empty() {
+            self.within_radius(0, search, &radius, &mut found);
+        }
+        found
+    }
+
+    /// the nearest point and how many nodes the search visited
+    fn nearest(&self, search: &T) -> (Option<&T>, usize) {
+        let mut nearest = NearestSearch {
+            best: None,
+            visited: 0,
+        };
+        if !self.nodes.is_empty() {
+            self.nearest_below(0, search, &mut nearest);
+        }
+        (nearest.best.map(|(point, _)| point), nearest.visited)
+    }
+
+    fn node_points(&self, index: usize) -> impl Iterator<Item = &T> {
+        let node = &self.nodes[index];
+        std::iter::repeat_n(&self.points[node.start], node.count)
+            .chain(&self.points[node.start + 1..node.end])
+    }
+
+    /// the children of `index`, the one on the side of `search` first, and the distance from
+    /// `search` to the split
+    fn children(&self, index: usize, search: &T) -> ([Option<usize>; 2], T::Distance) {
+        let node = &self.nodes[index];
+        let split = &self.points[node.start];
+        let [left, right] = [node.left, node.right].map(|child| child.map(I::to_usize));
+        let children = if compare_element(search, split, node.dimension).is_lt() {
+            [left, right]
+        } else {
+            [right, left]
+        };
+        let target_to_split =
+            T::key_distance(&search.kdkey(node.dimension), &split.kdkey(node.dimension));
+        (children, target_to_split)
+    }
+
+    /// lower bound for the distance from `search` to any point below `index`, `None` if
+    /// `search` lies within the subtree's bounds
+    fn lower_bound(&self, index: usize, search: &T) -> Option<T::Distance> {
+        let bounds = &self.bounds[index * self.dimensions..(index + 1) * self.dimensions];
+        bounds
+            .iter()
+            .enumerate()
+            .filter_map(|(dimension, (min, max))| {
+                let key = search.kdkey(dimension);
+                if key < *min {
+                    Some(T::key_distance(&key, min))
+                } else if key > *max {
+                    Some(T::key_distance(&key, max))
+                } else {
+                    None
+                }
+            })
+            .max()
+    }
+
+    /// whether the subtree below `index` may hold a point closer to `search` than `distance`
+    fn may_beat(&self, index: usize, search: &T, distance: &T::Distance) -> bool {
+        self.lower_bound(index, search)
+            .is_none_or(|bound| bound < *distance)
+    }
+
+    fn nearest_below<'t>(&'t self, index: usize, search: &T, nearest: &mut NearestSearch<'t, T>) {
+        nearest.visited += 1;
+        let ([first, second], target_to_split) = self.children(index, search);
+
+        let enter = |child: usize, nearest: &NearestSearch<T>| {
+            nearest
+                .best
+                .as_ref()
+                .is_none_or(|(_, best)| self.may_beat(child, search, best))
+        };
+        if let Some(first) = first.filter(|&first| enter(first, nearest)) {
+            self.nearest_below(first, search, nearest);
+        }
+
+        for point in self.node_points(index) {
+            let distance = T::distance(search, point);
+            if nearest
+                .best
+                .as_ref()
+                .is_none_or(|(_, best)| distance < *best)
+            {
+                nearest.best = Some((point, distance));
+            }
+        }
+
+        // as in `Nearest`, then by the bounds
+        if let Some(second) = second {
+            let beyond = nearest
+                .best
+                .as_ref()
+                .is_some_and(|(_, best)| *best <= target_to_split);
+            if !beyond && enter(second, nearest) {
+                self.nearest_below(second, search, nearest);
+            }
+        }
+    }
+
+    fn k_nearest<'t>(
+        &'t self,
+        index: usize,
+        search: &T,
+        k: usize,
+        heap: &mut BinaryHeap<Candidate<'t, T>>,
+        found: &mut usize,
+    ) {
+        let ([first, second], target_to_split) = self.children(index, search);
+        let enter = |child: usize, heap: &BinaryHeap<Candidate<T>>| {
+            heap.len() < k
+                || heap
+                    .peek()
+                    .is_none_or(|worst| self.may_beat(child, search, &worst.distance))
+        };
+
+        if let Some(first) = first.filter(|&first| enter(first, heap)) {
+            self.k_nearest(first, search, k, heap, found);
+        }
+
+        for point in self.node_points(index) {
+            let candidate = Candidate {
+                distance: T::distance(search, point),
+                found: *found,
+                data: point,
+            };
+            *found += 1;
+            if heap.len() < k {
+                heap.push(candidate);
+            } else if heap.peek().is_some_and(|worst| candidate < *worst) {
+                heap.pop();
+                heap.push(candidate);
+            }
+        }
+
+        // as in `KNearest`, then by the bounds
+        if let Some(second) = second {
+            let wraps = heap.len() < k
+                || heap
+                    .peek()
+                    .is_none_or(|worst| worst.distance > target_to_split);
+            if wraps && enter(second, heap) {
+                self.k_nearest(second, search, k, heap, found);
+            }
+        }
+    }
+
+    fn within_radius<'t>(
+        &'t self,
+        index: usize,
+        search: &T,
+        radius: &T::Distance,
+        found: &mut Vec<&'t T>,
+    ) {
+        let ([first, second], target_to_split) = self.children(index, search);
+        let enter = |child: usize| {
+            self.lower_bound(child, search)
+                .is_none_or(|bound| bound <= *radius)
+        };
+
+        if let Some(first) = first.filter(|&first| enter(first)) {
+            self.within_radius(first, search, radius, found);
+        }
+        found.extend(
+            self.node_points(index)
+                .filter(|point| T::distance(search, point) <= *radius),
+        );
+        if let Some(second) = second.filter(|&second| target_to_split <= *radius && enter(second)) {
+            self.within_radius(second, search, radius, found);
+        }
+    }
+}
+
+/// the best point of a nearest neighbour search so far, with its distance
+struct NearestSearch<'t, T: KDPoint> {
+    best: Option<(&'t T, T::Distance)>,
+    visited: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Nearest, NearestHook, Point2D, Visitor, F64};
+    use rand::random;
+
+    fn random_points(n: usize) -> Vec<Point2D> {
+        (0..n).map(|_| (random(), random()).into()).collect()
+    }
+
+    fn coordinates<'t>(points: impl IntoIterator<Item = &'t Point2D>) -> Vec<(f64, f64)> {
+        points.into_iter().map(|point| (point.x, point.y)).collect()
+    }
+
+    /// counts the nodes a `Nearest` search enters
+    struct Entered(usize);
+
+    impl<'t> NearestHook<'t, Point2D> for Entered {
+        fn entered(&mut self, _: &'t Node<Point2D>, _: &F64, _: Option<&F64>) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn answers_as_source() {
+        let data = random_points(2000);
+        let source = KDTree::make(data.clone());
+        let frozen = KDTree::make(data).freeze();
+        assert_eq!(frozen.len(), source.len());
+        let mut all = coordinates(frozen.iter());
+        let mut expected = coordinates(source.iter());
+        all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(all, expected);
+
+        let (mut visited, mut frozen_visited) = (0, 0);
+        for _ in 0..200 {
+            // some searches outside the points, where the bounds help the most
+            let search = Point2D::from((3.0 * random::<f64>() - 1.0, random::<f64>()));
+            assert_eq!(
+                coordinates(frozen.find_nearest(&search)),
+                coordinates(source.find_nearest(&search))
+            );
+            assert_eq!(
+                coordinates(frozen.find_k_nearest(&search, 7)),
+                coordinates(source.find_k_nearest(&search, 7))
+            );
+            let radius = F64::from(0.01);
+            assert_eq!(
+                coordinates(frozen.find_within_radius(&search, radius)),
+                coordinates(source.find_within_radius(&search, radius))
+            );
+
+            let mut nearest = Nearest::with_hook(&search, |_| true, Entered(0));
+            nearest.visit(source.root.as_ref().unwrap());
+            let (_, frozen_nodes) = frozen.nearest(&search);
+            assert!(frozen_nodes <= nearest.hook.0);
+            visited += nearest.hook.0;
+            frozen_visited += frozen_nodes;
+        }
+        assert!(frozen_visited < visited);
+    }
+
+    /// an index of at most 3 nodes, to run out of
+    #[derive(Debug, Clone, Copy)]
+    struct Tiny(u8);
+
+    impl NodeIndex for Tiny {
+        const CAPACITY: usize = 3;
+
+        fn from_usize(index: usize) -> Option<Self> {
+            (index < Self::CAPACITY).then_some(Self(index as u8))
+        }
+
+        fn to_usize(self) -> usize {
+            self.0.into()
+        }
+    }
+
+    #[test]
+    fn node_indices() {
+        use std::mem::size_of;
+
+        assert_eq!(size_of::<Option<U32>>(), 4);
+        assert_eq!(size_of::<Option<U64>>(), 8);
+        // two child links per node: `U64` takes as much room as those of a `KDTree`, `U32` saves 8
+        // bytes
+        let boxed = size_of::<Option<Box<Node<Point2D>>>>();
+        assert_eq!(size_of::<Option<U64>>(), boxed);
+        assert_eq!(2 * (boxed - size_of::<Option<U32>>()), 8);
+
+        let last = u32::MAX as usize - 1;
+        assert_eq!(U32::from_usize(last).map(U32::to_usize), Some(last));
+        assert_eq!(U32::from_usize(0).map(U32::to_usize), Some(0));
+        assert!(U32::from_usize(U32::CAPACITY).is_none());
+        assert!(U32::from_usize(1 << 33).is_none());
+        assert_eq!(U64::from_usize(1 << 33).map(U64::to_usize), Some(1 << 33));
+
+        let data = random_points(100);
+        let narrow = KDTree::make(data.clone()).freeze();
+        let wide = KDTree::make(data.clone()).try_freeze::<U64>().unwrap();
+        for _ in 0..20 {
+            let search = Point2D::from((random(), random()));
+            assert_eq!(
+                coordinates(narrow.find_k_nearest(&search, 5)),
+                coordinates(wide.find_k_nearest(&search, 5))
+            );
+        }
+
+        let tiny = KDTree::make(data[..3].to_vec())
+            .try_freeze::<Tiny>()
+            .unwrap();
+        assert_eq!(tiny.iter().count(), 3);
+        assert_eq!(
+            KDTree::make(data[..4].to_vec()).try_freeze::<Tiny>().err(),
+            Some(Error::CapacityExceeded {
+                capacity: 3,
+                needed: 4
+            })
+        );
+    }
+
+    #[test]
+    fn counted_and_empty() {
+        let data: Vec<Point2D> = (0..30).map(|i| ((i % 3) as f64, 0.0).into()).collect();
+        let frozen = KDTree::make_with_counts(data).freeze();
+        assert_eq!(frozen.len(), 30);
+        assert_eq!(frozen.iter().count(), 30);
+        let search = Point2D::from((0.1, 0.0));
+        assert_eq!(frozen.find_k_nearest(&search, 12).len(), 12);
+        assert_eq!(frozen.find_within_radius(&search, F64::from(0.5)).len(), 10);
+
+        let empty = KDTree::<Point2D>::make(Vec::new()).freeze();
+        assert!(empty.is_empty());
+        assert!(empty.find_nearest(&search).is_none());
+        assert!(empty.find_k_nearest(&search, 3).is_empty());
+        assert!(empty.find_within_radius(&search, F64::from(1.0)).is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+//! snapping points to a regular grid before indexing them
+
+use std::collections::HashMap;
+
+use super::{Coordinates, KDTree, F64};
+
+/// snaps every coordinate to the nearest multiple of `cell`, halfway cases away from zero
+///
+/// panics unless `cell` is positive and finite
+pub fn quantize<P: Coordinates>(points: &mut [P], cell: f64) {
+    assert!(
+        cell > 0.0 && cell.is_finite(),
+        "cell size {cell} is not positive and finite"
+    );
+    for point in points {
+        for dimension in 0..point.dimensions() {
+            let snapped = (point.coordinate(dimension) / cell).round() * cell;
+            // adding 0 turns -0 into 0
+            point.set_coordinate(dimension, snapped + 0.0);
+        }
+    }
+}
+
+fn coordinates<P: Coordinates>(point: &P) -> Vec<F64> {
+    (0..point.dimensions())
+        .map(|dimension| point.coordinate(dimension).into())
+        .collect()
+}
+
+impl<T: Coordinates> KDTree<T> {
+    /// `quantize`s `data`, keeps only one of the points ending up at the same place and builds a
+    /// tree from them; also returns how many points were dropped
+    pub fn make_quantized(mut data: Vec<T>, cell: f64) -> (Self, usize) {
+        quantize(&mut data, cell);
+
+        let len = data.len();
+        data.sort_by_cached_key(coordinates);
+        data.dedup_by(|lhs, rhs| coordinates(lhs) == coordinates(rhs));
+        let collapsed = len - data.len();
+
+        (Self::make(data), collapsed)
+    }
+}
+
+/// divides space into cubes of side `s` and replaces the points in each of them by their centroid,
+/// in the order in which the cubes are first occupied
+///
+/// cubes are half open, i.e. the cube `[k * s, (k + 1) * s)` holds a point exactly on its lower
+/// boundary but not one on its upper boundary; this holds for negative coordinates as well
+///
+/// panics unless `s` is positive and finite
+pub fn voxel_downsample<P: Coordinates + Clone>(points: &[P], s: f64) -> Vec<P> {
+    assert!(
+        s > 0.0 && s.is_finite(),
+        "voxel size {s} is not positive and finite"
+    );
+
+    let mut cells: HashMap<Vec<i64>, usize> = HashMap::new();
+    let mut sums: Vec<(P, Vec<f64>, usize)> = Vec::new();
+    for point in points {
+        let coordinates: Vec<f64> = (0..point.dimensions())
+            .map(|dimension| point.coordinate(dimension))
+            .collect();
+        let cell = coordinates.iter().map(|c| (c / s).floor() as i64).collect();
+        let idx = *cells.entry(cell).or_insert_with(|| {
+            sums.push((point.clone(), vec![0.0; coordinates.len()], 0));
+            sums.len() - 1
+        });
+
+        let (_, sum, count) = &mut sums[idx];
+        sum.iter_mut().zip(&coordinates).for_each(|(s, c)| *s += c);
+        *count += 1;
+    }
+
+    sums.into_iter()
+        .map(|(mut point, sum, count)| {
+            for (dimension, sum) in sum.into_iter().enumerate() {
+                point.set_coordinate(dimension, sum / count as f64);
+            }
+            point
+        })
+        .collect()
+}
+
+impl<T: Coordinates + Clone> KDTree<T> {
+    /// a new tree of the points `voxel_downsample` keeps of this one
+    pub fn downsampled(&self, s: f64) -> Self {
+        let points: Vec<T> = self.iter().cloned().collect();
+        Self::make(voxel_downsample(&points, s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2D, Point3D};
+
+    #[test]
+    fn cluster_collapses() {
+        use rand::random;
+
+        let data: Vec<Point2D> = (0..50)
+            .map(|_| (3.02 + random::<f64>() * 0.01, 7.01 - random::<f64>() * 0.01).into())
+            .collect();
+        let (tree, collapsed) = KDTree::make_quantized(data, 0.5);
+
+        assert_eq!(collapsed, 49);
+        let points: Vec<_> = tree.iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(points, [(3.0, 7.0)]);
+    }
+
+    #[test]
+    fn cell_boundaries() {
+        let mut points: Vec<Point2D> = [0.49, 0.5, 0.51, -0.49, -0.5, -0.51, 2.5, 1e-300]
+            .into_iter()
+            .map(|x| (x, 0.0).into())
+            .collect();
+        quantize(&mut points, 1.0);
+
+        let xs: Vec<_> = points.iter().map(|p| p.x).collect();
+        assert_eq!(xs, [0.0, 1.0, 1.0, 0.0, -1.0, -1.0, 3.0, 0.0]);
+        // no negative zeros
+        assert!(xs.iter().all(|x| *x != 0.0 || x.is_sign_positive()));
+
+        let (tree, collapsed) = KDTree::make_quantized(points, 1.0);
+        assert_eq!(collapsed, 4);
+        assert_eq!(tree.iter().count(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "cell size")]
+    fn empty_cells() {
+        quantize(&mut [Point2D::from((1.0, 1.0))], 0.0);
+    }
+
+    fn xyz(point: &Point3D<F64>) -> [f64; 3] {
+        [point.x, point.y, point.z].map(f64::from)
+    }
+
+    #[test]
+    fn voxel_centroids() {
+        let points: Vec<Point3D<F64>> = [
+            [0.1, 0.1, 0.1],
+            [0.3, 0.5, 0.9],
+            [1.5, 0.0, 0.0],
+            [0.2, 0.3, 0.2],
+        ]
+        .map(Point3D::from)
+        .to_vec();
+
+        let downsampled = voxel_downsample(&points, 1.0);
+        assert_eq!(downsampled.len(), 2);
+        let centroid = xyz(&downsampled[0]);
+        for (c, expected) in centroid.iter().zip([0.2, 0.3, 0.4]) {
+            assert!((c - expected).abs() < 1e-12, "{centroid:?}");
+        }
+        assert_eq!(xyz(&downsampled[1]), [1.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn voxel_boundaries() {
+        // floor, not truncate: -0.1 and 0.1 are in different cubes, -1.0 is in the one of -0.1
+        // while -1.01 is not, and 1.0 is not in the one of 0.99
+        let points: Vec<Point3D<F64>> = [-0.1, 0.1, -1.0, -1.01, 1.0, 0.99]
+            .map(|x| Point3D::from([x, 0.0, 0.0]))
+            .to_vec();
+        let xs: Vec<_> = voxel_downsample(&points, 1.0)
+            .iter()
+            .map(|p| xyz(p)[0])
+            .collect();
+        assert_eq!(xs.len(), 4);
+        assert!((xs[0] - -0.55).abs() < 1e-12);
+        assert!((xs[1] - 0.545).abs() < 1e-12);
+        assert_eq!(xs[2..], [-1.01, 1.0]);
+    }
+
+    #[test]
+    fn downsampled_tree() {
+        use rand::random;
+
+        let data: Vec<Point2D> = (0..1000)
+            .map(|_| (random::<f64>() * 4.0 - 2.0, random::<f64>() * 4.0 - 2.0).into())
+            .collect();
+        let tree = KDTree::make(data);
+
+        let coarse = tree.downsampled(1.0);
+        assert_eq!(coarse.iter().count(), 16);
+        assert!(coarse.validate());
+        assert!(coarse.iter().all(|p| p.x.abs() < 2.0 && p.y.abs() < 2.0));
+    }
+}
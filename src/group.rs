@@ -0,0 +1,167 @@
+use super::{KDPoint, KDTree};
+
+/// several trees covering disjoint regions of space, queried as one
+///
+/// each tree comes with the (inclusive) box spanned by two corner points that contains all of its
+/// points, queries skip the trees whose box cannot contain a result and search the others on
+/// threads of their own
+#[derive(Debug)]
+pub struct KDTreeGroup<T: KDPoint> {
+    members: Vec<(KDTree<T>, T, T)>,
+}
+
+impl<T: KDPoint> Default for KDTreeGroup<T> {
+    fn default() -> Self {
+        Self {
+            members: Vec::new(),
+        }
+    }
+}
+
+impl<T: KDPoint> KDTreeGroup<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds `tree`, all of its points have to lie within the box spanned by `min` and `max`
+    ///
+    /// panics if one does not, queries would miss it
+    pub fn push(&mut self, tree: KDTree<T>, min: T, max: T) {
+        assert!(
+            tree.iter()
+                .all(|point| super::is_within_box(point, &min, &max)),
+            "a point of the tree lies outside of its box"
+        );
+        self.members.push((tree, min, max));
+    }
+
+    pub fn trees(&self) -> impl Iterator<Item = &KDTree<T>> {
+        self.members.iter().map(|(tree, _, _)| tree)
+    }
+
+    /// all points of all trees within `radius` of `search`, see `KDTree::find_within_radius`
+    ///
+    /// every tree but one that may hold any is searched on a scoped thread, the results are
+    /// merged in the order of the trees
+    pub fn find_within_radius(&self, search: &T, radius: T::Distance) -> Vec<&T>
+    where
+        T: Sync,
+        T::Distance: Send,
+        KDTree<T>: Sync,
+    {
+        let mut trees = self
+            .members
+            .iter()
+            .filter(|(_, min, max)| may_intersect(search, &radius, min, max))
+            .map(|(tree, _, _)| tree);
+        let Some(first) = trees.next() else {
+            return Vec::new();
+        };
+        std::thread::scope(|scope| {
+            let others: Vec<_> = trees
+                .map(|tree| {
+                    let radius = radius.clone();
+                    scope.spawn(move || tree.find_within_radius(search, radius))
+                })
+                .collect();
+            let mut found = first.find_within_radius(search, radius.clone());
+            for other in others {
+                let other = other
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                found.extend(other);
+            }
+            found
+        })
+    }
+}
+
+/// whether the ball around `search` might reach into the box spanned by `min` and `max`, judged by
+/// the distance along each axis on its own
+fn may_intersect<T: KDPoint>(search: &T, radius: &T::Distance, min: &T, max: &T) -> bool {
+    (0..min.dimensions()).all(|dimension| {
+        let key = search.kdkey(dimension);
+        let (lo, hi) = (min.kdkey(dimension), max.kdkey(dimension));
+        if key < lo {
+            T::key_distance(&key, &lo) <= *radius
+        } else if key > hi {
+            T::key_distance(&key, &hi) <= *radius
+        } else {
+            true
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2D, F64};
+
+    #[test]
+    fn radius_matches_single_tree() {
+        use rand::random;
+
+        let data: Vec<Point2D> = (0..600)
+            .map(|_| (random::<f64>() * 3.0, random::<f64>()).into())
+            .collect();
+        let single = KDTree::make(data.clone());
+
+        // three vertical stripes
+        let mut group = KDTreeGroup::new();
+        for stripe in 0..3 {
+            let lo = stripe as f64;
+            let members = data
+                .iter()
+                .filter(|p| lo <= p.x && p.x < lo + 1.0)
+                .cloned()
+                .collect();
+            group.push(
+                KDTree::make(members),
+                (lo, 0.0).into(),
+                (lo + 1.0, 1.0).into(),
+            );
+        }
+        assert_eq!(
+            group.trees().map(|tree| tree.iter().count()).sum::<usize>(),
+            600
+        );
+
+        let key = |p: &&Point2D| (F64::from(p.x), F64::from(p.y));
+        for _ in 0..50 {
+            let search: Point2D = (random::<f64>() * 3.0, random::<f64>()).into();
+            let radius = F64::from(random::<f64>() * 0.1);
+
+            let mut expected: Vec<_> = single
+                .find_within_radius(&search, radius)
+                .iter()
+                .map(key)
+                .collect();
+            let mut found: Vec<_> = group
+                .find_within_radius(&search, radius)
+                .iter()
+                .map(key)
+                .collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of its box")]
+    fn push_checks_box() {
+        let tree = KDTree::make(vec![Point2D::from((0.5, 0.5)), (1.5, 0.5).into()]);
+        KDTreeGroup::new().push(tree, (0.0, 0.0).into(), (1.0, 1.0).into());
+    }
+
+    #[test]
+    fn skips_distant_boxes() {
+        let search: Point2D = (0.0, 0.0).into();
+        let (min, max) = ((2.0, -1.0).into(), (3.0, 1.0).into());
+        assert!(!may_intersect(&search, &F64::from(3.9), &min, &max));
+        assert!(may_intersect(&search, &F64::from(4.0), &min, &max));
+
+        let inside: Point2D = (2.5, 0.0).into();
+        assert!(may_intersect(&inside, &F64::from(0.0), &min, &max));
+    }
+}
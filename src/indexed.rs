@@ -0,0 +1,418 @@
+//! points addressed by an index, such as the row of a table they came from
+
+use std::collections::{HashMap, HashSet};
+
+use super::{KDPoint, KDTree, Node};
+
+/// a point along with the index it is addressed by, keys and distances are those of `point`
+#[derive(Debug, Clone)]
+pub struct Indexed<T> {
+    pub index: usize,
+    pub point: T,
+}
+
+impl<T: KDPoint> KDPoint for Indexed<T> {
+    type Key = T::Key;
+    type Distance = T::Distance;
+
+    fn dimensions(&self) -> usize {
+        self.point.dimensions()
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        self.point.kdkey(dimension)
+    }
+
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        T::distance(&lhs.point, &rhs.point)
+    }
+
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        T::key_distance(lhs, rhs)
+    }
+}
+
+/// a tree whose points can be looked up and removed by index
+///
+/// points are indexed by their position in the data the tree is made from, inserted points get
+/// the next index not handed out yet. Every index maps to the keys of its point, which lead a
+/// search to it however the tree was rebuilt since.
+#[derive(Debug)]
+pub struct IndexedKDTree<T: KDPoint> {
+    tree: KDTree<Indexed<T>>,
+    keys: HashMap<usize, Vec<T::Key>>,
+    next_index: usize,
+}
+
+impl<T: KDPoint> IndexedKDTree<T> {
+    pub fn make(data: Vec<T>) -> Self {
+        let next_index = data.len();
+        let data: Vec<_> = data
+            .into_iter()
+            .enumerate()
+            .map(|(index, point)| Indexed { index, point })
+            .collect();
+        let keys = data
+            .iter()
+            .map(|indexed| (indexed.index, Node::keys(indexed)))
+            .collect();
+        Self {
+            tree: KDTree::make(data),
+            keys,
+            next_index,
+        }
+    }
+
+    /// the points with their indices, for queries
+    pub fn tree(&self) -> &KDTree<Indexed<T>> {
+        &self.tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// inserts `point` and returns its index
+    pub fn insert(&mut self, point: T) -> usize {
+        let index = self.next_index;
+        self.next_index += 1;
+        let indexed = Indexed { index, point };
+        self.keys.insert(index, Node::keys(&indexed));
+        self.tree.insert(indexed);
+        index
+    }
+
+    /// the point at `index`, `None` if there is none
+    pub fn get_index(&self, index: usize) -> Option<&T> {
+        let keys = self.keys.get(&index)?;
+        let root = self.tree.root.as_ref()?;
+        let mut path = Vec::new();
+        let position = root.locate(keys, &|point| point.index == index, &mut path)?;
+        let indexed = root.descend(&path)?.points().nth(position)?;
+        Some(&indexed.point)
+    }
+
+    /// removes the point at `index` and returns it, `None` if there is none
+    ///
+    /// the subtree below the node holding it is rebuilt, as for `BoundedKDTree::insert`
+    pub fn remove_index(&mut self, index: usize) -> Option<T> {
+        let keys = self.keys.remove(&index)?;
+        let mut path = Vec::new();
+        let position =
+            self.tree
+                .root
+                .as_ref()?
+                .locate(&keys, &|point| point.index == index, &mut path)?;
+        let removed = self.tree.remove_at(&path, position)?;
+        debug_assert_eq!(removed.index, index);
+        Some(removed.point)
+    }
+
+    /// removes the points at `indices` in a single pass over the tree and returns them in the
+    /// order of `indices`; indices without a point and repeated ones are skipped
+    ///
+    /// if more than a quarter of the points go, the tree is rebuilt once, otherwise only the
+    /// subtrees below nodes whose own point was removed
+    pub fn remove_indices(&mut self, indices: &[usize]) -> Vec<T> {
+        let mut targets = HashSet::new();
+        for index in indices {
+            if self.keys.remove(index).is_some() {
+                targets.insert(*index);
+            }
+        }
+        if targets.is_empty() {
+            return Vec::new();
+        }
+        let rebuild = targets.len() * 4 > self.len();
+        let mut removed: HashMap<usize, T> = self
+            .tree
+            .remove_where(|point| targets.contains(&point.index), rebuild)
+            .into_iter()
+            .map(|indexed| (indexed.index, indexed.point))
+            .collect();
+        debug_assert_eq!(removed.len(), targets.len());
+        indices
+            .iter()
+            .filter_map(|index| removed.remove(index))
+            .collect()
+    }
+
+    /// `KDTree::rebalance`, the indices stay as they are
+    pub fn rebalance(&mut self) {
+        self.tree.rebalance();
+    }
+
+    /// for every index handed out so far, the distance of its point to the `k`-th nearest other
+    /// point, or to the farthest one if the tree holds no more than `k` points
+    ///
+    /// `None` for indices without a point, and for all of them if `k` is 0 or there is no other
+    /// point. The `k`-th neighbour distance is a simple density estimate, large values mark
+    /// outliers.
+    pub fn knn_distances(&self, k: usize) -> Vec<Option<T::Distance>> {
+        let mut distances: Vec<_> = (0..self.next_index).map(|_| None).collect();
+        for point in self.tree.iter() {
+            distances[point.index] = self
+                .tree
+                .find_k_nearest_skipping(point, k, point)
+                .pop()
+                .map(|(_, distance)| distance);
+        }
+        distances
+    }
+
+    /// writes the index of the point nearest to `queries[i]` to `out[i]`, or `u32::MAX` for all of
+    /// them if the tree is empty; on ties, any of the nearest points
+    ///
+    /// allocates nothing. Chunks of `queries` and `out` of the same length can be handed to
+    /// separate threads.
+    ///
+    /// panics if `queries` and `out` differ in length, or an index does not fit into a `u32`
+    pub fn assign_nearest_into(&self, queries: &[T], out: &mut [u32]) {
+        assert_eq!(
+            queries.len(),
+            out.len(),
+            "{} queries, but {} outputs",
+            queries.len(),
+            out.len()
+        );
+        for (query, out) in queries.iter().zip(out) {
+            *out = self
+                .tree
+                .find_nearest_unindexed(query)
+                .map(|nearest| u32::try_from(nearest.index).expect("index does not fit into a u32"))
+                .unwrap_or(u32::MAX);
+        }
+    }
+}
+
+impl<T: KDPoint> KDTree<Indexed<T>> {
+    /// `find_nearest` for a point without an index, so that it need not be copied into one
+    fn find_nearest_unindexed(&self, search: &T) -> Option<&Indexed<T>> {
+        let mut best = None;
+        if let Some(root) = &self.root {
+            root.nearest_unindexed(search, &mut best);
+        }
+        best.map(|(point, _)| point)
+    }
+}
+
+impl<T: KDPoint> Node<Indexed<T>> {
+    /// as `Nearest`, comparing `search` to the indexed points' own
+    fn nearest_unindexed<'t>(
+        &'t self,
+        search: &T,
+        best: &mut Option<(&'t Indexed<T>, T::Distance)>,
+    ) {
+        let (search_key, key) = (
+            search.kdkey(self.dimension()),
+            self.data.kdkey(self.dimension()),
+        );
+        let target_to_split = T::key_distance(&search_key, &key);
+        let [first, second] = if search_key < key {
+            [&self.left, &self.right]
+        } else {
+            [&self.right, &self.left]
+        };
+
+        if let Some(first) = first {
+            first.nearest_unindexed(search, best);
+        }
+        for point in self.points() {
+            let distance = T::distance(search, &point.point);
+            if best.as_ref().is_none_or(|(_, best)| distance < *best) {
+                *best = Some((point, distance));
+            }
+        }
+        let beyond = best
+            .as_ref()
+            .is_some_and(|(_, best)| *best <= target_to_split);
+        if let Some(second) = second.as_ref().filter(|_| !beyond) {
+            second.nearest_unindexed(search, best);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point2D;
+    use rand::{random, Rng};
+
+    fn coordinates(point: Option<&Point2D>) -> Option<(f64, f64)> {
+        point.map(|point| (point.x, point.y))
+    }
+
+    #[test]
+    fn make_and_queries() {
+        let data: Vec<Point2D> = (0..50).map(|i| (i as f64, (i % 7) as f64).into()).collect();
+        let tree = IndexedKDTree::make(data.clone());
+        assert_eq!(tree.len(), 50);
+        for (index, point) in data.iter().enumerate() {
+            assert_eq!(coordinates(tree.get_index(index)), Some((point.x, point.y)));
+        }
+        assert!(tree.get_index(50).is_none());
+
+        let search = Indexed {
+            index: usize::MAX,
+            point: Point2D::from((20.2, 6.0)),
+        };
+        assert_eq!(tree.tree().find_nearest(&search).unwrap().index, 20);
+    }
+
+    #[test]
+    fn assign_nearest_into() {
+        let tree = IndexedKDTree::make((0..200).map(|_| (random(), random()).into()).collect());
+        let queries: Vec<Point2D> = (0..100).map(|_| (random(), random()).into()).collect();
+        let mut out = vec![0; queries.len()];
+        tree.assign_nearest_into(&queries, &mut out);
+        for (query, &index) in queries.iter().zip(&out) {
+            let search = Indexed {
+                index: usize::MAX,
+                point: query.clone(),
+            };
+            let nearest = tree.tree().find_nearest(&search).unwrap();
+            assert_eq!(index as usize, nearest.index);
+        }
+
+        let empty = IndexedKDTree::<Point2D>::make(Vec::new());
+        empty.assign_nearest_into(&queries, &mut out);
+        assert!(out.iter().all(|&index| index == u32::MAX));
+        empty.assign_nearest_into(&[], &mut []);
+    }
+
+    #[test]
+    #[should_panic(expected = "2 queries, but 1 outputs")]
+    fn assign_nearest_into_mismatch() {
+        let tree = IndexedKDTree::make(vec![Point2D::from((0.0, 0.0))]);
+        tree.assign_nearest_into(&[(1.0, 1.0).into(), (2.0, 2.0).into()], &mut [0]);
+    }
+
+    #[test]
+    fn knn_distances() {
+        let mut data: Vec<Point2D> = (0..200)
+            .map(|_| (random::<f64>() * 0.1, random::<f64>() * 0.1).into())
+            .collect();
+        // the outlier in the middle, so that build order and tree order differ
+        data.insert(100, (5.0, 5.0).into());
+        let mut tree = IndexedKDTree::make(data.clone());
+
+        let distances = tree.knn_distances(4);
+        assert_eq!(distances.len(), data.len());
+        for (index, point) in data.iter().enumerate() {
+            let mut others: Vec<_> = data
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != index)
+                .map(|(_, other)| Point2D::distance(point, other))
+                .collect();
+            others.sort();
+            assert_eq!(distances[index], Some(others[3]));
+        }
+        let outlier = (0..data.len()).max_by_key(|index| distances[*index]);
+        assert_eq!(outlier, Some(100));
+
+        tree.remove_index(7);
+        let distances = tree.knn_distances(4);
+        assert_eq!(distances.len(), data.len());
+        let missing: Vec<_> = (0..data.len())
+            .filter(|i| distances[*i].is_none())
+            .collect();
+        assert_eq!(missing, [7]);
+
+        assert_eq!(tree.knn_distances(0).len(), data.len());
+        assert!(tree.knn_distances(0).iter().all(Option::is_none));
+        let single = IndexedKDTree::make(vec![Point2D::from((0.0, 0.0))]);
+        assert_eq!(single.knn_distances(3), [None]);
+    }
+
+    #[test]
+    fn remove_indices() {
+        for fraction in [0.3, 0.1] {
+            let data: Vec<Point2D> = (0..1000).map(|_| (random(), random()).into()).collect();
+            let mut tree = IndexedKDTree::make(data.clone());
+            let mut indices: Vec<usize> =
+                (0..1000).filter(|_| random::<f64>() < fraction).collect();
+            // unknown and repeated indices
+            let expected: Vec<(f64, f64)> =
+                indices.iter().map(|&i| (data[i].x, data[i].y)).collect();
+            indices.extend([5000, indices[0]]);
+
+            let removed = tree.remove_indices(&indices);
+            assert_eq!(
+                removed.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>(),
+                expected
+            );
+            assert!(tree.tree().validate());
+            assert_eq!(tree.len(), 1000 - expected.len());
+            assert!(tree.get_index(indices[0]).is_none());
+            assert!(tree.remove_indices(&indices).is_empty());
+
+            let survivors: Vec<Point2D> = (0..1000)
+                .filter(|i| tree.get_index(*i).is_some())
+                .map(|i| data[i].clone())
+                .collect();
+            assert_eq!(survivors.len(), tree.len());
+            let fresh = KDTree::make(survivors);
+            for _ in 0..50 {
+                let search = Indexed {
+                    index: usize::MAX,
+                    point: Point2D::from((random(), random())),
+                };
+                let nearest = tree.tree().find_nearest(&search).unwrap();
+                let expected = fresh.find_nearest(&search.point).unwrap();
+                assert_eq!((nearest.point.x, nearest.point.y), (expected.x, expected.y));
+            }
+        }
+    }
+
+    /// random inserts, removals, lookups and rebalances, checked against a map of the points
+    /// that should be there
+    #[test]
+    fn random_operations() {
+        let mut rng = rand::thread_rng();
+        // few distinct coordinates, so that many points share keys
+        let mut point = || Point2D::from((rng.gen_range(0..8) as f64, rng.gen_range(0..8) as f64));
+        let data: Vec<Point2D> = (0..100).map(|_| point()).collect();
+        let mut shadow: HashMap<usize, (f64, f64)> = data
+            .iter()
+            .enumerate()
+            .map(|(index, point)| (index, (point.x, point.y)))
+            .collect();
+        let mut tree = IndexedKDTree::make(data);
+        let mut next_index = 100;
+
+        for step in 0..3000 {
+            let index = (random::<f64>() * next_index as f64 * 1.1) as usize;
+            match random::<u8>() % 8 {
+                0..=2 => {
+                    let inserted = point();
+                    assert_eq!(tree.insert(inserted.clone()), next_index);
+                    shadow.insert(next_index, (inserted.x, inserted.y));
+                    next_index += 1;
+                }
+                3..=5 => {
+                    let removed = tree.remove_index(index);
+                    assert_eq!(coordinates(removed.as_ref()), shadow.remove(&index));
+                }
+                6 => assert_eq!(
+                    coordinates(tree.get_index(index)),
+                    shadow.get(&index).copied()
+                ),
+                _ => tree.rebalance(),
+            }
+
+            if step % 100 == 0 {
+                assert!(tree.tree().validate());
+                assert_eq!(tree.len(), shadow.len());
+                assert_eq!(tree.tree().iter().count(), shadow.len());
+                for (&index, &expected) in &shadow {
+                    assert_eq!(coordinates(tree.get_index(index)), Some(expected));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Lloyd's k-means with the assignment step answered by a tree over the centroids
+
+use super::{Coordinates, KDPoint, KDTree};
+
+/// a centroid and its position in the list of centroids
+#[derive(Debug, Clone)]
+struct Centroid<P> {
+    index: usize,
+    point: P,
+}
+
+impl<P: KDPoint> KDPoint for Centroid<P> {
+    type Key = P::Key;
+    type Distance = P::Distance;
+
+    fn dimensions(&self) -> usize {
+        self.point.dimensions()
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        self.point.kdkey(dimension)
+    }
+
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        P::distance(&lhs.point, &rhs.point)
+    }
+
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        P::key_distance(lhs, rhs)
+    }
+}
+
+/// index of the nearest centroid for every point, empty if there are no centroids
+pub fn assign_all<P: KDPoint + Clone>(centroids: &[P], points: &[P]) -> Vec<usize> {
+    let tree = KDTree::make(
+        centroids
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(index, point)| Centroid { index, point })
+            .collect(),
+    );
+    points
+        .iter()
+        .map_while(|point| {
+            let search = Centroid {
+                index: usize::MAX,
+                point: point.clone(),
+            };
+            tree.find_nearest_map(&search, |centroid| centroid.index)
+        })
+        .collect()
+}
+
+/// mean of the points assigned to each of the `previous` centroids, a centroid without points
+/// stays where it was
+pub fn update_centroids<P: Coordinates + Clone>(
+    points: &[P],
+    assignment: &[usize],
+    previous: &[P],
+) -> Vec<P> {
+    assert_eq!(points.len(), assignment.len(), "one assignment per point");
+
+    let dimensions = previous.first().map(P::dimensions).unwrap_or(0);
+    let mut sums = vec![vec![0.0; dimensions]; previous.len()];
+    let mut counts = vec![0; previous.len()];
+    for (point, &cluster) in points.iter().zip(assignment) {
+        for (dimension, sum) in sums[cluster].iter_mut().enumerate() {
+            *sum += point.coordinate(dimension);
+        }
+        counts[cluster] += 1;
+    }
+
+    previous
+        .iter()
+        .zip(sums.into_iter().zip(counts))
+        .map(|(centroid, (sum, count))| {
+            let mut centroid = centroid.clone();
+            if count > 0 {
+                for (dimension, sum) in sum.into_iter().enumerate() {
+                    centroid.set_coordinate(dimension, sum / count as f64);
+                }
+            }
+            centroid
+        })
+        .collect()
+}
+
+/// clusters `points` into (at most) `k` clusters in at most `iters` rounds of Lloyd's algorithm,
+/// returns the centroids and the index of each point's centroid
+///
+/// the initial centroids are `k` points evenly spaced through `points`, so the result only
+/// depends on the input; rounds stop early once no point changes its cluster. With `k` 0 both are
+/// empty.
+pub fn kmeans<P: Coordinates + Clone>(
+    points: &[P],
+    k: usize,
+    iters: usize,
+) -> (Vec<P>, Vec<usize>) {
+    let k = k.min(points.len());
+    if k == 0 {
+        return (Vec::new(), Vec::new());
+    }
+    let mut centroids: Vec<P> = (0..k)
+        .map(|i| points[i * points.len() / k].clone())
+        .collect();
+    let mut assignment = assign_all(&centroids, points);
+    for _ in 0..iters {
+        centroids = update_centroids(points, &assignment, &centroids);
+        let next = assign_all(&centroids, points);
+        if next == assignment {
+            break;
+        }
+        assignment = next;
+    }
+    (centroids, assignment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point3D, F64};
+    use rand::random;
+
+    const CENTERS: [[f64; 3]; 3] = [[0.0, 0.0, 0.0], [10.0, 0.0, 5.0], [0.0, 10.0, -5.0]];
+
+    /// 100 points around each of `CENTERS`, the clusters one after the other
+    fn clusters() -> Vec<Point3D<F64>> {
+        CENTERS
+            .iter()
+            .flat_map(|center| (0..100).map(|_| center.map(|c| c + random::<f64>() - 0.5).into()))
+            .collect()
+    }
+
+    fn coordinates(point: &Point3D<F64>) -> [f64; 3] {
+        [0, 1, 2].map(|dimension| point.coordinate(dimension))
+    }
+
+    #[test]
+    fn assignment_matches_brute_force() {
+        let centroids: Vec<Point3D<F64>> = (0..20)
+            .map(|_| [random::<f64>(), random(), random()].into())
+            .collect();
+        let points: Vec<Point3D<F64>> = (0..200)
+            .map(|_| [random::<f64>(), random(), random()].into())
+            .collect();
+
+        let assignment = assign_all(&centroids, &points);
+        for (point, &index) in points.iter().zip(&assignment) {
+            let nearest = centroids
+                .iter()
+                .map(|centroid| Point3D::distance(point, centroid))
+                .min()
+                .unwrap();
+            assert_eq!(Point3D::distance(point, &centroids[index]), nearest);
+        }
+        assert!(assign_all(&[], &points).is_empty());
+    }
+
+    #[test]
+    fn update_keeps_empty_clusters() {
+        let points: Vec<Point3D<F64>> = vec![[0.0, 0.0, 0.0].into(), [2.0, 4.0, 6.0].into()];
+        let previous: Vec<Point3D<F64>> = vec![[1.0, 1.0, 1.0].into(), [9.0, 9.0, 9.0].into()];
+        let centroids = update_centroids(&points, &[0, 0], &previous);
+        assert_eq!(coordinates(&centroids[0]), [1.0, 2.0, 3.0]);
+        assert_eq!(coordinates(&centroids[1]), [9.0, 9.0, 9.0]);
+    }
+
+    #[test]
+    fn separated_clusters() {
+        let points = clusters();
+        let (centroids, assignment) = kmeans(&points, 3, 20);
+        assert_eq!(centroids.len(), 3);
+
+        for (cluster, center) in CENTERS.iter().enumerate() {
+            let members = &assignment[cluster * 100..(cluster + 1) * 100];
+            assert!(members.iter().all(|&index| index == members[0]));
+
+            let centroid = coordinates(&centroids[members[0]]);
+            for (c, expected) in centroid.iter().zip(center) {
+                assert!((c - expected).abs() < 0.2, "{centroid:?} != {center:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn more_clusters_than_points() {
+        let points = &clusters()[..2];
+        let (centroids, assignment) = kmeans(points, 5, 10);
+        assert_eq!(centroids.len(), 2);
+        assert_eq!(assignment, [0, 1]);
+        let (centroids, assignment) = kmeans::<Point3D<F64>>(&[], 3, 10);
+        assert!(centroids.is_empty() && assignment.is_empty());
+        let (centroids, assignment) = kmeans(&clusters(), 0, 3);
+        assert!(centroids.is_empty() && assignment.is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+//! reader for uncompressed LAS files with point data record formats 0 to 3
+//!
+//! LAZ (compressed) files and the extended formats 6 to 10 are rejected.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::{Error, Point3D, F64};
+
+/// per point attributes of LAS point formats 0 to 3
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LasAttributes {
+    pub intensity: u16,
+    pub return_number: u8,
+    pub number_of_returns: u8,
+    pub classification: u8,
+    pub scan_angle_rank: i8,
+    pub user_data: u8,
+    pub point_source_id: u16,
+    /// only present in formats 1 and 3
+    pub gps_time: Option<f64>,
+    /// red, green and blue, only present in formats 2 and 3
+    pub color: Option<[u16; 3]>,
+}
+
+/// the `Error` inside can be recovered with `get_ref` and `downcast_ref`
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        Error::CorruptData(message.into()),
+    )
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn i32_at(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn f64_at(bytes: &[u8], offset: usize) -> f64 {
+    f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+struct Header {
+    point_offset: u32,
+    format: u8,
+    record_length: usize,
+    count: u64,
+    scale: [f64; 3],
+    offset: [f64; 3],
+}
+
+impl Header {
+    const MIN_SIZE: usize = 227;
+
+    fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut bytes = [0; Self::MIN_SIZE];
+        r.read_exact(&mut bytes)?;
+
+        if &bytes[..4] != b"LASF" {
+            return Err(invalid("missing LASF signature"));
+        }
+        let version = (bytes[24], bytes[25]);
+        let header_size = u16_at(&bytes, 94) as usize;
+        if header_size < Self::MIN_SIZE {
+            return Err(invalid(format!("header size {header_size} too small")));
+        }
+
+        let format = bytes[104];
+        if format & 0xc0 != 0 {
+            return Err(invalid("compressed (LAZ) point data is not supported"));
+        }
+        if format > 3 {
+            return Err(invalid(format!(
+                "point data format {format} is not supported"
+            )));
+        }
+        let record_length = u16_at(&bytes, 105) as usize;
+        let min_length = [20, 28, 26, 34][format as usize];
+        if record_length < min_length {
+            return Err(invalid(format!(
+                "record length {record_length} too small for point format {format}"
+            )));
+        }
+
+        let mut count = u32_at(&bytes, 107) as u64;
+        if version >= (1, 4) && count == 0 {
+            // legacy count is zero for large files, the 64 bit count follows the 1.3 header
+            if header_size < 255 {
+                return Err(invalid("LAS 1.4 header too small"));
+            }
+            let mut extended = vec![0; 255 - Self::MIN_SIZE];
+            r.read_exact(&mut extended)?;
+            count = u64::from_le_bytes(extended[247 - Self::MIN_SIZE..].try_into().unwrap());
+        }
+
+        Ok(Self {
+            point_offset: u32_at(&bytes, 96),
+            format,
+            record_length,
+            count,
+            scale: [
+                f64_at(&bytes, 131),
+                f64_at(&bytes, 139),
+                f64_at(&bytes, 147),
+            ],
+            offset: [
+                f64_at(&bytes, 155),
+                f64_at(&bytes, 163),
+                f64_at(&bytes, 171),
+            ],
+        })
+    }
+}
+
+fn read_records<R: Read + Seek, P>(
+    mut r: R,
+    mut parse: impl FnMut(&Header, &[u8]) -> P,
+) -> io::Result<Vec<P>> {
+    r.seek(SeekFrom::Start(0))?;
+    let header = Header::read(&mut r)?;
+    r.seek(SeekFrom::Start(header.point_offset as u64))?;
+
+    let mut points = Vec::with_capacity(header.count.min(1 << 20) as usize);
+    let mut record = vec![0; header.record_length];
+    for _ in 0..header.count {
+        r.read_exact(&mut record)?;
+        points.push(parse(&header, &record));
+    }
+    Ok(points)
+}
+
+fn coordinates(header: &Header, record: &[u8]) -> Point3D<F64> {
+    let [x, y, z] = [0, 1, 2]
+        .map(|axis| i32_at(record, 4 * axis) as f64 * header.scale[axis] + header.offset[axis]);
+    [x, y, z].into()
+}
+
+fn attributes(header: &Header, record: &[u8]) -> LasAttributes {
+    let color_at = |offset: usize| [0, 1, 2].map(|channel| u16_at(record, offset + 2 * channel));
+    LasAttributes {
+        intensity: u16_at(record, 12),
+        return_number: record[14] & 0b111,
+        number_of_returns: (record[14] >> 3) & 0b111,
+        classification: record[15],
+        scan_angle_rank: record[16] as i8,
+        user_data: record[17],
+        point_source_id: u16_at(record, 18),
+        gps_time: matches!(header.format, 1 | 3).then(|| f64_at(record, 20)),
+        color: match header.format {
+            2 => Some(color_at(20)),
+            3 => Some(color_at(28)),
+            _ => None,
+        },
+    }
+}
+
+/// reads all points of a LAS file, with scale and offset of the header applied
+pub fn points_from_las<R: Read + Seek>(r: R) -> io::Result<Vec<Point3D<F64>>> {
+    read_records(r, coordinates)
+}
+
+/// like `points_from_las`, but keeps the attributes of each point
+pub fn points_with_attributes_from_las<R: Read + Seek>(
+    r: R,
+) -> io::Result<Vec<(Point3D<F64>, LasAttributes)>> {
+    read_records(r, |header, record| {
+        (coordinates(header, record), attributes(header, record))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const FORMAT3: &[u8] = include_bytes!("../tests/data/format3.las");
+
+    fn assert_close(point: &Point3D<F64>, expected: [f64; 3]) {
+        let actual = [point.x, point.y, point.z].map(f64::from);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{actual:?} != {expected:?}");
+        }
+    }
+
+    /// rewrites the fixture as point format `format`, dropping the fields it does not have
+    fn convert(format: u8) -> Vec<u8> {
+        let point_offset = u32_at(FORMAT3, 96) as usize;
+        let record_length = [20, 28, 26, 34][format as usize];
+
+        let mut bytes = FORMAT3[..point_offset].to_vec();
+        bytes[104] = format;
+        bytes[105..107].copy_from_slice(&(record_length as u16).to_le_bytes());
+        for record in FORMAT3[point_offset..].chunks(34) {
+            bytes.extend(&record[..20]);
+            if matches!(format, 1 | 3) {
+                bytes.extend(&record[20..28]);
+            }
+            if matches!(format, 2 | 3) {
+                bytes.extend(&record[28..34]);
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn coordinates_apply_scale_and_offset() {
+        let points = points_from_las(Cursor::new(FORMAT3)).unwrap();
+        assert_eq!(points.len(), 4);
+        assert_close(&points[0], [1000.0, 2000.0, -5.0]);
+        assert_close(&points[1], [1012.34, 1999.5, 10.25]);
+        assert_close(&points[2], [1012.35, 2001.01, 10.251]);
+        assert_close(&points[3], [987.65, 2100.0, 0.0]);
+    }
+
+    #[test]
+    fn attributes_format3() {
+        let points = points_with_attributes_from_las(Cursor::new(FORMAT3)).unwrap();
+        let (_, first) = points[0];
+        assert_eq!(
+            first,
+            LasAttributes {
+                intensity: 100,
+                return_number: 1,
+                number_of_returns: 1,
+                classification: 2,
+                scan_angle_rank: -5,
+                user_data: 0,
+                point_source_id: 7,
+                gps_time: Some(1.5),
+                color: Some([65535, 0, 0]),
+            }
+        );
+
+        let (_, last) = points[3];
+        assert_eq!(last.intensity, 65535);
+        assert_eq!((last.return_number, last.number_of_returns), (3, 5));
+        assert_eq!(last.scan_angle_rank, -90);
+        assert_eq!(last.gps_time, Some(1e6));
+        assert_eq!(last.color, Some([1, 2, 3]));
+    }
+
+    #[test]
+    fn all_formats() {
+        for format in 0..=3 {
+            let bytes = convert(format);
+            let points = points_with_attributes_from_las(Cursor::new(bytes)).unwrap();
+            assert_eq!(points.len(), 4);
+            assert_close(&points[1].0, [1012.34, 1999.5, 10.25]);
+
+            let attributes = points[2].1;
+            assert_eq!(attributes.intensity, 300);
+            assert_eq!(attributes.classification, 5);
+            assert_eq!(attributes.point_source_id, 8);
+            assert_eq!(attributes.gps_time.is_some(), matches!(format, 1 | 3));
+            assert_eq!(
+                attributes.color,
+                matches!(format, 2 | 3).then_some([0, 0, 65535])
+            );
+        }
+    }
+
+    #[test]
+    fn build_tree() {
+        let points = points_from_las(Cursor::new(FORMAT3)).unwrap();
+        let tree = crate::KDTree::make(points);
+        let nearest = tree.find_nearest(&[1012.0, 2000.0, 10.0].into()).unwrap();
+        assert_close(nearest, [1012.34, 1999.5, 10.25]);
+    }
+
+    #[test]
+    fn rejects_unsupported() {
+        let mut laz = FORMAT3.to_vec();
+        laz[104] |= 0x80;
+        assert!(points_from_las(Cursor::new(laz)).is_err());
+
+        let mut extended = FORMAT3.to_vec();
+        extended[104] = 6;
+        assert!(points_from_las(Cursor::new(extended)).is_err());
+
+        assert!(points_from_las(Cursor::new(&FORMAT3[..100])).is_err());
+        assert!(points_from_las(Cursor::new(b"LASX".repeat(100))).is_err());
+    }
+}
@@ -1,16 +1,121 @@
-use std::{cmp::Ordering, fmt::Debug};
+use std::{
+    cell::Cell,
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BinaryHeap},
+    fmt::{self, Debug},
+    ops::{Bound, RangeBounds},
+    sync::OnceLock,
+};
 
 pub trait KDPoint {
     type Key: Ord;
-    type Distance: Ord;
+    type Distance: Ord + Clone;
 
+    /// number of dimensions, i.e. the keys `0..dimensions()` describe a point
+    ///
+    /// all points in a tree have the same number of dimensions, those of the tree (see
+    /// `KDTree::tree_dimensions`). Points passed to queries may have more, the tree never asks for
+    /// their keys beyond its own dimensions; whether `distance` takes the extra ones into account
+    /// is up to the implementation.
+    fn dimensions(&self) -> usize;
+    /// key in `dimension`, the tree only asks for dimensions below `dimensions()`
     fn kdkey(&self, dimension: usize) -> Self::Key;
+    /// distance from `lhs` to `rhs`, which need not be symmetric: searches always pass the search
+    /// point as `lhs` and a candidate as `rhs`
     fn distance(lhs: &Self, rhs: &Self) -> Self::Distance;
+    /// lower bound for the distance from a point with key `lhs` to any point whose key lies on the
+    /// other side of `rhs`, in the same direction as `distance`
     fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance;
 }
 
+/// access to the coordinates of points living in (a subspace of) euclidean space
+pub trait Coordinates: KDPoint {
+    fn coordinate(&self, dimension: usize) -> f64;
+    fn set_coordinate(&mut self, dimension: usize, value: f64);
+}
+
 mod points;
-use points::*;
+pub use points::*;
+
+mod error;
+pub use error::Error;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+mod ply;
+pub use ply::*;
+
+mod csv;
+
+mod group;
+pub use group::KDTreeGroup;
+
+mod bounded;
+pub use bounded::BoundedKDTree;
+
+mod kmeans;
+pub use kmeans::{assign_all, kmeans, update_centroids};
+
+mod trace;
+pub use trace::{QueryTrace, TraceEvent};
+
+mod annotated;
+pub use annotated::{AnnotatedKDTree, NodeHandle};
+
+mod forest;
+pub use forest::KDForest;
+
+mod cache;
+pub use cache::QueryCache;
+
+mod indexed;
+pub use indexed::{Indexed, IndexedKDTree};
+
+mod sink;
+pub use sink::{sender_sink, SinkClosed};
+
+mod summary;
+pub use summary::KeySummary;
+
+mod moments;
+pub use moments::Moments;
+
+mod frozen;
+pub use frozen::{FrozenKDTree, NodeIndex, U32, U64};
+
+mod categorical;
+pub use categorical::{CategoricalPoint, CategoryTable, MixedKey};
+
+mod flat;
+pub use flat::{FlatPoints, FlatRow};
+
+mod pool;
+pub use pool::{Job, ScopedThreads, ThreadPool};
+
+mod versioned;
+use versioned::next_version;
+pub use versioned::VersionedResults;
+
+mod grid;
+pub use grid::{quantize, voxel_downsample};
+
+mod summarized;
+pub use summarized::{Max, Min, Monoid, Sum, SummarizedKDTree};
+
+mod classify;
+pub use classify::Weighting;
+
+#[cfg(feature = "las")]
+mod las;
+#[cfg(feature = "las")]
+pub use las::*;
+
+#[cfg(feature = "bench")]
+pub mod workloads;
+
+#[cfg(test)]
+mod counting;
 
 fn compare_element<E: KDPoint>(left: &E, right: &E, dimension: usize) -> Ordering {
     E::Key::cmp(&left.kdkey(dimension), &right.kdkey(dimension))
@@ -23,232 +128,6359 @@ fn make_compare<E: KDPoint>(dimension: usize) -> impl Fn(&E, &E) -> Ordering {
 mod partition_functions;
 use partition_functions::hoare_b as partition;
 
+/// construction options
+#[derive(Debug, Clone, Default)]
+struct Layout {
+    /// cycle through these dimensions instead of all of them
+    axis_map: Option<Vec<usize>>,
+    /// runs of at least this many equal keys are kept in a bucket instead of being split further
+    min_bucket: Option<usize>,
+    /// what inserts ending up deeper than `max_depth` do
+    depth_policy: DepthPolicy,
+    /// overrides the default maximum depth, see `KDTree::max_depth`
+    max_depth: Option<usize>,
+    /// points with equal keys in all dimensions share a node, see `KDTree::make_with_counts`
+    counts: bool,
+    /// see `KDTree::set_auto_maintenance`
+    maintenance: Option<Maintenance>,
+}
+
+/// every `interval` inserts, the tree is rebuilt if it is more than `max_ratio` times as deep as
+/// it needs to be
+#[derive(Debug, Clone, Copy)]
+struct Maintenance {
+    interval: usize,
+    max_ratio: f64,
+}
+
+/// what happens when an insert places a point deeper than the tree's maximum depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DepthPolicy {
+    /// nothing, the tree just gets deeper
+    #[default]
+    Ignore,
+    /// the tree remembers it, see `KDTree::needs_rebalance`
+    Flag,
+    /// the smallest subtree above the point that is more than twice as deep as it needs to be is
+    /// rebuilt right away
+    RebuildSubtree,
+}
+
+impl Layout {
+    /// split dimension at `depth` for points with `dimensions` dimensions
+    fn axis(&self, depth: usize, dimensions: usize) -> usize {
+        let dimension = match &self.axis_map {
+            Some(map) => map[depth % map.len()],
+            None => depth % dimensions,
+        };
+        debug_assert!(
+            dimension < dimensions,
+            "split dimension {dimension} of points with {dimensions} dimensions"
+        );
+        dimension
+    }
+}
+
+/// where an insert put a point
+struct Placement {
+    /// turns from the node the insert started at to the node holding the point (true: left), last
+    /// turn first while the insert unwinds
+    path: Vec<bool>,
+    /// size of the subtree just left, while still looking for one to rebuild
+    unbalanced: Option<usize>,
+    /// depth of the subtree that was rebuilt, `path` may not lead to the point below it
+    rebuilt: Option<usize>,
+}
+
+/// smallest and largest key in one dimension, inclusive, `None` for unbounded
+type KeyRange<K> = (Option<K>, Option<K>);
+
+/// smallest and largest key in every dimension, each empty while unknown
+type Extremes<K> = Vec<[OnceLock<K>; 2]>;
+
+/// points with their counts and the region of key space they were cut from
+type Piece<T> = (Vec<(T, usize)>, Bounds<<T as KDPoint>::Key>);
+
 #[derive(Debug)]
 struct Node<T> {
     data: T,
+    /// split dimension, narrower than `usize` to share a word with `equal_left`
+    dimension: u32,
+    /// side the next inserted point with an equal key goes to
+    equal_left: bool,
+    /// copies and bucket, `None` unless there are any, so plain trees do not pay for them
+    extra: Option<Box<Extra<T>>>,
     left: Option<Box<Self>>,
     right: Option<Box<Self>>,
 }
 
-impl<T: KDPoint> Node<T> {
-    fn new(data: T) -> Self {
+/// the points of a node beyond `data` itself
+#[derive(Debug)]
+struct Extra<T> {
+    /// copies of `data`, more than 1 only in trees made with `make_with_counts`
+    count: usize,
+    /// more points with the same key as `data` in the split dimension
+    bucket: Vec<T>,
+}
+
+impl<T> Node<T> {
+    fn new(data: T, dimension: usize) -> Self {
         Self {
             data,
+            dimension: u32::try_from(dimension).expect("split dimension fits into u32"),
+            equal_left: false,
+            extra: None,
             left: None,
             right: None,
         }
     }
 
-    fn make(mut data: Vec<T>, dimension: usize) -> Option<Self> {
+    fn dimension(&self) -> usize {
+        self.dimension as usize
+    }
+
+    /// copies of `data`
+    fn count(&self) -> usize {
+        self.extra.as_ref().map_or(1, |extra| extra.count)
+    }
+
+    /// more points with the same key as `data` in the split dimension
+    fn bucket(&self) -> &[T] {
+        self.extra.as_ref().map_or(&[], |extra| &extra.bucket)
+    }
+
+    fn extra_mut(&mut self) -> &mut Extra<T> {
+        self.extra.get_or_insert_with(|| {
+            Box::new(Extra {
+                count: 1,
+                bucket: Vec::new(),
+            })
+        })
+    }
+
+    fn set_count(&mut self, count: usize) {
+        if count != 1 || self.extra.is_some() {
+            self.extra_mut().count = count;
+        }
+    }
+
+    fn set_bucket(&mut self, bucket: Vec<T>) {
+        if !bucket.is_empty() || self.extra.is_some() {
+            self.extra_mut().bucket = bucket;
+        }
+    }
+
+    fn take_bucket(&mut self) -> Vec<T> {
+        self.extra
+            .as_mut()
+            .map(|extra| std::mem::take(&mut extra.bucket))
+            .unwrap_or_default()
+    }
+
+    /// `data`, its count and the bucket
+    fn into_own(self) -> (T, usize, Vec<T>) {
+        match self.extra {
+            Some(extra) => (self.data, extra.count, extra.bucket),
+            None => (self.data, 1, Vec::new()),
+        }
+    }
+
+    /// `data`, as often as it was counted, and the bucket
+    fn points(&self) -> impl Iterator<Item = &T> {
+        std::iter::repeat_n(&self.data, self.count()).chain(self.bucket())
+    }
+
+    /// number of points in this subtree
+    fn size(&self) -> usize {
+        let left = self.left.as_ref().map(|child| child.size()).unwrap_or(0);
+        let right = self.right.as_ref().map(|child| child.size()).unwrap_or(0);
+        self.count() + self.bucket().len() + left + right
+    }
+
+    /// all points of this subtree with their counts, in the order of `into_points`
+    fn into_counted(self) -> Vec<(T, usize)> {
+        let mut points = Vec::new();
+        let mut stack = vec![self];
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.left.take().map(|child| *child));
+            stack.extend(node.right.take().map(|child| *child));
+            let (data, count, bucket) = node.into_own();
+            points.push((data, count));
+            points.extend(bucket.into_iter().map(|point| (point, 1)));
+        }
+        points
+    }
+
+    /// all points of this subtree, starting with those of `points`, counted points only once
+    fn into_points(self) -> Vec<T> {
+        let mut points = Vec::new();
+        let mut stack = vec![self];
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.left.take().map(|child| *child));
+            stack.extend(node.right.take().map(|child| *child));
+            let (data, _, bucket) = node.into_own();
+            points.push(data);
+            points.extend(bucket);
+        }
+        points
+    }
+}
+
+impl<T: KDPoint> Node<T> {
+    fn make(data: Vec<T>, depth: usize, layout: &Layout) -> Option<Self> {
+        let (mut node, left, right) = Self::split(data, depth, layout)?;
+        node.left = Self::make(left, depth + 1, layout).map(Box::new);
+        node.right = Self::make(right, depth + 1, layout).map(Box::new);
+        Some(node)
+    }
+
+    /// `make`, building the subtrees on both sides of a split on threads of their own until
+    /// `threads` run at once, the same tree in the end
+    fn make_threads(data: Vec<T>, depth: usize, layout: &Layout, threads: usize) -> Option<Self>
+    where
+        T: Send,
+    {
+        // below that, starting a thread costs more than it saves
+        const MIN_POINTS: usize = 1 << 12;
+
+        if threads <= 1 || data.len() < MIN_POINTS {
+            return Self::make(data, depth, layout);
+        }
+        let (mut node, left, right) = Self::split(data, depth, layout)?;
+        let (left, right) = std::thread::scope(|scope| {
+            let left = scope.spawn(|| Self::make_threads(left, depth + 1, layout, threads / 2));
+            let right = Self::make_threads(right, depth + 1, layout, threads - threads / 2);
+            let left = left
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            (left, right)
+        });
+        node.left = left.map(Box::new);
+        node.right = right.map(Box::new);
+        Some(node)
+    }
+
+    /// the node `make` puts at `depth` for `data`, still without children, and the points that go
+    /// to its left and right
+    fn split(mut data: Vec<T>, depth: usize, layout: &Layout) -> Option<(Self, Vec<T>, Vec<T>)> {
+        if data.is_empty() {
+            return None;
+        }
+
+        let dimension = layout.axis(depth, data[0].dimensions());
+
+        // keys equal to the median directly follow `median_start`
+        let median_start = partition(&mut data, make_compare(dimension));
+        let median_end = median_start
+            + data[median_start..]
+                .iter()
+                .take_while(|e| compare_element(*e, &data[median_start], dimension).is_eq())
+                .count();
+        debug_assert!((median_start..median_end).contains(&(data.len() / 2)));
+
+        let is_bucket = layout
+            .min_bucket
+            .map(|min_bucket| median_end - median_start >= min_bucket)
+            .unwrap_or(false);
+
+        let (left, mut bucket, right) = if is_bucket {
+            let right = data.split_off(median_end);
+            let bucket = data.split_off(median_start);
+            (data, bucket, right)
+        } else {
+            // splitting in the middle of the run instead of at its start keeps duplicate heavy
+            // data balanced, with equal keys on both sides of the split
+            let right = data.split_off(data.len() / 2 + 1);
+            let bucket = data.split_off(data.len() - 1);
+            (data, bucket, right)
+        };
+        let element = bucket.pop().unwrap();
+
+        let mut node = Self::new(element, dimension);
+        node.equal_left = true;
+        node.set_bucket(bucket);
+        Some((node, left, right))
+    }
+
+    /// like `make`, but the side of each split containing `hotspot` only reaches `share` of the
+    /// points beyond the hotspot's key, unless that is more than half; no buckets
+    fn make_biased(
+        mut data: Vec<T>,
+        depth: usize,
+        layout: &Layout,
+        hotspot: &T,
+        share: f64,
+    ) -> Option<Self> {
         if data.is_empty() {
             return None;
         }
 
-        let idx = partition(&mut data, make_compare(dimension));
-        assert!(idx < data.len());
+        let dimension = layout.axis(depth, data[0].dimensions());
+        data.sort_by(make_compare(dimension));
+        let len = data.len();
+        let median = len / 2;
+        let beyond_hotspot = (len as f64 * share).ceil() as usize;
+        let hotspot_side = |before_hotspot: usize| before_hotspot + 1 + beyond_hotspot;
+
+        // points before the split go left, those after it right
+        let below = data.partition_point(|e| compare_element(e, hotspot, dimension).is_lt());
+        let split = if below < median {
+            hotspot_side(below).min(median)
+        } else {
+            let above = data.len() - below;
+            len - 1 - hotspot_side(above).min(len - 1 - median)
+        };
+
+        let right = data.split_off(split + 1);
+        let element = data.pop().unwrap();
+        let left = Self::make_biased(data, depth + 1, layout, hotspot, share);
+        let right = Self::make_biased(right, depth + 1, layout, hotspot, share);
+
+        let mut node = Self::new(element, dimension);
+        node.equal_left = true;
+        node.left = left.map(Box::new);
+        node.right = right.map(Box::new);
+        Some(node)
+    }
+
+    /// builds a subtree from points with counts using `make`, the points must differ in at least
+    /// one key unless all counts are 1
+    fn make_counted(
+        data: Vec<(T, usize)>,
+        make: impl FnOnce(Vec<T>) -> Option<Self>,
+    ) -> Option<Self> {
+        let (data, counts): (Vec<T>, Vec<usize>) = data.into_iter().unzip();
+        if counts.iter().all(|&count| count == 1) {
+            return make(data);
+        }
+        let mut counts: BTreeMap<Vec<T::Key>, usize> =
+            data.iter().map(Self::keys).zip(counts).collect();
+        let mut node = make(data)?;
+        node.restore_counts(&mut counts);
+        Some(node)
+    }
+
+    /// the keys of `point` in all dimensions
+    fn keys(point: &T) -> Vec<T::Key> {
+        (0..point.dimensions())
+            .map(|dimension| point.kdkey(dimension))
+            .collect()
+    }
+
+    fn restore_counts(&mut self, counts: &mut BTreeMap<Vec<T::Key>, usize>) {
+        self.set_count(counts.remove(&Self::keys(&self.data)).unwrap_or(1));
+        for child in [&mut self.left, &mut self.right].into_iter().flatten() {
+            child.restore_counts(counts);
+        }
+    }
+
+    /// cuts this subtree, covering `bounds`, into `n` pieces along the splits and appends their
+    /// points and regions to `pieces`
+    fn split_into(mut self, n: usize, bounds: Bounds<T::Key>, pieces: &mut Vec<Piece<T>>)
+    where
+        T::Key: Clone,
+    {
+        let left_size = self.left.as_ref().map(|left| left.size()).unwrap_or(0);
+        let right_size = self.right.as_ref().map(|right| right.size()).unwrap_or(0);
+        if n == 1 || left_size + right_size == 0 {
+            pieces.push((self.into_counted(), bounds));
+            return;
+        }
 
-        let mut right = data.split_off(idx);
-        assert!(!right.is_empty());
+        let n_left = match (left_size, right_size) {
+            (0, _) => 0,
+            (_, 0) => n,
+            _ => {
+                let share = left_size as f64 / (left_size + right_size) as f64;
+                ((n as f64 * share).round() as usize).clamp(1, n - 1)
+            }
+        };
+        let dimension = self.dimension();
+        let mut left_bounds = bounds.clone();
+        left_bounds.max[dimension] = Some(self.data.kdkey(dimension));
+        let mut right_bounds = bounds;
+        right_bounds.min[dimension] = Some(self.data.kdkey(dimension));
 
-        let element = right.swap_remove(0);
-        let left = data;
-        let right = right;
+        let first = pieces.len();
+        if let Some(left) = self.left.take() {
+            left.split_into(n_left, left_bounds, pieces);
+        }
+        if let Some(right) = self.right.take() {
+            right.split_into(n - n_left, right_bounds, pieces);
+        }
 
-        let left = Self::make(left, dimension + 1);
-        let right = Self::make(right, dimension + 1);
+        // the split's own points lie on the boundary of the pieces on both sides, and the pieces
+        // on either side cover all of it
+        let (data, count, bucket) = self.into_own();
+        let own = std::iter::once((data, count)).chain(bucket.into_iter().map(|point| (point, 1)));
+        for (point, count) in own {
+            let piece = pieces[first..]
+                .iter_mut()
+                .find(|(_, bounds)| bounds.contains(&point));
+            let Some((points, _)) = piece else {
+                unreachable!("the pieces below a node cover its region");
+            };
+            points.push((point, count));
+        }
+    }
 
-        Some(Self {
-            data: element,
-            left: left.map(Box::new),
-            right: right.map(Box::new),
+    /// the node at the end of `path` (true: left)
+    fn descend(&self, path: &[bool]) -> Option<&Self> {
+        path.iter().try_fold(self, |node, &left| {
+            if left { &node.left } else { &node.right }.as_deref()
         })
     }
 
-    fn insert(&mut self, data: T, dimension: usize) {
-        let selfkey = self.data.kdkey(dimension);
-        let datakey = data.kdkey(dimension);
+    /// a point with `keys` in all dimensions
+    fn find_by_keys(&self, keys: &[T::Key]) -> Option<&T> {
+        let mut path = Vec::new();
+        let index = self.locate(keys, &|_| true, &mut path)?;
+        self.descend(&path)?.points().nth(index)
+    }
+
+    /// index among the points of its node of a point with `keys` in all dimensions that is
+    /// `accept`ed, the turns from this node to that node are appended to `path` (true: left)
+    fn locate(
+        &self,
+        keys: &[T::Key],
+        accept: &impl Fn(&T) -> bool,
+        path: &mut Vec<bool>,
+    ) -> Option<usize> {
+        let equal = |point: &T| {
+            keys.iter()
+                .enumerate()
+                .all(|(dimension, key)| point.kdkey(dimension) == *key)
+        };
+        if let Some(index) = self
+            .points()
+            .position(|point| equal(point) && accept(point))
+        {
+            return Some(index);
+        }
+        // equal keys may sit on either side
+        let ordering = keys[self.dimension()].cmp(&self.data.kdkey(self.dimension()));
+        for (child, is_left) in [(&self.left, true), (&self.right, false)] {
+            let on_side = if is_left {
+                ordering.is_le()
+            } else {
+                ordering.is_ge()
+            };
+            if let Some(child) = child.as_ref().filter(|_| on_side) {
+                path.push(is_left);
+                if let Some(index) = child.locate(keys, accept, path) {
+                    return Some(index);
+                }
+                path.pop();
+            }
+        }
+        None
+    }
+
+    /// counts `data` once more at the node holding a point with the same keys in all dimensions,
+    /// if there is one; `path` holds the turns to it (true: left) in reverse
+    fn count_duplicate(&mut self, data: &T, path: &mut Vec<bool>) -> bool {
+        if (0..data.dimensions())
+            .all(|dimension| compare_element(data, &self.data, dimension).is_eq())
+        {
+            self.extra_mut().count += 1;
+            return true;
+        }
+        // equal keys may sit on either side
+        let ordering = compare_element(data, &self.data, self.dimension());
+        for (child, is_left) in [(&mut self.left, true), (&mut self.right, false)] {
+            let on_side = if is_left {
+                ordering.is_le()
+            } else {
+                ordering.is_ge()
+            };
+            if let Some(child) = child.as_mut().filter(|_| on_side) {
+                if child.count_duplicate(data, path) {
+                    path.push(is_left);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// inserts `data` below this node at `depth`, returns where `data` ends up
+    ///
+    /// if `rebuild_beyond` is given and exceeded, the smallest subtree on the way back up that is
+    /// more than twice as deep as its size requires is rebuilt; until one is found, the size of
+    /// the subtree just left is passed up along with the path
+    fn insert(
+        &mut self,
+        data: T,
+        depth: usize,
+        layout: &Layout,
+        rebuild_beyond: Option<usize>,
+    ) -> Placement {
+        let selfkey = self.data.kdkey(self.dimension());
+        let datakey = data.kdkey(self.dimension());
 
-        let child = if selfkey < datakey {
+        // smaller keys to the left, greater ones to the right, equal keys join the bucket if there
+        // is one or alternate between both sides so that they do not end up in a single chain
+        let go_left = match datakey.cmp(&selfkey) {
+            Ordering::Less => true,
+            Ordering::Equal if !self.bucket().is_empty() => {
+                self.extra_mut().bucket.push(data);
+                return Placement {
+                    path: Vec::new(),
+                    unbalanced: None,
+                    rebuilt: None,
+                };
+            }
+            Ordering::Equal => {
+                let go_left = self.equal_left;
+                self.equal_left = !go_left;
+                go_left
+            }
+            Ordering::Greater => false,
+        };
+        let child = if go_left {
             &mut self.left
         } else {
             &mut self.right
         };
 
-        if let Some(child) = child {
-            child.insert(data, dimension + 1);
+        let mut placement = if let Some(child) = child {
+            child.insert(data, depth + 1, layout, rebuild_beyond)
         } else {
-            *child = Some(Box::new(Self::new(data)));
+            // the tree's dimensions, in case `data` has more
+            let dimension = layout.axis(depth + 1, self.data.dimensions());
+            *child = Some(Box::new(Self::new(data, dimension)));
+            let too_deep = rebuild_beyond.is_some_and(|max_depth| depth + 1 > max_depth);
+            Placement {
+                path: Vec::new(),
+                unbalanced: too_deep.then_some(1),
+                rebuilt: None,
+            }
+        };
+        placement.path.push(go_left);
+        let Some(child_size) = placement.unbalanced else {
+            return placement;
+        };
+
+        if placement.path.len() > 2 * KDTree::<T>::optimal_depth(child_size) {
+            let points = child.take().map(|child| child.into_counted());
+            *child = Self::make_counted(points.unwrap_or_default(), |points| {
+                Self::make(points, depth + 1, layout)
+            })
+            .map(Box::new);
+            placement.unbalanced = None;
+            placement.rebuilt = Some(depth + 1);
+            return placement;
+        }
+        let sibling = if go_left { &self.right } else { &self.left };
+        let sibling_size = sibling.as_ref().map(|sibling| sibling.size()).unwrap_or(0);
+        placement.unbalanced = Some(child_size + sibling_size + 1 + self.bucket().len());
+        placement
+    }
+
+    /// removes all points of the subtree in `slot` at `depth` outside the box spanned by `min`
+    /// and `max`, returns how many
+    ///
+    /// `bounds` holds the keys the splits above allow in every dimension: subtrees entirely outside
+    /// the box are dropped, those entirely inside kept as they are. Only nodes whose own point goes
+    /// while points remain below are rebuilt.
+    fn prune_outside(
+        slot: &mut Option<Box<Self>>,
+        min: &T,
+        max: &T,
+        bounds: &mut [KeyRange<T::Key>],
+        depth: usize,
+        layout: &Layout,
+    ) -> usize {
+        let Some(node) = slot else {
+            return 0;
+        };
+        let outside = bounds.iter().enumerate().any(|(dimension, (lo, hi))| {
+            hi.as_ref().is_some_and(|hi| *hi < min.kdkey(dimension))
+                || lo.as_ref().is_some_and(|lo| *lo > max.kdkey(dimension))
+        });
+        if outside {
+            let removed = node.size();
+            *slot = None;
+            return removed;
+        }
+        let inside = bounds.iter().enumerate().all(|(dimension, (lo, hi))| {
+            lo.as_ref().is_some_and(|lo| *lo >= min.kdkey(dimension))
+                && hi.as_ref().is_some_and(|hi| *hi <= max.kdkey(dimension))
+        });
+        if inside {
+            return 0;
+        }
+
+        // equal keys may sit on both sides, so the split bounds both inclusively
+        let dimension = node.dimension();
+        let hi = bounds[dimension].1.replace(node.data.kdkey(dimension));
+        let mut removed = Self::prune_outside(&mut node.left, min, max, bounds, depth + 1, layout);
+        bounds[dimension].1 = hi;
+        let lo = bounds[dimension].0.replace(node.data.kdkey(dimension));
+        removed += Self::prune_outside(&mut node.right, min, max, bounds, depth + 1, layout);
+        bounds[dimension].0 = lo;
+
+        let mut bucket = node.take_bucket();
+        let before = bucket.len();
+        bucket.retain(|point| is_within_box(point, min, max));
+        removed += before - bucket.len();
+        if is_within_box(&node.data, min, max) {
+            node.set_bucket(bucket);
+            return removed;
+        }
+        removed += node.count();
+        // bucket points share the key of `data` in the split dimension
+        if let Some(point) = bucket.pop() {
+            node.data = point;
+            node.set_count(1);
+            node.set_bucket(bucket);
+            return removed;
+        }
+        let mut points = slot
+            .take()
+            .map(|node| node.into_counted())
+            .unwrap_or_default();
+        // `data` comes first
+        points.swap_remove(0);
+        *slot =
+            Self::make_counted(points, |points| Self::make(points, depth, layout)).map(Box::new);
+        removed
+    }
+
+    /// removes the points of the subtree in `slot` for which `remove` holds, appending them to
+    /// `removed`
+    ///
+    /// as in `prune_outside`, only nodes whose own point goes while points remain below are
+    /// rebuilt; counted nodes are not supported
+    fn remove_where(
+        slot: &mut Option<Box<Self>>,
+        remove: &mut impl FnMut(&T) -> bool,
+        removed: &mut Vec<T>,
+        depth: usize,
+        layout: &Layout,
+    ) {
+        let Some(node) = slot else {
+            return;
+        };
+        Self::remove_where(&mut node.left, remove, removed, depth + 1, layout);
+        Self::remove_where(&mut node.right, remove, removed, depth + 1, layout);
+
+        let (gone, mut kept): (Vec<T>, Vec<T>) = node
+            .take_bucket()
+            .into_iter()
+            .partition(|point| remove(point));
+        removed.extend(gone);
+        if !remove(&node.data) {
+            node.set_bucket(kept);
+            return;
+        }
+        // bucket points share the key of `data` in the split dimension
+        if let Some(point) = kept.pop() {
+            removed.push(std::mem::replace(&mut node.data, point));
+            node.set_bucket(kept);
+            return;
+        }
+        let mut points = slot
+            .take()
+            .map(|node| node.into_points())
+            .unwrap_or_default();
+        // `data` comes first
+        removed.push(points.swap_remove(0));
+        *slot = Self::make(points, depth, layout).map(Box::new);
+    }
+
+    /// moves nodes of the subtree in `slot` below their child where that shortens a run of nodes
+    /// with a single child, bottom up
+    ///
+    /// a node whose only child has a single child as well, on the other side of the child's split
+    /// than the node's own points, becomes that free child: the node's points stay on the same
+    /// side of every split above
+    fn splice(slot: &mut Option<Box<Self>>, report: &mut CompressReport) {
+        let Some(node) = slot else {
+            return;
+        };
+        Self::splice(&mut node.left, report);
+        Self::splice(&mut node.right, report);
+        let Some(child) = node.single_child() else {
+            return;
+        };
+        if child.single_child().is_none() {
+            return;
+        }
+
+        let split = child.data.kdkey(child.dimension());
+        let free_left = child.left.is_none();
+        let fits = node.points().all(|point| {
+            let ordering = point.kdkey(child.dimension()).cmp(&split);
+            ordering.is_eq() || ordering.is_lt() == free_left
+        });
+        if fits {
+            let mut parent = slot.take().unwrap();
+            let mut child = parent.left.take().or(parent.right.take()).unwrap();
+            if free_left {
+                child.left = Some(parent);
+            } else {
+                child.right = Some(parent);
+            }
+            *slot = Some(child);
+            report.spliced += 1;
+        }
+    }
+
+    /// rebuilds the topmost subtrees below `slot` at `depth` that start with a node with a single
+    /// child and are deeper than their points need
+    fn rebuild_runs(
+        slot: &mut Option<Box<Self>>,
+        depth: usize,
+        layout: &Layout,
+        report: &mut CompressReport,
+    ) {
+        let Some(node) = slot else {
+            return;
+        };
+        if node.single_child().is_none() || node.depth() <= KDTree::<T>::optimal_depth(node.size())
+        {
+            Self::rebuild_runs(&mut node.left, depth + 1, layout, report);
+            Self::rebuild_runs(&mut node.right, depth + 1, layout, report);
+            return;
+        }
+        let points = slot.take().map(|node| node.into_counted());
+        *slot = Self::make_counted(points.unwrap_or_default(), |points| {
+            Self::make(points, depth, layout)
+        })
+        .map(Box::new);
+        report.rebuilt += 1;
+    }
+
+    /// the smallest (or largest, if `max`) key in `dimension` in the subtree
+    ///
+    /// below a node splitting on `dimension` only the side towards that end can beat its key
+    fn extreme_key(&self, dimension: usize, max: bool) -> T::Key {
+        let keys = self.points().map(|point| point.kdkey(dimension));
+        let own = if max { keys.max() } else { keys.min() };
+        let children = match (self.dimension() == dimension, max) {
+            (true, false) => [self.left.as_deref(), None],
+            (true, true) => [None, self.right.as_deref()],
+            (false, _) => [self.left.as_deref(), self.right.as_deref()],
+        };
+        children
+            .into_iter()
+            .flatten()
+            .map(|child| child.extreme_key(dimension, max))
+            .chain(own)
+            .reduce(|a, b| if max { a.max(b) } else { a.min(b) })
+            .unwrap()
+    }
+
+    /// the child of a node with exactly one
+    fn single_child(&self) -> Option<&Self> {
+        match (&self.left, &self.right) {
+            (Some(child), None) | (None, Some(child)) => Some(child),
+            _ => None,
+        }
+    }
+
+    /// number of nodes in this subtree with exactly one child
+    fn single_child_nodes(&self) -> usize {
+        let below: usize = [&self.left, &self.right]
+            .into_iter()
+            .flatten()
+            .map(|child| child.single_child_nodes())
+            .sum();
+        below + usize::from(self.single_child().is_some())
+    }
+
+    /// checks every point against the splits of all its ancestors, `ancestors` holds their data,
+    /// split dimension and whether the path continued into their left subtree
+    fn validate<'t>(&'t self, ancestors: &mut Vec<(&'t T, usize, bool)>) -> bool {
+        let key = |data: &T, dimension| data.kdkey(dimension);
+        let in_place = self.points().all(|point| {
+            ancestors.iter().all(|(ancestor, dimension, is_left)| {
+                let ordering = T::Key::cmp(&key(point, *dimension), &key(ancestor, *dimension));
+                ordering.is_eq() || ordering.is_lt() == *is_left
+            })
+        });
+        let bucket_equal = self
+            .bucket()
+            .iter()
+            .all(|point| compare_element(point, &self.data, self.dimension()).is_eq());
+        if !in_place || !bucket_equal {
+            return false;
         }
+
+        [(&self.left, true), (&self.right, false)]
+            .into_iter()
+            .all(|(child, is_left)| match child {
+                Some(child) => {
+                    ancestors.push((&self.data, self.dimension(), is_left));
+                    let valid = child.validate(ancestors);
+                    ancestors.pop();
+                    valid
+                }
+                None => true,
+            })
+    }
+
+    /// appends this subtree's shape to `shape` in pre-order, returns the index of this node
+    fn shape(&self, shape: &mut Vec<(Option<usize>, Option<usize>)>) -> usize {
+        let idx = shape.len();
+        shape.push((None, None));
+        let left = self.left.as_ref().map(|child| child.shape(shape));
+        let right = self.right.as_ref().map(|child| child.shape(shape));
+        shape[idx] = (left, right);
+        idx
+    }
+
+    fn depth(&self) -> usize {
+        let left = self.left.as_ref().map(|child| child.depth()).unwrap_or(0);
+        let right = self.right.as_ref().map(|child| child.depth()).unwrap_or(0);
+        1 + usize::max(left, right)
     }
 }
 
 trait Visitor<'t, T> {
-    fn dimension(&self) -> usize;
-    fn inc_dimension(&mut self);
-    fn dec_dimension(&mut self);
-
     fn visit(&mut self, node: &'t Node<T>);
 
     fn visit_left(&mut self, node: &'t Node<T>) {
         if let Some(child) = &node.left {
-            self.inc_dimension();
             self.visit(child);
-            self.dec_dimension();
         }
     }
     fn visit_right(&mut self, node: &'t Node<T>) {
         if let Some(child) = &node.right {
-            self.inc_dimension();
             self.visit(child);
-            self.dec_dimension();
         }
     }
 }
 
+#[allow(dead_code)]
 trait MutVisitor<'t, T>: Visitor<'t, T> {
     fn visit_mut(&mut self, node: &'t mut Node<T>);
 
     fn visit_left_mut(&mut self, node: &'t mut Node<T>) {
         if let Some(child) = &mut node.left {
-            self.inc_dimension();
             self.visit_mut(child);
-            self.dec_dimension();
         }
     }
     fn visit_right_mut(&mut self, node: &'t mut Node<T>) {
         if let Some(child) = &mut node.right {
-            self.inc_dimension();
             self.visit_mut(child);
-            self.dec_dimension();
         }
     }
 }
 
-#[derive(Debug)]
-pub struct KDTree<T> {
+pub struct KDTree<T: KDPoint> {
     root: Option<Node<T>>,
+    layout: Layout,
+    len: usize,
+    /// an insert went deeper than `max_depth` under `DepthPolicy::Flag`
+    needs_rebalance: bool,
+    /// inserts since auto maintenance last checked the depth
+    inserts_since_check: usize,
+    /// smallest and largest key in every dimension, each found on first use (see `min_key`) and
+    /// forgotten by removals that may have changed it
+    extremes: OnceLock<Extremes<T::Key>>,
+    /// see `version`
+    version: u64,
+}
+
+// the extremes follow from the points, leaving them out spares keys from having to be `Debug`
+impl<T: KDPoint + Debug> Debug for KDTree<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KDTree")
+            .field("root", &self.root)
+            .field("layout", &self.layout)
+            .field("len", &self.len)
+            .field("needs_rebalance", &self.needs_rebalance)
+            .field("inserts_since_check", &self.inserts_since_check)
+            .field("version", &self.version)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<T: KDPoint> KDTree<T> {
     pub fn make(data: Vec<T>) -> Self {
-        Self {
-            root: Node::make(data, 0),
-        }
+        Self::make_with(data, Layout::default())
     }
 
-    /// insert new point, might unbalance the tree
-    pub fn insert(&mut self, data: T) {
-        if let Some(root) = &mut self.root {
-            root.insert(data, 0);
-        } else {
-            self.root = Some(Node::new(data))
+    /// `make`, building the subtrees below the top splits on up to `threads` scoped threads
+    ///
+    /// the same tree as `make` gives; with `threads` at most 1 or only a few thousand points, it
+    /// is built on the calling thread alone
+    pub fn make_threads(data: Vec<T>, threads: usize) -> Self
+    where
+        T: Send,
+    {
+        let mut tree = Self::make(Vec::new());
+        if cfg!(debug_assertions) {
+            check_key_order(&data);
         }
+        tree.len = data.len();
+        tree.root = Node::make_threads(data, 0, &tree.layout, threads);
+        tree
     }
 
-    pub fn find_nearest(&self, search: &T) -> Option<&T> {
-        struct Vizz<'t, 's, T: KDPoint> {
-            dimension: usize,
-            best: Option<&'t T>,
-            distance: Option<T::Distance>,
-            search: &'s T,
-        }
+    /// like `make`, but fails if the points do not all have the same number of dimensions
+    pub fn try_make(data: Vec<T>) -> Result<Self, Error> {
+        check_dimensions(&data)?;
+        Ok(Self::make(data))
+    }
 
-        impl<'t, 's, T: KDPoint> Vizz<'t, 's, T> {
-            fn new(search: &'s T) -> Self {
-                Self {
-                    dimension: 0,
-                    best: None,
-                    distance: None,
-                    search,
-                }
-            }
+    /// like `make`, but the tree splits only on the dimensions in `axis_map`, in that order, while
+    /// distances still take all dimensions into account
+    ///
+    /// queries stay exact: the distance along a single axis never exceeds the full distance, so
+    /// pruning on a subset of axes only gives up some of the pruning, never a result. Worth it when
+    /// the remaining axes hardly tell the points apart.
+    ///
+    /// panics if `axis_map` is empty
+    pub fn make_with_axes(data: Vec<T>, axis_map: Vec<usize>) -> Self {
+        assert!(!axis_map.is_empty(), "axis map must not be empty");
+        Self::make_with(
+            data,
+            Layout {
+                axis_map: Some(axis_map),
+                ..Layout::default()
+            },
+        )
+    }
 
-            fn cmp(&self, lhs: &T, rhs: &T) -> Ordering {
-                T::Key::cmp(&lhs.kdkey(self.dimension), &rhs.kdkey(self.dimension))
-            }
+    /// `make_with_axes`, failing instead of panicking, and if the points do not all have the same
+    /// number of dimensions or have fewer than an axis requires
+    pub fn try_make_with_axes(data: Vec<T>, axis_map: Vec<usize>) -> Result<Self, Error> {
+        if axis_map.is_empty() {
+            return Err(Error::EmptyInput("axis map"));
         }
-
-        impl<'t, 's, T: KDPoint> Visitor<'t, T> for Vizz<'t, 's, T> {
-            fn dimension(&self) -> usize {
-                self.dimension
+        if let Some(dimensions) = check_dimensions(&data)? {
+            if let Some(&axis) = axis_map.iter().find(|&&axis| axis >= dimensions) {
+                return Err(Error::DimensionMismatch {
+                    expected: axis + 1,
+                    got: dimensions,
+                });
             }
+        }
+        Ok(Self::make_with_axes(data, axis_map))
+    }
 
-            fn inc_dimension(&mut self) {
-                self.dimension += 1;
-            }
+    /// like `make`, but wherever at least `min_run` points share the median key in a node's split
+    /// dimension, they are all kept in that node instead of being split further
+    ///
+    /// this keeps trees over axes with few distinct values shallow. Points inserted later with the
+    /// same key join such a bucket as well.
+    ///
+    /// panics if `min_run` is 0
+    pub fn make_with_buckets(data: Vec<T>, min_run: usize) -> Self {
+        assert!(min_run > 0, "buckets need at least one point");
+        Self::make_with(
+            data,
+            Layout {
+                min_bucket: Some(min_run),
+                ..Layout::default()
+            },
+        )
+    }
 
-            fn dec_dimension(&mut self) {
-                self.dimension -= 1;
-            }
+    /// like `make`, but points with equal keys in all dimensions share a single node that counts
+    /// them, only the first of them is kept
+    ///
+    /// queries still see every copy: they return the kept point once per copy, and `len` counts
+    /// all of them. Inserts count exact duplicates as well.
+    pub fn make_with_counts(data: Vec<T>) -> Self {
+        Self::make_with(
+            data,
+            Layout {
+                counts: true,
+                ..Layout::default()
+            },
+        )
+    }
 
-            fn visit(&mut self, node: &'t Node<T>) {
-                let [first, second] = if self.cmp(self.search, &node.data).is_lt() {
-                    [Self::visit_left, Self::visit_right]
-                } else {
-                    [Self::visit_right, Self::visit_left]
-                };
+    /// one tree per shard, made as by `make` from the points `shard_of` assigns to it; shards
+    /// without points get an empty tree
+    ///
+    /// panics if `shards` is 0 or `shard_of` returns a shard not below `shards`
+    pub fn make_sharded(data: Vec<T>, shards: usize, shard_of: impl Fn(&T) -> usize) -> Vec<Self> {
+        assert!(shards > 0, "there must be at least one shard");
+        let mut sharded: Vec<Vec<T>> = (0..shards).map(|_| Vec::new()).collect();
+        for point in data {
+            let shard = shard_of(&point);
+            assert!(shard < shards, "shard {shard} out of {shards}");
+            sharded[shard].push(point);
+        }
+        sharded.into_iter().map(Self::make).collect()
+    }
 
-                // traverse first child
-                first(self, node);
+    /// how far `data`, in its order, is from being sorted by key in `dimension`: the share of pairs
+    /// out of order, 0 if it is sorted (as `from_sorted_by_axis` wants it), 1 if it is sorted in
+    /// reverse; 0 for fewer than two points
+    ///
+    /// pairs with equal keys are in order either way, counting takes `O(n log n)`
+    pub fn axis_presortedness(data: &[T], dimension: usize) -> f64 {
+        let n = data.len();
+        if n < 2 {
+            return 0.0;
+        }
+        let keys: Vec<T::Key> = data.iter().map(|point| point.kdkey(dimension)).collect();
+        let mut order: Vec<usize> = (0..n).collect();
+        let inversions = count_inversions(&keys, &mut order, &mut Vec::with_capacity(n));
+        inversions as f64 / (n * (n - 1) / 2) as f64
+    }
 
-                // check if current is better
-                let curr_dist = T::distance(self.search, &node.data);
-                if self
-                    .distance
-                    .as_ref()
-                    .map(|best_dist| curr_dist < *best_dist)
-                    .unwrap_or(true)
-                {
-                    self.best = Some(&node.data);
-                    self.distance = Some(curr_dist);
-                }
+    /// like `make`, but for `data` already sorted by key in `axis`: the root splits on `axis` at
+    /// the middle point by index, without partitioning
+    ///
+    /// partitioning the root touches every point, the subtrees below it are made as by `make`,
+    /// splitting on the dimensions following `axis`. Equal keys may end up on both sides of the
+    /// root, as they do with `make`. If `data` is not sorted the tree is invalid (see `validate`).
+    ///
+    /// panics if `axis` is not below the number of dimensions of the points
+    pub fn from_sorted_by_axis(mut data: Vec<T>, axis: usize) -> Self {
+        assert!(
+            data.first().is_none_or(|point| axis < point.dimensions()),
+            "axis {axis} beyond the dimensions of the points"
+        );
+        if cfg!(debug_assertions) {
+            check_key_order(&data);
+        }
 
-                // check if need for traversal into second child
-                let target_to_split = T::key_distance(
-                    &self.search.kdkey(self.dimension),
-                    &node.data.kdkey(self.dimension),
-                );
+        let layout = Layout::default();
+        let len = data.len();
+        let root = (len > 0).then(|| {
+            let right = data.split_off(len / 2 + 1);
+            let element = data.pop().unwrap();
+            // continuing the cycle of dimensions as if `axis` was at the root's depth
+            let left = Node::make(data, axis + 1, &layout);
+            let right = Node::make(right, axis + 1, &layout);
+            let mut node = Node::new(element, axis);
+            node.equal_left = true;
+            node.left = left.map(Box::new);
+            node.right = right.map(Box::new);
+            node
+        });
+        Self::from_parts(root, layout, len)
+    }
 
-                // if current best "range" is wrapping over to other side of split, traverse other child
-                if *self.distance.as_ref().unwrap() > target_to_split {
-                    second(self, node);
-                }
+    /// `from_sorted_by_axis`, failing instead of panicking, and if the points do not all have the
+    /// same number of dimensions
+    pub fn try_from_sorted_by_axis(data: Vec<T>, axis: usize) -> Result<Self, Error> {
+        if let Some(dimensions) = check_dimensions(&data)? {
+            if axis >= dimensions {
+                return Err(Error::DimensionMismatch {
+                    expected: axis + 1,
+                    got: dimensions,
+                });
             }
         }
+        Ok(Self::from_sorted_by_axis(data, axis))
+    }
 
-        let mut visitor = Vizz::new(search);
-        if let Some(root) = &self.root {
-            visitor.visit(root);
+    /// a tree of `len` points below `root`, with everything else as it is for a new tree
+    fn from_parts(root: Option<Node<T>>, layout: Layout, len: usize) -> Self {
+        Self {
+            root,
+            layout,
+            len,
+            needs_rebalance: false,
+            inserts_since_check: 0,
+            extremes: OnceLock::new(),
+            version: next_version(),
         }
-        visitor.best
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn make_with(data: Vec<T>, layout: Layout) -> Self {
+        if cfg!(debug_assertions) {
+            check_key_order(&data);
+        }
 
-    // #[test]
-    fn main() {
-        use rand::random;
+        let len = data.len();
+        let root = if layout.counts {
+            let mut counted: BTreeMap<Vec<T::Key>, (T, usize)> = BTreeMap::new();
+            for point in data {
+                counted.entry(Node::keys(&point)).or_insert((point, 0)).1 += 1;
+            }
+            Node::make_counted(counted.into_values().collect(), |points| {
+                Node::make(points, 0, &layout)
+            })
+        } else {
+            Node::make(data, 0, &layout)
+        };
+        Self::from_parts(root, layout, len)
+    }
 
-        let data: Vec<Point3D<F64>> = (0..10)
-            .map(|_| [random::<f64>(), random::<f64>(), random::<f64>()].into())
-            .collect();
+    /// sets what inserts that end up deeper than `max_depth()` do
+    pub fn with_depth_policy(mut self, policy: DepthPolicy) -> Self {
+        self.layout.depth_policy = policy;
+        self
+    }
 
-        println!("{data:#?}");
+    /// replaces the default maximum depth of `4 * log2(len) + 16`
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.layout.max_depth = Some(max_depth);
+        self
+    }
 
-        let tree = KDTree::make(data);
+    /// makes every `interval`-th insert compare `depth()` to `optimal_depth(len())` and rebalance
+    /// the tree if it is more than `max_ratio` times as deep; an `interval` of 0 turns this off
+    ///
+    /// checking walks the whole tree, so `interval` should be large against the cost of that.
+    /// Works alongside the `DepthPolicy`, which only looks at the path of each insert.
+    pub fn set_auto_maintenance(&mut self, interval: usize, max_ratio: f64) {
+        self.layout.maintenance = (interval > 0).then_some(Maintenance {
+            interval,
+            max_ratio,
+        });
+        self.inserts_since_check = 0;
+    }
 
-        println!("{tree:#?}");
+    /// finds the smallest and largest key in every dimension up front instead of on first use,
+    /// see `axis_range`
+    pub fn with_axis_ranges(self) -> Self {
+        let dimensions = self.extremes().len();
+        for dimension in 0..dimensions {
+            self.extreme_key(dimension, false);
+            self.extreme_key(dimension, true);
+        }
+        self
     }
 
-    // #[cfg(öksdf)]
-    mod partition {
-        use super::*;
+    /// `min_key` and `max_key` of `dimension` together
+    pub fn axis_range(&self, dimension: usize) -> Option<(T::Key, T::Key)>
+    where
+        T::Key: Clone,
+    {
+        Some((self.min_key(dimension)?, self.max_key(dimension)?))
+    }
 
-        fn check<T: Debug + Ord>(data: &Vec<T>, claimed_median_idx: usize) {
-            assert!(claimed_median_idx < data.len());
+    /// smallest key in `dimension`, `None` if the tree is empty or its points have fewer
+    /// dimensions
+    ///
+    /// kept up to date by inserts, so that it takes constant time except for the first call and
+    /// the first after removing a point with that key, which search the tree for it
+    pub fn min_key(&self, dimension: usize) -> Option<T::Key>
+    where
+        T::Key: Clone,
+    {
+        self.extreme_key(dimension, false).cloned()
+    }
+
+    /// largest key in `dimension`, like `min_key`
+    pub fn max_key(&self, dimension: usize) -> Option<T::Key>
+    where
+        T::Key: Clone,
+    {
+        self.extreme_key(dimension, true).cloned()
+    }
+
+    fn extremes(&self) -> &Extremes<T::Key> {
+        self.extremes.get_or_init(|| {
+            (0..self.tree_dimensions())
+                .map(|_| Default::default())
+                .collect()
+        })
+    }
+
+    fn extreme_key(&self, dimension: usize, max: bool) -> Option<&T::Key> {
+        let root = self.root.as_ref()?;
+        let [min_key, max_key] = self.extremes().get(dimension)?;
+        let key = if max { max_key } else { min_key };
+        Some(key.get_or_init(|| root.extreme_key(dimension, max)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// number of dimensions of the points in the tree, 0 if it is empty
+    ///
+    /// queries only look at this many keys of the points passed to them, see
+    /// `KDPoint::dimensions`
+    pub fn tree_dimensions(&self) -> usize {
+        self.root
+            .as_ref()
+            .map(|root| root.data.dimensions())
+            .unwrap_or(0)
+    }
+
+    /// depth beyond which inserts trigger the `DepthPolicy`, counting the root as depth 0
+    pub fn max_depth(&self) -> usize {
+        self.layout
+            .max_depth
+            .unwrap_or_else(|| 4 * self.len.max(1).ilog2() as usize + 16)
+    }
+
+    /// whether an insert went deeper than `max_depth()` under `DepthPolicy::Flag` since the tree
+    /// was last rebuilt
+    pub fn needs_rebalance(&self) -> bool {
+        self.needs_rebalance
+    }
+
+    /// rebuilds the tree balanced and clears `needs_rebalance`
+    pub fn rebalance(&mut self) {
+        let points = self.root.take().map(Node::into_counted).unwrap_or_default();
+        self.root = Node::make_counted(points, |points| Node::make(points, 0, &self.layout));
+        self.needs_rebalance = false;
+        self.version = next_version();
+    }
+
+    /// insert new point, might unbalance the tree, see `DepthPolicy`
+    pub fn insert(&mut self, data: T) {
+        self.insert_placed(data);
+    }
+
+    /// inserts `data` unless a point is already within `min_separation` of it (inclusive),
+    /// returns whether it did
+    ///
+    /// the check stops at the first point that close, inserting points this way keeps them a net
+    /// as `dedup_within` leaves it
+    pub fn insert_if_far(&mut self, data: T, min_separation: T::Distance) -> bool {
+        if self.any_within_radius(&data, min_separation) {
+            return false;
+        }
+        self.insert(data);
+        true
+    }
+
+    /// `insert`, returning the path from the root to the point's node (true: left)
+    fn insert_placed(&mut self, data: T) -> Placement {
+        let mut placement = self.place(data);
+        if self.maintenance_due() {
+            self.rebalance();
+            placement.rebuilt = Some(0);
+        }
+        placement
+    }
+
+    /// `insert_placed` without auto maintenance
+    fn place(&mut self, data: T) -> Placement {
+        self.len += 1;
+        self.version = next_version();
+        if self.root.is_none() {
+            self.extremes = OnceLock::new();
+        }
+        if let Some(extremes) = self.extremes.get_mut() {
+            for (dimension, [min, max]) in extremes.iter_mut().enumerate() {
+                let key = data.kdkey(dimension);
+                if min.get().is_some_and(|min| key < *min) {
+                    *min = OnceLock::from(key);
+                } else if max.get().is_some_and(|max| key > *max) {
+                    *max = OnceLock::from(key);
+                }
+            }
+        }
+        let max_depth = self.max_depth();
+        let Some(root) = &mut self.root else {
+            let dimension = self.layout.axis(0, data.dimensions());
+            self.root = Some(Node::new(data, dimension));
+            return Placement {
+                path: Vec::new(),
+                unbalanced: None,
+                rebuilt: None,
+            };
+        };
+        if self.layout.counts {
+            let mut path = Vec::new();
+            if root.count_duplicate(&data, &mut path) {
+                path.reverse();
+                return Placement {
+                    path,
+                    unbalanced: None,
+                    rebuilt: None,
+                };
+            }
+        }
+
+        let mut placement = match self.layout.depth_policy {
+            DepthPolicy::Ignore => root.insert(data, 0, &self.layout, None),
+            DepthPolicy::Flag => {
+                let placement = root.insert(data, 0, &self.layout, None);
+                self.needs_rebalance |= placement.path.len() > max_depth;
+                placement
+            }
+            DepthPolicy::RebuildSubtree => {
+                let mut placement = root.insert(data, 0, &self.layout, Some(max_depth));
+                // no subtree below the root was unbalanced enough
+                if placement.unbalanced.take().is_some() {
+                    self.rebalance();
+                    placement.rebuilt = Some(0);
+                }
+                placement
+            }
+        };
+        placement.path.reverse();
+        placement
+    }
+
+    /// counts an insert for auto maintenance, whether it is time to check and the tree is too
+    /// deep
+    fn maintenance_due(&mut self) -> bool {
+        let Some(Maintenance {
+            interval,
+            max_ratio,
+        }) = self.layout.maintenance
+        else {
+            return false;
+        };
+        self.inserts_since_check += 1;
+        if self.inserts_since_check < interval {
+            return false;
+        }
+        self.inserts_since_check = 0;
+        self.depth() as f64 > max_ratio * Self::optimal_depth(self.len) as f64
+    }
+
+    /// rebuilds the tree so that points near `hotspot` sit higher up: instead of at the median,
+    /// every split is placed `(1 - weight) / 2` of the points past the hotspot, making the
+    /// hotspot's side smaller whenever the hotspot is off-center
+    ///
+    /// this trades balance for speed of queries near the hotspot, queries elsewhere get slower;
+    /// a `weight` of 0 gives a balanced tree again (without buckets)
+    ///
+    /// panics unless `weight` is within `0.0..=1.0`
+    pub fn rebuild_biased(&mut self, hotspot: &T, weight: f64) {
+        if let Err(error) = self.try_rebuild_biased(hotspot, weight) {
+            panic!("{error}");
+        }
+    }
+
+    /// `rebuild_biased`, failing instead of panicking, and if `hotspot` has a different number of
+    /// dimensions than the points; the tree is left as it was on failure
+    pub fn try_rebuild_biased(&mut self, hotspot: &T, weight: f64) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&weight) {
+            return Err(Error::InvalidWeight(weight));
+        }
+        if let Some(root) = &self.root {
+            let expected = root.data.dimensions();
+            if hotspot.dimensions() != expected {
+                return Err(Error::DimensionMismatch {
+                    expected,
+                    got: hotspot.dimensions(),
+                });
+            }
+        }
+        let points = self.root.take().map(Node::into_counted).unwrap_or_default();
+        let share = (1.0 - weight) / 2.0;
+        self.root = Node::make_counted(points, |points| {
+            Node::make_biased(points, 0, &self.layout, hotspot, share)
+        });
+        self.needs_rebalance = false;
+        self.version = next_version();
+        Ok(())
+    }
+
+    /// greedily drops every point within `eps` of a point kept before it, in the order of `iter()`,
+    /// and rebuilds the tree from the rest; returns how many points were dropped
+    ///
+    /// the kept points form an `eps`-net: no two of them are within `eps` of each other and every
+    /// dropped point is within `eps` of a kept one
+    pub fn dedup_within(&mut self, eps: T::Distance) -> usize {
+        let points = self.root.take().map(Node::into_counted).unwrap_or_default();
+
+        let mut kept = KDTree::from_parts(None, self.layout.clone(), 0);
+        let mut dropped = 0;
+        for (point, count) in points {
+            // further copies of a counted point are within `eps` of the one kept
+            dropped += count - usize::from(kept.insert_if_far(point, eps.clone()));
+        }
+
+        let kept = kept.root.map(Node::into_points).unwrap_or_default();
+        self.len = kept.len();
+        self.root = Node::make(kept, 0, &self.layout);
+        self.needs_rebalance = false;
+        self.version = next_version();
+        if dropped > 0 {
+            self.extremes = OnceLock::new();
+        }
+        dropped
+    }
+
+    /// removes the point with the greatest `key`, `None` if the tree is empty
+    ///
+    /// the subtree below the node holding it is rebuilt, which keeps it balanced but costs more
+    /// the closer that node is to the root; trees made with `make_with_counts` are not supported
+    fn remove_max_by_key<K: Ord>(&mut self, key: impl Fn(&T) -> K) -> Option<T> {
+        // path from the root (true: left) and index among the node's points
+        let mut best: Option<(K, Vec<bool>, usize)> = None;
+        let mut stack: Vec<(&Node<T>, Vec<bool>)> =
+            self.root.iter().map(|root| (root, Vec::new())).collect();
+        while let Some((node, path)) = stack.pop() {
+            for (index, point) in node.points().enumerate() {
+                let point_key = key(point);
+                if best
+                    .as_ref()
+                    .map(|(max, ..)| point_key > *max)
+                    .unwrap_or(true)
+                {
+                    best = Some((point_key, path.clone(), index));
+                }
+            }
+            for (child, is_left) in [(&node.left, true), (&node.right, false)] {
+                if let Some(child) = child {
+                    let mut child_path = path.clone();
+                    child_path.push(is_left);
+                    stack.push((child, child_path));
+                }
+            }
+        }
+        let (_, path, index) = best?;
+        self.remove_at(&path, index)
+    }
+
+    /// removes the point at `index` among the points of the node at the end of `path` (true:
+    /// left), rebuilding the subtree below that node
+    fn remove_at(&mut self, path: &[bool], index: usize) -> Option<T> {
+        let removed = self.take_at(path, index)?;
+        // other points may share the key, but finding out would take a search as well
+        if let Some(extremes) = self.extremes.get_mut() {
+            for (dimension, ends) in extremes.iter_mut().enumerate() {
+                let key = removed.kdkey(dimension);
+                for end in ends {
+                    if end.get() == Some(&key) {
+                        end.take();
+                    }
+                }
+            }
+        }
+        Some(removed)
+    }
+
+    fn take_at(&mut self, path: &[bool], index: usize) -> Option<T> {
+        self.len -= 1;
+        self.version = next_version();
+
+        let Some((&is_left, path)) = path.split_last() else {
+            let mut points = self.root.take()?.into_points();
+            let removed = points.swap_remove(index);
+            self.root = Node::make(points, 0, &self.layout);
+            return Some(removed);
+        };
+        let mut node = self.root.as_mut()?;
+        for &left in path {
+            node = if left {
+                &mut node.left
+            } else {
+                &mut node.right
+            }
+            .as_mut()?;
+        }
+        let child = if is_left {
+            &mut node.left
+        } else {
+            &mut node.right
+        };
+        let mut points = child.take()?.into_points();
+        let removed = points.swap_remove(index);
+        *child = Node::make(points, path.len() + 1, &self.layout).map(Box::new);
+        Some(removed)
+    }
+
+    /// all points, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut stack: Vec<&Node<T>> = self.root.iter().collect();
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.left.as_deref());
+            stack.extend(node.right.as_deref());
+            Some(node)
+        })
+        .flat_map(Node::points)
+    }
+
+    /// the topology of the tree without any points: for every node in pre-order, the indices of
+    /// its left and right child in the returned list; trees of the same shape give equal
+    /// signatures
+    pub fn shape_signature(&self) -> Vec<(Option<usize>, Option<usize>)> {
+        let mut shape = Vec::new();
+        if let Some(root) = &self.root {
+            root.shape(&mut shape);
+        }
+        shape
+    }
+
+    /// points of all nodes `depth` steps below the root, the root is at depth 0
+    pub fn points_at_depth(&self, depth: usize) -> Vec<&T> {
+        let mut level: Vec<&Node<T>> = self.root.iter().collect();
+        for _ in 0..depth {
+            level = level
+                .into_iter()
+                .flat_map(|node| [node.left.as_deref(), node.right.as_deref()])
+                .flatten()
+                .collect();
+        }
+        level.into_iter().flat_map(Node::points).collect()
+    }
+
+    /// split dimension and key of every node in breadth-first order, each level from left to
+    /// right, `None` for nodes without children
+    ///
+    /// with every node's children following those of the nodes before it on its level, this is
+    /// enough to traverse a flat copy of the tree
+    pub fn split_planes(&self) -> Vec<Option<(usize, T::Key)>> {
+        let mut planes = Vec::new();
+        let mut level: Vec<&Node<T>> = self.root.iter().collect();
+        while !level.is_empty() {
+            planes.extend(level.iter().map(|node| {
+                (node.left.is_some() || node.right.is_some())
+                    .then(|| (node.dimension(), node.data.kdkey(node.dimension())))
+            }));
+            level = level
+                .into_iter()
+                .flat_map(|node| [node.left.as_deref(), node.right.as_deref()])
+                .flatten()
+                .collect();
+        }
+        planes
+    }
+
+    /// checks the structure of the tree: below every node, all points in the left subtree have a
+    /// smaller or equal key in the node's split dimension, all points in the right subtree an equal
+    /// or greater one
+    pub fn validate(&self) -> bool {
+        self.root
+            .as_ref()
+            .map(|root| root.validate(&mut Vec::new()))
+            .unwrap_or(true)
+    }
+
+    /// number of levels, i.e. nodes on the longest path from the root to a leaf
+    pub fn depth(&self) -> usize {
+        self.root.as_ref().map(Node::depth).unwrap_or(0)
+    }
+
+    /// smallest depth any binary tree holding `size` points can have, `ceil(log2(size + 1))`
+    pub fn optimal_depth(size: usize) -> usize {
+        (usize::BITS - size.leading_zeros()) as usize
+    }
+
+    /// the point closest to `search`, `None` if the tree is empty
+    ///
+    /// closeness is measured from `search`, i.e. by `distance(search, point)`, which matters for
+    /// asymmetric distances
+    ///
+    /// if several points are equally close, any one of them is returned
+    ///
+    /// for the point types of this crate, points with NaN coordinates are at NaN distance from
+    /// everything, which `F64` orders after all numbers: they are only returned if no other point
+    /// is left
+    pub fn find_nearest(&self, search: &T) -> Option<&T> {
+        self.find_nearest_accepting(search, |_| true)
+    }
+
+    /// `find_nearest`, handing out only `f` applied to the result
+    pub fn find_nearest_map<R>(&self, search: &T, f: impl Fn(&T) -> R) -> Option<R> {
+        self.find_nearest(search).map(f)
+    }
+
+    /// lazily answers `find_nearest` for each of `queries`, in order
+    pub fn query_stream<'t>(
+        &'t self,
+        queries: impl Iterator<Item = T> + 't,
+    ) -> impl Iterator<Item = Option<&'t T>> + 't {
+        queries.map(move |search| self.find_nearest(&search))
+    }
+
+    /// like `find_nearest`, but ignores points inside the (inclusive) box spanned by
+    /// `forbidden_min` and `forbidden_max`
+    pub fn find_nearest_avoiding(
+        &self,
+        search: &T,
+        forbidden_min: &T,
+        forbidden_max: &T,
+    ) -> Option<&T> {
+        self.find_nearest_accepting(search, |candidate| {
+            !is_within_box(candidate, forbidden_min, forbidden_max)
+        })
+    }
+
+    /// the point closest to `search` with its distance, if that is at most `cutoff`
+    ///
+    /// the search starts out with `cutoff` as the distance to beat, so unlike filtering the
+    /// result of `find_nearest` it never enters subtrees farther away than that
+    pub fn find_nearest_within(
+        &self,
+        search: &T,
+        cutoff: T::Distance,
+    ) -> Option<(&T, T::Distance)> {
+        let mut visitor = Nearest::new(search, |_| true).with_cutoff(cutoff);
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        visitor.best.zip(visitor.distance)
+    }
+
+    /// all points as close to `search` as the nearest one, in no particular order; empty if the
+    /// tree is
+    pub fn find_all_nearest(&self, search: &T) -> Vec<&T> {
+        match self.find_nearest(search) {
+            Some(nearest) => self.find_within_radius(search, T::distance(search, nearest)),
+            None => Vec::new(),
+        }
+    }
+
+    /// `find_all_nearest`, ordered by `secondary`; points with equal secondary keys stay in the
+    /// order `find_all_nearest` returns them in
+    pub fn find_all_nearest_by<K: Ord>(&self, search: &T, secondary: impl Fn(&T) -> K) -> Vec<&T> {
+        let mut nearest = self.find_all_nearest(search);
+        nearest.sort_by_key(|point| secondary(point));
+        nearest
+    }
+
+    /// like `find_nearest`, but only considers points whose key in `dimension` lies within
+    /// `lo..=hi`; subtrees entirely outside that slab are skipped
+    pub fn find_nearest_in_slab(
+        &self,
+        search: &T,
+        dimension: usize,
+        lo: T::Key,
+        hi: T::Key,
+    ) -> Option<&T> {
+        let slab = Slab { dimension, lo, hi };
+        let accept = |candidate: &T| slab.contains(&candidate.kdkey(dimension));
+        let mut visitor = Nearest::with_hook(search, accept, &slab);
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        visitor.best
+    }
+
+    /// the (up to) `k` points closest to `search`, ordered by ascending distance
+    ///
+    /// equally distant points, including those tying for the last place, go in the order the
+    /// search comes across them, so an unchanged tree gives the same result every time
+    pub fn find_k_nearest(&self, search: &T, k: usize) -> Vec<&T> {
+        self.find_k_nearest_with_distances(search, k)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect()
+    }
+
+    /// like `find_k_nearest`, but also returns the distance of each point to `search`
+    pub fn find_k_nearest_with_distances(&self, search: &T, k: usize) -> Vec<(&T, T::Distance)> {
+        self.find_k_nearest_accepting(search, k, |_| true)
+    }
+
+    /// inserts `point` and returns its `k` nearest other points, nearest first
+    ///
+    /// like `insert` followed by `find_k_nearest_excluding` on the inserted point, without looking
+    /// for it. Only when the insert rebuilt the subtree holding it (see `DepthPolicy`), another
+    /// point with the same keys in all dimensions may be left out in its place. Like every insert,
+    /// this may unbalance the tree when used repeatedly.
+    pub fn insert_and_knn(&mut self, point: T, k: usize) -> Vec<&T> {
+        let keys = Node::keys(&point);
+        let placement = self.insert_placed(point);
+        let Some(root) = &self.root else {
+            unreachable!("the tree holds at least the inserted point");
+        };
+        let inserted = match placement.rebuilt {
+            // a new node has no bucket yet, an existing one only takes points into its bucket or
+            // counts them
+            None => root
+                .descend(&placement.path)
+                .map(|node| node.bucket().last().unwrap_or(&node.data)),
+            Some(_) => root.find_by_keys(&keys),
+        };
+        let Some(inserted) = inserted else {
+            unreachable!("the inserted point is in the tree");
+        };
+
+        self.find_k_nearest_skipping(inserted, k, inserted)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect()
+    }
+
+    /// like `find_k_nearest`, but never returns `search` itself, i.e. when `search` refers to a
+    /// point of this tree, its `k` nearest *other* points are returned
+    ///
+    /// of a point counted several times (see `make_with_counts`) only one copy is left out
+    pub fn find_k_nearest_excluding(&self, search: &T, k: usize) -> Vec<&T> {
+        self.find_k_nearest_skipping(search, k, search)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect()
+    }
+
+    /// `find_k_nearest_with_distances` without one copy of `skipped`, which refers to a point of
+    /// this tree
+    fn find_k_nearest_skipping(&self, search: &T, k: usize, skipped: &T) -> Vec<(&T, T::Distance)> {
+        // counted duplicates all refer to the same point, only one copy is left out
+        let done = Cell::new(false);
+        self.find_k_nearest_accepting(search, k, |candidate| {
+            let skip = !done.get() && std::ptr::eq(candidate, skipped);
+            done.set(done.get() || skip);
+            !skip
+        })
+    }
+
+    /// for every point (in the order of `iter()`), the distance to its `k`-th nearest other point,
+    /// or to the farthest one if the tree holds no more than `k` points; empty if `k` is 0 or there
+    /// are less than two points
+    ///
+    /// see `IndexedKDTree::knn_distances` for these distances by the index of their point
+    fn k_distances(&self, k: usize) -> Vec<T::Distance> {
+        self.iter()
+            .filter_map(|point| {
+                self.find_k_nearest_skipping(point, k, point)
+                    .pop()
+                    .map(|(_, distance)| distance)
+            })
+            .collect()
+    }
+
+    /// the distance of every point to its `k`-th nearest other point (see
+    /// `IndexedKDTree::knn_distances`) sorted ascending, the k-distance plot used to pick `eps`
+    /// for DBSCAN: a good choice is where the plot bends upwards
+    pub fn k_distance_plot(&self, k: usize) -> Vec<T::Distance> {
+        let mut distances = self.k_distances(k);
+        distances.sort();
+        distances
+    }
+
+    /// `k_distance_plot` with the square root of every distance, i.e. euclidean distances for the
+    /// point types of this crate
+    pub fn k_distance_plot_sqrt(&self, k: usize) -> Vec<f64>
+    where
+        T::Distance: Into<f64>,
+    {
+        self.k_distance_plot(k)
+            .into_iter()
+            .map(|distance| distance.into().sqrt())
+            .collect()
+    }
+
+    /// counts of the nearest neighbour distances (see `k_distance_plot`) in `bins` bins of equal
+    /// width between the smallest and largest of them, all in the first bin if those are equal
+    ///
+    /// the distances are taken as they are, i.e. squared for the point types of this crate
+    pub fn nn_distance_histogram(&self, bins: usize) -> Vec<usize>
+    where
+        T::Distance: Into<f64>,
+    {
+        let mut histogram = vec![0; bins];
+        if bins == 0 {
+            return histogram;
+        }
+        let distances: Vec<f64> = self.k_distances(1).into_iter().map(Into::into).collect();
+        let min = distances.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = distances.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        let width = (max - min) / bins as f64;
+        for distance in distances {
+            let bin = if width > 0.0 {
+                ((distance - min) / width) as usize
+            } else {
+                0
+            };
+            histogram[bin.min(bins - 1)] += 1;
+        }
+        histogram
+    }
+
+    /// the point with the smallest sum of distances to all points, the first of them in the order
+    /// of `iter()` on ties, `None` if the tree is empty
+    ///
+    /// compares every pair of points, so this takes quadratic time; the distances are summed as
+    /// they are, i.e. squared for the point types of this crate
+    pub fn medoid(&self) -> Option<&T>
+    where
+        T::Distance: Into<f64>,
+    {
+        let total = |point: &T| -> f64 {
+            self.iter()
+                .map(|other| T::distance(point, other).into())
+                .sum()
+        };
+        self.iter()
+            .map(|point| (point, total(point)))
+            .fold(None, |best: Option<(&T, f64)>, (point, sum)| match best {
+                Some((_, best_sum)) if best_sum <= sum => best,
+                _ => Some((point, sum)),
+            })
+            .map(|(point, _)| point)
+    }
+
+    fn find_k_nearest_accepting(
+        &self,
+        search: &T,
+        k: usize,
+        accept: impl Fn(&T) -> bool,
+    ) -> Vec<(&T, T::Distance)> {
+        let mut visitor = KNearest::new(search, k, self.len(), accept);
+        if let (Some(root), true) = (&self.root, k > 0) {
+            visitor.visit(root);
+        }
+        visitor
+            .heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|candidate| (candidate.data, candidate.distance))
+            .collect()
+    }
+
+    /// approximate `find_k_nearest`, stops searching once at least `target_recall` (between 0 and
+    /// 1) of the `k` points found are guaranteed to belong to the exact result
+    ///
+    /// subtrees are searched best first, in order of the lower bound on their distance from
+    /// `search` given by the split planes in between. A point found is guaranteed to be among the
+    /// exact result as soon as its distance does not exceed the bound of any subtree not searched
+    /// yet. Counting those points underestimates the recall, so on average the recall achieved
+    /// is at least `target_recall`, often considerably more. A target of 1 gives the exact result.
+    pub fn find_k_nearest_recall(&self, search: &T, k: usize, target_recall: f64) -> Vec<&T> {
+        let required = (target_recall.clamp(0.0, 1.0) * k as f64).ceil() as usize;
+        let mut found: BinaryHeap<Candidate<T>> = BinaryHeap::with_capacity(k.min(self.len()));
+        let mut regions = BinaryHeap::new();
+        if let (Some(root), true) = (&self.root, k > 0) {
+            regions.push(Reverse(Region {
+                bound: None,
+                node: root,
+            }));
+        }
+
+        let mut considered = 0;
+        while let Some(Reverse(Region { bound, node })) = regions.pop() {
+            for point in node.points() {
+                let candidate = Candidate {
+                    distance: T::distance(search, point),
+                    found: considered,
+                    data: point,
+                };
+                considered += 1;
+                if found.len() < k {
+                    found.push(candidate);
+                } else if found
+                    .peek()
+                    .map(|worst| candidate < *worst)
+                    .unwrap_or(false)
+                {
+                    found.pop();
+                    found.push(candidate);
+                }
+            }
+
+            let go_left = compare_element(search, &node.data, node.dimension()).is_lt();
+            let (near, far) = if go_left {
+                (&node.left, &node.right)
+            } else {
+                (&node.right, &node.left)
+            };
+            let to_split = T::key_distance(
+                &search.kdkey(node.dimension()),
+                &node.data.kdkey(node.dimension()),
+            );
+            if let Some(near) = near {
+                regions.push(Reverse(Region {
+                    bound: bound.clone(),
+                    node: near,
+                }));
+            }
+            if let Some(far) = far {
+                regions.push(Reverse(Region {
+                    bound: Ord::max(bound, Some(to_split)),
+                    node: far,
+                }));
+            }
+
+            // nothing left to search is closer than `next`
+            let next = match regions.peek() {
+                Some(Reverse(region)) => &region.bound,
+                None => break,
+            };
+            let certain = found
+                .iter()
+                .filter(|candidate| Some(&candidate.distance) <= next.as_ref())
+                .count();
+            if found.len() >= k && certain >= required {
+                break;
+            }
+        }
+
+        found
+            .into_sorted_vec()
+            .into_iter()
+            .map(|candidate| candidate.data)
+            .collect()
+    }
+
+    /// all points with `T::distance(search, point) <= radius`, in no particular order
+    ///
+    /// `radius` lives in the same space as `T::Distance`, i.e. it is a squared distance for the
+    /// point types of this crate
+    pub fn find_within_radius(&self, search: &T, radius: T::Distance) -> Vec<&T> {
+        let mut found = Vec::new();
+        self.visit_within_radius(search, radius, |point| {
+            found.push(point);
+            true
+        });
+        found
+    }
+
+    /// calls `found` with every point within `radius` of `search` until it returns `false`
+    fn visit_within_radius<'t>(
+        &'t self,
+        search: &T,
+        radius: T::Distance,
+        found: impl FnMut(&'t T) -> bool,
+    ) {
+        let mut visitor = WithinRadius {
+            radius,
+            search,
+            found,
+            stopped: false,
+        };
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+    }
+
+    /// number of points with `T::distance(search, point) <= radius`
+    pub fn count_within_radius(&self, search: &T, radius: T::Distance) -> usize {
+        let mut count = 0;
+        self.visit_within_radius(search, radius, |_| {
+            count += 1;
+            true
+        });
+        count
+    }
+
+    /// the point with the greatest `T::distance(center, point)` that is still at most `radius`,
+    /// `None` if there is no point within `radius`
+    pub fn farthest_within_radius(&self, center: &T, radius: T::Distance) -> Option<&T> {
+        self.find_within_radius(center, radius)
+            .into_iter()
+            .max_by_key(|point| T::distance(center, point))
+    }
+
+    /// whether any point has `T::distance(search, point) <= radius`, stops at the first one found
+    ///
+    /// checks every node before descending, so a hit near the root ends the search right away
+    pub fn any_within_radius(&self, search: &T, radius: T::Distance) -> bool {
+        let mut visitor = AnyWithinRadius {
+            radius,
+            search,
+            found: false,
+        };
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        visitor.found
+    }
+
+    /// all points without a point of `other` within `eps`, i.e. what `other` lacks, in no
+    /// particular order
+    pub fn difference_within(&self, other: &KDTree<T>, eps: T::Distance) -> Vec<&T> {
+        self.iter()
+            .filter(|point| !other.any_within_radius(point, eps.clone()))
+            .collect()
+    }
+
+    /// `difference_within` in both directions: the points of `self` missing in `other` and the
+    /// points of `other` missing in `self`
+    pub fn symmetric_difference_within<'t>(
+        &'t self,
+        other: &'t KDTree<T>,
+        eps: T::Distance,
+    ) -> (Vec<&'t T>, Vec<&'t T>) {
+        (
+            self.difference_within(other, eps.clone()),
+            other.difference_within(self, eps),
+        )
+    }
+
+    /// cuts the tree along its split planes into `n` trees of about `len() / n` points each (fewer
+    /// if there are not enough points), each with the region of key space it covers
+    ///
+    /// every point ends up in exactly one piece. The regions only overlap on their boundaries, as
+    /// points with the key of a split may lie on either side. Each piece is rebuilt balanced.
+    pub fn split_into(self, n: usize) -> Vec<(KDTree<T>, Bounds<T::Key>)>
+    where
+        T::Key: Clone,
+    {
+        let Some(root) = self.root else {
+            return Vec::new();
+        };
+        let dimensions = root.data.dimensions();
+        let unbounded = Bounds {
+            min: vec![None; dimensions],
+            max: vec![None; dimensions],
+        };
+        let mut pieces = Vec::new();
+        root.split_into(n.max(1), unbounded, &mut pieces);
+
+        pieces
+            .into_iter()
+            .map(|(points, bounds)| {
+                let layout = self.layout.clone();
+                let len = points.iter().map(|(_, count)| count).sum();
+                let root = Node::make_counted(points, |points| Node::make(points, 0, &layout));
+                (Self::from_parts(root, layout, len), bounds)
+            })
+            .collect()
+    }
+
+    /// removes all points outside the box spanned by `min` and `max` (inclusive), returns how many
+    ///
+    /// subtrees entirely outside the box are dropped and those entirely inside kept without
+    /// looking at their points; only subtrees below a removed split point are rebuilt
+    pub fn prune_outside(&mut self, min: &T, max: &T) -> usize {
+        let mut root = self.root.take().map(Box::new);
+        let dimensions = root
+            .as_ref()
+            .map(|root| root.data.dimensions())
+            .unwrap_or(0);
+        let mut bounds: Vec<_> = (0..dimensions).map(|_| (None, None)).collect();
+        let removed = Node::prune_outside(&mut root, min, max, &mut bounds, 0, &self.layout);
+        self.root = root.map(|root| *root);
+        self.len -= removed;
+        if removed > 0 {
+            self.extremes = OnceLock::new();
+            self.version = next_version();
+        }
+        removed
+    }
+
+    /// removes all points for which `remove` holds in one pass, returns them in no particular
+    /// order
+    ///
+    /// with `rebuild`, the whole tree is rebuilt once, otherwise only the subtrees below removed
+    /// split points as by `prune_outside`; trees made with `make_with_counts` are not supported
+    fn remove_where(&mut self, mut remove: impl FnMut(&T) -> bool, rebuild: bool) -> Vec<T> {
+        let mut removed = Vec::new();
+        if rebuild {
+            let points = self.root.take().map(Node::into_points).unwrap_or_default();
+            let kept;
+            (removed, kept) = points.into_iter().partition(|point| remove(point));
+            self.root = Node::make(kept, 0, &self.layout);
+            self.needs_rebalance = false;
+        } else {
+            let mut root = self.root.take().map(Box::new);
+            Node::remove_where(&mut root, &mut remove, &mut removed, 0, &self.layout);
+            self.root = root.map(|root| *root);
+        }
+        self.len -= removed.len();
+        if !removed.is_empty() {
+            self.extremes = OnceLock::new();
+        }
+        if rebuild || !removed.is_empty() {
+            self.version = next_version();
+        }
+        removed
+    }
+
+    /// shortens runs of nodes with a single child, as left behind by removing many points
+    ///
+    /// lighter than `rebalance`: nodes are first moved below their child wherever that keeps the
+    /// order of the tree, then the topmost subtrees starting with a node with a single child that
+    /// are still deeper than their points need are rebuilt
+    pub fn compress(&mut self) -> CompressReport {
+        let single_child_nodes =
+            |root: &Option<Node<T>>| root.as_ref().map(Node::single_child_nodes).unwrap_or(0);
+        let before = single_child_nodes(&self.root);
+        let mut report = CompressReport::default();
+        let mut root = self.root.take().map(Box::new);
+        Node::splice(&mut root, &mut report);
+        Node::rebuild_runs(&mut root, 0, &self.layout, &mut report);
+        self.root = root.map(|root| *root);
+        self.version = next_version();
+        report.eliminated = before.saturating_sub(single_child_nodes(&self.root));
+        report
+    }
+
+    /// folds `f` over all points inside the box spanned by `min` and `max` (inclusive), without
+    /// collecting them first
+    ///
+    /// points are visited in no particular order, so `f` should not depend on it. To combine the
+    /// values of many points, `SummarizedKDTree::summary_in_range` takes whole subtrees at once.
+    pub fn fold_in_range<A>(&self, min: &T, max: &T, init: A, f: impl FnMut(A, &T) -> A) -> A {
+        let mut visitor = InRange {
+            min,
+            max,
+            acc: Some(init),
+            f,
+        };
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        visitor.acc.unwrap()
+    }
+
+    /// all points whose key in each dimension `d` lies within `bounds[d]`, in no particular order
+    ///
+    /// dimensions past the end of `bounds` are not constrained, and the bounds of each dimension
+    /// may be inclusive, exclusive or missing, e.g. `(Bound::Excluded(lo), Bound::Unbounded)`
+    ///
+    /// panics if there are more bounds than the tree has dimensions
+    pub fn within_box<R: RangeBounds<T::Key>>(&self, bounds: &[R]) -> Vec<&T> {
+        let Some(root) = &self.root else {
+            return Vec::new();
+        };
+        assert!(
+            bounds.len() <= root.data.dimensions(),
+            "{} bounds for points of {} dimensions",
+            bounds.len(),
+            root.data.dimensions()
+        );
+        let mut visitor = InBox {
+            bounds,
+            found: Vec::new(),
+        };
+        visitor.visit(root);
+        visitor.found
+    }
+
+    /// like `find_nearest`, but also returns the root points of all subtrees the search skipped
+    pub fn find_nearest_with_pruned(&self, search: &T) -> (Option<&T>, Vec<&T>) {
+        let mut visitor = Nearest::with_hook(search, |_: &T| true, Vec::new());
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        (visitor.best, visitor.hook)
+    }
+
+    /// like `find_nearest`, but also returns, for every subtree the search skipped, the distance to
+    /// the split that justified it and the best distance at that point
+    ///
+    /// the split distance is never smaller than the best distance, the gap between them tells how
+    /// close the search came to having to descend
+    pub fn find_nearest_with_prune_slack(
+        &self,
+        search: &T,
+    ) -> (Option<&T>, Vec<PruneSlack<T::Distance>>) {
+        let mut visitor = Nearest::with_hook(search, |_: &T| true, Vec::new());
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        (visitor.best, visitor.hook)
+    }
+
+    /// like `find_nearest`, but stops searching as soon as the best point so far is
+    /// `good_enough`, given its distance, and returns that point
+    ///
+    /// the result is not necessarily the nearest point then, only the first best point so far
+    /// that was good enough; without any, it is the nearest point
+    pub fn find_nearest_until(
+        &self,
+        search: &T,
+        good_enough: impl Fn(&T, T::Distance) -> bool,
+    ) -> Option<&T> {
+        let until = Until {
+            good_enough,
+            found: None,
+        };
+        let mut visitor = Nearest::with_hook(search, |_: &T| true, until);
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        visitor.hook.found.or(visitor.best)
+    }
+
+    /// the point closest to any of `queries`, with the index of that query and the distance
+    ///
+    /// a single search for all queries at once: every query is at least as far from a subtree as
+    /// from any split above it on the other side, and the subtree is skipped once the best point
+    /// is no farther than the nearest query can get to it. A point
+    /// equally close to several queries is reported with the first of them.
+    pub fn find_nearest_to_any(&self, queries: &[T]) -> Option<(&T, usize, T::Distance)> {
+        if queries.is_empty() {
+            return None;
+        }
+        let mut visitor = NearestToAny {
+            queries,
+            best: None,
+        };
+        if let Some(root) = &self.root {
+            visitor.visit(root, &vec![None; queries.len()]);
+        }
+        visitor.best
+    }
+
+    fn find_nearest_accepting(&self, search: &T, accept: impl Fn(&T) -> bool) -> Option<&T> {
+        let mut visitor = Nearest::new(search, accept);
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        visitor.best
+    }
+}
+
+/// queries handing out copies of the points, which outlive the tree they came from
+impl<T: KDPoint + Clone> KDTree<T> {
+    /// `find_within_radius`, cloning the points as they are found
+    pub fn find_within_radius_cloned(&self, search: &T, radius: T::Distance) -> Vec<T> {
+        let mut found = Vec::new();
+        self.visit_within_radius(search, radius, |point| {
+            found.push(point.clone());
+            true
+        });
+        found
+    }
+
+    /// all points inside the box spanned by `min` and `max` (inclusive), in no particular order
+    pub fn find_in_range_cloned(&self, min: &T, max: &T) -> Vec<T> {
+        self.fold_in_range(min, max, Vec::new(), |mut found, point| {
+            found.push(point.clone());
+            found
+        })
+    }
+
+    /// `find_k_nearest_with_distances`, cloning the points once the search is done
+    pub fn find_k_nearest_cloned(&self, search: &T, k: usize) -> Vec<(T, T::Distance)> {
+        let mut visitor = KNearest::new(search, k, self.len(), |_: &T| true);
+        if let (Some(root), true) = (&self.root, k > 0) {
+            visitor.visit(root);
+        }
+        visitor
+            .heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|candidate| (candidate.data.clone(), candidate.distance))
+            .collect()
+    }
+}
+
+impl<T: SquaredEuclidean> KDTree<T> {
+    /// `find_within_radius` with `radius` in actual distance units rather than squared, i.e. all
+    /// points at most `radius` away, including those exactly at `radius`; none if `radius` is
+    /// negative or NaN
+    ///
+    /// passing a plain radius to `find_within_radius` silently searches a smaller ball for radii
+    /// above 1 (and a larger one below):
+    ///
+    /// ```
+    /// use kdtree::{KDTree, Point2D};
+    ///
+    /// let tree = KDTree::make(vec![Point2D::from((0.0, 0.0)), Point2D::from((1.5, 0.0))]);
+    /// let search = Point2D::from((0.0, 0.0));
+    /// // 2.0 taken as squared: only points within 1.41 of `search`
+    /// assert_eq!(tree.find_within_radius(&search, 2.0.into()).len(), 1);
+    /// assert_eq!(tree.within_distance(&search, 2.0).len(), 2);
+    /// ```
+    pub fn within_distance(&self, search: &T, radius: f64) -> Vec<&T> {
+        match squared(radius) {
+            Some(radius) => self.find_within_radius(search, radius),
+            None => Vec::new(),
+        }
+    }
+
+    /// the `k` nearest points at most `radius` (in actual distance units) away, nearest first;
+    /// none if `radius` is negative or NaN
+    pub fn k_nearest_within_distance(&self, search: &T, k: usize, radius: f64) -> Vec<&T> {
+        let Some(radius) = squared(radius) else {
+            return Vec::new();
+        };
+        self.find_k_nearest_with_distances(search, k)
+            .into_iter()
+            .take_while(|(_, distance)| *distance <= radius)
+            .map(|(point, _)| point)
+            .collect()
+    }
+}
+
+/// `radius` squared, `None` unless it is at least 0
+fn squared(radius: f64) -> Option<F64> {
+    (radius >= 0.0).then(|| F64::from(radius * radius))
+}
+
+impl<T: Coordinates> KDTree<T> {
+    /// center and (euclidean) radius of a sphere containing all points, `None` if the tree is
+    /// empty
+    ///
+    /// this is a loose bound, not the minimal enclosing sphere: the center is the center of the
+    /// axis aligned bounding box, the radius the largest distance from there to any point
+    pub fn bounding_sphere(&self) -> Option<(Vec<f64>, f64)> {
+        let (min, max) = self.bounding_box()?;
+        let center: Vec<f64> = min
+            .iter()
+            .zip(&max)
+            .map(|(lo, hi)| (lo + hi) / 2.0)
+            .collect();
+        let radius = self
+            .iter()
+            .map(|point| {
+                center
+                    .iter()
+                    .enumerate()
+                    .map(|(dimension, c)| (point.coordinate(dimension) - c).powi(2))
+                    .sum::<f64>()
+                    .sqrt()
+            })
+            .fold(0.0, f64::max);
+        Some((center, radius))
+    }
+
+    /// smallest and largest coordinate in every dimension, `None` if the tree is empty
+    fn bounding_box(&self) -> Option<(Vec<f64>, Vec<f64>)> {
+        let dimensions = self.root.as_ref()?.data.dimensions();
+        let mut min = vec![f64::INFINITY; dimensions];
+        let mut max = vec![f64::NEG_INFINITY; dimensions];
+        for point in self.iter() {
+            for dimension in 0..dimensions {
+                let coordinate = point.coordinate(dimension);
+                min[dimension] = min[dimension].min(coordinate);
+                max[dimension] = max[dimension].max(coordinate);
+            }
+        }
+        Some((min, max))
+    }
+
+    /// every node's point together with the bounding box of its subtree, in pre-order
+    ///
+    /// the boxes are computed bottom-up in a single pass before the first item is returned
+    pub fn iter_with_bounds(&self) -> impl Iterator<Item = (&T, BoundingBox)> {
+        let mut nodes = Vec::new();
+        if let Some(root) = &self.root {
+            root.bounds(&mut nodes);
+        }
+        nodes.into_iter()
+    }
+
+    /// like `find_nearest`, but only considers points `p` in the half-space
+    /// `dot(p - plane_point, plane_normal) >= 0`
+    ///
+    /// points behind the plane are filtered out one by one. On top of that, the region each
+    /// subtree may occupy is tracked from the splits above it, and subtrees whose whole region lies
+    /// behind the plane are skipped without looking at them. Only the filtering decides the result:
+    /// a region reaches past the plane wherever one of its points might, so skipping never loses a
+    /// candidate.
+    pub fn find_nearest_in_halfspace(
+        &self,
+        search: &T,
+        plane_point: &T,
+        plane_normal: &[f64],
+    ) -> Option<&T> {
+        debug_assert_eq!(plane_normal.len(), plane_point.dimensions());
+        let plane = Plane {
+            origin: (0..plane_normal.len())
+                .map(|dimension| plane_point.coordinate(dimension))
+                .collect(),
+            normal: plane_normal,
+        };
+        let in_front = |candidate: &T| {
+            plane.reaches(|dimension| {
+                let coordinate = candidate.coordinate(dimension);
+                (coordinate, coordinate)
+            })
+        };
+        let half_space = HalfSpace {
+            plane: &plane,
+            regions: Vec::new(),
+        };
+        let mut visitor = Nearest::with_hook(search, in_front, half_space);
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        visitor.best
+    }
+}
+
+/// what `KDTree::compress` did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompressReport {
+    /// nodes moved below their child
+    pub spliced: usize,
+    /// subtrees rebuilt
+    pub rebuilt: usize,
+    /// by how much the number of nodes with a single child went down, 0 if it did not
+    pub eliminated: usize,
+}
+
+/// region of key space: the smallest and largest key in every dimension, inclusive, `None` where
+/// there is no bound
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bounds<K> {
+    pub min: Vec<Option<K>>,
+    pub max: Vec<Option<K>>,
+}
+
+impl<K: Ord> Bounds<K> {
+    pub fn contains<T: KDPoint<Key = K>>(&self, point: &T) -> bool {
+        let within = |dimension: usize| {
+            let key = point.kdkey(dimension);
+            self.min[dimension].as_ref().is_none_or(|min| *min <= key)
+                && self.max[dimension].as_ref().is_none_or(|max| key <= *max)
+        };
+        (0..self.min.len()).all(within)
+    }
+}
+
+/// axis aligned box, the smallest and largest coordinate in every dimension
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub min: Vec<f64>,
+    pub max: Vec<f64>,
+}
+
+impl BoundingBox {
+    fn of<T: Coordinates>(point: &T) -> Self {
+        let coordinates: Vec<f64> = (0..point.dimensions())
+            .map(|dimension| point.coordinate(dimension))
+            .collect();
+        Self {
+            min: coordinates.clone(),
+            max: coordinates,
+        }
+    }
+
+    /// grows this box to contain `other` as well
+    fn extend(&mut self, other: &Self) {
+        for (min, other) in self.min.iter_mut().zip(&other.min) {
+            *min = min.min(*other);
+        }
+        for (max, other) in self.max.iter_mut().zip(&other.max) {
+            *max = max.max(*other);
+        }
+    }
+
+    /// whether `other` lies within this box, boundaries included
+    pub fn contains(&self, other: &Self) -> bool {
+        (0..self.min.len()).all(|dimension| {
+            self.min[dimension] <= other.min[dimension]
+                && other.max[dimension] <= self.max[dimension]
+        })
+    }
+}
+
+impl<T: Coordinates> Node<T> {
+    /// appends this node and its subtree to `nodes` in pre-order, returns this subtree's box
+    fn bounds<'t>(&'t self, nodes: &mut Vec<(&'t T, BoundingBox)>) -> BoundingBox {
+        let idx = nodes.len();
+        let mut bounds = BoundingBox::of(&self.data);
+        for point in self.bucket() {
+            bounds.extend(&BoundingBox::of(point));
+        }
+        nodes.push((&self.data, bounds.clone()));
+
+        for child in [&self.left, &self.right].into_iter().flatten() {
+            bounds.extend(&child.bounds(nodes));
+        }
+        nodes[idx].1 = bounds.clone();
+        bounds
+    }
+}
+
+impl<T: Coordinates + Clone> KDTree<T> {
+    /// up to `m` distinct points spread over the space the tree covers, deterministic for a given
+    /// tree
+    ///
+    /// the budget is handed down the tree and split between both children in proportion to the
+    /// width of their cells along the split dimension, so dense clusters do not eat up the sample
+    /// the way they do when sampling uniformly; a cell left with a budget of one is represented by
+    /// its point closest to the cell's center
+    pub fn stratified_sample(&self, m: usize) -> Vec<&T> {
+        let mut sample = Vec::with_capacity(m);
+        if let (Some(root), Some((min, max))) = (&self.root, self.bounding_box()) {
+            let mut cell: Vec<_> = min.into_iter().zip(max).collect();
+            root.stratified_sample(m, &mut cell, &mut sample);
+        }
+        sample
+    }
+}
+
+impl<T: Coordinates + Clone> Node<T> {
+    /// adds `budget` points of this subtree to `sample`, `cell` holds the bounds of the subtree in
+    /// every dimension
+    fn stratified_sample<'t>(
+        &'t self,
+        budget: usize,
+        cell: &mut [(f64, f64)],
+        sample: &mut Vec<&'t T>,
+    ) {
+        if budget == 0 {
+            return;
+        }
+        if budget >= self.size() {
+            let mut stack = vec![self];
+            while let Some(node) = stack.pop() {
+                sample.extend(node.points());
+                stack.extend(node.left.as_deref());
+                stack.extend(node.right.as_deref());
+            }
+            return;
+        }
+        if budget == 1 {
+            let mut center = self.data.clone();
+            for (dimension, (lo, hi)) in cell.iter().enumerate() {
+                center.set_coordinate(dimension, (lo + hi) / 2.0);
+            }
+            let mut visitor = Nearest::new(&center, |_: &T| true);
+            visitor.visit(self);
+            sample.extend(visitor.best);
+            return;
+        }
+
+        let (lo, hi) = cell[self.dimension()];
+        let split = self.data.coordinate(self.dimension());
+        let children = [(&self.left, (lo, split)), (&self.right, (split, hi))];
+        let [left_size, right_size] =
+            children.map(|(child, _)| child.as_ref().map(|c| c.size()).unwrap_or(0));
+        let [left_width, right_width] = children.map(|(child, (lo, hi))| match child {
+            Some(_) => hi - lo,
+            None => 0.0,
+        });
+
+        // whatever the children cannot hold is taken from this node
+        let for_children = budget.min(left_size + right_size);
+        let share = if left_width + right_width > 0.0 {
+            left_width / (left_width + right_width)
+        } else {
+            0.5
+        };
+        let for_left = ((for_children as f64 * share).round() as usize)
+            .clamp(for_children.saturating_sub(right_size), left_size);
+        sample.extend(self.points().take(budget - for_children));
+
+        for ((child, bounds), child_budget) in children
+            .into_iter()
+            .zip([for_left, for_children - for_left])
+        {
+            if let Some(child) = child {
+                let parent = std::mem::replace(&mut cell[self.dimension()], bounds);
+                child.stratified_sample(child_budget, cell, sample);
+                cell[self.dimension()] = parent;
+            }
+        }
+    }
+}
+
+/// the number of dimensions all points of `data` share, `None` if there are no points
+fn check_dimensions<T: KDPoint>(data: &[T]) -> Result<Option<usize>, Error> {
+    let Some(first) = data.first() else {
+        return Ok(None);
+    };
+    let expected = first.dimensions();
+    match data.iter().find(|point| point.dimensions() != expected) {
+        Some(point) => Err(Error::DimensionMismatch {
+            expected,
+            got: point.dimensions(),
+        }),
+        None => Ok(Some(expected)),
+    }
+}
+
+/// panics if the key order of `T` is not a total order on (a sample of) `data`
+fn check_key_order<T: KDPoint>(data: &[T]) {
+    let sample = &data[..data.len().min(8)];
+    let dimensions = sample.first().map(T::dimensions).unwrap_or(0);
+    for dimension in 0..dimensions {
+        if let Err(violation) =
+            partition_functions::check_comparator(sample, make_compare(dimension))
+        {
+            panic!("keys in dimension {dimension} are not a total order: {violation}");
+        }
+    }
+}
+
+/// sorts `order`, indices into `keys`, by key and returns how many pairs were out of order, by
+/// merge sort with `buffer` as scratch space
+fn count_inversions<K: Ord>(keys: &[K], order: &mut [usize], buffer: &mut Vec<usize>) -> usize {
+    if order.len() < 2 {
+        return 0;
+    }
+    let middle = order.len() / 2;
+    let (left, right) = order.split_at_mut(middle);
+    let mut inversions =
+        count_inversions(keys, left, buffer) + count_inversions(keys, right, buffer);
+
+    buffer.clear();
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        if keys[right[j]] < keys[left[i]] {
+            // ahead of all of `left[i..]`
+            inversions += left.len() - i;
+            buffer.push(right[j]);
+            j += 1;
+        } else {
+            buffer.push(left[i]);
+            i += 1;
+        }
+    }
+    buffer.extend_from_slice(&left[i..]);
+    buffer.extend_from_slice(&right[j..]);
+    order.copy_from_slice(buffer);
+    inversions
+}
+
+/// merges the results of `find_k_nearest_with_distances` on several trees into the `k` nearest
+/// overall, nearest first; ties keep the order of `results`
+pub fn merge_knn<'t, T: KDPoint>(
+    results: &[Vec<(&'t T, T::Distance)>],
+    k: usize,
+) -> Vec<(&'t T, T::Distance)> {
+    let mut merged: Vec<_> = results.iter().flatten().cloned().collect();
+    merged.sort_by(|(_, lhs), (_, rhs)| lhs.cmp(rhs));
+    merged.truncate(k);
+    merged
+}
+
+fn is_within_box<T: KDPoint>(point: &T, min: &T, max: &T) -> bool {
+    (0..point.dimensions()).all(|dimension| {
+        let key = point.kdkey(dimension);
+        min.kdkey(dimension) <= key && key <= max.kdkey(dimension)
+    })
+}
+
+/// observes the decisions of a `Nearest` search
+trait NearestHook<'t, T: KDPoint> {
+    /// the search arrives at `node`, whose split is `target_to_split` away from the search, with
+    /// the best point found so far at `best_distance`
+    fn entered(
+        &mut self,
+        _node: &'t Node<T>,
+        _target_to_split: &T::Distance,
+        _best_distance: Option<&T::Distance>,
+    ) {
+    }
+
+    /// the search is done with `node` and its subtrees
+    fn finished(&mut self, _node: &'t Node<T>) {}
+
+    /// `point` at `distance` is the best one so far
+    fn improved(&mut self, _point: &'t T, _distance: &T::Distance) {}
+
+    /// the subtree below `child` is skipped, as the split is `target_to_split` away from the
+    /// search while the best point found so far is at `best_distance`
+    fn pruned(
+        &mut self,
+        _child: &'t Node<T>,
+        _target_to_split: &T::Distance,
+        _best_distance: &T::Distance,
+    ) {
+    }
+
+    /// whether the search must not enter the left (or right) subtree of `node` at all
+    fn skips(&self, _node: &Node<T>, _left: bool) -> bool {
+        false
+    }
+}
+
+impl<'t, T: KDPoint> NearestHook<'t, T> for () {}
+
+/// collects the roots of all pruned subtrees
+impl<'t, T: KDPoint> NearestHook<'t, T> for Vec<&'t T> {
+    fn pruned(&mut self, child: &'t Node<T>, _: &T::Distance, _: &T::Distance) {
+        self.push(&child.data);
+    }
+}
+
+/// the distance from the search to the split that made a nearest neighbour search skip a subtree,
+/// and the best distance found by then
+pub type PruneSlack<D> = (D, D);
+
+impl<'t, T: KDPoint> NearestHook<'t, T> for Vec<PruneSlack<T::Distance>> {
+    fn pruned(&mut self, _: &'t Node<T>, target_to_split: &T::Distance, best: &T::Distance) {
+        self.push((target_to_split.clone(), best.clone()));
+    }
+}
+
+/// skips everything left once a best point so far is `good_enough`, which is kept in `found`
+struct Until<'t, T, G> {
+    good_enough: G,
+    found: Option<&'t T>,
+}
+
+impl<'t, T: KDPoint, G: Fn(&T, T::Distance) -> bool> NearestHook<'t, T> for Until<'t, T, G> {
+    fn improved(&mut self, point: &'t T, distance: &T::Distance) {
+        if self.found.is_none() && (self.good_enough)(point, distance.clone()) {
+            self.found = Some(point);
+        }
+    }
+
+    fn skips(&self, _node: &Node<T>, _left: bool) -> bool {
+        self.found.is_some()
+    }
+}
+
+/// plane through `origin`, `normal` pointing to its front side
+struct Plane<'n> {
+    origin: Vec<f64>,
+    normal: &'n [f64],
+}
+
+impl<'n> Plane<'n> {
+    /// whether the box with the given smallest and largest coordinate in every dimension reaches
+    /// the front side, boundary included
+    fn reaches(&self, span: impl Fn(usize) -> (f64, f64)) -> bool {
+        let farthest: f64 = (0..self.normal.len())
+            .map(|dimension| {
+                let normal = self.normal[dimension];
+                let (min, max) = span(dimension);
+                match normal.partial_cmp(&0.0) {
+                    Some(Ordering::Greater) => normal * (max - self.origin[dimension]),
+                    Some(Ordering::Less) => normal * (min - self.origin[dimension]),
+                    _ => 0.0,
+                }
+            })
+            .sum();
+        farthest >= 0.0
+    }
+}
+
+/// skips subtrees entirely behind `plane`; while searching, `regions` holds the entered nodes
+/// along with the coordinates their subtree may span
+struct HalfSpace<'p, 'n, 't, T> {
+    plane: &'p Plane<'n>,
+    regions: Vec<(&'t Node<T>, BoundingBox)>,
+}
+
+impl<'p, 'n, 't, T: Coordinates> HalfSpace<'p, 'n, 't, T> {
+    /// the region of a child of `node`, whose own region is `region`
+    fn child_region(node: &Node<T>, region: &BoundingBox, left: bool) -> BoundingBox {
+        let mut region = region.clone();
+        let split = node.data.coordinate(node.dimension());
+        if left {
+            region.max[node.dimension()] = split;
+        } else {
+            region.min[node.dimension()] = split;
+        }
+        region
+    }
+}
+
+impl<'p, 'n, 't, T: Coordinates> NearestHook<'t, T> for HalfSpace<'p, 'n, 't, T> {
+    fn entered(&mut self, node: &'t Node<T>, _: &T::Distance, _: Option<&T::Distance>) {
+        let region = match self.regions.last() {
+            Some((parent, region)) => {
+                let left = parent
+                    .left
+                    .as_deref()
+                    .is_some_and(|left| std::ptr::eq(left, node));
+                Self::child_region(parent, region, left)
+            }
+            None => BoundingBox {
+                min: vec![f64::NEG_INFINITY; node.data.dimensions()],
+                max: vec![f64::INFINITY; node.data.dimensions()],
+            },
+        };
+        self.regions.push((node, region));
+    }
+
+    fn finished(&mut self, _node: &'t Node<T>) {
+        self.regions.pop();
+    }
+
+    fn skips(&self, node: &Node<T>, left: bool) -> bool {
+        let Some((_, region)) = self.regions.last() else {
+            return false;
+        };
+        let child = Self::child_region(node, region, left);
+        !self
+            .plane
+            .reaches(|dimension| (child.min[dimension], child.max[dimension]))
+    }
+}
+
+/// the points with a key in `lo..=hi` in `dimension`
+struct Slab<K> {
+    dimension: usize,
+    lo: K,
+    hi: K,
+}
+
+impl<K: Ord> Slab<K> {
+    fn contains(&self, key: &K) -> bool {
+        self.lo <= *key && *key <= self.hi
+    }
+}
+
+/// skips the subtrees outside the slab
+impl<'t, T: KDPoint> NearestHook<'t, T> for &Slab<T::Key> {
+    fn skips(&self, node: &Node<T>, left: bool) -> bool {
+        if node.dimension() != self.dimension {
+            return false;
+        }
+        let key = node.data.kdkey(node.dimension());
+        if left {
+            key < self.lo
+        } else {
+            key > self.hi
+        }
+    }
+}
+
+/// nearest neighbour search, only points passing `accept` are considered as result
+struct Nearest<'t, 's, T: KDPoint, F, H = ()> {
+    best: Option<&'t T>,
+    /// distance of `best`, or the cutoff while there is none
+    distance: Option<T::Distance>,
+    search: &'s T,
+    accept: F,
+    hook: H,
+}
+
+impl<'t, 's, T: KDPoint, F: Fn(&T) -> bool> Nearest<'t, 's, T, F> {
+    fn new(search: &'s T, accept: F) -> Self {
+        Self::with_hook(search, accept, ())
+    }
+}
+
+impl<'t, 's, T: KDPoint, F: Fn(&T) -> bool, H: NearestHook<'t, T>> Nearest<'t, 's, T, F, H> {
+    fn with_hook(search: &'s T, accept: F, hook: H) -> Self {
+        Self {
+            best: None,
+            distance: None,
+            search,
+            accept,
+            hook,
+        }
+    }
+
+    /// only points at most `cutoff` away are candidates
+    fn with_cutoff(mut self, cutoff: T::Distance) -> Self {
+        self.distance = Some(cutoff);
+        self
+    }
+
+    /// whether a point at `distance` beats the best one so far, or does not exceed the cutoff
+    fn improves(&self, distance: &T::Distance) -> bool {
+        match (&self.distance, self.best) {
+            (None, _) => true,
+            (Some(cutoff), None) => distance <= cutoff,
+            (Some(best_dist), Some(_)) => distance < best_dist,
+        }
+    }
+}
+
+impl<'t, 's, T: KDPoint, F: Fn(&T) -> bool, H: NearestHook<'t, T>> Visitor<'t, T>
+    for Nearest<'t, 's, T, F, H>
+{
+    fn visit(&mut self, node: &'t Node<T>) {
+        let go_left = compare_element(self.search, &node.data, node.dimension()).is_lt();
+        let [first, second] = if go_left {
+            [Self::visit_left, Self::visit_right]
+        } else {
+            [Self::visit_right, Self::visit_left]
+        };
+        let target_to_split = T::key_distance(
+            &self.search.kdkey(node.dimension()),
+            &node.data.kdkey(node.dimension()),
+        );
+        self.hook
+            .entered(node, &target_to_split, self.distance.as_ref());
+
+        // traverse first child
+        if !self.hook.skips(node, go_left) {
+            first(self, node);
+        }
+
+        // check if current is better
+        for point in node.points().filter(|point| (self.accept)(point)) {
+            let curr_dist = T::distance(self.search, point);
+            if self.improves(&curr_dist) {
+                self.hook.improved(point, &curr_dist);
+                self.best = Some(point);
+                self.distance = Some(curr_dist);
+            }
+        }
+
+        // if current best "range" is wrapping over to other side of split, traverse other child;
+        // points there are at least `target_to_split` away, so at equality they could tie with the
+        // best but never beat it, while they would still be within a cutoff
+        match &self.distance {
+            Some(best_dist)
+                if *best_dist < target_to_split
+                    || (*best_dist == target_to_split && self.best.is_some()) =>
+            {
+                let skipped = if go_left { &node.right } else { &node.left };
+                if let Some(skipped) = skipped {
+                    self.hook.pruned(skipped, &target_to_split, best_dist);
+                }
+            }
+            _ if self.hook.skips(node, !go_left) => {}
+            _ => second(self, node),
+        }
+        self.hook.finished(node);
+    }
+}
+
+/// search for the point closest to any of `queries`
+struct NearestToAny<'t, 'q, T: KDPoint> {
+    queries: &'q [T],
+    best: Option<(&'t T, usize, T::Distance)>,
+}
+
+impl<'t, 'q, T: KDPoint> NearestToAny<'t, 'q, T> {
+    /// searches the subtree at `node`, which every query in turn is at least `bounds` away from,
+    /// `None` standing for no bound at all
+    fn visit(&mut self, node: &'t Node<T>, bounds: &[Option<T::Distance>]) {
+        let left = self.side_bounds(node, bounds, Ordering::Less);
+        let right = self.side_bounds(node, bounds, Ordering::Greater);
+        let nearest = |bounds: &[Option<T::Distance>]| bounds.iter().min().cloned().flatten();
+        let (left_bound, right_bound) = (nearest(&left), nearest(&right));
+        let [first, second] = if left_bound <= right_bound {
+            [
+                (&node.left, left, left_bound),
+                (&node.right, right, right_bound),
+            ]
+        } else {
+            [
+                (&node.right, right, right_bound),
+                (&node.left, left, left_bound),
+            ]
+        };
+
+        if let (Some(child), bounds, bound) = first {
+            if self.reaches(&bound) {
+                self.visit(child, &bounds);
+            }
+        }
+
+        for point in node.points() {
+            for (index, query) in self.queries.iter().enumerate() {
+                let distance = T::distance(query, point);
+                if self
+                    .best
+                    .as_ref()
+                    .map(|(_, _, best)| distance < *best)
+                    .unwrap_or(true)
+                {
+                    self.best = Some((point, index, distance));
+                }
+            }
+        }
+
+        if let (Some(child), bounds, bound) = second {
+            if self.reaches(&bound) {
+                self.visit(child, &bounds);
+            }
+        }
+    }
+
+    /// `bounds` for the side of `node`'s split where keys compare as `side` to the split's key:
+    /// queries on the other side are at least as far away as the split
+    fn side_bounds(
+        &self,
+        node: &Node<T>,
+        bounds: &[Option<T::Distance>],
+        side: Ordering,
+    ) -> Vec<Option<T::Distance>> {
+        let split = node.data.kdkey(node.dimension());
+        self.queries
+            .iter()
+            .zip(bounds)
+            .map(|(query, bound)| {
+                let key = query.kdkey(node.dimension());
+                if key.cmp(&split) == side {
+                    bound.clone()
+                } else {
+                    Ord::max(bound.clone(), Some(T::key_distance(&key, &split)))
+                }
+            })
+            .collect()
+    }
+
+    /// whether a subtree `bound` away may hold a closer point than the best one
+    fn reaches(&self, bound: &Option<T::Distance>) -> bool {
+        match (&self.best, bound) {
+            (Some((_, _, best)), Some(bound)) => best > bound,
+            _ => true,
+        }
+    }
+}
+
+/// a point found by a k nearest search, ordered by distance and then by `found`, the number of
+/// points considered before it, so that of equally distant points the one found first ranks
+/// ahead and the result does not depend on the heap's internal order
+struct Candidate<'t, T: KDPoint> {
+    distance: T::Distance,
+    found: usize,
+    data: &'t T,
+}
+
+impl<'t, T: KDPoint> PartialEq for Candidate<'t, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance == other.distance && self.found == other.found
+    }
+}
+
+impl<'t, T: KDPoint> Eq for Candidate<'t, T> {}
+
+impl<'t, T: KDPoint> PartialOrd for Candidate<'t, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'t, T: KDPoint> Ord for Candidate<'t, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .cmp(&other.distance)
+            .then(self.found.cmp(&other.found))
+    }
+}
+
+/// subtree whose points are all at least `bound` away from the search, `None` standing for no
+/// bound at all
+struct Region<'t, T: KDPoint> {
+    bound: Option<T::Distance>,
+    node: &'t Node<T>,
+}
+
+impl<'t, T: KDPoint> PartialEq for Region<'t, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bound == other.bound
+    }
+}
+
+impl<'t, T: KDPoint> Eq for Region<'t, T> {}
+
+impl<'t, T: KDPoint> PartialOrd for Region<'t, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'t, T: KDPoint> Ord for Region<'t, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bound.cmp(&other.bound)
+    }
+}
+
+/// k nearest neighbour search, `heap` holds the best `k` candidates found so far with the worst
+/// on top
+struct KNearest<'t, 's, T: KDPoint, F> {
+    k: usize,
+    heap: BinaryHeap<Candidate<'t, T>>,
+    /// points considered so far
+    found: usize,
+    search: &'s T,
+    accept: F,
+}
+
+impl<'t, 's, T: KDPoint, F: Fn(&T) -> bool> KNearest<'t, 's, T, F> {
+    /// `len` is the number of points in the tree, no more candidates than that are kept
+    fn new(search: &'s T, k: usize, len: usize, accept: F) -> Self {
+        Self {
+            k,
+            heap: BinaryHeap::with_capacity(k.min(len)),
+            found: 0,
+            search,
+            accept,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.heap.len() >= self.k
+    }
+}
+
+impl<'t, 's, T: KDPoint, F: Fn(&T) -> bool> Visitor<'t, T> for KNearest<'t, 's, T, F> {
+    fn visit(&mut self, node: &'t Node<T>) {
+        let [first, second] = if compare_element(self.search, &node.data, node.dimension()).is_lt()
+        {
+            [Self::visit_left, Self::visit_right]
+        } else {
+            [Self::visit_right, Self::visit_left]
+        };
+
+        first(self, node);
+
+        for point in node.points().filter(|point| (self.accept)(point)) {
+            let candidate = Candidate {
+                distance: T::distance(self.search, point),
+                found: self.found,
+                data: point,
+            };
+            self.found += 1;
+            if !self.is_full() {
+                self.heap.push(candidate);
+            } else if self
+                .heap
+                .peek()
+                .map(|worst| candidate < *worst)
+                .unwrap_or(false)
+            {
+                self.heap.pop();
+                self.heap.push(candidate);
+            }
+        }
+
+        let target_to_split = T::key_distance(
+            &self.search.kdkey(node.dimension()),
+            &node.data.kdkey(node.dimension()),
+        );
+
+        // the other side can only contribute if there is still room or the worst candidate's
+        // "range" wraps over the split, at equality it only holds ties for the last place
+        if !self.is_full()
+            || self
+                .heap
+                .peek()
+                .map(|worst| worst.distance > target_to_split)
+                .unwrap_or(true)
+        {
+            second(self, node);
+        }
+    }
+}
+
+/// calls `found` with every point within `radius`, until it returns `false`
+struct WithinRadius<'s, T: KDPoint, F> {
+    radius: T::Distance,
+    search: &'s T,
+    found: F,
+    stopped: bool,
+}
+
+impl<'t, 's, T: KDPoint + 't, F: FnMut(&'t T) -> bool> Visitor<'t, T> for WithinRadius<'s, T, F> {
+    fn visit(&mut self, node: &'t Node<T>) {
+        let [first, second] = if compare_element(self.search, &node.data, node.dimension()).is_lt()
+        {
+            [Self::visit_left, Self::visit_right]
+        } else {
+            [Self::visit_right, Self::visit_left]
+        };
+
+        first(self, node);
+        if self.stopped {
+            return;
+        }
+
+        for point in node.points() {
+            if T::distance(self.search, point) <= self.radius && !(self.found)(point) {
+                self.stopped = true;
+                return;
+            }
+        }
+
+        let target_to_split = T::key_distance(
+            &self.search.kdkey(node.dimension()),
+            &node.data.kdkey(node.dimension()),
+        );
+
+        // points exactly on the radius may lie on the other side of the split
+        if target_to_split <= self.radius {
+            second(self, node);
+        }
+    }
+}
+
+struct AnyWithinRadius<'s, T: KDPoint> {
+    radius: T::Distance,
+    search: &'s T,
+    found: bool,
+}
+
+impl<'t, 's, T: KDPoint> Visitor<'t, T> for AnyWithinRadius<'s, T> {
+    fn visit(&mut self, node: &'t Node<T>) {
+        self.found = node
+            .points()
+            .any(|point| T::distance(self.search, point) <= self.radius);
+        if self.found {
+            return;
+        }
+
+        let [first, second] = if compare_element(self.search, &node.data, node.dimension()).is_lt()
+        {
+            [Self::visit_left, Self::visit_right]
+        } else {
+            [Self::visit_right, Self::visit_left]
+        };
+
+        first(self, node);
+        if self.found {
+            return;
+        }
+
+        let target_to_split = T::key_distance(
+            &self.search.kdkey(node.dimension()),
+            &node.data.kdkey(node.dimension()),
+        );
+        if target_to_split <= self.radius {
+            second(self, node);
+        }
+    }
+}
+
+/// folds over all points in a box, `acc` is only `None` while `f` runs
+struct InRange<'s, T, A, F> {
+    min: &'s T,
+    max: &'s T,
+    acc: Option<A>,
+    f: F,
+}
+
+impl<'t, 's, T: KDPoint, A, F: FnMut(A, &T) -> A> Visitor<'t, T> for InRange<'s, T, A, F> {
+    fn visit(&mut self, node: &'t Node<T>) {
+        // equal keys may sit on both sides of the split
+        if compare_element(self.min, &node.data, node.dimension()).is_le() {
+            self.visit_left(node);
+        }
+
+        for point in node.points() {
+            if is_within_box(point, self.min, self.max) {
+                let acc = self.acc.take().unwrap();
+                self.acc = Some((self.f)(acc, point));
+            }
+        }
+
+        if compare_element(self.max, &node.data, node.dimension()).is_ge() {
+            self.visit_right(node);
+        }
+    }
+}
+
+/// collects the points within `bounds`, a range of keys for each of the first dimensions
+struct InBox<'b, 't, T, R> {
+    bounds: &'b [R],
+    found: Vec<&'t T>,
+}
+
+impl<'b, 't, T: KDPoint, R: RangeBounds<T::Key>> Visitor<'t, T> for InBox<'b, 't, T, R> {
+    fn visit(&mut self, node: &'t Node<T>) {
+        // equal keys may sit on both sides of the split
+        let (left, right) = match self.bounds.get(node.dimension()) {
+            Some(range) => {
+                let key = node.data.kdkey(node.dimension());
+                let left = match range.start_bound() {
+                    Bound::Included(start) => *start <= key,
+                    Bound::Excluded(start) => *start < key,
+                    Bound::Unbounded => true,
+                };
+                let right = match range.end_bound() {
+                    Bound::Included(end) => key <= *end,
+                    Bound::Excluded(end) => key < *end,
+                    Bound::Unbounded => true,
+                };
+                (left, right)
+            }
+            None => (true, true),
+        };
+
+        if left {
+            self.visit_left(node);
+        }
+        let bounds = self.bounds;
+        self.found.extend(node.points().filter(|point| {
+            bounds
+                .iter()
+                .enumerate()
+                .all(|(dimension, range)| range.contains(&point.kdkey(dimension)))
+        }));
+        if right {
+            self.visit_right(node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_and_find() {
+        use rand::random;
+
+        let data: Vec<Point3D<F64>> = (0..10)
+            .map(|_| [random::<f64>(), random::<f64>(), random::<f64>()].into())
+            .collect();
+
+        let tree = KDTree::make(data.clone());
+        assert!(tree.validate());
+        assert_eq!(tree.len(), 10);
+        for point in &data {
+            let found = tree.find_nearest(point).unwrap();
+            assert_eq!(Point3D::distance(point, found), F64::from(0.0));
+        }
+    }
+
+    mod insert {
+        use super::*;
+        use rand::random;
+
+        #[test]
+        fn insert_and_knn() {
+            let point = || -> Point2D { (random::<f64>(), random::<f64>()).into() };
+            let distances = |found: Vec<&Point2D>, search: &Point2D| -> Vec<F64> {
+                found
+                    .into_iter()
+                    .map(|p| Point2D::distance(search, p))
+                    .collect()
+            };
+            let data: Vec<Point2D> = (0..50).map(|_| point()).collect();
+            for policy in [DepthPolicy::Ignore, DepthPolicy::RebuildSubtree] {
+                let mut online = KDTree::make(data.clone())
+                    .with_depth_policy(policy)
+                    .with_max_depth(3);
+                let mut separate = KDTree::make(data.clone());
+                for i in 0..100 {
+                    // every third point a duplicate
+                    let new = if i % 3 == 0 {
+                        data[i % 50].clone()
+                    } else {
+                        point()
+                    };
+                    let found = distances(online.insert_and_knn(new.clone(), 5), &new);
+                    separate.insert(new.clone());
+                    // the inserted point itself comes first, at distance 0
+                    let expected = distances(separate.find_k_nearest(&new, 6), &new);
+                    assert_eq!(found, expected[1..]);
+                }
+                assert_eq!(online.len(), 150);
+                assert!(online.validate());
+            }
+
+            let mut counted = KDTree::make_with_counts(vec![Point2D::from((0.0, 0.0)); 2]);
+            let found = counted.insert_and_knn((0.0, 0.0).into(), 5);
+            assert_eq!(found.len(), 2);
+            let mut empty = KDTree::make(Vec::new());
+            assert!(empty
+                .insert_and_knn(Point2D::from((0.0, 0.0)), 3)
+                .is_empty());
+            assert_eq!(empty.insert_and_knn((1.0, 0.0).into(), 3).len(), 1);
+        }
+
+        #[test]
+        fn inserted_point_is_found() {
+            let data: Vec<Point2D> = vec![(0.0, 0.0).into(), (1.0, 0.0).into(), (2.0, 0.0).into()];
+            let mut tree = KDTree::make(data);
+            tree.insert((1.9, 0.5).into());
+            assert!(tree.validate());
+
+            let nearest = tree.find_nearest(&(1.9, 0.5).into()).unwrap();
+            assert_eq!((nearest.x, nearest.y), (1.9, 0.5));
+        }
+
+        #[test]
+        fn mixed_make_and_insert() {
+            let point = || -> Point2D { (random::<f64>(), random::<f64>()).into() };
+            let mut data: Vec<Point2D> = (0..100).map(|_| point()).collect();
+            let mut tree = KDTree::make(data.clone());
+
+            for _ in 0..100 {
+                let new = point();
+                data.push(new.clone());
+                tree.insert(new);
+            }
+            assert!(tree.validate());
+
+            for _ in 0..100 {
+                let search = point();
+                let expected = data
+                    .iter()
+                    .map(|p| Point2D::distance(&search, p))
+                    .min()
+                    .unwrap();
+                let found = tree.find_nearest(&search).unwrap();
+                assert_eq!(Point2D::distance(&search, found), expected);
+            }
+        }
+
+        /// inserts `data` one by one and checks that the tree stays shallow and queries correct
+        fn check_inserted(data: Vec<Point2D>) {
+            let mut tree = KDTree::make(Vec::new());
+            for p in &data {
+                tree.insert(p.clone());
+            }
+            assert!(tree.validate());
+            let depth = tree.depth();
+            assert!(
+                depth <= 3 * KDTree::<Point2D>::optimal_depth(data.len()),
+                "{depth}"
+            );
+
+            let radius = F64::from(0.01);
+            for _ in 0..20 {
+                let search: Point2D = (random::<f64>(), random::<f64>()).into();
+                let expected = data
+                    .iter()
+                    .map(|p| Point2D::distance(&search, p))
+                    .min()
+                    .unwrap();
+                let found = tree.find_nearest(&search).unwrap();
+                assert_eq!(Point2D::distance(&search, found), expected);
+
+                let expected = data
+                    .iter()
+                    .filter(|p| Point2D::distance(&search, p) <= radius)
+                    .count();
+                assert_eq!(tree.find_within_radius(&search, radius).len(), expected);
+            }
+        }
+
+        #[test]
+        fn identical_points() {
+            check_inserted(vec![(0.5, 0.5).into(); 10_000]);
+        }
+
+        #[test]
+        fn equal_in_one_dimension() {
+            check_inserted((0..10_000).map(|_| (0.5, random::<f64>()).into()).collect());
+        }
+
+        #[test]
+        fn validate_detects_misplaced_points() {
+            let mut tree = KDTree::make(vec![Point2D::from((1.0, 0.0))]);
+            assert!(tree.validate());
+
+            // greater point on the left side of the root
+            tree.root.as_mut().unwrap().left = Some(Box::new(Node::new((2.0, 0.0).into(), 1)));
+            assert!(!tree.validate());
+        }
+    }
+
+    mod bounding_sphere {
+        use super::*;
+        use rand::random;
+
+        #[test]
+        fn contains_all_points() {
+            let data: Vec<Point3D<F64>> = (0..500)
+                .map(|_| {
+                    [
+                        random::<f64>() * 10.0,
+                        random::<f64>(),
+                        random::<f64>() - 5.0,
+                    ]
+                    .into()
+                })
+                .collect();
+            let tree = KDTree::make(data.clone());
+
+            let (center, radius) = tree.bounding_sphere().unwrap();
+            assert_eq!(center.len(), 3);
+            for point in &data {
+                let distance = (0..3)
+                    .map(|d| (point.coordinate(d) - center[d]).powi(2))
+                    .sum::<f64>()
+                    .sqrt();
+                assert!(distance <= radius);
+            }
+        }
+
+        #[test]
+        fn trivial() {
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .bounding_sphere()
+                .is_none());
+
+            let tree = KDTree::make(vec![Point2D::from((1.0, 2.0))]);
+            assert_eq!(tree.bounding_sphere(), Some((vec![1.0, 2.0], 0.0)));
+        }
+    }
+
+    mod iter_with_bounds {
+        use super::*;
+        use rand::random;
+
+        #[test]
+        fn nested_boxes() {
+            let data: Vec<Point3D<F64>> = (0..300)
+                .map(|_| [random::<f64>() * 4.0, random(), -random::<f64>()].into())
+                .collect();
+            let tree = KDTree::make(data);
+
+            let nodes: Vec<_> = tree.iter_with_bounds().collect();
+            assert_eq!(nodes.len(), 300);
+            let (min, max) = tree.bounding_box().unwrap();
+            assert_eq!(nodes[0].1, BoundingBox { min, max });
+
+            // walk the tree alongside the pre-order list, checking each box against its parent's
+            let root = tree.root.as_ref().unwrap();
+            let mut stack = vec![(root, None::<BoundingBox>)];
+            let mut boxes = nodes.iter();
+            while let Some((node, parent)) = stack.pop() {
+                let (point, bounds) = boxes.next().unwrap();
+                assert!(std::ptr::eq(*point, &node.data));
+                assert!(bounds.contains(&BoundingBox::of(*point)));
+                if let Some(parent) = parent {
+                    assert!(parent.contains(bounds));
+                }
+                for child in [&node.right, &node.left].into_iter().flatten() {
+                    stack.push((child, Some(bounds.clone())));
+                }
+            }
+        }
+
+        #[test]
+        fn leaves_and_empty() {
+            let tree = KDTree::make(vec![Point2D::from((1.0, 2.0))]);
+            let nodes: Vec<_> = tree.iter_with_bounds().collect();
+            assert_eq!(nodes[0].1.min, [1.0, 2.0]);
+            assert_eq!(nodes[0].1.max, [1.0, 2.0]);
+            assert_eq!(
+                KDTree::<Point2D>::make(Vec::new())
+                    .iter_with_bounds()
+                    .count(),
+                0
+            );
+        }
+    }
+
+    mod stratified_sample {
+        use super::*;
+        use rand::{random, seq::SliceRandom};
+
+        fn min_pairwise_distance(points: &[&Point2D]) -> f64 {
+            let mut min = f64::INFINITY;
+            for (i, a) in points.iter().enumerate() {
+                for b in &points[i + 1..] {
+                    let (dx, dy) = (
+                        a.coordinate(0) - b.coordinate(0),
+                        a.coordinate(1) - b.coordinate(1),
+                    );
+                    min = min.min((dx * dx + dy * dy).sqrt());
+                }
+            }
+            min
+        }
+
+        #[test]
+        fn size_and_uniqueness() {
+            let data: Vec<Point2D> = (0..300).map(|_| (random(), random()).into()).collect();
+            let tree = KDTree::make(data);
+            for m in [0, 1, 2, 17, 299, 300, 1000] {
+                let sample = tree.stratified_sample(m);
+                assert_eq!(sample.len(), m.min(300));
+                for (i, a) in sample.iter().enumerate() {
+                    assert!(!sample[i + 1..].iter().any(|b| std::ptr::eq(*a, *b)));
+                }
+            }
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .stratified_sample(5)
+                .is_empty());
+        }
+
+        #[test]
+        fn deterministic() {
+            let data: Vec<Point2D> = (0..500).map(|_| (random(), random()).into()).collect();
+            let coordinates = |tree: &KDTree<Point2D>| -> Vec<(f64, f64)> {
+                tree.stratified_sample(40)
+                    .into_iter()
+                    .map(|p| (p.coordinate(0), p.coordinate(1)))
+                    .collect()
+            };
+            assert_eq!(
+                coordinates(&KDTree::make(data.clone())),
+                coordinates(&KDTree::make(data))
+            );
+        }
+
+        #[test]
+        fn duplicates() {
+            let data: Vec<Point2D> = (0..100).map(|i| ((i % 3) as f64, 0.0).into()).collect();
+            let tree = KDTree::make_with_buckets(data, 4);
+            let sample = tree.stratified_sample(50);
+            assert_eq!(sample.len(), 50);
+        }
+
+        #[test]
+        fn spread_on_clustered_data() {
+            // most points in a tiny cluster, the rest spread over the unit square
+            let cluster = (0..900)
+                .map(|_| (0.5 + random::<f64>() * 0.01, 0.5 + random::<f64>() * 0.01).into());
+            let spread = (0..100).map(|_| (random(), random()).into());
+            let data: Vec<Point2D> = cluster.chain(spread).collect();
+            let tree = KDTree::make(data.clone());
+
+            let stratified = tree.stratified_sample(20);
+            let uniform: Vec<_> = data.choose_multiple(&mut rand::thread_rng(), 20).collect();
+            assert!(min_pairwise_distance(&stratified) > min_pairwise_distance(&uniform));
+        }
+    }
+
+    mod range {
+        use super::*;
+        use rand::random;
+
+        fn grid(n: usize) -> Vec<Point2D> {
+            (0..n * n)
+                .map(|i| ((i % n) as f64, (i / n) as f64).into())
+                .collect()
+        }
+
+        fn sorted_keys<'t>(points: impl IntoIterator<Item = &'t Point2D>) -> Vec<(F64, F64)> {
+            let mut keys: Vec<_> = points
+                .into_iter()
+                .map(|p| (p.x.into(), p.y.into()))
+                .collect();
+            keys.sort();
+            keys
+        }
+
+        fn check_prune(mut tree: KDTree<Point2D>, data: &[Point2D], min: Point2D, max: Point2D) {
+            let kept: Vec<&Point2D> = data
+                .iter()
+                .filter(|p| is_within_box(*p, &min, &max))
+                .collect();
+            let removed = tree.prune_outside(&min, &max);
+            assert_eq!(removed, data.len() - kept.len());
+            assert_eq!(tree.len(), kept.len());
+            assert!(tree.validate());
+            assert_eq!(sorted_keys(tree.iter()), sorted_keys(kept.iter().copied()));
+
+            let search: Point2D = (random::<f64>() * 20.0, random::<f64>() * 20.0).into();
+            let nearest = kept.iter().map(|p| Point2D::distance(&search, p)).min();
+            let found = tree.find_nearest(&search);
+            assert_eq!(found.map(|p| Point2D::distance(&search, p)), nearest);
+        }
+
+        #[test]
+        fn prune_outside_grid() {
+            let data = grid(20);
+            let boxes = [
+                // along the split planes of the first levels
+                ((0.0, 0.0), (9.0, 19.0)),
+                ((10.0, 10.0), (19.0, 19.0)),
+                // across them
+                ((2.5, 3.2), (13.7, 8.9)),
+                ((-5.0, 7.0), (4.0, 30.0)),
+                // a single point, none at all and all of them
+                ((7.0, 7.0), (7.0, 7.0)),
+                ((50.0, 50.0), (60.0, 60.0)),
+                ((0.0, 0.0), (19.0, 19.0)),
+            ];
+            for (min, max) in boxes {
+                check_prune(KDTree::make(data.clone()), &data, min.into(), max.into());
+            }
+
+            let mut tree = KDTree::make(data.clone());
+            let shape = tree.shape_signature();
+            assert_eq!(
+                tree.prune_outside(&(-1.0, -1.0).into(), &(20.0, 20.0).into()),
+                0
+            );
+            assert_eq!(tree.shape_signature(), shape);
+            let mut empty = KDTree::<Point2D>::make(Vec::new());
+            assert_eq!(
+                empty.prune_outside(&(0.0, 0.0).into(), &(1.0, 1.0).into()),
+                0
+            );
+        }
+
+        #[test]
+        fn prune_outside_buckets_and_counts() {
+            // every point three times, and only 5 distinct x keys
+            let data: Vec<Point2D> = grid(10)
+                .into_iter()
+                .flat_map(|p| std::iter::repeat_n(Point2D::from(((p.x / 2.0).floor(), p.y)), 3))
+                .collect();
+            for _ in 0..20 {
+                let (a, b): (f64, f64) = (random::<f64>() * 6.0 - 0.5, random::<f64>() * 6.0);
+                let (c, d): (f64, f64) = (random::<f64>() * 11.0 - 0.5, random::<f64>() * 11.0);
+                let min = Point2D::from((a.min(b), c.min(d)));
+                let max = Point2D::from((a.max(b), c.max(d)));
+                let buckets = KDTree::make_with_buckets(data.clone(), 3);
+                check_prune(buckets, &data, min.clone(), max.clone());
+                check_prune(KDTree::make_with_counts(data.clone()), &data, min, max);
+            }
+        }
+
+        fn brute_force_sum(data: &[Point2D], min: &Point2D, max: &Point2D) -> (usize, f64) {
+            data.iter()
+                .filter(|p| is_within_box(*p, min, max))
+                .fold((0, 0.0), |(count, sum), p| (count + 1, sum + p.x))
+        }
+
+        #[test]
+        fn against_brute_force() {
+            let mut data: Vec<Point2D> = (0..400)
+                .map(|_| {
+                    (
+                        (random::<f64>() * 100.0).round(),
+                        (random::<f64>() * 100.0).round(),
+                    )
+                        .into()
+                })
+                .collect();
+            let mut tree = KDTree::make(data.clone());
+
+            for round in 0..2 {
+                for _ in 0..50 {
+                    let (a, b): (f64, f64) = (random::<f64>() * 100.0, random::<f64>() * 100.0);
+                    let (c, d): (f64, f64) = (random::<f64>() * 100.0, random::<f64>() * 100.0);
+                    let min = Point2D::from((a.min(b).round(), c.min(d).round()));
+                    let max = Point2D::from((a.max(b).round(), c.max(d).round()));
+
+                    let folded = tree.fold_in_range(&min, &max, (0, 0.0), |(count, sum), p| {
+                        (count + 1, sum + p.x)
+                    });
+                    let (count, sum) = brute_force_sum(&data, &min, &max);
+                    assert_eq!(folded.0, count);
+                    assert!((folded.1 - sum).abs() < 1e-6);
+                }
+
+                if round == 0 {
+                    for _ in 0..200 {
+                        let point: Point2D = (
+                            (random::<f64>() * 100.0).round(),
+                            (random::<f64>() * 100.0).round(),
+                        )
+                            .into();
+                        data.push(point.clone());
+                        tree.insert(point);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn boundaries_and_duplicates() {
+            let data: Vec<Point2D> = (0..50).map(|i| ((i % 5) as f64, 1.0).into()).collect();
+            let tree = KDTree::make(data);
+            let count = |min: (f64, f64), max: (f64, f64)| {
+                tree.fold_in_range(&min.into(), &max.into(), 0, |count, _| count + 1)
+            };
+            assert_eq!(count((1.0, 1.0), (3.0, 1.0)), 30);
+            assert_eq!(count((4.0, 0.0), (9.0, 0.5)), 0);
+            assert_eq!(count((-1.0, 0.0), (9.0, 2.0)), 50);
+            assert_eq!(count((2.0, 0.0), (1.0, 2.0)), 0);
+
+            let empty = KDTree::<Point2D>::make(Vec::new());
+            assert_eq!(
+                empty.fold_in_range(&(0.0, 0.0).into(), &(1.0, 1.0).into(), 7, |a, _| a + 1),
+                7
+            );
+        }
+
+        /// a random bound on a coarse grid of keys, so that points lie on them
+        fn random_bound() -> Bound<F64> {
+            let key = F64::from((random::<u8>() % 12) as f64);
+            match random::<u8>() % 3 {
+                0 => Bound::Included(key),
+                1 => Bound::Excluded(key),
+                _ => Bound::Unbounded,
+            }
+        }
+
+        fn check_within_box<P: KDPoint<Key = F64> + Clone>(data: &[P]) {
+            let dimensions = data[0].dimensions();
+            for tree in [
+                KDTree::make(data.to_vec()),
+                KDTree::make_with_buckets(data.to_vec(), 3),
+            ] {
+                for _ in 0..100 {
+                    // sometimes fewer bounds than dimensions
+                    let constrained = 1 + random::<usize>() % dimensions;
+                    let bounds: Vec<(Bound<F64>, Bound<F64>)> = (0..constrained)
+                        .map(|_| (random_bound(), random_bound()))
+                        .collect();
+                    let inside = |p: &P| {
+                        bounds
+                            .iter()
+                            .enumerate()
+                            .all(|(d, range)| range.contains(&p.kdkey(d)))
+                    };
+                    let found = tree.within_box(&bounds);
+                    assert!(found.iter().all(|p| inside(p)));
+                    assert_eq!(found.len(), data.iter().filter(|p| inside(p)).count());
+                }
+            }
+        }
+
+        #[test]
+        fn within_box_against_brute_force() {
+            let coarse = || (random::<u8>() % 10) as f64;
+            let plane: Vec<Point2D> = (0..500).map(|_| (coarse(), coarse()).into()).collect();
+            check_within_box(&plane);
+            let space: Vec<Point3D<F64>> = (0..500)
+                .map(|_| [coarse(), coarse(), coarse()].into())
+                .collect();
+            check_within_box(&space);
+
+            let tree = KDTree::make(grid(10));
+            let key = |k: f64| F64::from(k);
+            assert_eq!(tree.within_box(&[key(2.0)..key(5.0)]).len(), 30);
+            assert_eq!(
+                tree.within_box(&[key(2.0)..=key(5.0), key(0.0)..=key(0.0)])
+                    .len(),
+                4
+            );
+            let top_row = [
+                (Bound::Unbounded, Bound::Unbounded),
+                (Bound::Included(key(9.0)), Bound::Unbounded),
+            ];
+            assert_eq!(tree.within_box(&top_row).len(), 10);
+            assert_eq!(tree.within_box::<std::ops::RangeFull>(&[]).len(), 100);
+            assert!(tree.within_box(&[key(5.0)..key(5.0)]).is_empty());
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .within_box(&[key(0.0)..key(1.0)])
+                .is_empty());
+        }
+
+        #[test]
+        #[should_panic(expected = "3 bounds for points of 2 dimensions")]
+        fn within_box_too_many_bounds() {
+            KDTree::make(grid(2)).within_box(&[.., .., ..]);
+        }
+    }
+
+    mod difference {
+        use super::*;
+        use rand::random;
+
+        fn key(p: &Point2D) -> (F64, F64) {
+            (p.x.into(), p.y.into())
+        }
+
+        #[test]
+        fn any_within_radius() {
+            let data: Vec<Point2D> = (0..300).map(|_| (random(), random()).into()).collect();
+            let tree = KDTree::make(data.clone());
+            for _ in 0..100 {
+                let search: Point2D = (random(), random()).into();
+                let radius = F64::from(random::<f64>() * 0.01);
+                let expected = data.iter().any(|p| Point2D::distance(&search, p) <= radius);
+                assert_eq!(tree.any_within_radius(&search, radius), expected);
+            }
+            assert!(!KDTree::make(Vec::new()).any_within_radius(&data[0], F64::from(1.0)));
+        }
+
+        #[test]
+        fn any_agrees_with_count() {
+            let data: Vec<Point2D> = (0..300).map(|_| (random(), random()).into()).collect();
+            let tree = KDTree::make(data.clone());
+            for _ in 0..100 {
+                let search: Point2D = (random(), random()).into();
+                let radius = F64::from(random::<f64>() * 0.01);
+                let count = tree.count_within_radius(&search, radius);
+                let expected = data
+                    .iter()
+                    .filter(|p| Point2D::distance(&search, p) <= radius)
+                    .count();
+                assert_eq!(count, expected);
+                assert_eq!(tree.any_within_radius(&search, radius), count > 0);
+            }
+        }
+
+        #[test]
+        fn any_stops_early() {
+            use crate::counting::{distance_calls, Counted};
+
+            let calls = |f: &dyn Fn()| distance_calls(f).1;
+
+            let data: Vec<Counted> = (0..1000)
+                .map(|_| Counted((random(), random()).into()))
+                .collect();
+            let tree = KDTree::make(data);
+            // every point is a hit, the root is the first one checked
+            let search = Counted((0.5, 0.5).into());
+            let radius = F64::from(1.0);
+            assert_eq!(
+                calls(&|| assert!(tree.any_within_radius(&search, radius))),
+                1
+            );
+            assert_eq!(
+                calls(&|| assert_eq!(tree.count_within_radius(&search, radius), 1000)),
+                1000
+            );
+        }
+
+        #[test]
+        fn overlapping_scans() {
+            // both scans share the grid points in 0 <= x < 6, a sees 0 <= x < 10, b 3 <= x < 13,
+            // and b has a wall of its own at y = 20
+            let grid = |xs: std::ops::Range<i32>| {
+                xs.flat_map(|x| (0..10).map(move |y| Point2D::from((x as f64, y as f64))))
+                    .collect::<Vec<_>>()
+            };
+            let a = KDTree::make(grid(0..10));
+            let mut b_points = grid(3..13);
+            b_points.extend((0..5).map(|x| Point2D::from((x as f64, 20.0))));
+            let b = KDTree::make(b_points);
+
+            // a squared distance, half a grid step
+            let eps = F64::from(0.25);
+            let mut only_a: Vec<_> = a.difference_within(&b, eps).into_iter().map(key).collect();
+            let mut expected: Vec<_> = grid(0..3).iter().map(key).collect();
+            only_a.sort();
+            expected.sort();
+            assert_eq!(only_a, expected);
+
+            let (only_a, only_b) = a.symmetric_difference_within(&b, eps);
+            assert_eq!(only_a.len(), 30);
+            let mut only_b: Vec<_> = only_b.into_iter().map(key).collect();
+            let mut expected: Vec<_> = grid(10..13).iter().map(key).collect();
+            expected.extend((0..5).map(|x| (F64::from(x as f64), F64::from(20.0))));
+            only_b.sort();
+            expected.sort();
+            assert_eq!(only_b, expected);
+
+            // slightly shifted copies still match
+            let shifted: Vec<Point2D> = grid(0..10)
+                .iter()
+                .map(|p| (p.x + 0.3, p.y + 0.3).into())
+                .collect();
+            let shifted = KDTree::make(shifted);
+            assert!(a.difference_within(&shifted, F64::from(0.2)).is_empty());
+            assert_eq!(a.difference_within(&shifted, F64::from(0.1)).len(), 100);
+        }
+    }
+
+    mod dedup {
+        use super::*;
+        use rand::random;
+
+        #[test]
+        fn jittered_clusters() {
+            let centers: Vec<(f64, f64)> = (0..20)
+                .map(|i| ((i % 5) as f64 * 10.0, (i / 5) as f64 * 10.0))
+                .collect();
+            let mut data: Vec<Point2D> = Vec::new();
+            for &(x, y) in &centers {
+                for _ in 0..10 {
+                    data.push((x + random::<f64>() * 1e-3, y - random::<f64>() * 1e-3).into());
+                }
+            }
+            let mut tree = KDTree::make(data);
+
+            assert_eq!(tree.dedup_within(F64::from(1e-4)), 180);
+            assert!(tree.validate());
+            assert_eq!(tree.depth(), KDTree::<Point2D>::optimal_depth(20));
+            for (x, y) in centers {
+                let center = Point2D::from((x, y));
+                assert_eq!(tree.find_within_radius(&center, F64::from(1e-4)).len(), 1);
+            }
+
+            assert_eq!(tree.dedup_within(F64::from(1e-4)), 0);
+            assert_eq!(tree.iter().count(), 20);
+        }
+
+        #[test]
+        fn deterministic_net() {
+            let data: Vec<Point2D> = (0..500).map(|_| (random(), random()).into()).collect();
+            let eps = F64::from(0.01);
+            let kept = |data: Vec<Point2D>| {
+                let mut tree = KDTree::make(data);
+                tree.dedup_within(eps);
+                tree.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>()
+            };
+            let net = kept(data.clone());
+            assert_eq!(net, kept(data.clone()));
+
+            // no two kept points are within eps, every point is covered by a kept one
+            let net: Vec<Point2D> = net.into_iter().map(Point2D::from).collect();
+            for (i, a) in net.iter().enumerate() {
+                assert!(net[i + 1..].iter().all(|b| Point2D::distance(a, b) > eps));
+            }
+            let net = KDTree::make(net);
+            assert!(data.iter().all(|p| net.any_within_radius(p, eps)));
+        }
+
+        #[test]
+        fn empty() {
+            let mut tree = KDTree::<Point2D>::make(Vec::new());
+            assert_eq!(tree.dedup_within(F64::from(1.0)), 0);
+        }
+
+        #[test]
+        fn insert_if_far() {
+            let landmarks: Vec<(f64, f64)> = (0..10).map(|i| (i as f64, (i % 3) as f64)).collect();
+            let separation = F64::from(0.01);
+            let mut tree = KDTree::make(Vec::new());
+            for round in 0..100 {
+                for &(x, y) in &landmarks {
+                    let jittered = Point2D::from((x + random::<f64>() * 0.05, y));
+                    let inserted = tree.insert_if_far(jittered, separation);
+                    if round == 0 {
+                        assert!(inserted);
+                    }
+                }
+            }
+            // jitter of at most 0.05, while points are kept at least 0.1 (squared 0.01) apart
+            assert_eq!(tree.len(), 10);
+            assert!(tree.validate());
+
+            assert!(tree.insert_if_far((100.0, 100.0).into(), separation));
+            assert!(!tree.insert_if_far((100.0, 100.1).into(), separation));
+            assert!(tree
+                .find_nearest(&(100.0, 100.0).into())
+                .is_some_and(|p| (p.x, p.y) == (100.0, 100.0)));
+        }
+    }
+
+    mod histogram {
+        use super::*;
+
+        #[test]
+        fn uniform_grid() {
+            let data: Vec<Point2D> = (0..100)
+                .map(|i| ((i % 10) as f64, (i / 10) as f64).into())
+                .collect();
+            let tree = KDTree::make(data);
+            assert_eq!(tree.nn_distance_histogram(4), vec![100, 0, 0, 0]);
+            assert_eq!(tree.nn_distance_histogram(1), vec![100]);
+        }
+
+        #[test]
+        fn spread() {
+            // pairs at distances 1, 2, 3 and 4, far apart from each other
+            let data: Vec<Point2D> = (0..4)
+                .flat_map(|i| {
+                    let x = 100.0 * i as f64;
+                    [(x, 0.0).into(), (x + (i + 1) as f64, 0.0).into()]
+                })
+                .collect();
+            let tree = KDTree::make(data);
+            // squared distances 1, 4, 9 and 16
+            assert_eq!(tree.nn_distance_histogram(3), vec![4, 2, 2]);
+            assert_eq!(tree.nn_distance_histogram(15).iter().sum::<usize>(), 8);
+            assert_eq!(tree.nn_distance_histogram(15)[14], 2);
+        }
+
+        #[test]
+        fn degenerate() {
+            let tree = KDTree::make(vec![Point2D::from((1.0, 1.0))]);
+            assert_eq!(tree.nn_distance_histogram(3), vec![0, 0, 0]);
+            assert!(tree.nn_distance_histogram(0).is_empty());
+        }
+    }
+
+    mod split {
+        use super::*;
+        use rand::random;
+
+        /// whether the interiors of `a` and `b` are disjoint
+        fn disjoint(a: &Bounds<F64>, b: &Bounds<F64>) -> bool {
+            (0..a.min.len()).any(|dimension| {
+                let below = |upper: &Option<F64>, lower: &Option<F64>| match (upper, lower) {
+                    (Some(upper), Some(lower)) => upper <= lower,
+                    _ => false,
+                };
+                below(&a.max[dimension], &b.min[dimension])
+                    || below(&b.max[dimension], &a.min[dimension])
+            })
+        }
+
+        #[test]
+        fn pieces() {
+            let data: Vec<Point2D> = (0..1000).map(|_| (random(), random()).into()).collect();
+            for n in [1, 2, 3, 8] {
+                let tree = KDTree::make(data.clone());
+                let pieces = tree.split_into(n);
+                assert_eq!(pieces.len(), n);
+                assert_eq!(
+                    pieces.iter().map(|(piece, _)| piece.len()).sum::<usize>(),
+                    1000
+                );
+
+                let mut keys: Vec<(F64, F64)> = Vec::new();
+                for (i, (piece, bounds)) in pieces.iter().enumerate() {
+                    assert!(piece.validate());
+                    assert_eq!(piece.iter().count(), piece.len());
+                    let target = 1000 / n;
+                    assert!(
+                        piece.len() > target / 2 && piece.len() < 2 * target,
+                        "piece of {} points for {n} pieces",
+                        piece.len()
+                    );
+                    assert!(piece.iter().all(|point| bounds.contains(point)));
+                    keys.extend(piece.iter().map(|p| (F64::from(p.x), F64::from(p.y))));
+                    for (_, other) in &pieces[i + 1..] {
+                        assert!(disjoint(bounds, other));
+                    }
+                }
+                let mut expected: Vec<_> = data
+                    .iter()
+                    .map(|p| (F64::from(p.x), F64::from(p.y)))
+                    .collect();
+                keys.sort();
+                expected.sort();
+                assert_eq!(keys, expected);
+
+                for _ in 0..50 {
+                    let search: Point2D = (random(), random()).into();
+                    let best = pieces
+                        .iter()
+                        .filter_map(|(piece, _)| piece.find_nearest(&search))
+                        .map(|p| Point2D::distance(&search, p))
+                        .min();
+                    let nearest = data.iter().map(|p| Point2D::distance(&search, p)).min();
+                    assert_eq!(best, nearest);
+                }
+            }
+        }
+
+        #[test]
+        fn few_points() {
+            let data: Vec<Point2D> = (0..3).map(|i| (i as f64, 0.0).into()).collect();
+            let pieces = KDTree::make(data).split_into(8);
+            assert!(pieces.len() <= 3);
+            assert_eq!(
+                pieces.iter().map(|(piece, _)| piece.len()).sum::<usize>(),
+                3
+            );
+            assert!(KDTree::<Point2D>::make(Vec::new()).split_into(4).is_empty());
+
+            // duplicates of the split keys, counted ones too
+            let data = vec![Point2D::from((1.0, 1.0)); 20];
+            for tree in [KDTree::make(data.clone()), KDTree::make_with_counts(data)] {
+                let pieces = tree.split_into(4);
+                assert_eq!(
+                    pieces.iter().map(|(piece, _)| piece.len()).sum::<usize>(),
+                    20
+                );
+            }
+        }
+    }
+
+    mod axis_ranges {
+        use super::*;
+        use rand::random;
+
+        fn scanned(tree: &KDTree<Point2D>, dimension: usize) -> Option<(F64, F64)> {
+            let keys = || tree.iter().map(|point| point.kdkey(dimension));
+            Some((keys().min()?, keys().max()?))
+        }
+
+        fn assert_scanned(tree: &KDTree<Point2D>) {
+            for dimension in 0..2 {
+                assert_eq!(tree.axis_range(dimension), scanned(tree, dimension));
+            }
+            assert!(tree.axis_range(2).is_none());
+        }
+
+        #[test]
+        fn follow_changes() {
+            let data: Vec<Point2D> = (0..500).map(|_| (random(), random()).into()).collect();
+            let mut tree = KDTree::make(data.clone());
+            assert_scanned(&tree);
+            tree = tree.with_axis_ranges();
+            assert_scanned(&tree);
+
+            tree.insert((2.0, -1.0).into());
+            assert_eq!(tree.axis_range(0).unwrap().1, F64::from(2.0));
+            assert_eq!(tree.axis_range(1).unwrap().0, F64::from(-1.0));
+            assert_scanned(&tree);
+
+            tree.rebalance();
+            assert_scanned(&tree);
+            tree.rebuild_biased(&(0.1, 0.1).into(), 0.5);
+            assert_scanned(&tree);
+
+            // drops (2, -1) along with everything else outside the unit square's left half
+            tree.prune_outside(&(0.0, 0.0).into(), &(0.5, 1.0).into());
+            assert!(tree.axis_range(0).unwrap().1 <= F64::from(0.5));
+            assert_scanned(&tree);
+            tree.dedup_within(F64::from(0.001));
+            assert_scanned(&tree);
+            tree.compress();
+            assert_scanned(&tree);
+
+            for piece in tree.split_into(4).into_iter().map(|(piece, _)| piece) {
+                assert_scanned(&piece);
+            }
+        }
+
+        #[test]
+        fn follow_removals() {
+            // as `BoundedKDTree` evicts points, removing the largest ones first narrows the ranges
+            let mut tree = KDTree::<Point2D>::make(Vec::new()).with_axis_ranges();
+            for _ in 0..200 {
+                tree.insert((random(), random()).into());
+                if tree.len() > 20 {
+                    let removed = tree.remove_max_by_key(|point| F64::from(point.x + point.y));
+                    assert!(removed.is_some());
+                }
+                assert_scanned(&tree);
+            }
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .with_axis_ranges()
+                .axis_range(0)
+                .is_none());
+        }
+
+        mod searches {
+            use super::*;
+            use crate::counting::{key_calls, Counted};
+
+            fn scanned(tree: &KDTree<Counted>, dimension: usize) -> Option<F64> {
+                tree.iter().map(|point| point.0.kdkey(dimension)).min()
+            }
+
+            #[test]
+            fn only_after_removing_an_extreme() {
+                let n = 2000;
+                let data: Vec<Counted> = (0..n)
+                    .map(|_| Counted((random(), random()).into()))
+                    .collect();
+                let mut tree = KDTree::make(data);
+
+                // the first call searches, but only the left of splits along x
+                let (min, calls) = key_calls(|| tree.min_key(0));
+                assert_eq!(min, scanned(&tree, 0));
+                assert!(0 < calls && calls < n / 4, "{calls} key calls");
+                assert_eq!(key_calls(|| tree.min_key(0)).1, 0);
+
+                // inserts keep it up to date
+                tree.insert(Counted((0.5, 0.5).into()));
+                tree.insert(Counted((-1.0, 0.5).into()));
+                assert_eq!(key_calls(|| tree.min_key(0)), (Some(F64::from(-1.0)), 0));
+
+                // removing an interior point keeps it
+                let interior = tree.remove_max_by_key(|point| {
+                    Reverse(Point2D::distance(&point.0, &(0.5, 0.5).into()))
+                });
+                assert_eq!(interior.map(|point| point.0.x), Some(0.5));
+                assert_eq!(key_calls(|| tree.min_key(0)), (Some(F64::from(-1.0)), 0));
+
+                // removing the smallest searches again
+                let extreme = tree.remove_max_by_key(|point| Reverse(F64::from(point.0.x)));
+                assert_eq!(extreme.map(|point| point.0.x), Some(-1.0));
+                let (min, calls) = key_calls(|| tree.min_key(0));
+                assert_eq!(min, scanned(&tree, 0));
+                assert!(calls > 0);
+                assert_eq!(key_calls(|| tree.min_key(0)).1, 0);
+            }
+        }
+    }
+
+    mod sharded {
+        use super::*;
+        use rand::random;
+
+        #[test]
+        fn covers_input() {
+            let data: Vec<Point2D> = (0..1000).map(|_| (random(), random()).into()).collect();
+            // bands along x
+            let shards = KDTree::make_sharded(data.clone(), 4, |point| (point.x * 4.0) as usize);
+            assert_eq!(shards.len(), 4);
+            assert_eq!(shards.iter().map(KDTree::len).sum::<usize>(), data.len());
+
+            let key = |point: &Point2D| (F64::from(point.x), F64::from(point.y));
+            let mut expected: Vec<_> = data.iter().map(key).collect();
+            let mut union: Vec<_> = shards.iter().flat_map(KDTree::iter).map(key).collect();
+            expected.sort();
+            union.sort();
+            assert_eq!(union, expected);
+
+            for (shard, tree) in shards.iter().enumerate() {
+                assert!(tree.validate());
+                assert!(tree.iter().all(|point| (point.x * 4.0) as usize == shard));
+                let points: Vec<&Point2D> = data
+                    .iter()
+                    .filter(|point| (point.x * 4.0) as usize == shard)
+                    .collect();
+                for _ in 0..20 {
+                    let search = Point2D::from((random(), random()));
+                    let nearest = tree.find_nearest(&search).unwrap();
+                    let brute = points
+                        .iter()
+                        .map(|point| Point2D::distance(&search, point))
+                        .min();
+                    assert_eq!(Some(Point2D::distance(&search, nearest)), brute);
+                }
+            }
+
+            let empty = KDTree::make_sharded(Vec::<Point2D>::new(), 3, |_| 0);
+            assert!(empty.iter().all(KDTree::is_empty));
+        }
+
+        #[test]
+        #[should_panic(expected = "shard 2 out of 2")]
+        fn shard_out_of_range() {
+            KDTree::make_sharded(vec![Point2D::from((0.0, 0.0))], 2, |_| 2);
+        }
+    }
+
+    mod threads {
+        use super::*;
+        use rand::random;
+
+        fn keys(tree: &KDTree<Point2D>) -> Vec<(F64, F64)> {
+            tree.iter().map(|p| (p.x.into(), p.y.into())).collect()
+        }
+
+        #[test]
+        fn same_tree_as_make() {
+            for len in [0, 1, 100, 5000, 20000] {
+                // a coarse grid, so that there are runs of equal keys
+                let data: Vec<Point2D> = (0..len)
+                    .map(|_| ((random::<u8>() % 50) as f64, random()).into())
+                    .collect();
+                let sequential = KDTree::make(data.clone());
+                for threads in [0, 1, 2, 3, 8] {
+                    let threaded = KDTree::make_threads(data.clone(), threads);
+                    assert_eq!(threaded.len(), len);
+                    assert!(threaded.validate());
+                    assert_eq!(threaded.shape_signature(), sequential.shape_signature());
+                    assert_eq!(keys(&threaded), keys(&sequential));
+                }
+            }
+        }
+
+        #[test]
+        fn eight_threads() {
+            let data: Vec<Point2D> = (0..300_000).map(|_| (random(), random()).into()).collect();
+            let tree = KDTree::make_threads(data.clone(), 8);
+            assert_eq!(tree.len(), data.len());
+            assert!(tree.depth() <= 20);
+            for _ in 0..100 {
+                let search = Point2D::from((random(), random()));
+                let nearest = tree.find_nearest(&search).unwrap();
+                let brute = data.iter().map(|p| Point2D::distance(&search, p)).min();
+                assert_eq!(Some(Point2D::distance(&search, nearest)), brute);
+            }
+        }
+    }
+
+    mod medoid {
+        use super::*;
+        use rand::random;
+
+        #[test]
+        fn center_of_a_cross() {
+            let data: Vec<Point2D> = [
+                (2.0, 0.0),
+                (1.0, 0.0),
+                (-1.0, 0.0),
+                (-2.0, 0.0),
+                (0.0, 2.0),
+                (0.0, 1.0),
+                (0.0, -1.0),
+                (0.0, -2.0),
+                (0.0, 0.0),
+            ]
+            .into_iter()
+            .map(Point2D::from)
+            .collect();
+            let tree = KDTree::make(data);
+            let medoid = tree.medoid().unwrap();
+            assert_eq!((medoid.x, medoid.y), (0.0, 0.0));
+
+            assert!(KDTree::<Point2D>::make(Vec::new()).medoid().is_none());
+            let single = KDTree::make(vec![Point2D::from((3.0, 4.0))]);
+            assert_eq!(single.medoid().map(|p| (p.x, p.y)), Some((3.0, 4.0)));
+        }
+
+        #[test]
+        fn brute_force() {
+            let data: Vec<Point2D> = (0..50).map(|_| (random(), random()).into()).collect();
+            let tree = KDTree::make(data.clone());
+            let total = |point: &Point2D| -> f64 {
+                data.iter()
+                    .map(|other| f64::from(Point2D::distance(point, other)))
+                    .sum()
+            };
+            let best = data.iter().map(total).fold(f64::INFINITY, f64::min);
+            // the sums are added up in a different order
+            assert!((total(tree.medoid().unwrap()) - best).abs() < 1e-9);
+        }
+    }
+
+    mod from_sorted {
+        use super::*;
+        use crate::counting::{key_calls, Counted};
+        use rand::random;
+
+        fn sorted_by(axis: usize, n: usize) -> Vec<Point2D> {
+            let mut data: Vec<Point2D> = (0..n).map(|_| (random(), random()).into()).collect();
+            data.sort_by(make_compare(axis));
+            data
+        }
+
+        #[test]
+        fn presortedness() {
+            let data = sorted_by(0, 500);
+            assert_eq!(KDTree::axis_presortedness(&data, 0), 0.0);
+            let reversed: Vec<Point2D> = data.iter().rev().cloned().collect();
+            assert_eq!(KDTree::axis_presortedness(&reversed, 0), 1.0);
+
+            // about half of the pairs of random points are out of order, in either dimension
+            let shuffled: Vec<Point2D> = (0..500).map(|_| (random(), random()).into()).collect();
+            for dimension in 0..2 {
+                let presortedness = KDTree::axis_presortedness(&shuffled, dimension);
+                assert!((0.4..0.6).contains(&presortedness), "{presortedness}");
+            }
+
+            // against counting every pair
+            let small: Vec<Point2D> = (0..60)
+                .map(|_| ((random::<u8>() % 8) as f64, 0.0).into())
+                .collect();
+            let pairs = (0..60)
+                .flat_map(|i| (i + 1..60).map(move |j| (i, j)))
+                .filter(|&(i, j)| small[j].x < small[i].x)
+                .count();
+            assert_eq!(
+                KDTree::axis_presortedness(&small, 0),
+                pairs as f64 / (60 * 59 / 2) as f64
+            );
+            assert_eq!(KDTree::axis_presortedness(&small, 1), 0.0);
+            assert_eq!(KDTree::<Point2D>::axis_presortedness(&[], 0), 0.0);
+        }
+
+        #[test]
+        fn agrees_with_make() {
+            for axis in [0, 1] {
+                let data = sorted_by(axis, 500);
+                let tree = KDTree::from_sorted_by_axis(data.clone(), axis);
+                assert!(tree.validate());
+                assert_eq!(tree.len(), 500);
+                assert_eq!(tree.iter().count(), 500);
+                assert_eq!(tree.root.as_ref().unwrap().dimension(), axis);
+                assert_eq!(tree.depth(), KDTree::<Point2D>::optimal_depth(500));
+
+                let expected = KDTree::make(data);
+                for _ in 0..50 {
+                    let search = Point2D::from((random::<f64>(), random::<f64>()));
+                    assert!(std::ptr::eq(
+                        tree.find_nearest(&search).unwrap(),
+                        tree.iter()
+                            .min_by_key(|p| Point2D::distance(&search, p))
+                            .unwrap()
+                    ));
+                    assert_eq!(
+                        tree.find_k_nearest(&search, 5)
+                            .into_iter()
+                            .map(|p| Point2D::distance(&search, p))
+                            .collect::<Vec<_>>(),
+                        expected
+                            .find_k_nearest(&search, 5)
+                            .into_iter()
+                            .map(|p| Point2D::distance(&search, p))
+                            .collect::<Vec<_>>()
+                    );
+                }
+            }
+
+            assert!(KDTree::<Point2D>::from_sorted_by_axis(Vec::new(), 1).is_empty());
+            let single = KDTree::from_sorted_by_axis(vec![Point2D::from((1.0, 2.0))], 1);
+            assert_eq!(single.len(), 1);
+            assert!(single.validate());
+
+            // equal keys around the middle
+            let runs: Vec<Point2D> = (0..40)
+                .map(|i| ((i / 10) as f64, i as f64).into())
+                .collect();
+            let runs = KDTree::from_sorted_by_axis(runs, 0);
+            assert!(runs.validate());
+        }
+
+        #[test]
+        #[should_panic(expected = "axis 2 beyond")]
+        fn axis_beyond_dimensions() {
+            KDTree::from_sorted_by_axis(sorted_by(0, 10), 2);
+        }
+
+        #[test]
+        fn try_from_sorted() {
+            assert_eq!(
+                KDTree::try_from_sorted_by_axis(sorted_by(0, 10), 2).unwrap_err(),
+                Error::DimensionMismatch {
+                    expected: 3,
+                    got: 2
+                }
+            );
+            assert!(KDTree::try_from_sorted_by_axis(sorted_by(1, 10), 1)
+                .unwrap()
+                .validate());
+            assert!(KDTree::<Point2D>::try_from_sorted_by_axis(Vec::new(), 2)
+                .unwrap()
+                .is_empty());
+            let mixed = vec![DynPoint::from(vec![0.0; 2]), DynPoint::from(vec![1.0; 3])];
+            assert!(KDTree::try_from_sorted_by_axis(mixed, 0).is_err());
+        }
+
+        #[test]
+        fn unsorted_is_invalid() {
+            let mut data = sorted_by(0, 50);
+            data.reverse();
+            assert!(!KDTree::from_sorted_by_axis(data, 0).validate());
+        }
+
+        #[test]
+        fn skips_partitioning_the_root() {
+            let data: Vec<Counted> = sorted_by(0, 4000).into_iter().map(Counted).collect();
+            let calls = |f: &dyn Fn()| key_calls(f).1;
+            let sorted = calls(&|| {
+                KDTree::from_sorted_by_axis(data.clone(), 0);
+            });
+            // the subtrees are made from the halves as they are
+            let layout = Layout::default();
+            let below = calls(&|| {
+                Node::make(data[..2000].to_vec(), 1, &layout);
+                Node::make(data[2001..].to_vec(), 1, &layout);
+            });
+            let partitioned = calls(&|| {
+                partition(&mut data.clone(), make_compare(0));
+            });
+            let checks = if cfg!(debug_assertions) {
+                calls(&|| check_key_order(&data))
+            } else {
+                0
+            };
+            // splitting the root looks at no key at all, partitioning at every one
+            assert_eq!(sorted, below + checks);
+            assert!(partitioned >= 4000);
+        }
+    }
+
+    mod cloned {
+        use super::*;
+        use rand::random;
+
+        fn key(p: &Point2D) -> (F64, F64) {
+            (p.x.into(), p.y.into())
+        }
+
+        fn sorted_keys<'p>(points: impl IntoIterator<Item = &'p Point2D>) -> Vec<(F64, F64)> {
+            let mut keys: Vec<_> = points.into_iter().map(key).collect();
+            keys.sort();
+            keys
+        }
+
+        #[test]
+        fn agree_with_references() {
+            let data: Vec<Point2D> = (0..300).map(|_| (random(), random()).into()).collect();
+            let tree = KDTree::make(data);
+            for _ in 0..20 {
+                let search = Point2D::from((random::<f64>(), random::<f64>()));
+                let radius = F64::from(0.02);
+                assert_eq!(
+                    sorted_keys(&tree.find_within_radius_cloned(&search, radius)),
+                    sorted_keys(tree.find_within_radius(&search, radius))
+                );
+
+                let (a, b) = (random::<f64>(), random::<f64>());
+                let min = Point2D::from((a.min(b), 0.2));
+                let max = Point2D::from((a.max(b), 0.6));
+                let mut in_range = tree.fold_in_range(&min, &max, Vec::new(), |mut found, p| {
+                    found.push(key(p));
+                    found
+                });
+                in_range.sort();
+                assert_eq!(
+                    sorted_keys(&tree.find_in_range_cloned(&min, &max)),
+                    in_range
+                );
+
+                let cloned: Vec<_> = tree
+                    .find_k_nearest_cloned(&search, 7)
+                    .into_iter()
+                    .map(|(p, distance)| (key(&p), distance))
+                    .collect();
+                let references: Vec<_> = tree
+                    .find_k_nearest_with_distances(&search, 7)
+                    .into_iter()
+                    .map(|(p, distance)| (key(p), distance))
+                    .collect();
+                assert_eq!(cloned, references);
+            }
+            assert!(tree.find_k_nearest_cloned(&(0.5, 0.5).into(), 0).is_empty());
+        }
+
+        #[test]
+        fn outlive_the_tree() {
+            let mut tree = KDTree::make(vec![Point2D::from((0.0, 0.0)), (1.0, 1.0).into()]);
+            let search = Point2D::from((0.0, 0.0));
+            let within = tree.find_within_radius_cloned(&search, F64::from(1.0));
+            let in_range = tree.find_in_range_cloned(&search, &(2.0, 2.0).into());
+            let nearest = tree.find_k_nearest_cloned(&search, 1);
+            tree.insert((0.1, 0.1).into());
+            drop(tree);
+            assert_eq!(sorted_keys(&within), [key(&search)]);
+            assert_eq!(in_range.len(), 2);
+            assert_eq!(key(&nearest[0].0), key(&search));
+        }
+    }
+
+    mod depth {
+        use super::*;
+
+        #[test]
+        fn optimal() {
+            for (size, depth) in [(0, 0), (1, 1), (7, 3), (8, 4), (1000, 10)] {
+                assert_eq!(KDTree::<Point2D>::optimal_depth(size), depth);
+            }
+        }
+
+        #[test]
+        fn balanced_make() {
+            for size in [0, 1, 7, 8, 1000] {
+                let data: Vec<Point2D> =
+                    (0..size).map(|i| (i as f64, -(i as f64)).into()).collect();
+                let tree = KDTree::make(data);
+                assert_eq!(tree.depth(), KDTree::<Point2D>::optimal_depth(size));
+            }
+        }
+
+        #[test]
+        fn shape() {
+            use rand::random;
+
+            let data: Vec<Point2D> = (0..100)
+                .map(|_| (random::<f64>(), random::<f64>()).into())
+                .collect();
+            // strictly increasing in every coordinate, so every comparison comes out the same
+            let transformed: Vec<Point2D> = data
+                .iter()
+                .map(|p| (2.0 * p.x + 5.0, p.y.powi(3)).into())
+                .collect();
+            let shape = KDTree::make(data.clone()).shape_signature();
+            assert_eq!(shape.len(), 100);
+            assert_eq!(shape[0].0, Some(1));
+            assert_eq!(KDTree::make(transformed).shape_signature(), shape);
+
+            let mut tree = KDTree::make(data);
+            tree.insert((0.5, 0.5).into());
+            assert_ne!(tree.shape_signature(), shape);
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .shape_signature()
+                .is_empty());
+        }
+
+        #[test]
+        fn compress_after_removal() {
+            use rand::random;
+
+            // increasing in both coordinates, so the inserts make a single chain
+            let data: Vec<Point2D> = (0..1000)
+                .map(|i| (i as f64, i as f64 + random::<f64>()).into())
+                .collect();
+            let mut tree = KDTree::make(Vec::new());
+            for point in &data {
+                tree.insert(point.clone());
+            }
+            let (min, max) = (Point2D::from((0.0, 0.0)), Point2D::from((199.0, 1000.0)));
+            assert_eq!(tree.prune_outside(&min, &max), 800);
+            assert_eq!(tree.depth(), 200);
+
+            let report = tree.compress();
+            assert_eq!(tree.depth(), KDTree::<Point2D>::optimal_depth(200));
+            assert!(report.spliced > 0);
+            assert!(report.eliminated > 100);
+            assert!(tree.validate());
+            assert_eq!(tree.len(), 200);
+            assert_eq!(tree.iter().count(), 200);
+            for _ in 0..100 {
+                let search = Point2D::from((random::<f64>() * 250.0, random::<f64>() * 250.0));
+                let expected = data[..200]
+                    .iter()
+                    .map(|p| Point2D::distance(&search, p))
+                    .min();
+                let found = tree.find_nearest(&search);
+                assert_eq!(found.map(|p| Point2D::distance(&search, p)), expected);
+            }
+
+            // nothing to do for a balanced tree
+            let data: Vec<Point2D> = (0..500).map(|_| (random(), random()).into()).collect();
+            let mut tree = KDTree::make(data);
+            assert_eq!(tree.compress().rebuilt, 0);
+            assert_eq!(tree.depth(), KDTree::<Point2D>::optimal_depth(500));
+            assert!(tree.validate());
+            assert_eq!(
+                KDTree::<Point2D>::make(Vec::new()).compress(),
+                CompressReport::default()
+            );
+        }
+
+        #[test]
+        fn split_planes() {
+            let data: Vec<Point2D> = (0..12)
+                .map(|i| (i as f64, (i * 7 % 12) as f64).into())
+                .collect();
+            let tree = KDTree::make(data);
+            let planes = tree.split_planes();
+            assert_eq!(planes.len(), 12);
+
+            // breadth first, left to right
+            let mut nodes = Vec::new();
+            for depth in 0..tree.depth() {
+                nodes.extend(tree.points_at_depth(depth));
+            }
+            for (plane, point) in planes.iter().zip(&nodes) {
+                if let Some((dimension, key)) = plane {
+                    assert_eq!(*key, point.kdkey(*dimension));
+                }
+            }
+            let root = tree.root.as_ref().unwrap();
+            assert_eq!(planes[0], Some((0, root.data.kdkey(0))));
+            let left = root.left.as_ref().unwrap();
+            assert_eq!(planes[1], Some((1, left.data.kdkey(1))));
+            // 1 + 2 + 4 nodes with children, 5 leaves at the bottom
+            assert!(planes[..7].iter().all(Option::is_some));
+            assert!(planes[7..].iter().all(Option::is_none));
+
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .split_planes()
+                .is_empty());
+            let single = KDTree::make(vec![Point2D::from((1.0, 1.0))]);
+            assert_eq!(single.split_planes(), [None]);
+        }
+
+        #[test]
+        fn levels() {
+            let data: Vec<Point2D> = (0..15)
+                .map(|i| (i as f64, (i * 7 % 15) as f64).into())
+                .collect();
+            let tree = KDTree::make(data);
+
+            let counts: Vec<_> = (0..5)
+                .map(|depth| tree.points_at_depth(depth).len())
+                .collect();
+            assert_eq!(counts, [1, 2, 4, 8, 0]);
+            assert!(std::ptr::eq(
+                tree.points_at_depth(0)[0],
+                &tree.root.as_ref().unwrap().data
+            ));
+        }
+
+        #[test]
+        fn buckets() {
+            use rand::random;
+
+            let shared = |_| (0.5, random::<f64>()).into();
+            let mut data: Vec<Point2D> = (0..1000).map(shared).collect();
+            let tree = KDTree::make_with_buckets(data.clone(), 16);
+            assert_eq!(tree.depth(), 1);
+
+            data.extend((0..200).map(|_| Point2D::from((random::<f64>(), random::<f64>()))));
+            let mut tree = KDTree::make_with_buckets(data.clone(), 16);
+            for p in (0..100).map(shared) {
+                data.push(p.clone());
+                tree.insert(p);
+            }
+            assert!(tree.depth() <= 1 + KDTree::<Point2D>::optimal_depth(200));
+            assert!(tree.validate());
+            assert_eq!(tree.iter().count(), data.len());
+
+            let radius = F64::from(0.001);
+            for _ in 0..20 {
+                let search: Point2D = (random::<f64>(), random::<f64>()).into();
+                let mut expected: Vec<_> =
+                    data.iter().map(|p| Point2D::distance(&search, p)).collect();
+                expected.sort();
+
+                let found: Vec<_> = tree
+                    .find_k_nearest_with_distances(&search, 5)
+                    .into_iter()
+                    .map(|(_, distance)| distance)
+                    .collect();
+                assert_eq!(found, expected[..5]);
+
+                let within = expected.iter().filter(|d| **d <= radius).count();
+                assert_eq!(tree.find_within_radius(&search, radius).len(), within);
+            }
+        }
+
+        #[test]
+        fn plain_node_size() {
+            // the node before counts and buckets, which must not grow it
+            #[allow(dead_code)]
+            struct Plain {
+                data: Point2D,
+                dimension: usize,
+                equal_left: bool,
+                left: Option<Box<Plain>>,
+                right: Option<Box<Plain>>,
+            }
+            assert_eq!(size_of::<Node<Point2D>>(), size_of::<Plain>());
+
+            fn plain(node: &Node<Point2D>) -> bool {
+                node.extra.is_none()
+                    && [&node.left, &node.right]
+                        .into_iter()
+                        .flatten()
+                        .all(|child| plain(child))
+            }
+            let data: Vec<Point2D> = (0..500)
+                .map(|_| (rand::random::<f64>(), rand::random::<f64>()).into())
+                .collect();
+            let mut tree = KDTree::make(data.clone());
+            for p in data.into_iter().take(100) {
+                tree.insert(p);
+            }
+            tree.prune_outside(&(0.0, 0.0).into(), &(0.5, 1.0).into());
+            assert!(tree.root.as_ref().is_none_or(plain));
+        }
+
+        #[test]
+        fn duplicate_heavy() {
+            use crate::counting::{key_calls, Counted};
+            use rand::random;
+
+            let n = 100_000;
+            let point = |i| match i % 10 {
+                0 => (random::<f64>(), random::<f64>()).into(),
+                _ => (0.5, 0.5).into(),
+            };
+            let data: Vec<Point2D> = (0..n).map(point).collect();
+
+            let (tree, accesses) =
+                key_calls(|| KDTree::make(data.iter().cloned().map(Counted).collect()));
+            let log = KDTree::<Point2D>::optimal_depth(n);
+            assert!(accesses <= 10 * n * log, "{accesses} key accesses");
+            assert_eq!(tree.depth(), log);
+            assert!(tree.validate());
+
+            for _ in 0..20 {
+                let search: Point2D = (random::<f64>(), random::<f64>()).into();
+                let expected = data
+                    .iter()
+                    .map(|p| Point2D::distance(&search, p))
+                    .min()
+                    .unwrap();
+                let found = tree.find_nearest(&Counted(search.clone())).unwrap();
+                assert_eq!(Point2D::distance(&search, &found.0), expected);
+            }
+        }
+
+        #[test]
+        fn small_sizes() {
+            use rand::random;
+
+            for size in 1..=64 {
+                // few distinct values, so there are plenty of ties
+                let data: Vec<Point2D> = (0..size)
+                    .map(|_| ((random::<u8>() % 4) as f64, (random::<u8>() % 4) as f64).into())
+                    .collect();
+                let tree = KDTree::make(data);
+                assert!(tree.validate());
+                assert_eq!(tree.iter().count(), size);
+            }
+        }
+
+        fn sorted_inserts(policy: DepthPolicy) -> KDTree<Point2D> {
+            let mut tree = KDTree::make(Vec::new())
+                .with_depth_policy(policy)
+                .with_max_depth(20);
+            for i in 0..300 {
+                tree.insert((i as f64, i as f64).into());
+                if policy == DepthPolicy::RebuildSubtree {
+                    // depth counts levels, the root is at depth 0
+                    assert!(tree.depth() <= tree.max_depth() + 1);
+                }
+                assert_eq!(
+                    tree.needs_rebalance(),
+                    policy == DepthPolicy::Flag && i > 20
+                );
+            }
+            assert!(tree.validate());
+            assert_eq!(tree.len(), 300);
+
+            let search: Point2D = (100.2, 100.4).into();
+            assert_eq!(tree.find_nearest(&search).unwrap().x, 100.0);
+            tree
+        }
+
+        #[test]
+        fn depth_policies() {
+            assert_eq!(sorted_inserts(DepthPolicy::Ignore).depth(), 300);
+
+            let mut flagged = sorted_inserts(DepthPolicy::Flag);
+            assert_eq!(flagged.depth(), 300);
+            flagged.rebalance();
+            assert!(!flagged.needs_rebalance());
+            assert_eq!(flagged.depth(), KDTree::<Point2D>::optimal_depth(300));
+
+            let rebuilt = sorted_inserts(DepthPolicy::RebuildSubtree);
+            assert!(rebuilt.depth() <= 21);
+        }
+
+        #[test]
+        fn auto_maintenance() {
+            let mut tree = KDTree::<Point2D>::make(Vec::new());
+            tree.set_auto_maintenance(100, 2.0);
+            for i in 0..2000 {
+                tree.insert((i as f64, i as f64).into());
+                let optimal = KDTree::<Point2D>::optimal_depth(tree.len());
+                // at most `interval` sorted inserts since the last check
+                assert!(tree.depth() <= 2 * optimal + 100);
+                if i % 100 == 99 {
+                    assert!(tree.depth() <= 2 * optimal);
+                }
+            }
+            assert!(tree.validate());
+            assert_eq!(tree.len(), 2000);
+
+            tree.set_auto_maintenance(0, 2.0);
+            let depth = tree.depth();
+            for i in 2000..2200 {
+                tree.insert((i as f64, i as f64).into());
+            }
+            assert!(tree.depth() >= depth + 200 - 1);
+        }
+
+        #[test]
+        fn default_max_depth() {
+            let data: Vec<Point2D> = (0..1024).map(|i| (i as f64, 0.0).into()).collect();
+            let tree = KDTree::make(data);
+            assert_eq!(tree.len(), 1024);
+            assert_eq!(tree.max_depth(), 4 * 10 + 16);
+            assert_eq!(KDTree::<Point2D>::make(Vec::new()).max_depth(), 16);
+        }
+    }
+
+    mod nearest {
+        use super::*;
+
+        fn random_points(n: usize) -> Vec<Point2D> {
+            use rand::random;
+            (0..n)
+                .map(|_| (random::<f64>(), random::<f64>()).into())
+                .collect()
+        }
+
+        fn distance_to(search: &Point2D) -> impl Fn(&&Point2D) -> F64 + '_ {
+            move |p| Point2D::distance(search, p)
+        }
+
+        #[test]
+        fn k_nearest_brute_force() {
+            let data = random_points(200);
+            let tree = KDTree::make(data.clone());
+
+            for _ in 0..20 {
+                let search = random_points(1).remove(0);
+                let mut expected: Vec<_> = data.iter().map(|p| distance_to(&search)(&p)).collect();
+                expected.sort();
+                expected.truncate(7);
+
+                let found: Vec<_> = tree
+                    .find_k_nearest(&search, 7)
+                    .iter()
+                    .map(distance_to(&search))
+                    .collect();
+                assert_eq!(found, expected);
+            }
+        }
+
+        #[test]
+        fn k_nearest_beyond_len() {
+            let data = random_points(20);
+            let tree = KDTree::make(data.clone());
+            let search = random_points(1).remove(0);
+
+            // nothing is reserved for more candidates than there are points
+            let k = usize::MAX;
+            assert_eq!(tree.find_k_nearest(&search, k).len(), 20);
+            assert_eq!(tree.find_k_nearest_cloned(&search, k).len(), 20);
+            assert_eq!(tree.find_k_nearest_recall(&search, k, 1.0).len(), 20);
+            assert_eq!(
+                KDTree::make(data).freeze().find_k_nearest(&search, k).len(),
+                20
+            );
+        }
+
+        #[test]
+        fn k_nearest_ties() {
+            // a grid, around its center 4 points tie at every ring
+            let data: Vec<Point2D> = (0..81)
+                .map(|i| ((i % 9) as f64, (i / 9) as f64).into())
+                .collect();
+            let tree = KDTree::make(data.clone());
+            let frozen = KDTree::make(data).freeze();
+            let search = (4.0, 4.0).into();
+
+            for k in [0, 1, 3, 5, 10, 81, 100] {
+                let found = tree.find_k_nearest(&search, k);
+                assert_eq!(found.len(), k.min(81));
+                assert!(found
+                    .windows(2)
+                    .all(|w| distance_to(&search)(&w[0]) <= distance_to(&search)(&w[1])));
+                for _ in 0..3 {
+                    let again = tree.find_k_nearest(&search, k);
+                    assert!(found.iter().zip(&again).all(|(a, b)| std::ptr::eq(*a, *b)));
+                    let frozen_found = frozen.find_k_nearest(&search, k);
+                    let frozen_again = frozen.find_k_nearest(&search, k);
+                    assert!(frozen_found
+                        .iter()
+                        .zip(&frozen_again)
+                        .all(|(a, b)| std::ptr::eq(*a, *b)));
+                }
+            }
+        }
+
+        #[test]
+        fn k_nearest_duplicates() {
+            // copies of the search point spread among the others, in plain and bucket trees
+            let mut data = random_points(100);
+            for i in 0..6 {
+                data.insert(i * 17, (0.5, 0.5).into());
+            }
+            let search = (0.5, 0.5).into();
+            for tree in [
+                KDTree::make(data.clone()),
+                KDTree::make_with_buckets(data.clone(), 4),
+            ] {
+                let found = tree.find_k_nearest(&search, 6);
+                assert!(found
+                    .iter()
+                    .all(|p| distance_to(&search)(p) == F64::from(0.0)));
+                for (i, a) in found.iter().enumerate() {
+                    assert!(found[..i].iter().all(|b| !std::ptr::eq(*a, *b)));
+                }
+                assert_eq!(tree.find_k_nearest(&search, 106).len(), 106);
+                assert_eq!(tree.find_k_nearest(&search, 200).len(), 106);
+            }
+        }
+
+        #[test]
+        fn in_slab_brute_force() {
+            use rand::random;
+
+            let data = random_points(300);
+            let tree = KDTree::make(data.clone());
+
+            for _ in 0..50 {
+                let search = random_points(1).remove(0);
+                let dimension = random::<usize>() % 2;
+                let (a, b) = (random::<f64>(), random::<f64>());
+                let (lo, hi) = (F64::from(a.min(b)), F64::from(a.max(b)));
+
+                let expected = data
+                    .iter()
+                    .filter(|p| (lo..=hi).contains(&p.kdkey(dimension)))
+                    .map(|p| distance_to(&search)(&p))
+                    .min();
+                let found = tree.find_nearest_in_slab(&search, dimension, lo, hi);
+                assert!(found.is_none_or(|p| (lo..=hi).contains(&p.kdkey(dimension))));
+                assert_eq!(found.map(|p| distance_to(&search)(&p)), expected);
+            }
+
+            let (lo, hi) = (F64::from(2.0), F64::from(3.0));
+            assert!(tree.find_nearest_in_slab(&data[0], 0, lo, hi).is_none());
+            let key = data[7].kdkey(1);
+            let found = tree.find_nearest_in_slab(&data[0], 1, key, key).unwrap();
+            assert!(std::ptr::eq(
+                found,
+                tree.iter().find(|p| p.kdkey(1) == key).unwrap()
+            ));
+        }
+
+        #[test]
+        fn within_radius_brute_force() {
+            let data = random_points(200);
+            let tree = KDTree::make(data.clone());
+
+            for _ in 0..20 {
+                let search = random_points(1).remove(0);
+                let radius = F64::from(0.05);
+                let mut expected: Vec<_> = data
+                    .iter()
+                    .map(|p| distance_to(&search)(&p))
+                    .filter(|d| *d <= radius)
+                    .collect();
+                expected.sort();
+
+                let mut found: Vec<_> = tree
+                    .find_within_radius(&search, radius)
+                    .iter()
+                    .map(distance_to(&search))
+                    .collect();
+                found.sort();
+                assert_eq!(found, expected);
+            }
+        }
+
+        #[test]
+        fn within_radius_3d() {
+            use rand::random;
+
+            let random_point = || Point3D::<F64>::from([random::<f64>(), random(), random()]);
+            let data: Vec<Point3D<F64>> = (0..500).map(|_| random_point()).collect();
+            let tree = KDTree::make(data.clone());
+            let within = |search: &Point3D<F64>, radius: F64| {
+                let mut found: Vec<F64> = tree
+                    .find_within_radius(search, radius)
+                    .into_iter()
+                    .map(|p| Point3D::distance(search, p))
+                    .collect();
+                found.sort();
+                let mut expected: Vec<F64> = data
+                    .iter()
+                    .map(|p| Point3D::distance(search, p))
+                    .filter(|d| *d <= radius)
+                    .collect();
+                expected.sort();
+                assert_eq!(found, expected);
+                found.len()
+            };
+
+            for _ in 0..50 {
+                // squared, points within 0.2 of the search
+                assert!(within(&random_point(), F64::from(0.2 * 0.2)) < data.len());
+                // no two points of the unit cube are more than 3 apart, squared
+                assert_eq!(within(&random_point(), F64::from(3.0)), data.len());
+            }
+            assert_eq!(within(&Point3D::from([5.0, 5.0, 5.0]), F64::from(1.0)), 0);
+            assert_eq!(within(&data[7], F64::from(0.0)), 1);
+        }
+
+        #[test]
+        fn farthest_within_radius_brute_force() {
+            let data = random_points(200);
+            let tree = KDTree::make(data.clone());
+
+            for radius in [0.0, 0.01, 0.05, 0.3, 2.0] {
+                let radius = F64::from(radius);
+                let search = random_points(1).remove(0);
+                let expected = data
+                    .iter()
+                    .map(|p| distance_to(&search)(&p))
+                    .filter(|d| *d <= radius)
+                    .max();
+                let found = tree.farthest_within_radius(&search, radius);
+                assert_eq!(found.as_ref().map(distance_to(&search)), expected);
+            }
+
+            // exactly on the radius
+            let tree = KDTree::make(vec![Point2D::from((0.0, 0.0)), Point2D::from((3.0, 4.0))]);
+            let found = tree.farthest_within_radius(&(0.0, 0.0).into(), F64::from(25.0));
+            assert_eq!(found.map(|p| (p.x, p.y)), Some((3.0, 4.0)));
+            let found = tree.farthest_within_radius(&(10.0, 10.0).into(), F64::from(1.0));
+            assert!(found.is_none());
+        }
+
+        fn subtree<'t, T>(node: &'t Node<T>, root: &T) -> Option<&'t Node<T>> {
+            if std::ptr::eq(&node.data, root) {
+                return Some(node);
+            }
+            [&node.left, &node.right]
+                .into_iter()
+                .flatten()
+                .find_map(|child| subtree(child, root))
+        }
+
+        fn collect<'t, T>(node: &'t Node<T>, points: &mut Vec<&'t T>) {
+            points.extend(node.points());
+            for child in [&node.left, &node.right].into_iter().flatten() {
+                collect(child, points);
+            }
+        }
+
+        #[test]
+        fn pruned_subtrees_are_farther() {
+            let tree = KDTree::make(random_points(500));
+            let root = tree.root.as_ref().unwrap();
+
+            let mut pruned_any = false;
+            for _ in 0..50 {
+                let search = random_points(1).remove(0);
+                let (nearest, pruned) = tree.find_nearest_with_pruned(&search);
+                let nearest = nearest.unwrap();
+                let nearest_distance = Point2D::distance(&search, nearest);
+                assert!(std::ptr::eq(nearest, tree.find_nearest(&search).unwrap()));
+
+                for pruned_root in pruned {
+                    pruned_any = true;
+                    let mut points = Vec::new();
+                    collect(subtree(root, pruned_root).unwrap(), &mut points);
+                    for point in points {
+                        assert!(Point2D::distance(&search, point) >= nearest_distance);
+                    }
+                }
+            }
+            assert!(pruned_any);
+        }
+
+        #[test]
+        fn in_halfspace() {
+            let data: Vec<Point2D> = [(-0.5, 0.0), (2.0, 0.0), (-3.0, 1.0), (0.0, 4.0)]
+                .into_iter()
+                .map(Point2D::from)
+                .collect();
+            let tree = KDTree::make(data);
+            let origin = Point2D::from((0.0, 0.0));
+            let coordinates = |p: Option<&Point2D>| p.map(|p| (p.x, p.y));
+
+            // the nearest point is behind, the one in front farther than one on the plane
+            let found = tree.find_nearest_in_halfspace(&origin, &origin, &[1.0, 0.0]);
+            assert_eq!(coordinates(found), Some((2.0, 0.0)));
+            let found = tree.find_nearest_in_halfspace(&(0.0, 3.0).into(), &origin, &[1.0, 0.0]);
+            assert_eq!(coordinates(found), Some((0.0, 4.0)));
+            let found = tree.find_nearest_in_halfspace(&origin, &origin, &[-1.0, 0.0]);
+            assert_eq!(coordinates(found), Some((-0.5, 0.0)));
+            let found = tree.find_nearest_in_halfspace(&origin, &(0.0, 5.0).into(), &[0.0, 1.0]);
+            assert_eq!(coordinates(found), None);
+        }
+
+        #[test]
+        fn in_halfspace_brute_force() {
+            use rand::random;
+            // a grid, so that planes along the axes pass through points and splits
+            let data: Vec<Point2D> = (0..400)
+                .map(|i| ((i % 20) as f64, (i / 20) as f64).into())
+                .collect();
+            let tree = KDTree::make(data.clone());
+            for round in 0..200 {
+                let search = Point2D::from((random::<f64>() * 20.0, random::<f64>() * 20.0));
+                let plane_point = Point2D::from(((round % 20) as f64, random::<f64>() * 20.0));
+                let angle = random::<f64>() * std::f64::consts::TAU;
+                let normal = if round % 2 == 0 {
+                    [angle.cos(), angle.sin()]
+                } else {
+                    [[1.0, 0.0], [-1.0, 0.0]][round % 4 / 2]
+                };
+
+                let in_front = |p: &Point2D| {
+                    (p.x - plane_point.x) * normal[0] + (p.y - plane_point.y) * normal[1] >= 0.0
+                };
+                let expected = data
+                    .iter()
+                    .filter(|p| in_front(p))
+                    .map(|p| Point2D::distance(&search, p))
+                    .min();
+                let found = tree.find_nearest_in_halfspace(&search, &plane_point, &normal);
+                assert!(found.is_none_or(in_front));
+                assert_eq!(found.map(|p| Point2D::distance(&search, p)), expected);
+            }
+        }
+
+        #[test]
+        fn to_any() {
+            let data = random_points(1000);
+            let tree = KDTree::make(data.clone());
+            for n_queries in 1..6 {
+                let queries = random_points(n_queries);
+                // the obvious way, one search per query
+                let expected = queries
+                    .iter()
+                    .map(|query| Point2D::distance(query, tree.find_nearest(query).unwrap()))
+                    .min();
+                let (point, index, distance) = tree.find_nearest_to_any(&queries).unwrap();
+                assert_eq!(Some(distance), expected);
+                assert_eq!(Point2D::distance(&queries[index], point), distance);
+            }
+
+            assert!(tree.find_nearest_to_any(&[]).is_none());
+            let empty = KDTree::<Point2D>::make(Vec::new());
+            assert!(empty.find_nearest_to_any(&random_points(3)).is_none());
+            // the same query twice, the first one is reported
+            let query = Point2D::from((0.5, 0.5));
+            let (_, index, _) = tree.find_nearest_to_any(&[query.clone(), query]).unwrap();
+            assert_eq!(index, 0);
+        }
+
+        #[test]
+        fn to_any_single_traversal() {
+            use crate::counting::{distance_calls, Counted};
+
+            let calls = |f: &dyn Fn()| distance_calls(f).1;
+
+            let tree = KDTree::make(random_points(10000).into_iter().map(Counted).collect());
+            let (mut single, mut separate) = (0, 0);
+            for _ in 0..20 {
+                let queries: Vec<Counted> = random_points(4).into_iter().map(Counted).collect();
+                // every node holds one point, which is compared to every query
+                single += calls(&|| {
+                    tree.find_nearest_to_any(&queries);
+                }) / queries.len();
+                separate += calls(&|| {
+                    for query in &queries {
+                        tree.find_nearest(query);
+                    }
+                });
+            }
+            assert!(single < separate, "{single} nodes vs {separate}");
+        }
+
+        #[test]
+        fn until_good_enough() {
+            use std::cell::Cell;
+
+            let data = random_points(1000);
+            let tree = KDTree::make(data.clone());
+            for _ in 0..20 {
+                let search = random_points(1).remove(0);
+                let threshold = F64::from(0.01);
+                let calls = Cell::new(0);
+                let found = tree
+                    .find_nearest_until(&search, |_, distance| {
+                        calls.set(calls.get() + 1);
+                        distance <= threshold
+                    })
+                    .unwrap();
+                let nearest = tree.find_nearest(&search).unwrap();
+                if Point2D::distance(&search, nearest) <= threshold {
+                    assert!(Point2D::distance(&search, found) <= threshold);
+                } else {
+                    // nothing was good enough, so the whole search ran
+                    assert!(std::ptr::eq(found, nearest));
+                }
+                // every improvement is judged until one is good enough
+                let improvements = tree
+                    .explain_nearest(&search)
+                    .events
+                    .iter()
+                    .filter(|event| matches!(event, TraceEvent::NewBest { .. }))
+                    .count();
+                assert!(calls.get() <= improvements);
+
+                // anything will do: the first point the search improves on nothing with
+                let first = tree
+                    .explain_nearest(&search)
+                    .events
+                    .into_iter()
+                    .find_map(|event| match event {
+                        TraceEvent::NewBest { point, .. } => Some(point),
+                        _ => None,
+                    })
+                    .unwrap();
+                let anything = tree.find_nearest_until(&search, |_, _| true).unwrap();
+                assert!(std::ptr::eq(anything, first));
+            }
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .find_nearest_until(&(0.0, 0.0).into(), |_, _| true)
+                .is_none());
+        }
+
+        #[test]
+        fn all_nearest_by() {
+            // (1, 0), (0, 1) and (-1, 0) are all 1 away from the origin
+            let data: Vec<Point2D> = [(1.0, 0.0), (3.0, 3.0), (0.0, 1.0), (-1.0, 0.0), (2.0, 0.0)]
+                .into_iter()
+                .map(Point2D::from)
+                .collect();
+            let tree = KDTree::make(data);
+            let search = Point2D::from((0.0, 0.0));
+            assert_eq!(tree.find_all_nearest(&search).len(), 3);
+
+            let coordinates = |points: Vec<&Point2D>| -> Vec<(f64, f64)> {
+                points.iter().map(|p| (p.x, p.y)).collect()
+            };
+            assert_eq!(
+                coordinates(tree.find_all_nearest_by(&search, |p| F64::from(p.x))),
+                [(-1.0, 0.0), (0.0, 1.0), (1.0, 0.0)]
+            );
+            // equal secondary keys keep their order
+            let mut expected = vec![(0.0, 1.0)];
+            expected.extend(
+                coordinates(tree.find_all_nearest(&search))
+                    .into_iter()
+                    .filter(|&(_, y)| y == 0.0),
+            );
+            assert_eq!(
+                coordinates(tree.find_all_nearest_by(&search, |p| Reverse(F64::from(p.y)))),
+                expected
+            );
+
+            let single = tree.find_all_nearest_by(&(2.9, 2.9).into(), |p| F64::from(p.x));
+            assert_eq!(coordinates(single), [(3.0, 3.0)]);
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .find_all_nearest_by(&search, |p| F64::from(p.x))
+                .is_empty());
+        }
+
+        #[test]
+        fn within_cutoff() {
+            let data = random_points(1000);
+            let tree = KDTree::make(data);
+            for _ in 0..20 {
+                let search = random_points(1).remove(0);
+                let nearest = tree.find_nearest(&search).unwrap();
+                let distance = Point2D::distance(&search, nearest);
+                let (within, within_distance) =
+                    tree.find_nearest_within(&search, distance).unwrap();
+                assert!(std::ptr::eq(within, nearest));
+                assert_eq!(within_distance, distance);
+                // nothing is closer than the nearest point
+                let (dx, dy) = (search.x - nearest.x, search.y - nearest.y);
+                let closer = F64::from((dx * dx + dy * dy) * 0.99);
+                assert!(tree.find_nearest_within(&search, closer).is_none());
+            }
+            assert!(KDTree::<Point2D>::make(Vec::new())
+                .find_nearest_within(&(0.0, 0.0).into(), F64::from(1.0))
+                .is_none());
+        }
+
+        #[test]
+        fn within_cutoff_prunes() {
+            struct Entered(usize);
+            impl<'t> NearestHook<'t, Point2D> for Entered {
+                fn entered(&mut self, _: &'t Node<Point2D>, _: &F64, _: Option<&F64>) {
+                    self.0 += 1;
+                }
+            }
+
+            let tree = KDTree::make(random_points(1000));
+            let search = Point2D::from((10.0, 10.0));
+            assert!(tree.find_nearest_within(&search, F64::from(1.0)).is_none());
+
+            let root = tree.root.as_ref().unwrap();
+            let mut unbounded = Nearest::with_hook(&search, |_| true, Entered(0));
+            unbounded.visit(root);
+            let mut bounded =
+                Nearest::with_hook(&search, |_| true, Entered(0)).with_cutoff(F64::from(1.0));
+            bounded.visit(root);
+            assert!(bounded.best.is_none());
+            // only the path down towards the search is entered
+            assert!(bounded.hook.0 <= root.depth());
+            assert!(bounded.hook.0 < unbounded.hook.0);
+        }
+
+        #[test]
+        fn prune_slack() {
+            // (5, 0) at the root splits along x, (0, 0) and (10, 0) below it along y
+            let tree = KDTree::make(
+                [(0.0, 0.0), (5.0, 0.0), (10.0, 0.0)]
+                    .into_iter()
+                    .map(Point2D::from)
+                    .collect(),
+            );
+            // (0, 0) is 1 + 1 away, the split at x = 5 4 * 4
+            let (nearest, slack) = tree.find_nearest_with_prune_slack(&(1.0, 1.0).into());
+            assert_eq!((nearest.unwrap().x, nearest.unwrap().y), (0.0, 0.0));
+            assert_eq!(slack, [(F64::from(16.0), F64::from(2.0))]);
+            // (10, 0) is 0.5 * 0.5 + 2 * 2 away, the split at x = 5 4.5 * 4.5
+            let (nearest, slack) = tree.find_nearest_with_prune_slack(&(9.5, 2.0).into());
+            assert_eq!(nearest.unwrap().x, 10.0);
+            assert_eq!(slack, [(F64::from(20.25), F64::from(4.25))]);
+            // the split at x = 5 is 2 * 2 away, closer than the best point (5, 0) at 2 * 2 + 2 * 2,
+            // so both sides are searched
+            let (_, slack) = tree.find_nearest_with_prune_slack(&(3.0, 2.0).into());
+            assert!(slack.is_empty());
+
+            let tree = KDTree::make(random_points(500));
+            for _ in 0..50 {
+                let search = random_points(1).remove(0);
+                let (nearest, slack) = tree.find_nearest_with_prune_slack(&search);
+                let (_, pruned) = tree.find_nearest_with_pruned(&search);
+                assert_eq!(slack.len(), pruned.len());
+                let nearest_distance = Point2D::distance(&search, nearest.unwrap());
+                for (split, best) in slack {
+                    assert!(best <= split);
+                    assert!(nearest_distance <= best);
+                }
+            }
+        }
+
+        #[test]
+        fn biased_towards_hotspot() {
+            // a jittered grid, so that the comparison does not depend on luck
+            let jitter = |i: usize| ((i * 7919) % 1000) as f64 / 1e5;
+            let data: Vec<Point2D> = (0..2025)
+                .map(|i| {
+                    (
+                        (i % 45) as f64 / 45.0 + jitter(i),
+                        (i / 45) as f64 / 45.0 + jitter(i + 1),
+                    )
+                        .into()
+                })
+                .collect();
+            let balanced = KDTree::make(data.clone());
+            let mut biased = KDTree::make(data.clone());
+            let hotspot = Point2D::from((0.2, 0.7));
+            biased.rebuild_biased(&hotspot, 0.9);
+            assert!(biased.validate());
+            assert_eq!(biased.iter().count(), 2025);
+
+            let near_hotspot: Vec<Point2D> = (0..400)
+                .map(|i| {
+                    (
+                        0.19 + (i % 20) as f64 * 0.001,
+                        0.69 + (i / 20) as f64 * 0.001,
+                    )
+                        .into()
+                })
+                .collect();
+            let visited = |tree: &KDTree<Point2D>| {
+                near_hotspot
+                    .iter()
+                    .map(|search| {
+                        let trace = tree.explain_nearest(search);
+                        trace
+                            .events
+                            .iter()
+                            .filter(|event| matches!(event, TraceEvent::Visited { .. }))
+                            .count()
+                    })
+                    .sum::<usize>()
+            };
+            assert!(visited(&biased) < visited(&balanced));
+
+            for search in near_hotspot.iter().chain(&random_points(100)) {
+                let expected = data.iter().map(|p| distance_to(search)(&p)).min();
+                let found = biased.find_nearest(search).map(|p| distance_to(search)(&p));
+                assert_eq!(found, expected);
+            }
+
+            biased.rebuild_biased(&hotspot, 0.0);
+            assert_eq!(biased.depth(), KDTree::<Point2D>::optimal_depth(2000));
+        }
+
+        #[test]
+        fn explain_matches_search() {
+            for _ in 0..50 {
+                let tree = KDTree::make(random_points(30));
+                let root = tree.root.as_ref().unwrap();
+                let search = random_points(1).remove(0);
+                let trace = tree.explain_nearest(&search);
+                assert!(std::ptr::eq(
+                    trace.nearest.unwrap(),
+                    tree.find_nearest(&search).unwrap()
+                ));
+
+                let mut best = None;
+                for event in &trace.events {
+                    match event {
+                        TraceEvent::Visited { depth, split, .. } => {
+                            assert!(*depth < tree.depth());
+                            assert!(subtree(root, split).is_some());
+                        }
+                        TraceEvent::NewBest { distance, .. } => {
+                            assert!(best.is_none_or(|best| distance < best));
+                            best = Some(distance);
+                        }
+                        TraceEvent::Pruned {
+                            root: pruned_root,
+                            best_distance,
+                            ..
+                        } => {
+                            let mut points = Vec::new();
+                            collect(subtree(root, pruned_root).unwrap(), &mut points);
+                            for point in points {
+                                assert!(Point2D::distance(&search, point) >= *best_distance);
+                            }
+                        }
+                    }
+                }
+                assert_eq!(
+                    best,
+                    Some(&Point2D::distance(&search, trace.nearest.unwrap()))
+                );
+            }
+        }
+
+        #[test]
+        fn k_nearest_excluding_self() {
+            let tree = KDTree::make(random_points(100));
+            for point in tree.iter() {
+                let found = tree.find_k_nearest_excluding(point, 3);
+                assert_eq!(found.len(), 3);
+                assert!(found.iter().all(|other| !std::ptr::eq(*other, point)));
+
+                let with_self = tree.find_k_nearest(point, 4);
+                assert!(std::ptr::eq(with_self[0], point));
+            }
+
+            // a query that is not part of the tree excludes nothing
+            let search = random_points(1).remove(0);
+            assert_eq!(
+                tree.find_k_nearest_excluding(&search, 5).len(),
+                tree.find_k_nearest(&search, 5).len()
+            );
+        }
+
+        #[test]
+        fn k_distance_plot() {
+            let data = random_points(300);
+            let tree = KDTree::make(data.clone());
+            for k in [1, 4, 10] {
+                // the k-th smallest distance to the other points, for every point
+                let mut naive: Vec<F64> = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, point)| {
+                        let mut distances: Vec<F64> = data
+                            .iter()
+                            .enumerate()
+                            .filter(|(j, _)| i != *j)
+                            .map(|(_, other)| Point2D::distance(point, other))
+                            .collect();
+                        distances.sort();
+                        distances[k - 1]
+                    })
+                    .collect();
+                naive.sort();
+                assert_eq!(tree.k_distance_plot(k), naive);
+
+                let sqrt = tree.k_distance_plot_sqrt(k);
+                assert!(sqrt.windows(2).all(|pair| pair[0] <= pair[1]));
+                for (root, distance) in sqrt.iter().zip(naive) {
+                    assert_eq!(*root, f64::from(distance).sqrt());
+                }
+            }
+            assert!(tree.k_distance_plot(0).is_empty());
+        }
+
+        #[test]
+        fn avoiding() {
+            let data: Vec<Point2D> = vec![
+                (0.0, 0.0).into(),
+                (1.0, 1.0).into(),
+                (2.0, 2.5).into(),
+                (4.0, 4.0).into(),
+                (-3.0, 1.0).into(),
+            ];
+            let tree = KDTree::make(data);
+
+            let search = (1.2, 1.2).into();
+            assert_eq!(
+                tree.find_nearest(&search).map(|p| (p.x, p.y)),
+                Some((1.0, 1.0))
+            );
+
+            let forbidden_min = (0.5, 0.5).into();
+            let forbidden_max = (1.5, 1.5).into();
+            let found = tree.find_nearest_avoiding(&search, &forbidden_min, &forbidden_max);
+            assert_eq!(found.map(|p| (p.x, p.y)), Some((2.0, 2.5)));
+        }
+
+        #[test]
+        fn avoiding_everything() {
+            let data: Vec<Point2D> = vec![(0.0, 0.0).into(), (1.0, 1.0).into()];
+            let tree = KDTree::make(data);
+
+            let forbidden_min = (-1.0, -1.0).into();
+            let forbidden_max = (2.0, 2.0).into();
+            let found =
+                tree.find_nearest_avoiding(&(0.2, 0.0).into(), &forbidden_min, &forbidden_max);
+            assert!(found.is_none());
+        }
+
+        // the root splits at x = 1, (0, 0) ends up on its left and (2, 0) on its right
+        fn straddling() -> KDTree<Point2D> {
+            let tree = KDTree::<Point2D>::make(vec![
+                (0.0, 0.0).into(),
+                (1.0, 5.0).into(),
+                (2.0, 0.0).into(),
+            ]);
+            assert_eq!(tree.root.as_ref().unwrap().data.x, 1.0);
+            tree
+        }
+
+        fn xs<'t>(points: impl IntoIterator<Item = &'t Point2D>) -> Vec<f64> {
+            let mut xs: Vec<_> = points.into_iter().map(|p| p.x).collect();
+            xs.sort_by(f64::total_cmp);
+            xs
+        }
+
+        #[test]
+        fn equidistant_across_split() {
+            let tree = straddling();
+            for search in [(1.0, 0.0).into(), (1.0, -1.0).into()] {
+                let nearest = tree.find_nearest(&search).unwrap();
+                assert_eq!(nearest.y, 0.0);
+                assert_eq!(
+                    Point2D::distance(&search, nearest),
+                    Point2D::distance(&search, &(0.0, 0.0).into())
+                );
+
+                assert_eq!(xs(tree.find_k_nearest(&search, 2)), [0.0, 2.0]);
+                let radius = Point2D::distance(&search, &(2.0, 0.0).into());
+                assert_eq!(xs(tree.find_within_radius(&search, radius)), [0.0, 2.0]);
+            }
+        }
+
+        #[test]
+        fn radius_through_far_side_point() {
+            let tree = straddling();
+
+            // near side is the left one, (2, 0) is exactly 1.5 away
+            let search = (0.5, 0.0).into();
+            assert_eq!(
+                xs(tree.find_within_radius(&search, F64::from(2.25))),
+                [0.0, 2.0]
+            );
+            assert_eq!(xs(tree.find_within_radius(&search, F64::from(2.2))), [0.0]);
+
+            // a radius as wide as the distance to the plane itself
+            let search = (0.0, 0.0).into();
+            assert_eq!(xs(tree.find_within_radius(&search, F64::from(1.0))), [0.0]);
+            assert_eq!(
+                xs(tree.find_within_radius(&search, F64::from(4.0))),
+                [0.0, 2.0]
+            );
+        }
+
+        #[test]
+        fn ties_on_a_grid() {
+            // integer coordinates put many points exactly on split planes and at equal distances
+            let data: Vec<Point2D> = (0..100)
+                .map(|i| ((i % 10) as f64, (i / 10) as f64).into())
+                .collect();
+            let tree = KDTree::make(data.clone());
+
+            for i in 0..100 {
+                let search: Point2D = ((i % 19) as f64 / 2.0, (i / 10) as f64 + 0.5).into();
+                let mut expected: Vec<_> = data.iter().map(|p| distance_to(&search)(&p)).collect();
+                expected.sort();
+
+                for k in [1, 4, 9] {
+                    let found: Vec<_> = tree
+                        .find_k_nearest(&search, k)
+                        .iter()
+                        .map(distance_to(&search))
+                        .collect();
+                    assert_eq!(found, expected[..k]);
+                }
+
+                let radius = expected[6];
+                let within = expected.iter().filter(|d| **d <= radius).count();
+                assert_eq!(tree.find_within_radius(&search, radius).len(), within);
+            }
+        }
+
+        #[test]
+        fn k_nearest_recall() {
+            let data = random_points(2000);
+            let tree = KDTree::make(data);
+            let k = 10;
+
+            for target in [0.3, 0.7, 0.9] {
+                let mut total = 0;
+                for _ in 0..100 {
+                    let search = random_points(1).remove(0);
+                    let exact = tree.find_k_nearest(&search, k);
+                    let approximate = tree.find_k_nearest_recall(&search, k, target);
+                    assert_eq!(approximate.len(), k);
+                    total += approximate
+                        .iter()
+                        .filter(|p| exact.iter().any(|e| std::ptr::eq(**p, *e)))
+                        .count();
+                }
+                let recall = total as f64 / (100 * k) as f64;
+                assert!(recall >= target, "recall {recall} below {target}");
+            }
+
+            for _ in 0..20 {
+                let search = random_points(1).remove(0);
+                let distances = |points: Vec<&Point2D>| -> Vec<_> {
+                    points.iter().map(distance_to(&search)).collect()
+                };
+                assert_eq!(
+                    distances(tree.find_k_nearest_recall(&search, k, 1.0)),
+                    distances(tree.find_k_nearest(&search, k))
+                );
+            }
+            assert!(tree
+                .find_k_nearest_recall(&(0.5, 0.5).into(), 0, 1.0)
+                .is_empty());
+        }
+
+        #[test]
+        fn nearest_map() {
+            let data = random_points(100);
+            let tree = KDTree::make(data);
+            let search = random_points(1).remove(0);
+
+            let x = tree.find_nearest_map(&search, |p| p.x);
+            assert_eq!(x, tree.find_nearest(&search).map(|p| p.x));
+            assert!(x.is_some());
+            assert_eq!(
+                KDTree::make(Vec::new()).find_nearest_map(&search, |p| p.x),
+                None
+            );
+        }
+
+        #[test]
+        fn stream() {
+            let tree = KDTree::make(random_points(100));
+            let queries = random_points(50);
+
+            let answers: Vec<_> = tree.query_stream(queries.clone().into_iter()).collect();
+            assert_eq!(answers.len(), queries.len());
+            for (search, answer) in queries.iter().zip(answers) {
+                assert!(std::ptr::eq(
+                    answer.unwrap(),
+                    tree.find_nearest(search).unwrap()
+                ));
+            }
+
+            // unbounded streams are fine, as long as one does not ask for every answer
+            let endless = std::iter::repeat_with(|| random_points(1).remove(0));
+            assert_eq!(tree.query_stream(endless).take(10).flatten().count(), 10);
+        }
+
+        #[test]
+        fn merge_knn_across_trees() {
+            let data = random_points(300);
+            let single = KDTree::make(data.clone());
+            let trees: Vec<_> = data.chunks(100).map(|c| KDTree::make(c.to_vec())).collect();
+
+            for _ in 0..20 {
+                let search = random_points(1).remove(0);
+                let results: Vec<_> = trees
+                    .iter()
+                    .map(|tree| tree.find_k_nearest_with_distances(&search, 5))
+                    .collect();
+                let merged = merge_knn(&results, 5);
+
+                let expected: Vec<_> = single
+                    .find_k_nearest_with_distances(&search, 5)
+                    .into_iter()
+                    .map(|(_, distance)| distance)
+                    .collect();
+                assert_eq!(merged.len(), 5);
+                for (point, distance) in &merged {
+                    assert_eq!(Point2D::distance(&search, point), *distance);
+                }
+                let distances: Vec<_> = merged.into_iter().map(|(_, d)| d).collect();
+                assert_eq!(distances, expected);
+            }
+
+            assert!(merge_knn::<Point2D>(&[vec![], vec![]], 3).is_empty());
+        }
+
+        /// indexes its coordinates with the dimension as is
+        #[derive(Debug, Clone)]
+        struct Plain([f64; 2]);
+
+        impl KDPoint for Plain {
+            type Key = F64;
+            type Distance = F64;
+
+            fn dimensions(&self) -> usize {
+                2
+            }
+
+            fn kdkey(&self, dimension: usize) -> Self::Key {
+                self.0[dimension].into()
+            }
+
+            fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+                Point2D::distance(&lhs.0.into(), &rhs.0.into())
+            }
+
+            fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+                Point2D::key_distance(lhs, rhs)
+            }
+        }
+
+        /// moving up (to greater coordinates) costs twice as much as moving down
+        #[derive(Debug, Clone, PartialEq)]
+        struct Uphill([i64; 2]);
+
+        impl Uphill {
+            fn cost(from: i64, to: i64) -> i64 {
+                if to > from {
+                    2 * (to - from)
+                } else {
+                    from - to
+                }
+            }
+        }
+
+        impl KDPoint for Uphill {
+            type Key = i64;
+            type Distance = i64;
+
+            fn dimensions(&self) -> usize {
+                2
+            }
+
+            fn kdkey(&self, dimension: usize) -> Self::Key {
+                self.0[dimension]
+            }
+
+            fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+                (0..2).map(|d| Self::cost(lhs.0[d], rhs.0[d])).sum()
+            }
+
+            fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+                Self::cost(*lhs, *rhs)
+            }
+        }
+
+        #[test]
+        fn directed_distance() {
+            use rand::random;
+
+            let data = vec![Uphill([0, 0]), Uphill([5, 0])];
+            let tree = KDTree::make(data.clone());
+            // 3 down to the first point, 2 * 2 up to the second one, but 2 down from the second
+            let search = Uphill([3, 0]);
+            assert_eq!(tree.find_nearest(&search), Some(&data[0]));
+            assert_eq!(
+                data.iter().min_by_key(|p| Uphill::distance(p, &search)),
+                Some(&data[1])
+            );
+
+            let data: Vec<Uphill> = (0..500)
+                .map(|_| Uphill([random::<i64>() % 1000, random::<i64>() % 1000]))
+                .collect();
+            let tree = KDTree::make(data.clone());
+            let mut differs = false;
+            for _ in 0..100 {
+                let search = Uphill([random::<i64>() % 1000, random::<i64>() % 1000]);
+                let from = data.iter().map(|p| Uphill::distance(&search, p)).min();
+                let found = tree.find_nearest(&search).unwrap();
+                assert_eq!(Some(Uphill::distance(&search, found)), from);
+
+                let to = data.iter().min_by_key(|p| Uphill::distance(p, &search));
+                differs |= Uphill::distance(&search, to.unwrap()) != from.unwrap();
+            }
+            assert!(differs);
+        }
+
+        #[test]
+        fn dimensions_wrap_in_the_tree() {
+            let plain = |p: Point2D| Plain([p.x, p.y]);
+            let data = random_points(200);
+            let mut tree = KDTree::make(data[..100].iter().cloned().map(plain).collect());
+            for p in &data[100..] {
+                tree.insert(plain(p.clone()));
+            }
+            assert!(tree.depth() > 2);
+            assert!(tree.validate());
+
+            for _ in 0..20 {
+                let search = random_points(1).remove(0);
+                let expected = data.iter().map(|p| distance_to(&search)(&p)).min().unwrap();
+                let found = tree.find_nearest(&plain(search.clone())).unwrap();
+                assert_eq!(Point2D::distance(&search, &found.0.into()), expected);
+            }
+        }
+
+        #[test]
+        fn axis_subset() {
+            use rand::random;
+
+            let point = || DynPoint::from((0..5).map(|_| random::<f64>()).collect::<Vec<_>>());
+            let data: Vec<DynPoint> = (0..300).map(|_| point()).collect();
+            let mut tree = KDTree::make_with_axes(data[..200].to_vec(), vec![3, 1]);
+            for p in &data[200..] {
+                tree.insert(p.clone());
+            }
+            assert!(tree.validate());
+            assert!(tree.root.iter().all(|root| root.dimension() == 3));
+
+            for _ in 0..50 {
+                let search = point();
+                let expected = data
+                    .iter()
+                    .map(|p| DynPoint::distance(&search, p))
+                    .min()
+                    .unwrap();
+                let found = tree.find_nearest(&search).unwrap();
+                assert_eq!(DynPoint::distance(&search, found), expected);
+            }
+        }
+
+        #[test]
+        fn extra_query_dimensions() {
+            use rand::random;
+
+            let point =
+                |n: usize| DynPoint::from((0..n).map(|_| random::<f64>()).collect::<Vec<_>>());
+            let data: Vec<DynPoint> = (0..300).map(|_| point(2)).collect();
+            let mut tree = KDTree::make(data[..200].to_vec());
+            for p in &data[200..] {
+                tree.insert(p.clone());
+            }
+            assert_eq!(tree.tree_dimensions(), 2);
+            assert!(tree.root.iter().all(|root| root.dimension() < 2));
+
+            let radius = F64::from(0.01);
+            for _ in 0..50 {
+                let plain = point(2);
+                // any metadata after the coordinates, far outside the points' range
+                let tagged = DynPoint::from([plain.coords.as_slice(), &[1e9, -7.0]].concat());
+                assert!(std::ptr::eq(
+                    tree.find_nearest(&tagged).unwrap(),
+                    tree.find_nearest(&plain).unwrap()
+                ));
+                let k_nearest = |search| {
+                    tree.find_k_nearest(search, 5)
+                        .into_iter()
+                        .map(|p: &DynPoint| p as *const DynPoint)
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(k_nearest(&tagged), k_nearest(&plain));
+                assert_eq!(
+                    tree.count_within_radius(&tagged, radius),
+                    tree.count_within_radius(&plain, radius)
+                );
+            }
+            assert_eq!(KDTree::<DynPoint>::make(Vec::new()).tree_dimensions(), 0);
+        }
+    }
+
+    mod distance_units {
+        use super::*;
+
+        #[test]
+        fn radius_not_squared() {
+            let data: Vec<Point2D> = [(0.0, 0.0), (3.0, 4.0), (0.0, 1.5), (6.0, 8.0), (1.0, 1.0)]
+                .into_iter()
+                .map(Point2D::from)
+                .collect();
+            let tree = KDTree::make(data);
+            let search = Point2D::from((0.0, 0.0));
+            let coordinates = |points: Vec<&Point2D>| {
+                let mut coordinates: Vec<(f64, f64)> = points.iter().map(|p| (p.x, p.y)).collect();
+                coordinates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                coordinates
+            };
+
+            // (3, 4) is exactly 5 away
+            assert_eq!(
+                coordinates(tree.within_distance(&search, 5.0)),
+                [(0.0, 0.0), (0.0, 1.5), (1.0, 1.0), (3.0, 4.0)]
+            );
+            assert_eq!(
+                coordinates(tree.within_distance(&search, 5.0)),
+                coordinates(tree.find_within_radius(&search, F64::from(25.0)))
+            );
+            assert_eq!(tree.within_distance(&search, 2.0).len(), 3);
+            assert_eq!(tree.within_distance(&search, 0.0).len(), 1);
+            assert!(tree.within_distance(&search, -1.0).is_empty());
+            assert!(tree.within_distance(&search, f64::NAN).is_empty());
+
+            let nearest = tree.k_nearest_within_distance(&search, 3, 5.0);
+            assert_eq!(
+                nearest.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>(),
+                [(0.0, 0.0), (1.0, 1.0), (0.0, 1.5)]
+            );
+            assert_eq!(tree.k_nearest_within_distance(&search, 10, 5.0).len(), 4);
+            assert_eq!(tree.k_nearest_within_distance(&search, 10, 10.0).len(), 5);
+            assert!(tree.k_nearest_within_distance(&search, 10, -5.0).is_empty());
+        }
+    }
+
+    mod counts {
+        use super::*;
+        use rand::random;
+
+        /// 50 distinct points, each repeated up to 20 times, the copies shuffled among the rest
+        fn duplicated() -> Vec<Point2D> {
+            let distinct: Vec<Point2D> = (0..50).map(|_| (random(), random()).into()).collect();
+            let mut data: Vec<Point2D> = distinct
+                .iter()
+                .flat_map(|point| std::iter::repeat_n(point.clone(), 1 + random::<usize>() % 20))
+                .collect();
+            for i in (1..data.len()).rev() {
+                data.swap(i, random::<usize>() % (i + 1));
+            }
+            data
+        }
+
+        fn distances<'t>(
+            found: impl IntoIterator<Item = &'t Point2D>,
+            search: &Point2D,
+        ) -> Vec<F64> {
+            let mut distances: Vec<_> = found
+                .into_iter()
+                .map(|point| Point2D::distance(search, point))
+                .collect();
+            distances.sort();
+            distances
+        }
+
+        fn check_queries(counted: &KDTree<Point2D>, plain: &KDTree<Point2D>) {
+            assert_eq!(counted.len(), plain.len());
+            assert_eq!(counted.iter().count(), plain.len());
+            assert!(counted.validate());
+            for _ in 0..20 {
+                let search: Point2D = (random(), random()).into();
+                let k = 1 + random::<usize>() % 40;
+                assert_eq!(
+                    distances(counted.find_k_nearest(&search, k), &search),
+                    distances(plain.find_k_nearest(&search, k), &search)
+                );
+                let radius = F64::from(random::<f64>() * 0.1);
+                assert_eq!(
+                    distances(counted.find_within_radius(&search, radius), &search),
+                    distances(plain.find_within_radius(&search, radius), &search)
+                );
+                assert_eq!(
+                    counted.count_within_radius(&search, radius),
+                    plain.count_within_radius(&search, radius)
+                );
+            }
+        }
+
+        #[test]
+        fn heavy_duplication() {
+            let data = duplicated();
+            let counted = KDTree::make_with_counts(data.clone());
+            let plain = KDTree::make(data.clone());
+            assert_eq!(counted.shape_signature().len(), 50);
+            assert_eq!(plain.shape_signature().len(), data.len());
+            check_queries(&counted, &plain);
+        }
+
+        #[test]
+        fn inserts_and_rebuilds() {
+            let data = duplicated();
+            let (first, rest) = data.split_at(data.len() / 2);
+            let mut counted = KDTree::make_with_counts(first.to_vec())
+                .with_depth_policy(DepthPolicy::RebuildSubtree);
+            for point in rest {
+                counted.insert(point.clone());
+            }
+            let mut plain = KDTree::make(data.clone());
+            assert_eq!(counted.shape_signature().len(), 50);
+            check_queries(&counted, &plain);
+
+            let new: Point2D = (2.0, 2.0).into();
+            for _ in 0..3 {
+                counted.insert(new.clone());
+                plain.insert(new.clone());
+            }
+            assert_eq!(counted.shape_signature().len(), 51);
+            check_queries(&counted, &plain);
+
+            counted.rebalance();
+            assert_eq!(counted.shape_signature().len(), 51);
+            check_queries(&counted, &plain);
+            counted.rebuild_biased(&new, 0.5);
+            assert_eq!(counted.shape_signature().len(), 51);
+            check_queries(&counted, &plain);
+        }
+
+        /// the same point in `tree`, for queries that take a point of the tree
+        fn same<'t>(tree: &'t KDTree<Point2D>, point: &Point2D) -> &'t Point2D {
+            tree.iter()
+                .find(|other| (other.x, other.y) == (point.x, point.y))
+                .unwrap()
+        }
+
+        #[test]
+        fn neighbours_of_copies() {
+            let data: Vec<Point2D> = vec![(0.0, 0.0).into(), (5.0, 0.0).into(), (0.0, 0.0).into()];
+            let counted = KDTree::make_with_counts(data.clone());
+            let plain = KDTree::make(data);
+            for tree in [&counted, &plain] {
+                assert_eq!(tree.k_distance_plot(1), [0.0, 0.0, 25.0].map(F64::from));
+                let origin = same(tree, &(0.0, 0.0).into());
+                assert_eq!(
+                    distances(tree.find_k_nearest_excluding(origin, 2), origin),
+                    [0.0, 25.0].map(F64::from)
+                );
+                assert_eq!(tree.nn_distance_histogram(2), [2, 1]);
+            }
+
+            let mut data = duplicated();
+            data.push((2.0, 2.0).into());
+            let counted = KDTree::make_with_counts(data.clone());
+            let plain = KDTree::make(data);
+            for k in [1, 3, 25] {
+                assert_eq!(counted.k_distance_plot(k), plain.k_distance_plot(k));
+            }
+            assert_eq!(
+                counted.nn_distance_histogram(7),
+                plain.nn_distance_histogram(7)
+            );
+            for point in counted.iter().step_by(17) {
+                let k = 1 + random::<usize>() % 30;
+                assert_eq!(
+                    distances(counted.find_k_nearest_excluding(point, k), point),
+                    distances(
+                        plain.find_k_nearest_excluding(same(&plain, point), k),
+                        point
+                    )
+                );
+            }
+        }
+
+        #[test]
+        fn dedup_copies() {
+            let data: Vec<Point2D> = vec![(0.0, 0.0).into(), (5.0, 0.0).into(), (0.0, 0.0).into()];
+            let mut counted = KDTree::make_with_counts(data.clone());
+            let mut plain = KDTree::make(data);
+            for tree in [&mut counted, &mut plain] {
+                assert_eq!(tree.dedup_within(F64::from(1.0)), 1);
+                assert_eq!(tree.len(), 2);
+            }
+
+            let data = duplicated();
+            let len = data.len();
+            let mut counted = KDTree::make_with_counts(data.clone());
+            let mut plain = KDTree::make(data);
+            // exact copies only, so the result does not depend on the order
+            assert_eq!(counted.dedup_within(F64::from(0.0)), len - 50);
+            assert_eq!(plain.dedup_within(F64::from(0.0)), len - 50);
+            check_queries(&counted, &plain);
+        }
+    }
+
+    mod errors {
+        use super::*;
+
+        fn points(dimensions: &[usize]) -> Vec<DynPoint> {
+            dimensions
+                .iter()
+                .map(|&dimensions| DynPoint::from(vec![0.5; dimensions]))
+                .collect()
+        }
+
+        #[test]
+        fn try_make() {
+            assert_eq!(KDTree::try_make(points(&[3, 3, 3])).unwrap().len(), 3);
+            assert!(KDTree::<DynPoint>::try_make(Vec::new()).unwrap().is_empty());
+            assert_eq!(
+                KDTree::try_make(points(&[3, 3, 2, 3])).unwrap_err(),
+                Error::DimensionMismatch {
+                    expected: 3,
+                    got: 2
+                }
+            );
+        }
+
+        #[test]
+        fn try_make_with_axes() {
+            assert!(KDTree::try_make_with_axes(points(&[3, 3]), vec![2, 0]).is_ok());
+            assert_eq!(
+                KDTree::try_make_with_axes(points(&[3, 3]), Vec::new()).unwrap_err(),
+                Error::EmptyInput("axis map")
+            );
+            assert_eq!(
+                KDTree::try_make_with_axes(points(&[3, 3]), vec![0, 3]).unwrap_err(),
+                Error::DimensionMismatch {
+                    expected: 4,
+                    got: 3
+                }
+            );
+            assert_eq!(
+                KDTree::try_make_with_axes(points(&[3, 4]), vec![0]).unwrap_err(),
+                Error::DimensionMismatch {
+                    expected: 3,
+                    got: 4
+                }
+            );
+        }
+
+        #[test]
+        fn try_rebuild_biased() {
+            let mut tree = KDTree::make(points(&[2; 10]));
+            let shape = tree.shape_signature();
+            let hotspot = DynPoint::from(vec![0.0, 0.0]);
+            assert_eq!(
+                tree.try_rebuild_biased(&hotspot, 1.5).unwrap_err(),
+                Error::InvalidWeight(1.5)
+            );
+            assert_eq!(
+                tree.try_rebuild_biased(&DynPoint::from(vec![0.0; 3]), 0.5)
+                    .unwrap_err(),
+                Error::DimensionMismatch {
+                    expected: 2,
+                    got: 3
+                }
+            );
+            assert_eq!(tree.shape_signature(), shape);
+            assert_eq!(tree.len(), 10);
+            assert!(tree.try_rebuild_biased(&hotspot, 0.5).is_ok());
+            assert!(tree.validate());
+        }
+
+        #[test]
+        #[should_panic(expected = "weight -0.5 not within 0 and 1")]
+        fn rebuild_biased_panics() {
+            KDTree::make(points(&[2; 3])).rebuild_biased(&DynPoint::from(vec![0.0; 2]), -0.5);
+        }
+
+        #[test]
+        fn display() {
+            assert_eq!(
+                Error::EmptyInput("axis map").to_string(),
+                "axis map must not be empty"
+            );
+            assert_eq!(
+                Error::DimensionMismatch {
+                    expected: 3,
+                    got: 2
+                }
+                .to_string(),
+                "expected 3 dimensions, got 2"
+            );
+            let error: Box<dyn std::error::Error> = Box::new(Error::InvalidWeight(2.0));
+            assert_eq!(error.to_string(), "weight 2 not within 0 and 1");
+        }
+    }
+
+    mod non_finite {
+        use super::*;
+        use rand::random;
+
+        fn finite_points(n: usize) -> Vec<Point2D> {
+            (0..n)
+                .map(|_| (random::<f64>(), random::<f64>()).into())
+                .collect()
+        }
+
+        fn broken_points() -> Vec<Point2D> {
+            [
+                (f64::NAN, 0.5),
+                (0.5, f64::NAN),
+                (-f64::NAN, -f64::NAN),
+                (f64::INFINITY, 0.5),
+                (0.5, f64::NEG_INFINITY),
+            ]
+            .into_iter()
+            .map(Point2D::from)
+            .collect()
+        }
+
+        fn is_finite(p: &Point2D) -> bool {
+            p.x.is_finite() && p.y.is_finite()
+        }
+
+        /// one tree built at once, one from inserts, both mixing finite and broken points
+        fn trees() -> Vec<(KDTree<Point2D>, Vec<Point2D>)> {
+            (0..2)
+                .map(|i| {
+                    let mut data = finite_points(100);
+                    data.extend(broken_points());
+                    data.extend(finite_points(100));
+                    let tree = if i == 0 {
+                        KDTree::make(data.clone())
+                    } else {
+                        let mut tree = KDTree::make(Vec::new());
+                        data.iter().for_each(|p| tree.insert(p.clone()));
+                        tree
+                    };
+                    assert_eq!(tree.iter().count(), data.len());
+                    (tree, data)
+                })
+                .collect()
+        }
+
+        #[test]
+        fn nan_is_greatest() {
+            let nan = F64::from(f64::NAN);
+            assert!(nan > F64::from(f64::INFINITY));
+            assert!(F64::from(-f64::NAN) > F64::from(f64::INFINITY));
+            assert_eq!(nan, F64::from(-f64::NAN));
+            assert_eq!(F64::from(0.0), F64::from(-0.0));
+        }
+
+        #[test]
+        fn nearest_ignores_nan() {
+            for (tree, data) in trees() {
+                for _ in 0..50 {
+                    let search = finite_points(1).remove(0);
+                    let expected = data
+                        .iter()
+                        .filter(|p| is_finite(p))
+                        .map(|p| Point2D::distance(&search, p))
+                        .min()
+                        .unwrap();
+                    let found = tree.find_nearest(&search).unwrap();
+                    assert!(is_finite(found));
+                    assert_eq!(Point2D::distance(&search, found), expected);
+                }
+            }
+        }
+
+        #[test]
+        fn k_nearest_and_radius_ignore_nan() {
+            for (tree, data) in trees() {
+                let search = finite_points(1).remove(0);
+
+                // the points at infinite distance come right before those at NaN distance
+                let found = tree.find_k_nearest(&search, data.len());
+                assert!(found[..found.len() - 3]
+                    .iter()
+                    .all(|p| !p.x.is_nan() && !p.y.is_nan()));
+                assert!(found[found.len() - 3..]
+                    .iter()
+                    .all(|p| p.x.is_nan() || p.y.is_nan()));
+
+                let found = tree.find_within_radius(&search, F64::from(f64::MAX));
+                assert_eq!(found.len(), 200);
+                assert!(found.iter().all(|p| is_finite(p)));
+            }
+        }
+
+        #[test]
+        fn only_nan() {
+            let tree = KDTree::make(vec![Point2D::from((f64::NAN, f64::NAN))]);
+            assert!(tree.find_nearest(&(0.0, 0.0).into()).is_some());
+            assert!(tree
+                .find_within_radius(&(0.0, 0.0).into(), F64::from(1.0))
+                .is_empty());
+        }
+    }
+
+    // #[cfg(öksdf)]
+    mod partition {
+        use super::*;
+
+        fn check<T: Debug + Ord>(data: &[T], claimed_median_idx: usize) {
+            assert!(claimed_median_idx < data.len());
 
             for i in 0..claimed_median_idx {
                 assert!(dbg!(&data[i]) < dbg!(&data[claimed_median_idx]));
@@ -268,6 +6500,16 @@ mod tests {
             assert_eq!(idx, 0);
         }
 
+        #[test]
+        fn tiny_inputs() {
+            use partition_functions::{hoare_a, hoare_b};
+
+            assert_eq!(hoare_a(&mut [], u32::cmp), 0);
+            assert_eq!(hoare_b(&mut [], u32::cmp), 0);
+            assert_eq!(hoare_a(&mut [17], u32::cmp), 0);
+            assert_eq!(hoare_b(&mut [17], u32::cmp), 0);
+        }
+
         #[test]
         fn pair() {
             let mut data = vec![1, 2];
@@ -278,25 +6520,22 @@ mod tests {
 
         #[test]
         fn unique() {
-            let mut data = (0..10).collect();
+            let mut data: Vec<u32> = (0..10).collect();
             let idx = partition(&mut data, u32::cmp);
             check(&data, idx);
 
-            let mut data = (0..11).collect();
+            let mut data: Vec<u32> = (0..11).collect();
             let idx = partition(&mut data, u32::cmp);
             check(&data, idx);
 
-            let mut data = (0..12).collect();
+            let mut data: Vec<u32> = (0..12).collect();
             let idx = partition(&mut data, u32::cmp);
             check(&data, idx);
         }
 
         #[test]
         fn sames() {
-            let mut data = (0..=32)
-                .map(|x| [x, x, x, x].into_iter())
-                .flatten()
-                .collect();
+            let mut data: Vec<u32> = (0..=32).flat_map(|x| [x, x, x, x].into_iter()).collect();
             let idx = partition(&mut data, u32::cmp);
             check(&data, idx);
         }
@@ -304,11 +6543,483 @@ mod tests {
         #[test]
         fn random_data() {
             use rand::random;
-            let mut data = (0..=random::<usize>() % 100)
+            let mut data: Vec<u32> = (0..=random::<usize>() % 100)
                 .map(|_| random::<u32>() % 10)
                 .collect();
             let idx = partition(&mut data, u32::cmp);
             check(&data, idx);
         }
+
+        #[test]
+        fn organ_pipe_on_small_stack() {
+            // an organ pipe of large keys in the middle, each round picks its maximum as pivot and
+            // only gets rid of a few elements
+            let n: usize = 200_000;
+            let pipe = 1_000;
+            let data: Vec<Point2D> = (0..n)
+                .map(|i| {
+                    let x = match i.abs_diff(n / 2) {
+                        offset if offset < pipe => (2 * n - offset) as f64,
+                        _ => (i * 7919 % n) as f64,
+                    };
+                    (x, (i % 7) as f64).into()
+                })
+                .collect();
+
+            let tree = std::thread::Builder::new()
+                .stack_size(256 * 1024)
+                .spawn(move || KDTree::make(data))
+                .unwrap()
+                .join()
+                .unwrap();
+            assert_eq!(tree.iter().count(), n);
+            assert!(tree.validate());
+        }
+
+        // rock, paper, scissors: every hand beats the next one
+        fn beats(lhs: &u8, rhs: &u8) -> Ordering {
+            match (rhs + 3 - lhs) % 3 {
+                0 => Ordering::Equal,
+                1 => Ordering::Greater,
+                _ => Ordering::Less,
+            }
+        }
+
+        #[test]
+        fn comparator_check() {
+            use partition_functions::check_comparator;
+
+            assert!(check_comparator(&[3, 1, 4, 1, 5], u32::cmp).is_ok());
+            assert!(check_comparator(&[0, 1, 2], beats).is_err());
+            assert!(check_comparator(&[0, 1], |_: &u8, _: &u8| Ordering::Less).is_err());
+        }
+
+        #[derive(Debug, PartialEq, Eq)]
+        struct Hand(u8);
+
+        impl PartialOrd for Hand {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Hand {
+            fn cmp(&self, other: &Self) -> Ordering {
+                beats(&self.0, &other.0)
+            }
+        }
+
+        struct HandPoint(u8);
+
+        impl KDPoint for HandPoint {
+            type Key = Hand;
+            type Distance = u8;
+
+            fn dimensions(&self) -> usize {
+                1
+            }
+
+            fn kdkey(&self, _: usize) -> Self::Key {
+                Hand(self.0)
+            }
+
+            fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+                lhs.0.abs_diff(rhs.0)
+            }
+
+            fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+                lhs.0.abs_diff(rhs.0)
+            }
+        }
+
+        #[test]
+        #[cfg(debug_assertions)]
+        #[should_panic(expected = "keys in dimension 0 are not a total order")]
+        fn make_rejects_inconsistent_keys() {
+            KDTree::make(vec![HandPoint(0), HandPoint(1), HandPoint(2)]);
+        }
+    }
+
+    /// every public method on trees of 0 to 3 points, made at once and inserted one by one
+    mod small_trees {
+        use super::*;
+
+        /// the first `n` of (0, 0), (2, 0) and (0, 2), squared distances 4 apart along the axes
+        fn points(n: usize) -> Vec<Point2D> {
+            [(0.0, 0.0), (2.0, 0.0), (0.0, 2.0)][..n]
+                .iter()
+                .map(|&p| p.into())
+                .collect()
+        }
+
+        fn trees(n: usize) -> [KDTree<Point2D>; 2] {
+            let mut inserted = KDTree::make(Vec::new());
+            for point in points(n) {
+                inserted.insert(point);
+            }
+            [KDTree::make(points(n)), inserted]
+        }
+
+        fn xy<'t>(points: impl IntoIterator<Item = &'t Point2D>) -> Vec<(f64, f64)> {
+            let mut xy: Vec<_> = points.into_iter().map(|p| (p.x, p.y)).collect();
+            xy.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            xy
+        }
+
+        fn expected(n: usize) -> Vec<(f64, f64)> {
+            xy(&points(n))
+        }
+
+        fn p(x: f64, y: f64) -> Point2D {
+            (x, y).into()
+        }
+
+        #[test]
+        fn shape() {
+            for n in 0..=3 {
+                for tree in trees(n) {
+                    assert!(tree.validate());
+                    assert_eq!(tree.len(), n);
+                    assert_eq!(tree.is_empty(), n == 0);
+                    assert_eq!(xy(tree.iter()), expected(n));
+                    assert_eq!(tree.tree_dimensions(), if n == 0 { 0 } else { 2 });
+                    assert_eq!(tree.depth() == 0, n == 0);
+                    assert!(tree.depth() <= n);
+                    assert_eq!(tree.shape_signature().len(), n);
+                    assert_eq!(tree.split_planes().len(), n);
+                    assert_eq!(tree.points_at_depth(0).len(), n.min(1));
+                    assert_eq!(tree.iter_with_bounds().count(), n);
+                    assert!(!tree.needs_rebalance());
+                }
+            }
+        }
+
+        #[test]
+        fn construction() {
+            for n in 0..=3 {
+                let check = |tree: KDTree<Point2D>| {
+                    assert!(tree.validate());
+                    assert_eq!(xy(tree.iter()), expected(n));
+                };
+                check(KDTree::try_make(points(n)).unwrap());
+                check(KDTree::make_with_axes(points(n), vec![1, 0]));
+                check(KDTree::make_with_buckets(points(n), 2));
+                check(KDTree::make_with_counts(points(n)));
+                let mut sorted = points(n);
+                sorted.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+                check(KDTree::from_sorted_by_axis(sorted, 0));
+                let frozen = KDTree::make(points(n)).freeze();
+                assert_eq!(frozen.len(), n);
+                assert_eq!(xy(frozen.iter()), expected(n));
+                assert_eq!(frozen.find_nearest(&(0.1, 0.1).into()).is_some(), n > 0);
+                assert_eq!(frozen.find_k_nearest(&(0.1, 0.1).into(), 5).len(), n);
+                assert_eq!(
+                    frozen
+                        .find_within_radius(&(0.0, 0.0).into(), F64::from(4.0))
+                        .len(),
+                    n
+                );
+                let sharded = KDTree::make_sharded(points(n), 2, |p| usize::from(p.x > 0.0));
+                assert_eq!(sharded.iter().map(KDTree::len).sum::<usize>(), n);
+                let pieces = KDTree::make(points(n)).split_into(2);
+                assert_eq!(pieces.iter().map(|(t, _)| t.len()).sum::<usize>(), n);
+                // x: 0, 2, 0
+                let inversions = if n == 3 { 1.0 / 3.0 } else { 0.0 };
+                assert_eq!(KDTree::axis_presortedness(&points(n), 0), inversions);
+            }
+        }
+
+        #[test]
+        fn nearest() {
+            let search = p(0.1, 0.1);
+            for n in 0..=3 {
+                let origin = (n > 0).then_some((0.0, 0.0));
+                for tree in trees(n) {
+                    let found = |point: Option<&Point2D>| point.map(|p| (p.x, p.y));
+                    assert_eq!(found(tree.find_nearest(&search)), origin);
+                    assert_eq!(tree.find_nearest_map(&search, |p| (p.x, p.y)), origin);
+                    // (0, 0.5) is at squared distance 0.25 from (0, 0), the cutoff is inclusive
+                    let within = tree.find_nearest_within(&p(0.0, 0.5), F64::from(0.25));
+                    assert_eq!(found(within.map(|(p, _)| p)), origin);
+                    assert!(tree
+                        .find_nearest_within(&p(0.0, 0.5), F64::from(0.24))
+                        .is_none());
+                    assert_eq!(
+                        found(tree.find_nearest_until(&search, |_, _| false)),
+                        origin
+                    );
+                    assert_eq!(
+                        tree.find_nearest_until(&search, |_, _| true).is_some(),
+                        n > 0
+                    );
+                    assert_eq!(found(tree.find_nearest_with_pruned(&search).0), origin);
+                    assert_eq!(found(tree.find_nearest_with_prune_slack(&search).0), origin);
+                    assert_eq!(
+                        tree.query_stream([search.clone()].into_iter())
+                            .map(found)
+                            .collect::<Vec<_>>(),
+                        [origin]
+                    );
+                    assert_eq!(
+                        tree.find_nearest_to_any(&[p(5.0, 5.0), search.clone()])
+                            .map(|(p, index, _)| ((p.x, p.y), index)),
+                        origin.map(|origin| (origin, 1))
+                    );
+                    assert!(tree.find_nearest_to_any(&[]).is_none());
+
+                    // all of them at squared distance 2 from (1, 1)
+                    assert_eq!(xy(tree.find_all_nearest(&p(1.0, 1.0))), expected(n));
+                    assert_eq!(
+                        xy(tree.find_all_nearest_by(&p(1.0, 1.0), |p| F64::from(p.y))),
+                        expected(n)
+                    );
+
+                    let avoiding =
+                        tree.find_nearest_avoiding(&search, &p(-1.0, -1.0), &p(1.0, 1.0));
+                    assert_eq!(avoiding.is_some(), n >= 2);
+                    let slab =
+                        tree.find_nearest_in_slab(&search, 0, F64::from(1.0), F64::from(3.0));
+                    assert_eq!(found(slab), (n >= 2).then_some((2.0, 0.0)));
+                    let halfspace =
+                        tree.find_nearest_in_halfspace(&search, &p(0.0, 1.0), &[0.0, 1.0]);
+                    assert_eq!(found(halfspace), (n >= 3).then_some((0.0, 2.0)));
+                }
+            }
+        }
+
+        #[test]
+        fn k_nearest() {
+            let search = p(0.1, 0.1);
+            for n in 0..=3 {
+                for tree in trees(n) {
+                    for k in 0..=4 {
+                        let found = tree.find_k_nearest(&search, k);
+                        assert_eq!(found.len(), k.min(n));
+                        if k > 0 && n > 0 {
+                            assert_eq!((found[0].x, found[0].y), (0.0, 0.0));
+                        }
+                        assert_eq!(
+                            tree.find_k_nearest_with_distances(&search, k).len(),
+                            k.min(n)
+                        );
+                        assert_eq!(tree.find_k_nearest_cloned(&search, k).len(), k.min(n));
+                        assert_eq!(tree.find_k_nearest_recall(&search, k, 1.0).len(), k.min(n));
+                        // leaving out every point itself, the farthest other one if there are fewer than k
+                        let with_others = if k > 0 && n >= 2 { n } else { 0 };
+                        assert_eq!(tree.k_distance_plot(k).len(), with_others);
+                    }
+                    // a point of the tree, by address
+                    if let Some(point) = tree.iter().next() {
+                        assert_eq!(tree.find_k_nearest_excluding(point, 3).len(), n - 1);
+                    }
+                    assert_eq!(
+                        tree.nn_distance_histogram(4).iter().sum::<usize>(),
+                        if n >= 2 { n } else { 0 }
+                    );
+                    // two points tie
+                    assert_eq!(
+                        tree.medoid().map(|p| (p.x, p.y)).filter(|_| n != 2),
+                        (n % 2 == 1).then_some((0.0, 0.0))
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn radius_and_range() {
+            let origin = p(0.0, 0.0);
+            for n in 0..=3 {
+                for tree in trees(n) {
+                    // radii are inclusive: a point exactly at the radius is within it
+                    let zero = F64::from(0.0);
+                    assert_eq!(tree.find_within_radius(&origin, zero).len(), n.min(1));
+                    assert_eq!(tree.count_within_radius(&origin, zero), n.min(1));
+                    assert_eq!(tree.any_within_radius(&origin, zero), n > 0);
+                    let four = F64::from(4.0);
+                    assert_eq!(xy(tree.find_within_radius(&origin, four)), expected(n));
+                    assert_eq!(
+                        xy(&tree.find_within_radius_cloned(&origin, four)),
+                        expected(n)
+                    );
+                    assert_eq!(tree.within_distance(&origin, 2.0).len(), n);
+                    assert_eq!(
+                        tree.k_nearest_within_distance(&origin, 2, 2.0).len(),
+                        n.min(2)
+                    );
+                    assert_eq!(
+                        tree.farthest_within_radius(&origin, four)
+                            .is_some_and(|p| p.x + p.y == 2.0),
+                        n >= 2
+                    );
+                    assert_eq!(tree.farthest_within_radius(&origin, four).is_some(), n > 0);
+                    assert_eq!(
+                        tree.find_within_radius_sink(&origin, four, |_| Ok(())),
+                        Ok(n)
+                    );
+
+                    // boxes are inclusive as well, down to a single point
+                    assert_eq!(tree.find_in_range_cloned(&origin, &origin).len(), n.min(1));
+                    assert_eq!(
+                        xy(&tree.find_in_range_cloned(&origin, &p(2.0, 2.0))),
+                        expected(n)
+                    );
+                    assert_eq!(
+                        tree.fold_in_range(&p(2.0, 0.0), &p(2.0, 0.0), 0, |c, _| c + 1),
+                        usize::from(n >= 2)
+                    );
+                    assert_eq!(
+                        tree.region_moments(&origin, &p(2.0, 2.0)).map(|m| m.count),
+                        (n > 0).then_some(n)
+                    );
+                    assert_eq!(
+                        tree.radius_moments(&origin, four).map(|m| m.count),
+                        (n > 0).then_some(n)
+                    );
+                    assert_eq!(tree.stratified_sample(5).len(), n);
+                    assert!(tree.stratified_sample(0).is_empty());
+                }
+            }
+        }
+
+        #[test]
+        fn keys_and_summaries() {
+            for n in 0..=3 {
+                for tree in trees(n) {
+                    let x_max = if n >= 2 { 2.0 } else { 0.0 };
+                    assert_eq!(tree.min_key(0), (n > 0).then_some(F64::from(0.0)));
+                    assert_eq!(tree.max_key(0), (n > 0).then_some(F64::from(x_max)));
+                    assert_eq!(
+                        tree.axis_range(0),
+                        (n > 0).then_some((F64::from(0.0), F64::from(x_max)))
+                    );
+                    assert_eq!(tree.key_quantiles(0, 2).len(), if n > 0 { 3 } else { 0 });
+                    assert_eq!(tree.largest_gap(0).is_some(), n >= 2);
+                    assert_eq!(
+                        tree.key_summary(0, 2).map(|s| s.counts.iter().sum()),
+                        (n > 0).then_some(n)
+                    );
+                    assert_eq!(tree.summaries(2).len(), if n > 0 { 2 } else { 0 });
+                    assert_eq!(tree.bounding_sphere().is_some(), n > 0);
+                    if n == 1 {
+                        assert_eq!(tree.bounding_sphere(), Some((vec![0.0, 0.0], 0.0)));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn set_operations() {
+            for n in 0..=3 {
+                for tree in trees(n) {
+                    let [other, _] = trees(3 - n);
+                    let eps = F64::from(0.0);
+                    // the first points of both are the same
+                    let shared = n.min(3 - n);
+                    assert_eq!(tree.difference_within(&other, eps).len(), n - shared);
+                    let (only_self, only_other) = tree.symmetric_difference_within(&other, eps);
+                    assert_eq!(
+                        (only_self.len(), only_other.len()),
+                        (n - shared, 3 - n - shared)
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn mutations() {
+            for n in 0..=3 {
+                for mut tree in trees(n) {
+                    tree.rebalance();
+                    tree.rebuild_biased(&p(0.0, 0.0), 0.5);
+                    assert!(tree.try_rebuild_biased(&p(0.0, 0.0), 0.5).is_ok());
+                    tree.compress();
+                    assert_eq!(tree.dedup_within(F64::from(0.0)), 0);
+                    assert!(tree.validate());
+                    assert_eq!(xy(tree.iter()), expected(n));
+
+                    // too close to (0, 0) if there is one
+                    assert_eq!(tree.insert_if_far(p(0.5, 0.0), F64::from(1.0)), n == 0);
+                    assert_eq!(tree.insert_and_knn(p(5.0, 5.0), 1).len(), 1);
+                    assert_eq!(tree.prune_outside(&p(0.0, 0.0), &p(2.0, 2.0)), 1);
+                    assert_eq!(tree.len(), n.max(1));
+                    assert!(tree.validate());
+
+                    for _ in 0..n.max(1) {
+                        assert!(tree.remove_max_by_key(|p| F64::from(p.x + p.y)).is_some());
+                        assert!(tree.validate());
+                    }
+                    assert!(tree.is_empty());
+                    assert!(tree.remove_max_by_key(|p| F64::from(p.x)).is_none());
+                    assert_eq!(tree.prune_outside(&p(0.0, 0.0), &p(1.0, 1.0)), 0);
+                    assert_eq!(tree.dedup_within(F64::from(1.0)), 0);
+                    tree.compress();
+                    tree.rebalance();
+                    assert!(tree.find_nearest(&p(0.0, 0.0)).is_none());
+                }
+            }
+        }
+
+        #[test]
+        fn wrappers() {
+            let search = p(0.1, 0.1);
+            for n in 0..=3 {
+                let forest = KDForest::make(points(n), 3, 7);
+                assert_eq!(forest.len(), n);
+                assert_eq!(forest.ann_search(&search, 2, 10).len(), n.min(2));
+                assert!(forest.ann_search(&search, 0, 10).is_empty());
+
+                for capacity in 0..=2 {
+                    let mut bounded = BoundedKDTree::new(capacity, p(0.0, 0.0));
+                    let evicted = points(n)
+                        .into_iter()
+                        .filter_map(|point| bounded.insert(point))
+                        .count();
+                    assert_eq!(bounded.len(), n.min(capacity));
+                    assert_eq!(evicted, n - n.min(capacity));
+                    assert!(bounded.tree().validate());
+                }
+
+                let mut annotated =
+                    AnnotatedKDTree::new(KDTree::make(points(n)), |points| points.len());
+                assert_eq!(
+                    annotated.metadata(&NodeHandle::root()).copied(),
+                    (n > 0).then_some(n)
+                );
+                annotated.insert(p(1.0, 1.0));
+                annotated.rebalance();
+                assert_eq!(annotated.metadata(&NodeHandle::root()), Some(&(n + 1)));
+                assert_eq!(annotated.nodes().count(), n + 1);
+
+                let mut group = KDTreeGroup::new();
+                group.push(KDTree::make(points(n)), p(0.0, 0.0), p(2.0, 2.0));
+                group.push(KDTree::make(Vec::new()), p(0.0, 0.0), p(0.0, 0.0));
+                assert_eq!(group.find_within_radius(&search, F64::from(9.0)).len(), n);
+
+                let mut cache = QueryCache::new(vec![search.clone()]);
+                let tree = KDTree::make(points(n));
+                assert_eq!(cache.refresh(&tree), 1);
+                assert_eq!(cache.answer(0).is_some(), n > 0);
+
+                let (centroids, assignment) = kmeans(&points(n), 2, 5);
+                assert_eq!((centroids.len(), assignment.len()), (n.min(2), n));
+                assert_eq!(
+                    assign_all(&centroids, &points(n)).len(),
+                    if n > 0 { n } else { 0 }
+                );
+                assert!(assign_all(&[], &points(n)).is_empty());
+
+                let tree = KDTree::make(points(n));
+                assert_eq!(tree.explain_nearest(&search).nearest.is_some(), n > 0);
+                assert_eq!(tree.downsampled(10.0).len(), n.min(1));
+                let (quantized, merged) = KDTree::make_quantized(points(n), 10.0);
+                assert_eq!((quantized.len(), merged), (n.min(1), n.saturating_sub(1)));
+                assert_eq!(voxel_downsample(&points(n), 10.0).len(), n.min(1));
+
+                let indexed = IndexedKDTree::make(points(n));
+                let mut out = vec![0; 1];
+                indexed.assign_nearest_into(std::slice::from_ref(&search), &mut out);
+                assert_eq!(out, [if n > 0 { 0 } else { u32::MAX }]);
+            }
+        }
     }
 }
@@ -0,0 +1,208 @@
+//! centroid and covariance of the points in a region, accumulated without collecting them
+
+use super::{Coordinates, KDTree};
+
+/// number, mean and covariance of a set of points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Moments {
+    pub count: usize,
+    /// mean coordinate in every dimension
+    pub mean: Vec<f64>,
+    /// population covariance (divided by `count`), upper triangle row by row: `(0, 0)`,
+    /// `(0, 1)`, .., `(0, d - 1)`, `(1, 1)`, ..
+    pub covariance: Vec<f64>,
+}
+
+impl Moments {
+    /// covariance between dimensions `i` and `j`, in either order
+    pub fn covariance(&self, i: usize, j: usize) -> f64 {
+        let (i, j) = (i.min(j), i.max(j));
+        let dimensions = self.mean.len();
+        // the rows before `i` hold d, d - 1, .., d - i + 1 entries
+        let row = i * (2 * dimensions - i + 1) / 2;
+        self.covariance[row + j - i]
+    }
+}
+
+/// Welford's running mean and sums of products of deviations
+struct Accumulator {
+    count: usize,
+    mean: Vec<f64>,
+    /// upper triangle of the sums, as in `Moments::covariance`
+    products: Vec<f64>,
+}
+
+impl Accumulator {
+    fn new(dimensions: usize) -> Self {
+        Self {
+            count: 0,
+            mean: vec![0.0; dimensions],
+            products: vec![0.0; dimensions * (dimensions + 1) / 2],
+        }
+    }
+
+    fn add<T: Coordinates>(&mut self, point: &T) {
+        self.count += 1;
+        let n = self.count as f64;
+        let delta: Vec<f64> = (0..self.mean.len())
+            .map(|dimension| point.coordinate(dimension) - self.mean[dimension])
+            .collect();
+        for (mean, delta) in self.mean.iter_mut().zip(&delta) {
+            *mean += delta / n;
+        }
+        let mut products = self.products.iter_mut();
+        for i in 0..delta.len() {
+            for j in i..delta.len() {
+                let after = point.coordinate(j) - self.mean[j];
+                *products.next().unwrap() += delta[i] * after;
+            }
+        }
+    }
+
+    fn finish(self) -> Option<Moments> {
+        (self.count > 0).then(|| Moments {
+            count: self.count,
+            covariance: self
+                .products
+                .into_iter()
+                .map(|sum| sum / self.count as f64)
+                .collect(),
+            mean: self.mean,
+        })
+    }
+}
+
+impl<T: Coordinates> KDTree<T> {
+    /// `Moments` of the points inside the box spanned by `min` and `max` (inclusive), `None` if
+    /// there are none
+    ///
+    /// accumulated while searching as in `fold_in_range`, counted points count once per copy
+    pub fn region_moments(&self, min: &T, max: &T) -> Option<Moments> {
+        let accumulator = Accumulator::new(self.tree_dimensions());
+        self.fold_in_range(min, max, accumulator, |mut accumulator, point| {
+            accumulator.add(point);
+            accumulator
+        })
+        .finish()
+    }
+
+    /// `Moments` of the points within `radius` of `center`, `None` if there are none
+    pub fn radius_moments(&self, center: &T, radius: T::Distance) -> Option<Moments> {
+        let mut accumulator = Accumulator::new(self.tree_dimensions());
+        self.visit_within_radius(center, radius, |point| {
+            accumulator.add(point);
+            true
+        });
+        accumulator.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KDPoint, Point3D, F64};
+    use rand::random;
+
+    /// mean and covariance computed directly from all of `points`
+    fn direct(points: &[&Point3D<F64>]) -> (Vec<f64>, [[f64; 3]; 3]) {
+        let n = points.len() as f64;
+        let mean: Vec<f64> = (0..3)
+            .map(|d| points.iter().map(|p| p.coordinate(d)).sum::<f64>() / n)
+            .collect();
+        let mut covariance = [[0.0; 3]; 3];
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = points
+                    .iter()
+                    .map(|p| (p.coordinate(i) - mean[i]) * (p.coordinate(j) - mean[j]))
+                    .sum::<f64>()
+                    / n;
+            }
+        }
+        (mean, covariance)
+    }
+
+    fn assert_close(moments: &Moments, points: &[&Point3D<F64>]) {
+        let (mean, covariance) = direct(points);
+        assert_eq!(moments.count, points.len());
+        for (i, row) in covariance.iter().enumerate() {
+            assert!((moments.mean[i] - mean[i]).abs() < 1e-9);
+            for (j, expected) in row.iter().enumerate() {
+                assert!((moments.covariance(i, j) - expected).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn match_direct_computation() {
+        // stretched along x, so that the covariance is not just noise
+        let data: Vec<Point3D<F64>> = (0..2000)
+            .map(|_| {
+                let t: f64 = random();
+                [10.0 * t, t + 0.1 * random::<f64>(), random()].into()
+            })
+            .collect();
+        let tree = KDTree::make(data.clone());
+        assert_eq!(
+            tree.region_moments(&[-1.0, -1.0, -1.0].into(), &[11.0, 2.0, 2.0].into())
+                .unwrap()
+                .covariance
+                .len(),
+            6
+        );
+
+        for _ in 0..20 {
+            let (a, b): (Point3D<F64>, Point3D<F64>) = (
+                [10.0 * random::<f64>(), random(), random()].into(),
+                [10.0 * random::<f64>(), random(), random()].into(),
+            );
+            let min: Point3D<F64> = [0, 1, 2]
+                .map(|d| a.coordinate(d).min(b.coordinate(d)))
+                .into();
+            let max: Point3D<F64> = [0, 1, 2]
+                .map(|d| a.coordinate(d).max(b.coordinate(d)))
+                .into();
+            let inside: Vec<_> = data
+                .iter()
+                .filter(|p| crate::is_within_box(*p, &min, &max))
+                .collect();
+            match tree.region_moments(&min, &max) {
+                Some(moments) => assert_close(&moments, &inside),
+                None => assert!(inside.is_empty()),
+            }
+
+            let radius = F64::from(1.0);
+            let within: Vec<_> = data
+                .iter()
+                .filter(|p| Point3D::distance(&a, p) <= radius)
+                .collect();
+            match tree.radius_moments(&a, radius) {
+                Some(moments) => assert_close(&moments, &within),
+                None => assert!(within.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn single_point_and_empty() {
+        let data: Vec<Point3D<F64>> = (0..10).map(|i| [i as f64, 0.0, 1.0].into()).collect();
+        let tree = KDTree::make(data);
+
+        let single = tree
+            .region_moments(&[2.5, -1.0, 0.0].into(), &[3.5, 1.0, 2.0].into())
+            .unwrap();
+        assert_eq!(single.count, 1);
+        assert_eq!(single.mean, [3.0, 0.0, 1.0]);
+        assert_eq!(single.covariance, [0.0; 6]);
+
+        assert!(tree
+            .region_moments(&[2.2, -1.0, 0.0].into(), &[2.8, 1.0, 2.0].into())
+            .is_none());
+        assert!(tree
+            .radius_moments(&[0.0, 5.0, 1.0].into(), F64::from(1.0))
+            .is_none());
+        assert!(KDTree::<Point3D<F64>>::make(Vec::new())
+            .radius_moments(&[0.0, 0.0, 0.0].into(), F64::from(1.0))
+            .is_none());
+    }
+}
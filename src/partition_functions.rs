@@ -1,13 +1,51 @@
 use std::cmp::Ordering;
 
-pub fn hoare_a<T>(data: &mut Vec<T>, key_cmp: impl Fn(&T, &T) -> Ordering) -> usize {
-    fn recurse<T>(
-        data: &mut Vec<T>,
-        target_idx: usize,
-        left: usize,
-        right: usize,
-        key_cmp: impl Fn(&T, &T) -> Ordering,
-    ) -> usize {
+/// checks `key_cmp` on all pairs and triples of `sample` for the properties partitioning relies
+/// on, returns a description of the first violation
+///
+/// a comparator that is not a total order makes the partition functions return garbage without
+/// noticing, this catches the common cases on a handful of elements
+pub fn check_comparator<T>(
+    sample: &[T],
+    key_cmp: impl Fn(&T, &T) -> Ordering,
+) -> Result<(), String> {
+    for (i, a) in sample.iter().enumerate() {
+        if key_cmp(a, a).is_ne() {
+            return Err(format!("element {i} does not compare equal to itself"));
+        }
+        for (j, b) in sample.iter().enumerate() {
+            let ab = key_cmp(a, b);
+            if ab != key_cmp(b, a).reverse() {
+                return Err(format!(
+                    "elements {i} and {j} compare inconsistently both ways"
+                ));
+            }
+            for (k, c) in sample.iter().enumerate() {
+                let bc = key_cmp(b, c);
+                if ab == bc && key_cmp(a, c) != ab {
+                    return Err(format!(
+                        "elements {i}, {j} and {k} compare {ab:?} pairwise, but not transitively"
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// see `hoare_b`
+#[allow(dead_code)]
+pub fn hoare_a<T>(data: &mut [T], key_cmp: impl Fn(&T, &T) -> Ordering) -> usize {
+    if data.len() <= 1 {
+        return 0;
+    }
+
+    // selection, looping instead of recursing into the side containing the target
+    let target_idx = data.len() / 2;
+    let mut left = 0;
+    let mut right = data.len() - 1;
+
+    loop {
         assert!(left <= target_idx && target_idx <= right);
         if left == right {
             return target_idx;
@@ -90,83 +128,98 @@ pub fn hoare_a<T>(data: &mut Vec<T>, key_cmp: impl Fn(&T, &T) -> Ordering) -> us
         }
 
         match key_cmp(&data[target_idx], &data[pivot_idx]) {
-            Ordering::Less => recurse(data, target_idx, left, pivot_idx - 1, key_cmp),
+            Ordering::Less => right = pivot_idx - 1,
             Ordering::Equal => {
                 assert!(pivot_idx <= target_idx);
-                pivot_idx
+                return pivot_idx;
             }
-            Ordering::Greater => recurse(data, target_idx, pivot_idx + 1, right, key_cmp),
+            Ordering::Greater => left = pivot_idx + 1,
         }
     }
-
-    recurse(data, data.len() / 2, 0, data.len() - 1, key_cmp)
 }
 
-pub fn hoare_b<T>(data: &mut Vec<T>, key_cmp: impl Fn(&T, &T) -> Ordering) -> usize {
-    fn recurse<T>(
-        data: &mut Vec<T>,
-        left: usize,
-        target_idx: usize,
-        right: usize,
-        key_cmp: impl Fn(&T, &T) -> Ordering,
+/// reorders `data` around its median and returns the median's index: everything before it is
+/// smaller, everything from it on is equal or greater
+///
+/// empty data has no median, 0 is returned anyway
+pub fn hoare_b<T>(data: &mut [T], key_cmp: impl Fn(&T, &T) -> Ordering) -> usize {
+    // moves everything in data[start..end] for which `goes_left` holds in front of the rest,
+    // returns the index of the first element for which it does not hold
+    fn split<T>(
+        data: &mut [T],
+        start: usize,
+        end: usize,
+        pivot_idx: usize,
+        goes_left: impl Fn(Ordering) -> bool,
+        key_cmp: &impl Fn(&T, &T) -> Ordering,
     ) -> usize {
-        assert!(left < right);
-        assert!(right < data.len());
-        assert!((left..=right).contains(&target_idx));
+        assert!(!(start..end).contains(&pivot_idx));
 
-        let mut pivot_idx = (right - left) / 2 + left;
-        assert!((left..=right).contains(&pivot_idx));
-
-        let mut l = left;
-        let mut r = right;
+        let mut l = start;
+        let mut r = end;
 
         loop {
-            // step l
-            while l < r {
-                match key_cmp(&data[l], &data[pivot_idx]) {
-                    Ordering::Less => l += 1,
-                    Ordering::Equal => {
-                        pivot_idx = usize::min(l, pivot_idx);
-                        break;
-                    }
-                    Ordering::Greater => break,
-                }
+            while l < r && goes_left(key_cmp(&data[l], &data[pivot_idx])) {
+                l += 1;
             }
-
-            // step r
-            while l < r {
-                match key_cmp(&data[r], &data[pivot_idx]) {
-                    Ordering::Less => break,
-                    Ordering::Equal => {
-                        pivot_idx = usize::min(r, pivot_idx);
-                        r -= 1
-                    }
-                    Ordering::Greater => r -= 1,
-                }
+            while l < r && !goes_left(key_cmp(&data[r - 1], &data[pivot_idx])) {
+                r -= 1;
             }
 
             if l >= r {
-                break;
+                break l;
             }
 
-            data.swap(l, r);
-            if r == pivot_idx {
-                pivot_idx = l;
-            } else if l == pivot_idx {
-                pivot_idx = r;
-            }
+            data.swap(l, r - 1);
+            l += 1;
+            r -= 1;
         }
+    }
 
-        if key_cmp(&data[pivot_idx], &data[target_idx]).is_eq() {
-            pivot_idx
-        } else {
-            match usize::cmp(&target_idx, &pivot_idx) {
-                Ordering::Less => recurse(data, left, target_idx, pivot_idx - 1, key_cmp),
-                Ordering::Equal => unreachable!(),
-                Ordering::Greater => recurse(data, pivot_idx + 1, target_idx, right, key_cmp),
-            }
-        }
+    if data.len() <= 1 {
+        return 0;
     }
 
-    recurse(data, 0, data.len() / 2, data.len() - 1, key_cmp)
+    // selection, looping instead of recursing into the side containing the target
+    let target_idx = data.len() / 2;
+    let mut left = 0;
+    let mut right = data.len() - 1;
+
+    loop {
+        assert!(left <= right);
+        assert!(right < data.len());
+        assert!((left..=right).contains(&target_idx));
+
+        if left == right {
+            return left;
+        }
+
+        // park pivot at the right end while partitioning the rest
+        let pivot_idx = (right - left) / 2 + left;
+        data.swap(pivot_idx, right);
+
+        // data[left..pivot_idx] < pivot <= data[pivot_idx + 1..=right]
+        let pivot_idx = split(data, left, right, right, Ordering::is_lt, &key_cmp);
+        data.swap(pivot_idx, right);
+
+        if target_idx < pivot_idx {
+            right = pivot_idx - 1;
+            continue;
+        }
+
+        // data[pivot_idx + 1..equal_end] == pivot < data[equal_end..=right]
+        let equal_end = split(
+            data,
+            pivot_idx + 1,
+            right + 1,
+            pivot_idx,
+            Ordering::is_eq,
+            &key_cmp,
+        );
+
+        if target_idx < equal_end {
+            return pivot_idx;
+        }
+        left = equal_end;
+    }
 }
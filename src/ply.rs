@@ -0,0 +1,440 @@
+use std::io::{self, BufRead, Read, Write};
+
+use super::{Coordinates, Error, KDTree, Point3D, F64};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+/// additional vertex property: name and how to compute it for a point, written as `double`
+pub type PlyScalar<'a, T> = (&'a str, &'a dyn Fn(&T) -> f64);
+
+impl<T: Coordinates> KDTree<T> {
+    /// writes all points as vertices of an ASCII PLY point cloud
+    pub fn write_ply<W: Write>(&self, w: W) -> io::Result<()> {
+        self.write_ply_with(w, PlyFormat::Ascii, &[])
+    }
+
+    /// writes all points as vertices of a PLY point cloud, each vertex carries `x y z` followed by
+    /// the given `scalars`
+    ///
+    /// points with less than 3 dimensions are padded with 0, points with more are rejected
+    pub fn write_ply_with<W: Write>(
+        &self,
+        mut w: W,
+        format: PlyFormat,
+        scalars: &[PlyScalar<T>],
+    ) -> io::Result<()> {
+        if self.iter().any(|point| point.dimensions() > 3) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PLY export supports at most 3 dimensions",
+            ));
+        }
+
+        writeln!(w, "ply")?;
+        match format {
+            PlyFormat::Ascii => writeln!(w, "format ascii 1.0")?,
+            PlyFormat::BinaryLittleEndian => writeln!(w, "format binary_little_endian 1.0")?,
+        }
+        writeln!(w, "element vertex {}", self.iter().count())?;
+        for name in ["x", "y", "z"]
+            .into_iter()
+            .chain(scalars.iter().map(|(name, _)| *name))
+        {
+            writeln!(w, "property double {name}")?;
+        }
+        writeln!(w, "end_header")?;
+
+        for point in self.iter() {
+            let values = (0..3)
+                .map(|dimension| {
+                    if dimension < point.dimensions() {
+                        point.coordinate(dimension)
+                    } else {
+                        0.0
+                    }
+                })
+                .chain(scalars.iter().map(|(_, scalar)| scalar(point)));
+
+            match format {
+                PlyFormat::Ascii => {
+                    let line: Vec<_> = values.map(|value| value.to_string()).collect();
+                    writeln!(w, "{}", line.join(" "))?;
+                }
+                PlyFormat::BinaryLittleEndian => {
+                    for value in values {
+                        w.write_all(&value.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+
+        w.flush()
+    }
+}
+
+/// the `Error` inside can be recovered with `get_ref` and `downcast_ref`
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        Error::CorruptData(message.into()),
+    )
+}
+
+/// scalar property types of the PLY format
+#[derive(Debug, Clone, Copy)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> io::Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return Err(invalid(format!("unknown PLY type `{name}`"))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn read_le(self, bytes: &[u8]) -> f64 {
+        match self {
+            Self::I8 => bytes[0] as i8 as f64,
+            Self::U8 => bytes[0] as f64,
+            Self::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Self::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Self::I32 => i32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+            Self::U32 => u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+            Self::F32 => f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+            Self::F64 => f64::from_le_bytes(bytes[..8].try_into().unwrap()),
+        }
+    }
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    /// property names and types, `None` for list properties
+    properties: Vec<(String, Option<PlyType>)>,
+}
+
+impl Element {
+    fn record_size(&self) -> io::Result<usize> {
+        self.properties
+            .iter()
+            .map(|(name, ty)| {
+                ty.map(PlyType::size).ok_or_else(|| {
+                    invalid(format!(
+                        "cannot skip list property `{name}` of element `{}`",
+                        self.name
+                    ))
+                })
+            })
+            .sum()
+    }
+}
+
+/// reads the `x`, `y` and `z` properties of the `vertex` element of a PLY file, everything else is
+/// ignored
+///
+/// elements stored before `vertex` must not contain list properties in binary files
+pub fn read_ply_points<R: BufRead>(mut r: R) -> io::Result<Vec<Point3D<F64>>> {
+    let mut line = String::new();
+    let mut next_line = |r: &mut R| -> io::Result<String> {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Err(invalid("unexpected end of PLY header"));
+        }
+        Ok(line.trim().to_string())
+    };
+
+    if next_line(&mut r)? != "ply" {
+        return Err(invalid("missing PLY magic"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        let line = next_line(&mut r)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", other, _] => return Err(invalid(format!("unsupported format `{other}`"))),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid element count `{count}`")))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", _, _, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("property outside of element"))?
+                .properties
+                .push((name.to_string(), None)),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("property outside of element"))?
+                .properties
+                .push((name.to_string(), Some(PlyType::parse(ty)?))),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(invalid(format!("unexpected header line `{line}`"))),
+        }
+    }
+    let format = format.ok_or_else(|| invalid("missing format"))?;
+
+    let vertex_idx = elements
+        .iter()
+        .position(|element| element.name == "vertex")
+        .ok_or_else(|| invalid("missing vertex element"))?;
+    let vertex = &elements[vertex_idx];
+    let mut axes = [0; 3];
+    for (idx, axis) in axes.iter_mut().zip(["x", "y", "z"]) {
+        *idx = vertex
+            .properties
+            .iter()
+            .position(|(name, ty)| name == axis && ty.is_some())
+            .ok_or_else(|| invalid(format!("missing vertex property `{axis}`")))?;
+    }
+
+    // the count is untrusted, the vector grows past this if the body really holds more
+    let mut points = Vec::with_capacity(vertex.count.min(1 << 20));
+    match format {
+        PlyFormat::Ascii => {
+            let mut lines = r.lines();
+            let skip = elements[..vertex_idx]
+                .iter()
+                .try_fold(0usize, |skip, e| skip.checked_add(e.count))
+                .ok_or_else(|| invalid("too many elements before `vertex`"))?;
+            for _ in 0..skip {
+                lines
+                    .next()
+                    .ok_or_else(|| invalid("unexpected end of PLY body"))??;
+            }
+            for _ in 0..vertex.count {
+                let line = lines
+                    .next()
+                    .ok_or_else(|| invalid("unexpected end of PLY body"))??;
+                let values: Vec<&str> = line.split_whitespace().collect();
+                let mut coords = [0.0; 3];
+                for (coord, idx) in coords.iter_mut().zip(axes) {
+                    *coord = values
+                        .get(idx)
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| invalid(format!("invalid vertex `{line}`")))?;
+                }
+                points.push(coords.into());
+            }
+        }
+        PlyFormat::BinaryLittleEndian => {
+            for element in &elements[..vertex_idx] {
+                let skip = element
+                    .record_size()?
+                    .checked_mul(element.count)
+                    .ok_or_else(|| invalid(format!("element `{}` is too large", element.name)))?;
+                io::copy(&mut r.by_ref().take(skip as u64), &mut io::sink())?;
+            }
+
+            let offsets: Vec<usize> = vertex
+                .properties
+                .iter()
+                .scan(0, |offset, (_, ty)| {
+                    let start = *offset;
+                    *offset += ty.map(PlyType::size).unwrap_or(0);
+                    Some(start)
+                })
+                .collect();
+            let mut record = vec![0; vertex.record_size()?];
+            for _ in 0..vertex.count {
+                r.read_exact(&mut record)?;
+                let coords = axes.map(|idx| {
+                    let ty = vertex.properties[idx].1.unwrap();
+                    ty.read_le(&record[offsets[idx]..])
+                });
+                points.push(coords.into());
+            }
+        }
+    }
+
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DynPoint, KDPoint};
+
+    fn cloud() -> Vec<Point3D<F64>> {
+        vec![
+            [0.0, 0.0, 0.0].into(),
+            [1.5, -2.0, 0.25].into(),
+            [-3.0, 4.0, 1e-3].into(),
+            [7.0, 7.0, -7.0].into(),
+        ]
+    }
+
+    fn sorted(points: impl IntoIterator<Item = Point3D<F64>>) -> Vec<[F64; 3]> {
+        let mut points: Vec<_> = points.into_iter().map(|p| [p.x, p.y, p.z]).collect();
+        points.sort();
+        points
+    }
+
+    fn round_trip(format: PlyFormat) {
+        let tree = KDTree::make(cloud());
+        let origin: Point3D<F64> = [0.0, 0.0, 0.0].into();
+        let distance = |p: &Point3D<F64>| f64::from(Point3D::distance(&origin, p));
+        let cluster = |p: &Point3D<F64>| if f64::from(p.x) < 0.0 { 1.0 } else { 0.0 };
+
+        let mut buffer = Vec::new();
+        tree.write_ply_with(
+            &mut buffer,
+            format,
+            &[("distance", &distance), ("cluster", &cluster)],
+        )
+        .unwrap();
+
+        let points = read_ply_points(buffer.as_slice()).unwrap();
+        assert_eq!(sorted(points), sorted(cloud()));
+    }
+
+    #[test]
+    fn round_trip_ascii() {
+        round_trip(PlyFormat::Ascii);
+    }
+
+    #[test]
+    fn round_trip_binary() {
+        round_trip(PlyFormat::BinaryLittleEndian);
+    }
+
+    #[test]
+    fn header() {
+        let tree = KDTree::make(cloud());
+        let origin: Point3D<F64> = [0.0, 0.0, 0.0].into();
+        let distance = |p: &Point3D<F64>| f64::from(Point3D::distance(&origin, p));
+
+        let mut buffer = Vec::new();
+        tree.write_ply_with(&mut buffer, PlyFormat::Ascii, &[("distance", &distance)])
+            .unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        let mut lines = text.lines();
+
+        for expected in [
+            "ply",
+            "format ascii 1.0",
+            "element vertex 4",
+            "property double x",
+            "property double y",
+            "property double z",
+            "property double distance",
+            "end_header",
+        ] {
+            assert_eq!(lines.next(), Some(expected));
+        }
+        for line in lines {
+            let values: Vec<f64> = line.split(' ').map(|v| v.parse().unwrap()).collect();
+            let [x, y, z, distance] = values.try_into().unwrap();
+            assert_eq!(distance, x * x + y * y + z * z);
+        }
+    }
+
+    #[test]
+    fn read_foreign_layout() {
+        // float coordinates in unusual order, extra properties and a face element
+        let mut buffer = b"ply\nformat binary_little_endian 1.0\ncomment foreign\n\
+            element vertex 2\nproperty uchar red\nproperty float z\nproperty float y\n\
+            property float x\nelement face 0\nproperty list uchar int vertex_indices\nend_header\n"
+            .to_vec();
+        for (red, [x, y, z]) in [(255u8, [1.0f32, 2.0, 3.0]), (0, [-1.0, 0.5, 8.0])] {
+            buffer.push(red);
+            for value in [z, y, x] {
+                buffer.extend(value.to_le_bytes());
+            }
+        }
+
+        let points = read_ply_points(buffer.as_slice()).unwrap();
+        assert_eq!(
+            sorted(points),
+            sorted([[1.0, 2.0, 3.0].into(), [-1.0, 0.5, 8.0].into()])
+        );
+    }
+
+    #[test]
+    fn corrupt_data() {
+        let error = read_ply_points(b"ply\nformat ascii 1.0\n".as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let inner = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<Error>());
+        assert_eq!(
+            inner,
+            Some(&Error::CorruptData("unexpected end of PLY header".into()))
+        );
+        assert_eq!(error.to_string(), "unexpected end of PLY header");
+    }
+
+    #[test]
+    fn huge_counts() {
+        let max = usize::MAX;
+        for format in ["ascii", "binary_little_endian"] {
+            let header = format!(
+                "ply\nformat {format} 1.0\nelement vertex {max}\nproperty float x\n\
+                property float y\nproperty float z\nend_header\n"
+            );
+            let error = read_ply_points(header.as_bytes()).unwrap_err();
+            assert!(matches!(
+                error.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ));
+        }
+
+        // skipping the elements in front of `vertex` would overflow
+        let header = format!(
+            "ply\nformat binary_little_endian 1.0\nelement skipped {max}\nproperty double a\n\
+            element vertex 0\nproperty float x\nproperty float y\nproperty float z\nend_header\n"
+        );
+        let error = read_ply_points(header.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "element `skipped` is too large");
+        let header = format!(
+            "ply\nformat ascii 1.0\nelement a {max}\nproperty float a\n\
+            element b 1\nproperty float b\nelement vertex 0\nproperty float x\n\
+            property float y\nproperty float z\nend_header\n"
+        );
+        let error = read_ply_points(header.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reject_higher_dimensions() {
+        let tree = KDTree::make(vec![DynPoint::from(vec![0.0; 4])]);
+        let error = tree.write_ply(Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
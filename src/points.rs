@@ -3,8 +3,13 @@ use std::{
     ops::{Add, Mul, Sub},
 };
 
-use super::KDPoint;
+use super::{Coordinates, KDPoint};
 
+/// `f64` with a total order: NaN (of any sign) is equal to itself and greater than everything
+/// else, so points at NaN distance, e.g. with a NaN coordinate, always come last in queries
+///
+/// all NaNs, whatever their sign or payload, are equal to each other, and so are `-0.0` and `0.0`;
+/// `==` and `cmp` always agree
 #[derive(Debug, Clone, Copy)]
 pub struct F64(f64);
 
@@ -16,7 +21,7 @@ impl From<f64> for F64 {
 
 impl PartialEq for F64 {
     fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+        self.cmp(other).is_eq()
     }
 }
 
@@ -24,13 +29,16 @@ impl Eq for F64 {}
 
 impl PartialOrd for F64 {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        f64::partial_cmp(&self.0, &other.0)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for F64 {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        f64::total_cmp(&self.0, &other.0)
+        match (self.0.is_nan(), other.0.is_nan()) {
+            (false, false) => self.0.partial_cmp(&other.0).unwrap(),
+            (lhs, rhs) => lhs.cmp(&rhs),
+        }
     }
 }
 
@@ -58,9 +66,9 @@ impl Mul<F64> for F64 {
     }
 }
 
-impl Into<f64> for F64 {
-    fn into(self) -> f64 {
-        self.0
+impl From<F64> for f64 {
+    fn from(value: F64) -> Self {
+        value.0
     }
 }
 
@@ -94,13 +102,12 @@ impl KDPoint for Point2D {
     type Key = F64;
     type Distance = F64;
 
+    fn dimensions(&self) -> usize {
+        2
+    }
+
     fn kdkey(&self, dimension: usize) -> Self::Key {
-        match dimension % 2 {
-            0 => self.x,
-            1 => self.y,
-            _ => unreachable!(),
-        }
-        .into()
+        self.coordinate(dimension).into()
     }
 
     fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
@@ -115,6 +122,36 @@ impl KDPoint for Point2D {
     }
 }
 
+impl Coordinates for Point2D {
+    fn coordinate(&self, dimension: usize) -> f64 {
+        match dimension {
+            0 => self.x,
+            1 => self.y,
+            _ => panic!("Point2D has no dimension {dimension}"),
+        }
+    }
+
+    fn set_coordinate(&mut self, dimension: usize, value: f64) {
+        match dimension {
+            0 => self.x = value,
+            1 => self.y = value,
+            _ => panic!("Point2D has no dimension {dimension}"),
+        }
+    }
+}
+
+/// points whose `distance` is the squared euclidean distance, for queries taking radii in actual
+/// distance units (see `KDTree::within_distance`)
+pub trait SquaredEuclidean: KDPoint<Distance = F64> {}
+
+impl SquaredEuclidean for Point2D {}
+
+impl SquaredEuclidean for Point3D<F64> {}
+
+impl SquaredEuclidean for DynPoint {}
+
+impl<P: SquaredEuclidean> SquaredEuclidean for Cached<P> where P::Key: Clone {}
+
 #[derive(Debug, Clone)]
 pub struct Point3D<T> {
     pub x: T,
@@ -143,12 +180,16 @@ impl<T: Ord + Copy + Add<T, Output = T> + Sub<T, Output = T> + Mul<T, Output = T
 
     type Distance = T;
 
+    fn dimensions(&self) -> usize {
+        3
+    }
+
     fn kdkey(&self, dimension: usize) -> Self::Key {
-        match dimension % 3 {
+        match dimension {
             0 => self.x,
             1 => self.y,
             2 => self.z,
-            _ => unreachable!(),
+            _ => panic!("Point3D has no dimension {dimension}"),
         }
     }
 
@@ -157,7 +198,7 @@ impl<T: Ord + Copy + Add<T, Output = T> + Sub<T, Output = T> + Mul<T, Output = T
         let ydiff = lhs.y - rhs.y;
         let zdiff = lhs.z - rhs.z;
 
-        (xdiff * xdiff + ydiff * ydiff + zdiff * zdiff).into()
+        xdiff * xdiff + ydiff * ydiff + zdiff * zdiff
     }
 
     fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
@@ -165,3 +206,404 @@ impl<T: Ord + Copy + Add<T, Output = T> + Sub<T, Output = T> + Mul<T, Output = T
         dist * dist
     }
 }
+
+impl Coordinates for Point3D<F64> {
+    fn coordinate(&self, dimension: usize) -> f64 {
+        match dimension {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => panic!("Point3D has no dimension {dimension}"),
+        }
+        .into()
+    }
+
+    fn set_coordinate(&mut self, dimension: usize, value: f64) {
+        let coordinate = match dimension {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Point3D has no dimension {dimension}"),
+        };
+        *coordinate = value.into();
+    }
+}
+
+/// point with a number of dimensions only known at runtime, all points in a tree have to agree on
+/// it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynPoint {
+    pub coords: Vec<f64>,
+}
+
+impl From<Vec<f64>> for DynPoint {
+    fn from(coords: Vec<f64>) -> Self {
+        Self { coords }
+    }
+}
+
+impl From<&[f64]> for DynPoint {
+    fn from(coords: &[f64]) -> Self {
+        coords.to_vec().into()
+    }
+}
+
+impl KDPoint for DynPoint {
+    type Key = F64;
+    type Distance = F64;
+
+    fn dimensions(&self) -> usize {
+        self.coords.len()
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        self.coords[dimension].into()
+    }
+
+    /// over the dimensions of `rhs`, a search point may have more
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        debug_assert!(lhs.coords.len() >= rhs.coords.len());
+        lhs.coords
+            .iter()
+            .zip(&rhs.coords)
+            .map(|(l, r)| (l - r) * (l - r))
+            .sum::<f64>()
+            .into()
+    }
+
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        let dist = *lhs - *rhs;
+        dist * dist
+    }
+}
+
+impl Coordinates for DynPoint {
+    fn coordinate(&self, dimension: usize) -> f64 {
+        self.coords[dimension]
+    }
+
+    fn set_coordinate(&mut self, dimension: usize, value: f64) {
+        self.coords[dimension] = value;
+    }
+}
+
+/// unit quaternion describing a rotation, components are normalized on construction
+///
+/// `q` and `-q` describe the same rotation (double cover), so the distance between two quaternions
+/// is the smaller of the distances to `rhs` and `-rhs`: the angle of the rotation taking one
+/// orientation to the other, in radians within `[0, pi]`. Keys are the absolute values of the
+/// components, which keeps the per-axis bound admissible under the sign ambiguity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuaternionPoint {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl QuaternionPoint {
+    /// panics if all components are zero
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Self {
+        let norm = (w * w + x * x + y * y + z * z).sqrt();
+        assert!(norm > 0.0, "zero quaternion has no rotation");
+        Self {
+            w: w / norm,
+            x: x / norm,
+            y: y / norm,
+            z: z / norm,
+        }
+    }
+
+    fn components(&self) -> [f64; 4] {
+        [self.w, self.x, self.y, self.z]
+    }
+
+    /// rotation angle corresponding to the euclidean distance `chord` of two unit quaternions
+    fn angle(chord: f64) -> F64 {
+        (4.0 * (chord / 2.0).min(1.0).asin()).into()
+    }
+}
+
+impl From<[f64; 4]> for QuaternionPoint {
+    fn from(value: [f64; 4]) -> Self {
+        let [w, x, y, z] = value;
+        Self::new(w, x, y, z)
+    }
+}
+
+impl KDPoint for QuaternionPoint {
+    type Key = F64;
+    type Distance = F64;
+
+    fn dimensions(&self) -> usize {
+        4
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        self.components()[dimension].abs().into()
+    }
+
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        let chord = |sign: f64| {
+            lhs.components()
+                .iter()
+                .zip(rhs.components())
+                .map(|(l, r)| (l - sign * r) * (l - sign * r))
+                .sum::<f64>()
+                .sqrt()
+        };
+        Self::angle(f64::min(chord(1.0), chord(-1.0)))
+    }
+
+    // |lhs - rhs| and |lhs + rhs| are both at least the difference of the absolute values of any
+    // one component
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        Self::angle((f64::from(*lhs) - f64::from(*rhs)).abs())
+    }
+}
+
+/// wraps a point whose `kdkey` is expensive, all keys are computed once on wrapping and looked up
+/// afterwards
+#[derive(Debug, Clone)]
+pub struct Cached<P: KDPoint> {
+    point: P,
+    keys: Vec<P::Key>,
+}
+
+impl<P: KDPoint> Cached<P> {
+    /// computes the keys of `point` in its `dimensions()` dimensions
+    pub fn new(point: P) -> Self {
+        let keys = (0..point.dimensions())
+            .map(|dimension| point.kdkey(dimension))
+            .collect();
+        Self { point, keys }
+    }
+
+    pub fn point(&self) -> &P {
+        &self.point
+    }
+
+    pub fn into_inner(self) -> P {
+        self.point
+    }
+}
+
+impl<P: KDPoint> From<P> for Cached<P> {
+    fn from(point: P) -> Self {
+        Self::new(point)
+    }
+}
+
+impl<P: KDPoint> KDPoint for Cached<P>
+where
+    P::Key: Clone,
+{
+    type Key = P::Key;
+    type Distance = P::Distance;
+
+    fn dimensions(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn kdkey(&self, dimension: usize) -> Self::Key {
+        self.keys[dimension].clone()
+    }
+
+    fn distance(lhs: &Self, rhs: &Self) -> Self::Distance {
+        P::distance(&lhs.point, &rhs.point)
+    }
+
+    fn key_distance(lhs: &Self::Key, rhs: &Self::Key) -> Self::Distance {
+        P::key_distance(lhs, rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod cached {
+        use super::*;
+        use crate::counting::{key_calls, Counted};
+        use crate::KDTree;
+        use rand::random;
+
+        fn random_point() -> Counted {
+            Counted((random(), random()).into())
+        }
+
+        #[test]
+        fn same_tree_and_results() {
+            let data: Vec<Counted> = (0..500).map(|_| random_point()).collect();
+            let searches: Vec<Counted> = (0..50).map(|_| random_point()).collect();
+            let plain = KDTree::make(data.clone());
+
+            let ((cached_data, cached_searches), calls) = key_calls(|| {
+                let cached_data: Vec<_> = data.into_iter().map(Cached::new).collect();
+                let cached_searches: Vec<_> = searches.iter().cloned().map(Cached::new).collect();
+                (cached_data, cached_searches)
+            });
+            assert!(calls > 0);
+
+            let (cached, calls) = key_calls(|| KDTree::make(cached_data));
+            assert_eq!(calls, 0);
+            assert_eq!(cached.shape_signature(), plain.shape_signature());
+            let coordinates = |p: &Point2D| (p.x, p.y);
+            let (cached_found, calls) = key_calls(|| -> Vec<_> {
+                cached_searches
+                    .iter()
+                    .map(|search| {
+                        let nearest = cached.find_nearest(search).unwrap();
+                        let k_nearest = cached.find_k_nearest(search, 5);
+                        (
+                            coordinates(&nearest.point().0),
+                            k_nearest
+                                .iter()
+                                .map(|p| coordinates(&p.point().0))
+                                .collect::<Vec<_>>(),
+                        )
+                    })
+                    .collect()
+            });
+            assert_eq!(calls, 0);
+
+            let plain_found: Vec<_> = searches
+                .iter()
+                .map(|search| {
+                    let nearest = plain.find_nearest(search).unwrap();
+                    let k_nearest = plain.find_k_nearest(search, 5);
+                    (
+                        coordinates(&nearest.0),
+                        k_nearest
+                            .iter()
+                            .map(|p| coordinates(&p.0))
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .collect();
+            assert_eq!(cached_found, plain_found);
+            assert!(plain
+                .iter()
+                .zip(cached.iter())
+                .all(|(p, c)| coordinates(&p.0) == coordinates(&c.point().0)));
+        }
+    }
+
+    mod float {
+        use super::*;
+        use std::cmp::Ordering;
+
+        #[test]
+        fn signed_zero() {
+            let (zero, negative) = (F64::from(0.0), F64::from(-0.0));
+            assert_eq!(zero, negative);
+            assert_eq!(zero.cmp(&negative), Ordering::Equal);
+            assert_eq!(negative.cmp(&zero), Ordering::Equal);
+            assert!(F64::from(-f64::MIN_POSITIVE) < negative);
+        }
+
+        #[test]
+        fn nan_payloads() {
+            let nans = [
+                f64::NAN,
+                -f64::NAN,
+                f64::from_bits(0x7ff0_0000_0000_0001),
+                f64::from_bits(0xfff8_dead_beef_0000),
+            ]
+            .map(F64::from);
+            for lhs in nans {
+                for rhs in nans {
+                    assert_eq!(lhs, rhs);
+                    assert_eq!(lhs.cmp(&rhs), Ordering::Equal);
+                }
+                assert!(lhs > F64::from(f64::INFINITY));
+                assert_eq!(F64::from(f64::INFINITY).cmp(&lhs), Ordering::Less);
+            }
+        }
+
+        #[test]
+        fn round_trip() {
+            let data: Vec<Point2D> = vec![
+                (-0.0, 0.0).into(),
+                (1.0, -0.0).into(),
+                (f64::NAN, 0.0).into(),
+            ];
+            let tree = crate::KDTree::make(data);
+            assert!(tree.validate());
+
+            let found = tree.find_nearest(&(0.0, -0.0).into()).unwrap();
+            assert_eq!((found.x, found.y), (0.0, 0.0));
+            let found = tree.find_nearest(&(1.0, 0.0).into()).unwrap();
+            assert_eq!((found.x, found.y), (1.0, 0.0));
+            assert_eq!(
+                tree.find_within_radius(&(0.0, 0.0).into(), F64::from(0.0))
+                    .len(),
+                1
+            );
+        }
+    }
+
+    mod quaternion {
+        use super::*;
+        use rand::random;
+
+        fn random_quaternion() -> QuaternionPoint {
+            [0; 4].map(|_| random::<f64>() - 0.5).into()
+        }
+
+        #[test]
+        fn double_cover() {
+            for _ in 0..100 {
+                let q = random_quaternion();
+                let negated = QuaternionPoint {
+                    w: -q.w,
+                    x: -q.x,
+                    y: -q.y,
+                    z: -q.z,
+                };
+                assert_eq!(QuaternionPoint::distance(&q, &negated), F64::from(0.0));
+            }
+        }
+
+        #[test]
+        fn rotation_angle() {
+            let identity = QuaternionPoint::new(1.0, 0.0, 0.0, 0.0);
+            for angle in [0.1, 1.0, 3.0f64] {
+                let rotation =
+                    QuaternionPoint::new((angle / 2.0).cos(), 0.0, 0.0, (angle / 2.0).sin());
+                let distance = f64::from(QuaternionPoint::distance(&identity, &rotation));
+                assert!((distance - angle).abs() < 1e-9, "{distance} != {angle}");
+            }
+        }
+
+        #[test]
+        fn key_distance_is_a_lower_bound() {
+            for _ in 0..1000 {
+                let (lhs, rhs) = (random_quaternion(), random_quaternion());
+                let distance = QuaternionPoint::distance(&lhs, &rhs);
+                for dimension in 0..4 {
+                    let bound =
+                        QuaternionPoint::key_distance(&lhs.kdkey(dimension), &rhs.kdkey(dimension));
+                    assert!(bound <= distance);
+                }
+            }
+        }
+
+        #[test]
+        fn nearest_brute_force() {
+            let data: Vec<QuaternionPoint> = (0..500).map(|_| random_quaternion()).collect();
+            let tree = crate::KDTree::make(data.clone());
+
+            for _ in 0..50 {
+                let search = random_quaternion();
+                let expected = data
+                    .iter()
+                    .map(|q| QuaternionPoint::distance(&search, q))
+                    .min()
+                    .unwrap();
+                let found = tree.find_nearest(&search).unwrap();
+                assert_eq!(QuaternionPoint::distance(&search, found), expected);
+            }
+        }
+    }
+}
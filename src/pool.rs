@@ -0,0 +1,136 @@
+//! batch queries split across threads the caller provides
+
+use std::thread;
+
+use super::{KDPoint, KDTree};
+
+/// a job of a batch query, borrowing the tree and the searches
+pub type Job<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+/// runs the jobs of a batch query, on whatever threads it has
+///
+/// implementing it for an application's own pool keeps the tree's work on that pool, e.g. for a
+/// rayon pool: `pool.scope(|scope| for job in jobs { scope.spawn(|_| job()) })`
+pub trait ThreadPool {
+    /// how many jobs are worth running at once, batches are split into that many
+    fn threads(&self) -> usize;
+
+    /// runs all `jobs`, returning once every one of them is done
+    fn run<'a>(&self, jobs: Vec<Job<'a>>);
+}
+
+/// a pool of `threads` scoped threads, started for each batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScopedThreads(pub usize);
+
+impl ThreadPool for ScopedThreads {
+    fn threads(&self) -> usize {
+        self.0
+    }
+
+    fn run<'a>(&self, jobs: Vec<Job<'a>>) {
+        thread::scope(|scope| {
+            for job in jobs {
+                scope.spawn(job);
+            }
+        });
+    }
+}
+
+impl<T: KDPoint + Sync> KDTree<T>
+where
+    T::Key: Send + Sync,
+{
+    /// `find_nearest` for each of `searches`, in order, split into `pool.threads()` jobs run by
+    /// `pool`
+    pub fn find_nearest_batch_in_pool(
+        &self,
+        searches: &[T],
+        pool: &impl ThreadPool,
+    ) -> Vec<Option<&T>> {
+        let mut found = vec![None; searches.len()];
+        let chunk = searches.len().div_ceil(pool.threads().max(1)).max(1);
+        let jobs = searches
+            .chunks(chunk)
+            .zip(found.chunks_mut(chunk))
+            .map(|(searches, found)| -> Job<'_> {
+                Box::new(move || {
+                    for (search, found) in searches.iter().zip(found) {
+                        *found = self.find_nearest(search);
+                    }
+                })
+            })
+            .collect();
+        pool.run(jobs);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point2D;
+    use rand::random;
+    use std::{collections::HashSet, sync::Mutex, thread::ThreadId};
+
+    /// two threads of its own, recording which ran the jobs
+    struct TwoThreads {
+        ran_on: Mutex<HashSet<ThreadId>>,
+    }
+
+    impl ThreadPool for TwoThreads {
+        fn threads(&self) -> usize {
+            2
+        }
+
+        fn run<'a>(&self, jobs: Vec<Job<'a>>) {
+            assert!(jobs.len() <= 2);
+            thread::scope(|scope| {
+                for job in jobs {
+                    scope.spawn(|| {
+                        self.ran_on.lock().unwrap().insert(thread::current().id());
+                        job();
+                    });
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn matches_sequential() {
+        let tree = KDTree::make((0..1000).map(|_| (random(), random()).into()).collect());
+        let searches: Vec<Point2D> = (0..101).map(|_| (random(), random()).into()).collect();
+        let sequential: Vec<_> = searches
+            .iter()
+            .map(|search| tree.find_nearest(search))
+            .collect();
+
+        let pool = TwoThreads {
+            ran_on: Mutex::new(HashSet::new()),
+        };
+        let found = tree.find_nearest_batch_in_pool(&searches, &pool);
+        assert_eq!(found.len(), sequential.len());
+        assert!(found
+            .iter()
+            .zip(&sequential)
+            .all(|(a, b)| std::ptr::eq(a.unwrap(), b.unwrap())));
+        let ran_on = pool.ran_on.into_inner().unwrap();
+        assert_eq!(ran_on.len(), 2);
+        assert!(!ran_on.contains(&thread::current().id()));
+
+        for threads in [0, 1, 3, 200] {
+            let found = tree.find_nearest_batch_in_pool(&searches, &ScopedThreads(threads));
+            assert!(found
+                .iter()
+                .zip(&sequential)
+                .all(|(a, b)| std::ptr::eq(a.unwrap(), b.unwrap())));
+        }
+
+        assert!(tree
+            .find_nearest_batch_in_pool(&[], &ScopedThreads(2))
+            .is_empty());
+        let empty = KDTree::<Point2D>::make(Vec::new());
+        let found = empty.find_nearest_batch_in_pool(&searches, &ScopedThreads(2));
+        assert!(found.iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,124 @@
+//! queries handing their results to a consumer one by one as they are found, for result sets too
+//! large to collect first
+
+use std::{fmt, sync::mpsc::Sender};
+
+use super::{KDPoint, KDTree};
+
+/// the consumer of a query's results is gone, the query stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkClosed;
+
+impl fmt::Display for SinkClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the consumer of the results is gone")
+    }
+}
+
+impl std::error::Error for SinkClosed {}
+
+/// a sink sending copies of the results to `sender`, closed once its receiver is dropped
+pub fn sender_sink<T: Clone>(sender: &Sender<T>) -> impl FnMut(&T) -> Result<(), SinkClosed> + '_ {
+    |point| sender.send(point.clone()).map_err(|_| SinkClosed)
+}
+
+impl<T: KDPoint> KDTree<T> {
+    /// `find_within_radius`, handing every point to `sink` as soon as it is found, returns how
+    /// many
+    ///
+    /// the search stops at the first point `sink` does not take
+    pub fn find_within_radius_sink(
+        &self,
+        search: &T,
+        radius: T::Distance,
+        mut sink: impl FnMut(&T) -> Result<(), SinkClosed>,
+    ) -> Result<usize, SinkClosed> {
+        let mut emitted = 0;
+        let mut closed = false;
+        self.visit_within_radius(search, radius, |point| match sink(point) {
+            Ok(()) => {
+                emitted += 1;
+                true
+            }
+            Err(SinkClosed) => {
+                closed = true;
+                false
+            }
+        });
+        if closed {
+            Err(SinkClosed)
+        } else {
+            Ok(emitted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2D, F64};
+    use rand::random;
+    use std::sync::mpsc::channel;
+
+    fn tree() -> KDTree<Point2D> {
+        KDTree::make((0..500).map(|_| (random(), random()).into()).collect())
+    }
+
+    #[test]
+    fn emits_everything() {
+        let tree = tree();
+        let (search, radius) = (Point2D::from((0.5, 0.5)), F64::from(0.05));
+        let (sender, receiver) = channel();
+        let emitted = tree.find_within_radius_sink(&search, radius, sender_sink(&sender));
+        let expected = tree.count_within_radius(&search, radius);
+        assert_eq!(emitted, Ok(expected));
+        drop(sender);
+        let received: Vec<Point2D> = receiver.into_iter().collect();
+        assert_eq!(received.len(), expected);
+        assert!(received
+            .iter()
+            .all(|p| Point2D::distance(&search, p) <= radius));
+    }
+
+    #[test]
+    fn stops_when_closed() {
+        let tree = tree();
+        let search = Point2D::from((0.5, 0.5));
+        let everything = F64::from(2.0);
+
+        let mut calls = 0;
+        let result = tree.find_within_radius_sink(&search, everything, |_| {
+            calls += 1;
+            if calls > 3 {
+                Err(SinkClosed)
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result, Err(SinkClosed));
+        assert_eq!(calls, 4);
+
+        // the consumer hangs up after three points
+        let (sender, receiver) = channel();
+        let mut receiver = Some(receiver);
+        let mut received = 0;
+        let mut send = sender_sink(&sender);
+        let mut sent = 0;
+        let result = tree.find_within_radius_sink(&search, everything, |point| {
+            let result = send(point);
+            sent += 1;
+            if sent == 3 {
+                received = receiver.take().unwrap().try_iter().count();
+            }
+            result
+        });
+        assert_eq!(result, Err(SinkClosed));
+        assert_eq!((received, sent), (3, 4));
+
+        let empty = KDTree::<Point2D>::make(Vec::new());
+        assert_eq!(
+            empty.find_within_radius_sink(&search, everything, |_| Err(SinkClosed)),
+            Ok(0)
+        );
+    }
+}
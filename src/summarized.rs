@@ -0,0 +1,505 @@
+//! values of the points combined over every subtree, for searches that skip the subtrees which
+//! cannot hold a match
+
+use std::ops::Add;
+
+use super::{is_within_box, KDPoint, KDTree, Nearest, NearestHook, Node, Visitor};
+
+/// values that combine over many points: `combine` must be associative, with `empty` changing
+/// nothing
+pub trait Monoid: Clone {
+    /// the value of no points at all
+    fn empty() -> Self;
+
+    fn combine(&self, other: &Self) -> Self;
+}
+
+/// the largest of the values, `None` for none
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Max<K>(pub Option<K>);
+
+impl<K: Ord + Clone> Monoid for Max<K> {
+    fn empty() -> Self {
+        Self(None)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        // `None` is below every value
+        Self(self.0.clone().max(other.0.clone()))
+    }
+}
+
+/// the smallest of the values, `None` for none
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Min<K>(pub Option<K>);
+
+impl<K: Ord + Clone> Monoid for Min<K> {
+    fn empty() -> Self {
+        Self(None)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Self(Some(a.min(b).clone())),
+            (a, b) => Self(a.clone().or_else(|| b.clone())),
+        }
+    }
+}
+
+/// the sum of the values, e.g. of weights for `summary_in_range`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Sum<V>(pub V);
+
+impl<V: Add<Output = V> + Default + Clone> Monoid for Sum<V> {
+    fn empty() -> Self {
+        Self(V::default())
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Self(self.0.clone() + other.0.clone())
+    }
+}
+
+/// the summary of a node's subtree, along with those of its children, in the shape of the tree
+#[derive(Debug, Clone, PartialEq)]
+struct SummaryNode<M> {
+    value: M,
+    left: Option<Box<Self>>,
+    right: Option<Box<Self>>,
+}
+
+impl<M: Monoid> SummaryNode<M> {
+    fn build<T>(node: &Node<T>, summarize: &dyn Fn(&T) -> M) -> Box<Self> {
+        let build = |child: &Option<Box<Node<T>>>| {
+            child.as_deref().map(|child| Self::build(child, summarize))
+        };
+        let (left, right) = (build(&node.left), build(&node.right));
+        let mut value = node
+            .points()
+            .fold(M::empty(), |value, point| value.combine(&summarize(point)));
+        for child in left.iter().chain(&right) {
+            value = value.combine(&child.value);
+        }
+        Box::new(Self { value, left, right })
+    }
+
+    /// the slot at the end of `path` (true: left) from `slot`, adding `value` to the summaries
+    /// on the way
+    fn descend<'a>(
+        mut slot: &'a mut Option<Box<Self>>,
+        path: &[bool],
+        value: &M,
+    ) -> &'a mut Option<Box<Self>> {
+        for &left in path {
+            let node = slot.as_mut().expect("summaries in the shape of the tree");
+            node.value = node.value.combine(value);
+            slot = if left {
+                &mut node.left
+            } else {
+                &mut node.right
+            };
+        }
+        slot
+    }
+}
+
+/// computes the value of a single point
+type Summarize<T, M> = Box<dyn Fn(&T) -> M>;
+
+/// the split keys bounding a subtree in one dimension, below and above
+type SplitBounds<K> = (Option<K>, Option<K>);
+
+/// a tree with the values of its points combined over every subtree
+///
+/// the summaries are kept up to date by `insert`, `remove_where` and `rebalance`: an insert adds
+/// the new point's value along its path, and summarizes a subtree it rebuilt afresh
+pub struct SummarizedKDTree<T: KDPoint, M> {
+    tree: KDTree<T>,
+    summaries: Option<Box<SummaryNode<M>>>,
+    summarize: Summarize<T, M>,
+}
+
+impl<T: KDPoint, M: Monoid> SummarizedKDTree<T, M> {
+    /// `summarize` gives the value of a single point, e.g. `Max(Some(point.time))`
+    pub fn new(tree: KDTree<T>, summarize: impl Fn(&T) -> M + 'static) -> Self {
+        let mut summarized = Self {
+            tree,
+            summaries: None,
+            summarize: Box::new(summarize),
+        };
+        summarized.resummarize();
+        summarized
+    }
+
+    /// the points, for queries
+    pub fn tree(&self) -> &KDTree<T> {
+        &self.tree
+    }
+
+    /// the values of all points combined
+    pub fn summary(&self) -> M {
+        self.summaries
+            .as_ref()
+            .map(|summary| summary.value.clone())
+            .unwrap_or_else(M::empty)
+    }
+
+    pub fn insert(&mut self, data: T) {
+        let value = (self.summarize)(&data);
+        let placement = self.tree.insert_placed(data);
+        let Some(depth) = placement.rebuilt else {
+            let end = SummaryNode::descend(&mut self.summaries, &placement.path, &value);
+            match end {
+                Some(node) => node.value = node.value.combine(&value),
+                None => {
+                    *end = Some(Box::new(SummaryNode {
+                        value,
+                        left: None,
+                        right: None,
+                    }))
+                }
+            }
+            return;
+        };
+
+        let path = &placement.path[..depth];
+        let mut node = self.tree.root.as_ref();
+        for &left in path {
+            node = node.and_then(|node| if left { &node.left } else { &node.right }.as_deref());
+        }
+        let summarize = &*self.summarize;
+        let rebuilt = node.map(|node| SummaryNode::build(node, summarize));
+        *SummaryNode::descend(&mut self.summaries, path, &value) = rebuilt;
+    }
+
+    /// removes all points for which `remove` holds, returns them in no particular order
+    ///
+    /// only the subtrees below removed split points are rebuilt, as by `prune_outside`, but if any
+    /// point goes the whole tree is summarized afresh. Trees made with `make_with_counts` are not
+    /// supported.
+    pub fn remove_where(&mut self, remove: impl FnMut(&T) -> bool) -> Vec<T> {
+        let removed = self.tree.remove_where(remove, false);
+        if !removed.is_empty() {
+            self.resummarize();
+        }
+        removed
+    }
+
+    /// `KDTree::rebalance`, summarizing the tree afresh
+    pub fn rebalance(&mut self) {
+        self.tree.rebalance();
+        self.resummarize();
+    }
+
+    /// the point closest to `search` among those whose own value passes `filter`
+    ///
+    /// subtrees whose summary fails `filter` are skipped, so it has to hold for a combination of
+    /// values whenever it holds for any of them, as `|max| max.0 >= Some(threshold)` does for
+    /// `Max`
+    pub fn find_nearest_where_summary(
+        &self,
+        search: &T,
+        filter: impl Fn(&M) -> bool,
+    ) -> Option<&T> {
+        let (root, summary) = self.tree.root.as_ref().zip(self.summaries.as_deref())?;
+        if !filter(&summary.value) {
+            return None;
+        }
+        let hook = SummaryFilter {
+            filter: &filter,
+            root: summary,
+            entered: Vec::new(),
+        };
+        let accept = |point: &T| filter(&(self.summarize)(point));
+        let mut visitor = Nearest::with_hook(search, accept, hook);
+        visitor.visit(root);
+        visitor.best
+    }
+
+    /// the values of all points inside the box spanned by `min` and `max` (inclusive) combined, in
+    /// no particular order
+    ///
+    /// a subtree that lies inside the box as a whole, as far as the split planes above it tell,
+    /// contributes its summary without being entered, so the cost depends on how many subtrees the
+    /// border of the box cuts through rather than on the number of points inside
+    pub fn summary_in_range(&self, min: &T, max: &T) -> M {
+        let Some((root, summary)) = self.tree.root.as_ref().zip(self.summaries.as_deref()) else {
+            return M::empty();
+        };
+        let mut bounds: Vec<SplitBounds<T::Key>> =
+            (0..root.data.dimensions()).map(|_| (None, None)).collect();
+        self.summary_within(root, summary, min, max, &mut bounds)
+    }
+
+    fn summary_within(
+        &self,
+        node: &Node<T>,
+        summary: &SummaryNode<M>,
+        min: &T,
+        max: &T,
+        bounds: &mut [SplitBounds<T::Key>],
+    ) -> M {
+        let inside = bounds.iter().enumerate().all(|(dimension, (lo, hi))| {
+            lo.as_ref().is_some_and(|lo| min.kdkey(dimension) <= *lo)
+                && hi.as_ref().is_some_and(|hi| *hi <= max.kdkey(dimension))
+        });
+        if inside {
+            return summary.value.clone();
+        }
+
+        let mut value = node
+            .points()
+            .filter(|point| is_within_box(*point, min, max))
+            .fold(M::empty(), |value, point| {
+                value.combine(&(self.summarize)(point))
+            });
+
+        // equal keys may sit on both sides of the split
+        let dimension = node.dimension();
+        let key = node.data.kdkey(dimension);
+        if min.kdkey(dimension) <= key {
+            if let Some((left, summary)) = node.left.as_deref().zip(summary.left.as_deref()) {
+                let outer = bounds[dimension].1.replace(node.data.kdkey(dimension));
+                value = value.combine(&self.summary_within(left, summary, min, max, bounds));
+                bounds[dimension].1 = outer;
+            }
+        }
+        if key <= max.kdkey(dimension) {
+            if let Some((right, summary)) = node.right.as_deref().zip(summary.right.as_deref()) {
+                let outer = bounds[dimension].0.replace(key);
+                value = value.combine(&self.summary_within(right, summary, min, max, bounds));
+                bounds[dimension].0 = outer;
+            }
+        }
+        value
+    }
+
+    fn resummarize(&mut self) {
+        self.summaries = self
+            .tree
+            .root
+            .as_ref()
+            .map(|root| SummaryNode::build(root, &*self.summarize));
+    }
+}
+
+/// skips the subtrees whose summary fails `filter`; while searching, `entered` holds the entered
+/// nodes along with their summaries
+struct SummaryFilter<'t, 'f, T, M, F> {
+    filter: &'f F,
+    root: &'t SummaryNode<M>,
+    entered: Vec<(&'t Node<T>, &'t SummaryNode<M>)>,
+}
+
+impl<'t, T: KDPoint, M, F: Fn(&M) -> bool> NearestHook<'t, T> for SummaryFilter<'t, '_, T, M, F> {
+    fn entered(&mut self, node: &'t Node<T>, _: &T::Distance, _: Option<&T::Distance>) {
+        let summary = match self.entered.last() {
+            Some((parent, summary)) => {
+                let left = parent
+                    .left
+                    .as_deref()
+                    .is_some_and(|left| std::ptr::eq(left, node));
+                if left { &summary.left } else { &summary.right }
+                    .as_deref()
+                    .expect("summaries in the shape of the tree")
+            }
+            None => self.root,
+        };
+        self.entered.push((node, summary));
+    }
+
+    fn finished(&mut self, _node: &'t Node<T>) {
+        self.entered.pop();
+    }
+
+    fn skips(&self, _node: &Node<T>, left: bool) -> bool {
+        let Some((_, summary)) = self.entered.last() else {
+            return false;
+        };
+        if left { &summary.left } else { &summary.right }
+            .as_deref()
+            .is_some_and(|child| !(self.filter)(&child.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DepthPolicy, Point2D, F64};
+    use rand::random;
+
+    /// a position observed at a time
+    #[derive(Debug, Clone)]
+    struct Observation {
+        at: Point2D,
+        time: u64,
+    }
+
+    impl KDPoint for Observation {
+        type Key = F64;
+        type Distance = F64;
+
+        fn dimensions(&self) -> usize {
+            2
+        }
+
+        fn kdkey(&self, dimension: usize) -> F64 {
+            self.at.kdkey(dimension)
+        }
+
+        fn distance(lhs: &Self, rhs: &Self) -> F64 {
+            Point2D::distance(&lhs.at, &rhs.at)
+        }
+
+        fn key_distance(lhs: &F64, rhs: &F64) -> F64 {
+            Point2D::key_distance(lhs, rhs)
+        }
+    }
+
+    fn observation(time: u64) -> Observation {
+        Observation {
+            at: (random(), random()).into(),
+            time,
+        }
+    }
+
+    fn latest(tree: KDTree<Observation>) -> SummarizedKDTree<Observation, Max<u64>> {
+        SummarizedKDTree::new(tree, |observation| Max(Some(observation.time)))
+    }
+
+    /// checks the summaries against ones computed from scratch
+    fn check(summarized: &SummarizedKDTree<Observation, Max<u64>>) {
+        let fresh = summarized
+            .tree
+            .root
+            .as_ref()
+            .map(|root| SummaryNode::build(root, &*summarized.summarize));
+        assert_eq!(summarized.summaries, fresh);
+        assert_eq!(
+            summarized.summary(),
+            Max(summarized.tree.iter().map(|o| o.time).max())
+        );
+    }
+
+    #[test]
+    fn combine() {
+        assert_eq!(Max(Some(3)).combine(&Max(None)), Max(Some(3)));
+        assert_eq!(Max(Some(3)).combine(&Max(Some(5))), Max(Some(5)));
+        assert_eq!(Min(None).combine(&Min(Some(3))), Min(Some(3)));
+        assert_eq!(Min(Some(3)).combine(&Min(Some(5))), Min(Some(3)));
+        assert_eq!(Min::<u8>::empty(), Min(None));
+    }
+
+    #[test]
+    fn summaries_follow_mutations() {
+        for policy in [DepthPolicy::Ignore, DepthPolicy::RebuildSubtree] {
+            let mut tree = KDTree::make((0..50).map(observation).collect())
+                .with_depth_policy(policy)
+                .with_max_depth(6);
+            tree.set_auto_maintenance(40, 2.0);
+            let mut summarized = latest(tree);
+            check(&summarized);
+
+            for time in 50..400 {
+                summarized.insert(observation(time));
+                check(&summarized);
+                if time % 50 == 0 {
+                    let removed = summarized.remove_where(|o| o.time + 100 < time);
+                    assert!(removed.iter().all(|o| o.time + 100 < time));
+                    check(&summarized);
+                }
+                if time % 120 == 0 {
+                    summarized.rebalance();
+                    check(&summarized);
+                }
+            }
+            summarized.remove_where(|_| true);
+            check(&summarized);
+            assert_eq!(summarized.summary(), Max(None));
+            assert!(summarized
+                .find_nearest_where_summary(&observation(0), |_| true)
+                .is_none());
+        }
+    }
+
+    /// compares `summary_in_range` with folding over the points of random boxes
+    fn check_ranges(summarized: &SummarizedKDTree<Observation, Sum<u64>>) {
+        for _ in 0..20 {
+            let (x, y): (f64, f64) = (random(), random());
+            let min = corner(x * 0.8, y * 0.8);
+            let max = corner(x * 0.8 + 0.2 * random::<f64>(), y * 0.8 + random::<f64>());
+            let expected = summarized
+                .tree()
+                .fold_in_range(&min, &max, 0, |sum, o| sum + o.time);
+            assert_eq!(summarized.summary_in_range(&min, &max), Sum(expected));
+        }
+    }
+
+    fn corner(x: f64, y: f64) -> Observation {
+        Observation {
+            at: (x, y).into(),
+            time: 0,
+        }
+    }
+
+    #[test]
+    fn sums_in_range() {
+        use std::{cell::Cell, rc::Rc};
+
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let tree = KDTree::make((0..2000).map(observation).collect())
+            .with_depth_policy(DepthPolicy::RebuildSubtree)
+            .with_max_depth(14);
+        let mut summarized = SummarizedKDTree::new(tree, move |o: &Observation| {
+            counter.set(counter.get() + 1);
+            Sum(o.time)
+        });
+        check_ranges(&summarized);
+
+        for time in 2000..2600 {
+            summarized.insert(observation(time));
+            if time % 100 == 0 {
+                check_ranges(&summarized);
+            }
+        }
+        summarized.remove_where(|o| o.time % 3 == 0);
+        check_ranges(&summarized);
+        summarized.rebalance();
+        check_ranges(&summarized);
+
+        // the subtrees inside count as a whole
+        let (min, max) = (corner(0.1, 0.1), corner(0.9, 0.9));
+        let inside = summarized.tree().fold_in_range(&min, &max, 0, |n, _| n + 1);
+        calls.set(0);
+        summarized.summary_in_range(&min, &max);
+        assert!(calls.get() * 4 < inside, "{} of {inside}", calls.get());
+
+        summarized.remove_where(|_| true);
+        assert_eq!(summarized.summary_in_range(&min, &max), Sum(0));
+    }
+
+    #[test]
+    fn matches_unpruned() {
+        let data: Vec<Observation> = (0..1000).map(observation).collect();
+        let summarized = latest(KDTree::make(data.clone()));
+
+        for _ in 0..100 {
+            let search = observation(0);
+            let threshold = 900 + random::<u64>() % 120;
+            let recent = |o: &Observation| o.time >= threshold;
+            let found =
+                summarized.find_nearest_where_summary(&search, |max| max.0 >= Some(threshold));
+            let unpruned = summarized.tree().find_nearest_accepting(&search, recent);
+            let expected = data
+                .iter()
+                .filter(|o| recent(o))
+                .map(|o| Observation::distance(&search, o))
+                .min();
+            assert!(found.is_none_or(recent));
+            let distance = |o: &Observation| Observation::distance(&search, o);
+            assert_eq!(found.map(distance), expected);
+            assert_eq!(unpruned.map(distance), expected);
+        }
+    }
+}
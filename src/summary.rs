@@ -0,0 +1,212 @@
+//! distribution of the keys along each axis, for choosing scales and weights
+
+use super::{KDPoint, KDTree};
+
+/// keys of all points in one dimension, as numbers
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySummary {
+    pub min: f64,
+    pub max: f64,
+    /// keys at ranks `i * (len - 1) / buckets` for `i` in `0..=buckets`, from `min` to `max`
+    pub quantiles: Vec<f64>,
+    /// `buckets + 1` bucket boundaries of equal width from `min` to `max`
+    pub edges: Vec<f64>,
+    /// keys per bucket, the last bucket includes `max`; all in the first if `min` and `max` are
+    /// equal
+    pub counts: Vec<usize>,
+}
+
+impl<T: KDPoint> KDTree<T> {
+    /// the keys in `dimension` at ranks `i * (len - 1) / quantiles` for `i` in `0..=quantiles`,
+    /// i.e. from the smallest to the largest; empty if the tree is or `quantiles` is 0
+    ///
+    /// exact, found by selection rather than sorting all keys
+    pub fn key_quantiles(&self, dimension: usize, quantiles: usize) -> Vec<T::Key>
+    where
+        T::Key: Clone,
+    {
+        let mut keys: Vec<T::Key> = self.iter().map(|point| point.kdkey(dimension)).collect();
+        let ranks = ranks(keys.len(), quantiles);
+        select_ranks(&mut keys, &ranks);
+        ranks.into_iter().map(|rank| keys[rank].clone()).collect()
+    }
+
+    /// the keys in `dimension` on either side of the widest interval without any, the lowest of
+    /// them on ties; `None` if there are fewer than two points
+    ///
+    /// widths are compared by `T::key_distance`
+    pub fn largest_gap(&self, dimension: usize) -> Option<(T::Key, T::Key)> {
+        let mut keys: Vec<T::Key> = self.iter().map(|point| point.kdkey(dimension)).collect();
+        keys.sort();
+        let (start, _) = keys
+            .windows(2)
+            .map(|pair| T::key_distance(&pair[1], &pair[0]))
+            .enumerate()
+            .rev()
+            .max_by(|(_, a), (_, b)| a.cmp(b))?;
+        let mut gap = keys.into_iter().skip(start);
+        Some((gap.next()?, gap.next()?))
+    }
+
+    /// `KeySummary` of the keys in `dimension` with `buckets` buckets, `None` if the tree is
+    /// empty or `buckets` is 0
+    ///
+    /// counted points count once per copy
+    pub fn key_summary(&self, dimension: usize, buckets: usize) -> Option<KeySummary>
+    where
+        T::Key: Into<f64>,
+    {
+        let mut keys: Vec<T::Key> = self.iter().map(|point| point.kdkey(dimension)).collect();
+        let ranks = ranks(keys.len(), buckets);
+        if ranks.is_empty() {
+            return None;
+        }
+        select_ranks(&mut keys, &ranks);
+        let values: Vec<f64> = keys.into_iter().map(Into::into).collect();
+        let quantiles: Vec<f64> = ranks.into_iter().map(|rank| values[rank]).collect();
+        let (min, max) = (quantiles[0], quantiles[buckets]);
+
+        let width = (max - min) / buckets as f64;
+        let edges = (0..=buckets)
+            .map(|i| {
+                if i == buckets {
+                    max
+                } else {
+                    min + width * i as f64
+                }
+            })
+            .collect();
+        let mut counts = vec![0; buckets];
+        for value in values {
+            let bucket = if width > 0.0 {
+                ((value - min) / width) as usize
+            } else {
+                0
+            };
+            counts[bucket.min(buckets - 1)] += 1;
+        }
+        Some(KeySummary {
+            min,
+            max,
+            quantiles,
+            edges,
+            counts,
+        })
+    }
+
+    /// `key_summary` for every dimension of the root's point, empty if the tree is or `buckets`
+    /// is 0
+    pub fn summaries(&self, buckets: usize) -> Vec<KeySummary>
+    where
+        T::Key: Into<f64>,
+    {
+        let dimensions = self
+            .root
+            .as_ref()
+            .map(|root| root.data.dimensions())
+            .unwrap_or(0);
+        (0..dimensions)
+            .filter_map(|dimension| self.key_summary(dimension, buckets))
+            .collect()
+    }
+}
+
+/// ranks `i * (len - 1) / parts` for `i` in `0..=parts`, none if `len` or `parts` is 0
+fn ranks(len: usize, parts: usize) -> Vec<usize> {
+    if len == 0 || parts == 0 {
+        return Vec::new();
+    }
+    (0..=parts).map(|i| i * (len - 1) / parts).collect()
+}
+
+/// reorders `keys` so that each of the ascending `ranks` holds the key of that rank
+fn select_ranks<K: Ord>(keys: &mut [K], ranks: &[usize]) {
+    let mut start = 0;
+    for &rank in ranks {
+        if rank >= start {
+            keys[start..].select_nth_unstable(rank - start);
+            start = rank + 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2D, F64};
+    use rand::random;
+
+    #[test]
+    fn exact_on_small_data() {
+        // x: 0, 1, .., 9, y: 10 times 5
+        let data: Vec<Point2D> = (0..10).rev().map(|i| (i as f64, 5.0).into()).collect();
+        let tree = KDTree::make(data);
+
+        let x = tree.key_summary(0, 3).unwrap();
+        assert_eq!((x.min, x.max), (0.0, 9.0));
+        assert_eq!(x.quantiles, [0.0, 3.0, 6.0, 9.0]);
+        assert_eq!(x.edges, [0.0, 3.0, 6.0, 9.0]);
+        assert_eq!(x.counts, [3, 3, 4]);
+
+        let y = tree.key_summary(1, 2).unwrap();
+        assert_eq!(y.quantiles, [5.0, 5.0, 5.0]);
+        assert_eq!(y.counts, [10, 0]);
+
+        assert_eq!(tree.summaries(3), [x, tree.key_summary(1, 3).unwrap()]);
+        assert!(tree.key_summary(0, 0).is_none());
+        assert!(KDTree::<Point2D>::make(Vec::new())
+            .key_summary(0, 4)
+            .is_none());
+        assert!(KDTree::<Point2D>::make(Vec::new()).summaries(4).is_empty());
+    }
+
+    #[test]
+    fn largest_gap() {
+        // x in [0, 1) and [5, 6), y uniform in [0, 10)
+        let mut data: Vec<Point2D> = (0..200)
+            .map(|i| {
+                let x = random::<f64>() + if i % 2 == 0 { 0.0 } else { 5.0 };
+                (x, 10.0 * random::<f64>()).into()
+            })
+            .collect();
+        data.push((1.0, 0.0).into());
+        data.push((5.0, 0.0).into());
+        let tree = KDTree::make(data);
+        assert_eq!(tree.largest_gap(0), Some((F64::from(1.0), F64::from(5.0))));
+        let (low, high) = tree.largest_gap(1).unwrap();
+        assert!(low < high && f64::from(high) - f64::from(low) < 5.0);
+
+        // equal gaps: the lowest
+        let tree = KDTree::make(vec![
+            Point2D::from((0.0, 0.0)),
+            (2.0, 0.0).into(),
+            (4.0, 0.0).into(),
+        ]);
+        assert_eq!(tree.largest_gap(0), Some((F64::from(0.0), F64::from(2.0))));
+        assert_eq!(tree.largest_gap(1), Some((F64::from(0.0), F64::from(0.0))));
+
+        assert!(KDTree::make(vec![Point2D::from((0.0, 0.0))])
+            .largest_gap(0)
+            .is_none());
+    }
+
+    #[test]
+    fn quantiles_match_sorting() {
+        for len in [1, 2, 7, 100, 1001] {
+            let data: Vec<Point2D> = (0..len).map(|_| (random(), random()).into()).collect();
+            let mut sorted: Vec<F64> = data.iter().map(|point| point.kdkey(1)).collect();
+            sorted.sort();
+            let tree = KDTree::make(data);
+            for quantiles in [1, 4, 10] {
+                let expected: Vec<F64> = (0..=quantiles)
+                    .map(|i| sorted[i * (len - 1) / quantiles])
+                    .collect();
+                assert_eq!(tree.key_quantiles(1, quantiles), expected);
+                let summary = tree.key_summary(1, quantiles).unwrap();
+                let expected: Vec<f64> = expected.into_iter().map(Into::into).collect();
+                assert_eq!(summary.quantiles, expected);
+                assert_eq!(summary.counts.iter().sum::<usize>(), len);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! step by step record of a nearest neighbour search, for finding out why a query returned what
+//! it did
+
+use std::fmt::{self, Debug, Display};
+
+use super::{KDPoint, KDTree, Nearest, NearestHook, Node, Visitor};
+
+#[derive(Debug, Clone)]
+pub enum TraceEvent<'t, T: KDPoint> {
+    /// the search entered the node holding `split` at `depth` (the root is at 0)
+    Visited {
+        depth: usize,
+        split: &'t T,
+        split_dimension: usize,
+        /// from the search to the split, along `split_dimension`
+        key_distance: T::Distance,
+        best_so_far: Option<T::Distance>,
+    },
+    /// `point` of the node at `depth` is the best one so far
+    NewBest {
+        depth: usize,
+        point: &'t T,
+        distance: T::Distance,
+    },
+    /// the subtree at `depth` below the node holding `root` was skipped, as the split of its
+    /// parent is `key_distance` away while the best point is at `best_distance`
+    Pruned {
+        depth: usize,
+        root: &'t T,
+        key_distance: T::Distance,
+        best_distance: T::Distance,
+    },
+}
+
+/// what `KDTree::explain_nearest` did, in order, and what it found
+#[derive(Clone)]
+pub struct QueryTrace<'t, T: KDPoint> {
+    pub events: Vec<TraceEvent<'t, T>>,
+    pub nearest: Option<&'t T>,
+}
+
+/// records everything a `Nearest` search reports
+struct Tracer<'t, T: KDPoint> {
+    /// depth of the next node entered
+    depth: usize,
+    events: Vec<TraceEvent<'t, T>>,
+}
+
+impl<'t, T: KDPoint> NearestHook<'t, T> for Tracer<'t, T> {
+    fn entered(
+        &mut self,
+        node: &'t Node<T>,
+        target_to_split: &T::Distance,
+        best_distance: Option<&T::Distance>,
+    ) {
+        self.events.push(TraceEvent::Visited {
+            depth: self.depth,
+            split: &node.data,
+            split_dimension: node.dimension(),
+            key_distance: target_to_split.clone(),
+            best_so_far: best_distance.cloned(),
+        });
+        self.depth += 1;
+    }
+
+    fn finished(&mut self, _node: &'t Node<T>) {
+        self.depth -= 1;
+    }
+
+    fn improved(&mut self, point: &'t T, distance: &T::Distance) {
+        self.events.push(TraceEvent::NewBest {
+            depth: self.depth - 1,
+            point,
+            distance: distance.clone(),
+        });
+    }
+
+    fn pruned(
+        &mut self,
+        child: &'t Node<T>,
+        target_to_split: &T::Distance,
+        best_distance: &T::Distance,
+    ) {
+        self.events.push(TraceEvent::Pruned {
+            depth: self.depth,
+            root: &child.data,
+            key_distance: target_to_split.clone(),
+            best_distance: best_distance.clone(),
+        });
+    }
+}
+
+impl<T: KDPoint> KDTree<T> {
+    /// like `find_nearest`, but records every node the search visits, every subtree it prunes
+    /// and every improvement of the best point
+    pub fn explain_nearest(&self, search: &T) -> QueryTrace<'_, T> {
+        let tracer = Tracer {
+            depth: 0,
+            events: Vec::new(),
+        };
+        let mut visitor = Nearest::with_hook(search, |_: &T| true, tracer);
+        if let Some(root) = &self.root {
+            visitor.visit(root);
+        }
+        QueryTrace {
+            events: visitor.hook.events,
+            nearest: visitor.best,
+        }
+    }
+}
+
+/// one line per event, indented by depth
+impl<'t, T: KDPoint + Debug> Display for QueryTrace<'t, T>
+where
+    T::Distance: Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in &self.events {
+            match event {
+                TraceEvent::Visited {
+                    depth,
+                    split,
+                    split_dimension,
+                    key_distance,
+                    best_so_far,
+                } => writeln!(
+                    f,
+                    "{:indent$}visit {split:?}: split on {split_dimension}, {key_distance:?} away, best {best_so_far:?}",
+                    "",
+                    indent = 2 * depth
+                )?,
+                TraceEvent::NewBest {
+                    depth,
+                    point,
+                    distance,
+                } => writeln!(
+                    f,
+                    "{:indent$}new best {point:?} at {distance:?}",
+                    "",
+                    indent = 2 * depth
+                )?,
+                TraceEvent::Pruned {
+                    depth,
+                    root,
+                    key_distance,
+                    best_distance,
+                } => writeln!(
+                    f,
+                    "{:indent$}prune below {root:?}: split {key_distance:?} away, best {best_distance:?}",
+                    "",
+                    indent = 2 * depth
+                )?,
+            }
+        }
+        write!(f, "nearest: {:?}", self.nearest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point2D;
+
+    #[test]
+    fn render() {
+        let tree = KDTree::<Point2D>::make(vec![
+            (0.0, 0.0).into(),
+            (5.0, 0.0).into(),
+            (10.0, 0.0).into(),
+        ]);
+        let trace = tree.explain_nearest(&(1.0, 0.0).into());
+        assert!(std::ptr::eq(
+            trace.nearest.unwrap(),
+            tree.find_nearest(&(1.0, 0.0).into()).unwrap()
+        ));
+
+        let rendered = trace.to_string();
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(lines.len(), trace.events.len() + 1);
+        assert!(lines[0].starts_with("visit Point2D { x: 5.0"));
+        assert!(lines[1].starts_with("  visit Point2D { x: 0.0"));
+        assert!(lines[2].starts_with("  new best Point2D { x: 0.0"));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("  prune below Point2D { x: 10.0")));
+        assert!(lines
+            .last()
+            .unwrap()
+            .starts_with("nearest: Some(Point2D { x: 0.0"));
+
+        let empty = KDTree::<Point2D>::make(Vec::new());
+        let empty = empty.explain_nearest(&(1.0, 0.0).into());
+        assert!(empty.events.is_empty());
+        assert_eq!(empty.to_string(), "nearest: None");
+    }
+}
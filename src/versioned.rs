@@ -0,0 +1,160 @@
+//! query results that know which state of the tree they were taken from, for caches that keep
+//! them around while the tree changes
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{KDPoint, KDTree};
+
+/// shared by all trees, so that no two states of any trees have the same version
+static VERSIONS: AtomicU64 = AtomicU64::new(0);
+
+/// a version no tree has had yet, greater than all before it
+pub(super) fn next_version() -> u64 {
+    VERSIONS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// copies of the points a query found, along with the `KDTree::version` it saw
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionedResults<T> {
+    version: u64,
+    results: Vec<T>,
+}
+
+impl<T: KDPoint> VersionedResults<T> {
+    /// the version of the tree at the time of the query
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn results(&self) -> &[T] {
+        &self.results
+    }
+
+    pub fn into_results(self) -> Vec<T> {
+        self.results
+    }
+
+    /// whether `tree` changed since the query, or is not the tree that was queried; the results
+    /// may then differ from what the query finds now
+    pub fn is_stale(&self, tree: &KDTree<T>) -> bool {
+        tree.version() != self.version
+    }
+}
+
+impl<T: KDPoint> KDTree<T> {
+    /// a number changed by every method changing the tree, including rebuilds that keep the
+    /// points; it only grows, and no two trees share one
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn versioned(&self, results: Vec<T>) -> VersionedResults<T> {
+        VersionedResults {
+            version: self.version,
+            results,
+        }
+    }
+}
+
+impl<T: KDPoint + Clone> KDTree<T> {
+    /// `find_within_radius_cloned`, remembering the version of the tree
+    pub fn find_within_radius_versioned(
+        &self,
+        search: &T,
+        radius: T::Distance,
+    ) -> VersionedResults<T> {
+        self.versioned(self.find_within_radius_cloned(search, radius))
+    }
+
+    /// `find_in_range_cloned`, remembering the version of the tree
+    pub fn find_in_range_versioned(&self, min: &T, max: &T) -> VersionedResults<T> {
+        self.versioned(self.find_in_range_cloned(min, max))
+    }
+
+    /// the `k` nearest points as in `find_k_nearest_cloned`, nearest first, remembering the
+    /// version of the tree
+    pub fn find_k_nearest_versioned(&self, search: &T, k: usize) -> VersionedResults<T> {
+        let nearest = self.find_k_nearest_cloned(search, k);
+        self.versioned(nearest.into_iter().map(|(point, _)| point).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2D, F64};
+
+    fn grid() -> Vec<Point2D> {
+        (0..100)
+            .map(|i| ((i % 10) as f64, (i / 10) as f64).into())
+            .collect()
+    }
+
+    #[test]
+    fn changes_with_the_tree() {
+        let first = KDTree::make(grid());
+        let mut tree = KDTree::make(grid());
+        assert!(tree.version() > first.version());
+
+        let mut last = tree.version();
+        let mut changed = |tree: &KDTree<Point2D>| {
+            let changed = tree.version() > last;
+            last = tree.version();
+            changed
+        };
+
+        let search = Point2D::from((4.5, 4.5));
+        tree.find_nearest(&search);
+        tree.find_k_nearest(&search, 5);
+        tree.find_within_radius(&search, F64::from(2.0));
+        tree.find_within_radius_versioned(&search, F64::from(2.0));
+        tree.iter().count();
+        assert!(!changed(&tree));
+
+        tree.insert((20.0, 20.0).into());
+        assert!(changed(&tree));
+        tree.remove_max_by_key(|point| point.kdkey(0));
+        assert!(changed(&tree));
+        tree.rebalance();
+        assert!(changed(&tree));
+        tree.prune_outside(&(0.0, 0.0).into(), &(9.0, 9.0).into());
+        assert!(!changed(&tree));
+        tree.prune_outside(&(0.0, 0.0).into(), &(9.0, 8.0).into());
+        assert!(changed(&tree));
+        tree.compress();
+        assert!(changed(&tree));
+        tree.dedup_within(F64::from(0.0));
+        assert!(changed(&tree));
+        assert!(tree.try_rebuild_biased(&search, 2.0).is_err());
+        assert!(!changed(&tree));
+        tree.rebuild_biased(&search, 0.5);
+        assert!(changed(&tree));
+    }
+
+    #[test]
+    fn staleness() {
+        let mut tree = KDTree::make(grid());
+        let search = Point2D::from((4.5, 4.5));
+        let nearest = tree.find_k_nearest_versioned(&search, 4);
+        let within = tree.find_within_radius_versioned(&search, F64::from(1.0));
+        let inside = tree.find_in_range_versioned(&(0.0, 0.0).into(), &(1.0, 1.0).into());
+        assert_eq!(nearest.results().len(), 4);
+        assert_eq!(within.clone().into_results().len(), 4);
+        assert_eq!(inside.results().len(), 4);
+
+        tree.find_nearest(&search);
+        assert!(!nearest.is_stale(&tree) && !within.is_stale(&tree) && !inside.is_stale(&tree));
+
+        // far from all of them, stale all the same
+        tree.insert((50.0, 50.0).into());
+        assert!(nearest.is_stale(&tree) && within.is_stale(&tree) && inside.is_stale(&tree));
+        let requeried = tree.find_k_nearest_versioned(&search, 4);
+        assert_eq!(requeried.version(), tree.version());
+        assert!(!requeried.is_stale(&tree));
+
+        // the same points in another tree
+        let other = KDTree::make(grid());
+        assert!(other.find_k_nearest_versioned(&search, 4).is_stale(&tree));
+        assert!(requeried.is_stale(&other));
+    }
+}
@@ -0,0 +1,162 @@
+//! reproducible point sets for benchmarks and tests, the same seed gives the same points
+//!
+//! all coordinates lie within `[0, 1)`, except for the tails of the clusters of `Workload::Clusters`
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::DynPoint;
+
+/// the kinds of point sets the benchmarks run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    /// uniform in the unit cube
+    Uniform,
+    /// gaussian clusters around uniform centers, see `clusters`
+    Clusters,
+    /// uniform, rounded to a coarse grid so that many points are equal, see `quantized`
+    Quantized,
+    /// uniform, sorted by the first coordinate, the worst order to insert in
+    Sorted,
+}
+
+impl Workload {
+    pub const ALL: [Self; 4] = [Self::Uniform, Self::Clusters, Self::Quantized, Self::Sorted];
+
+    /// `len` points of `dims` dimensions, with the parameters the benchmarks use
+    pub fn generate(self, len: usize, dims: usize, seed: u64) -> Vec<DynPoint> {
+        match self {
+            Self::Uniform => uniform(len, dims, seed),
+            Self::Clusters => clusters(len, dims, 16, 0.02, seed),
+            Self::Quantized => quantized(len, dims, 8, seed),
+            Self::Sorted => sorted(len, dims, seed),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Uniform => "uniform",
+            Self::Clusters => "clusters",
+            Self::Quantized => "quantized",
+            Self::Sorted => "sorted",
+        }
+    }
+}
+
+/// `len` points uniform in the unit cube of `dims` dimensions
+pub fn uniform(len: usize, dims: usize, seed: u64) -> Vec<DynPoint> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..len)
+        .map(|_| (0..dims).map(|_| rng.gen()).collect::<Vec<f64>>().into())
+        .collect()
+}
+
+/// `len` points spread evenly over `centers` gaussian clusters with standard deviation `sigma`
+/// in every dimension, around centers uniform in the unit cube
+pub fn clusters(len: usize, dims: usize, centers: usize, sigma: f64, seed: u64) -> Vec<DynPoint> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let centers: Vec<Vec<f64>> = (0..centers.max(1))
+        .map(|_| (0..dims).map(|_| rng.gen()).collect())
+        .collect();
+    (0..len)
+        .map(|i| {
+            let center = &centers[i % centers.len()];
+            center
+                .iter()
+                .map(|c| c + sigma * normal(&mut rng))
+                .collect::<Vec<f64>>()
+                .into()
+        })
+        .collect()
+}
+
+/// `len` uniform points rounded down to multiples of `1 / levels`, so there are at most
+/// `levels^dims` distinct ones
+pub fn quantized(len: usize, dims: usize, levels: usize, seed: u64) -> Vec<DynPoint> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let levels = levels.max(1);
+    (0..len)
+        .map(|_| {
+            (0..dims)
+                .map(|_| rng.gen_range(0..levels) as f64 / levels as f64)
+                .collect::<Vec<f64>>()
+                .into()
+        })
+        .collect()
+}
+
+/// `uniform`, sorted by the first coordinate
+pub fn sorted(len: usize, dims: usize, seed: u64) -> Vec<DynPoint> {
+    let mut points = uniform(len, dims, seed);
+    points.sort_by(|a, b| a.coords[0].total_cmp(&b.coords[0]));
+    points
+}
+
+/// standard normal by the Box-Muller transform
+fn normal(rng: &mut StdRng) -> f64 {
+    // 1 - u is within (0, 1], keeping ln finite
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KDPoint, F64};
+    use std::collections::HashSet;
+
+    fn bits(points: &[DynPoint]) -> Vec<Vec<u64>> {
+        points
+            .iter()
+            .map(|point| point.coords.iter().map(|c| c.to_bits()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn deterministic() {
+        for workload in Workload::ALL {
+            let points = workload.generate(500, 3, 7);
+            assert_eq!(points, workload.generate(500, 3, 7), "{}", workload.name());
+            assert_ne!(points, workload.generate(500, 3, 8), "{}", workload.name());
+        }
+    }
+
+    #[test]
+    fn sizes_and_dimensions() {
+        for workload in Workload::ALL {
+            for (len, dims) in [(0, 2), (1, 1), (1000, 3), (100, 16)] {
+                let points = workload.generate(len, dims, 1);
+                assert_eq!(points.len(), len);
+                assert!(points.iter().all(|point| point.coords.len() == dims));
+            }
+        }
+    }
+
+    #[test]
+    fn shapes() {
+        let uniform = uniform(1000, 2, 1);
+        assert!(uniform
+            .iter()
+            .flat_map(|point| &point.coords)
+            .all(|c| (0.0..1.0).contains(c)));
+
+        let distinct: HashSet<Vec<u64>> = bits(&quantized(1000, 2, 4, 1)).into_iter().collect();
+        assert!(distinct.len() <= 16);
+
+        let sorted = sorted(1000, 2, 1);
+        assert!(sorted.windows(2).all(|w| w[0].coords[0] <= w[1].coords[0]));
+
+        // most points close to one of a few centers
+        let clusters = clusters(1000, 2, 4, 0.01, 1);
+        let centers: Vec<&DynPoint> = clusters.iter().take(4).collect();
+        let close = clusters
+            .iter()
+            .filter(|point| {
+                centers
+                    .iter()
+                    .any(|center| DynPoint::distance(point, center) < F64::from(0.1 * 0.1))
+            })
+            .count();
+        assert!(close > 950);
+    }
+}